@@ -0,0 +1,71 @@
+use criterion::{
+    black_box, criterion_group, criterion_main, measurement::Measurement, BatchSize,
+    BenchmarkGroup, BenchmarkId, Criterion, SamplingMode, Throughput,
+};
+use ipa_core::{
+    protocol::{
+        context::dzkp_validator::TARGET_PROOF_SIZE,
+        ipa_prf::{boolean_ops::conversion_records_per_batch, CONV_CHUNK},
+    },
+    test_fixture::conversion::{conversion_setup, malicious_conversion},
+};
+use tokio::runtime::{Builder, Runtime};
+
+fn do_benchmark<M: Measurement>(
+    rt: &Runtime,
+    group: &mut BenchmarkGroup<M>,
+    count: usize,
+    records_per_batch: usize,
+) {
+    group.throughput(Throughput::Elements(count as u64));
+    group.bench_with_input(
+        BenchmarkId::new("malicious", format!("{count}:{records_per_batch}")),
+        &records_per_batch,
+        |b, &records_per_batch| {
+            b.to_async(rt).iter_batched(
+                || conversion_setup(count),
+                |input| malicious_conversion(black_box(records_per_batch), input),
+                BatchSize::PerIteration,
+            );
+        },
+    );
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let rt = Builder::new_multi_thread()
+        .worker_threads(3)
+        .thread_name("helper-worker")
+        .enable_time()
+        .build()
+        .expect("Creating runtime failed");
+
+    let mut group = c.benchmark_group("share_conversion");
+    group.sample_size(10);
+    group.sampling_mode(SamplingMode::Flat);
+
+    // The second argument to do_benchmark is the number of records whose multiplications are
+    // proven together, each record converting CONV_CHUNK match keys. Validating every record on
+    // its own is the slowest option, proofs over all records are the fastest.
+
+    #[cfg(not(coverage))]
+    {
+        do_benchmark(&rt, &mut group, 16 * CONV_CHUNK, 1);
+        do_benchmark(&rt, &mut group, 16 * CONV_CHUNK, 4);
+        do_benchmark(&rt, &mut group, 16 * CONV_CHUNK, 16);
+
+        // Batches sized from the default proof size, compared with the fixed 400 records per
+        // batch that IPA used before.
+        let records_per_batch = conversion_records_per_batch::<CONV_CHUNK>(TARGET_PROOF_SIZE);
+        do_benchmark(&rt, &mut group, 400 * CONV_CHUNK, records_per_batch);
+        do_benchmark(&rt, &mut group, 400 * CONV_CHUNK, 400);
+    }
+
+    #[cfg(coverage)]
+    {
+        do_benchmark(&rt, &mut group, 2 * CONV_CHUNK, 1);
+        do_benchmark(&rt, &mut group, 2 * CONV_CHUNK, 2);
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -101,6 +101,7 @@ impl Args {
             with_dp: self.with_dp,
             epsilon: self.epsilon,
             plaintext_match_keys: true,
+            unversioned_reports: false,
             ..Default::default()
         }
     }
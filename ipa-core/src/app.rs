@@ -1,4 +1,4 @@
-use std::sync::Weak;
+use std::{path::PathBuf, sync::Weak};
 
 use async_trait::async_trait;
 
@@ -6,12 +6,13 @@ use crate::{
     helpers::{
         query::{PrepareQuery, QueryConfig, QueryInput},
         routing::{Addr, RouteId},
-        ApiError, BodyStream, HandlerBox, HandlerRef, HelperIdentity, HelperResponse,
+        ApiError, BodyStream, DigestReport, HandlerBox, HandlerRef, HelperIdentity, HelperResponse,
         MpcTransportImpl, RequestHandler, ShardTransportImpl, Transport,
     },
-    hpke::{KeyRegistry, PrivateKeyOnly},
+    hpke::{KeyRegistry, PrivateKeyOnly, ResultSigningKey, RotatingKeyRegistry},
     protocol::QueryId,
     query::{NewQueryError, QueryProcessor, QueryStatus},
+    report::DEFAULT_HELPER_ORIGIN,
     sync::Arc,
 };
 
@@ -44,7 +45,18 @@ impl Setup {
 
     #[must_use]
     pub fn with_key_registry(key_registry: KeyRegistry<PrivateKeyOnly>) -> (Self, HandlerRef) {
-        let query_processor = QueryProcessor::new(key_registry);
+        Self::with_rotating_key_registry(Arc::new(key_registry.into()), DEFAULT_HELPER_ORIGIN)
+    }
+
+    /// Creates a new helper setup that decrypts query inputs using keys from `key_registry`.
+    /// The registry can be updated after the helper is started to rotate keys. Inputs must be
+    /// encrypted for the `helper_origin` of this helper network.
+    #[must_use]
+    pub fn with_rotating_key_registry(
+        key_registry: Arc<RotatingKeyRegistry>,
+        helper_origin: &str,
+    ) -> (Self, HandlerRef) {
+        let query_processor = QueryProcessor::with_rotating_keys(key_registry, helper_origin);
         let handler = HandlerBox::empty();
         let this = Self {
             query_processor,
@@ -55,6 +67,36 @@ impl Setup {
         (this, handler)
     }
 
+    /// Allows this helper to run queries that request encrypted results. Query results are
+    /// signed with `signing_key`, so report collectors can verify where they came from.
+    #[must_use]
+    pub fn with_result_signing_key(self, signing_key: ResultSigningKey) -> Self {
+        Self {
+            query_processor: self.query_processor.with_result_signing_key(signing_key),
+            ..self
+        }
+    }
+
+    /// Writes a transcript of the messages exchanged with other helpers for every query to `dir`.
+    /// Transcripts can be replayed to investigate queries where helpers disagree.
+    #[must_use]
+    pub fn with_transcript_dir(self, dir: PathBuf) -> Self {
+        Self {
+            query_processor: self.query_processor.with_transcript_dir(dir),
+            ..self
+        }
+    }
+
+    /// Keeps digests of the records exchanged with other helpers for the most recent query, so
+    /// they can be compared across helpers with [`HelperApp::query_digests`].
+    #[must_use]
+    pub fn with_channel_digests(self) -> Self {
+        Self {
+            query_processor: self.query_processor.with_channel_digests(),
+            ..self
+        }
+    }
+
     /// Instantiate [`HelperApp`] by connecting it to the provided transport implementation
     pub fn connect(
         self,
@@ -127,6 +169,23 @@ impl HelperApp {
             .await?
             .to_bytes())
     }
+
+    /// Retrieves digests of the records this helper exchanged with other helpers while running
+    /// a query.
+    ///
+    /// ## Errors
+    /// Propagates errors from the helper.
+    pub fn query_digests(&self, query_id: QueryId) -> Result<DigestReport, ApiError> {
+        Ok(self.inner.query_processor.digests(query_id)?)
+    }
+
+    /// Terminates a query, aborting it if it is running.
+    ///
+    /// ## Errors
+    /// Propagates errors from the helper.
+    pub fn kill_query(&self, query_id: QueryId) -> Result<(), ApiError> {
+        Ok(self.inner.query_processor.kill(query_id)?)
+    }
 }
 
 #[async_trait]
@@ -176,12 +235,24 @@ impl RequestHandler for Inner {
             }
             RouteId::QueryStatus => {
                 let query_id = ext_query_id(&req)?;
-                HelperResponse::from(qp.query_status(query_id)?)
+                HelperResponse::from(qp.query_progress(query_id)?)
             }
             RouteId::CompleteQuery => {
                 let query_id = ext_query_id(&req)?;
                 HelperResponse::from(qp.complete(query_id).await?)
             }
+            RouteId::KillQuery => {
+                let query_id = ext_query_id(&req)?;
+                HelperResponse::from(qp.kill(query_id)?)
+            }
+            RouteId::QueryStallReport => {
+                let query_id = ext_query_id(&req)?;
+                HelperResponse::from(qp.stall_report(query_id)?)
+            }
+            RouteId::QueryDigests => {
+                let query_id = ext_query_id(&req)?;
+                HelperResponse::from(qp.digests(query_id)?)
+            }
         })
     }
 }
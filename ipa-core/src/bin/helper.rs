@@ -5,21 +5,35 @@ use std::{
     os::fd::{FromRawFd, RawFd},
     path::{Path, PathBuf},
     process,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use clap::{self, Parser, Subcommand};
-use hyper::http::uri::Scheme;
+use hyper::{http::uri::Scheme, Uri};
 use ipa_core::{
     cli::{
         client_config_setup, keygen, test_setup, ConfGenArgs, KeygenArgs, TestSetupArgs, Verbosity,
     },
-    config::{hpke_registry, HpkeServerConfig, NetworkConfig, ServerConfig, TlsConfig},
+    config::{
+        hpke_registry, load_key_dir, HpkeServerConfig, NetworkConfig, ServerConfig, TlsConfig,
+        KEY_MANIFEST_FILE,
+    },
     error::BoxError,
     helpers::HelperIdentity,
-    net::{ClientIdentity, HttpShardTransport, HttpTransport, MpcHelperClient},
+    hpke::{ResultSigningKey, RotatingKeyRegistry},
+    net::{ClientIdentity, HttpShardTransport, HttpTransport, MpcHelperClient, QueryTracing},
+    telemetry::{
+        prometheus::{PrometheusConfig, PrometheusRecorder},
+        trace::{SpanExporter, SpanSink},
+    },
     AppSetup,
 };
-use tracing::{error, info};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    task::JoinHandle,
+};
+use tracing::{error, info, warn};
 
 #[cfg(not(target_env = "msvc"))]
 #[global_allocator]
@@ -89,6 +103,54 @@ struct ServerArgs {
     /// Private key for decrypting match keys
     #[arg(long, requires = "mk_public_key")]
     mk_private_key: Option<PathBuf>,
+
+    /// Directory with private keys for decrypting match keys, described by a `manifest.toml` file.
+    ///
+    /// Keys are reloaded when the manifest changes or when helper receives `SIGHUP`, which allows
+    /// rotating keys without restarting the helper.
+    #[arg(long, conflicts_with_all = ["mk_public_key", "mk_private_key"])]
+    mk_key_dir: Option<PathBuf>,
+
+    /// How often to check the key directory for changes and retire expired keys, in seconds.
+    #[arg(long, default_value = "60")]
+    mk_key_check_interval: u64,
+
+    /// File with the hex-encoded key used to sign query results. Required to run queries
+    /// that request encrypted results.
+    #[arg(long)]
+    result_signing_key: Option<PathBuf>,
+
+    /// Directory to write a transcript of the messages exchanged with other helpers for every
+    /// query. Transcripts contain secret PRSS seeds of this helper.
+    #[arg(long)]
+    transcript_dir: Option<PathBuf>,
+
+    /// Keep digests of the records exchanged with other helpers for the most recent query, so
+    /// they can be compared across helpers to find where their views of the query diverged
+    #[arg(long)]
+    channel_digests: bool,
+
+    /// Export metrics in Prometheus format on the `/metrics` endpoint
+    #[arg(long)]
+    metrics: bool,
+
+    /// Label exported metrics with the protocol step. IPA has thousands of steps, so this
+    /// significantly increases the number of exported time series.
+    #[arg(long, requires = "metrics")]
+    metrics_per_step: bool,
+
+    /// Label exported query metrics with the query id
+    #[arg(long, requires = "metrics")]
+    metrics_per_query: bool,
+
+    /// Append spans of distributed query traces to this file, in OTLP JSON format
+    #[arg(long)]
+    trace_file: Option<PathBuf>,
+
+    /// Send spans of distributed query traces to the OpenTelemetry collector at this URL
+    /// using OTLP/HTTP.
+    #[arg(long, conflicts_with = "trace_file")]
+    otlp_endpoint: Option<Uri>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -109,6 +171,20 @@ fn read_file(path: &Path) -> Result<BufReader<fs::File>, BoxError> {
 async fn server(args: ServerArgs) -> Result<(), BoxError> {
     let my_identity = HelperIdentity::try_from(args.identity.expect("enforced by clap")).unwrap();
 
+    let metrics = if args.metrics {
+        let config = PrometheusConfig {
+            per_step: args.metrics_per_step,
+            per_query: args.metrics_per_query,
+        };
+        Some(
+            PrometheusRecorder::new(config)
+                .install()
+                .map_err(|e| format!("failed to install metrics recorder: {e}"))?,
+        )
+    } else {
+        None
+    };
+
     let (identity, server_tls) = match (args.tls_cert, args.tls_key) {
         (Some(cert_file), Some(key_file)) => {
             let mut key = read_file(&key_file)?;
@@ -125,18 +201,23 @@ async fn server(args: ServerArgs) -> Result<(), BoxError> {
         _ => panic!("should have been rejected by clap"),
     };
 
-    let mk_encryption = args.mk_private_key.map(|sk_path| HpkeServerConfig::File {
-        private_key_file: sk_path,
-    });
-
-    let key_registry = hpke_registry(mk_encryption.as_ref()).await?;
-    let (setup, handler) = AppSetup::with_key_registry(key_registry);
+    let mk_encryption = match (args.mk_private_key, args.mk_key_dir) {
+        (Some(sk_path), None) => Some(HpkeServerConfig::File {
+            private_key_file: sk_path,
+        }),
+        (None, Some(key_dir)) => Some(HpkeServerConfig::Directory { key_dir }),
+        (None, None) => None,
+        (Some(_), Some(_)) => panic!("should have been rejected by clap"),
+    };
 
-    let server_config = ServerConfig {
-        port: args.port,
-        disable_https: args.disable_https,
-        tls: server_tls,
-        hpke_config: mk_encryption,
+    let key_registry = Arc::new(hpke_registry(mk_encryption.as_ref()).await?);
+    let _key_reloader = match mk_encryption {
+        Some(HpkeServerConfig::Directory { ref key_dir }) => Some(spawn_key_reloader(
+            Arc::clone(&key_registry),
+            key_dir.clone(),
+            Duration::from_secs(args.mk_key_check_interval),
+        )),
+        _ => None,
     };
 
     let scheme = if args.disable_https {
@@ -147,6 +228,30 @@ async fn server(args: ServerArgs) -> Result<(), BoxError> {
     let network_config_path = args.network.as_deref().unwrap();
     let network_config = NetworkConfig::from_toml_str(&fs::read_to_string(network_config_path)?)?
         .override_scheme(&scheme);
+
+    let (mut setup, handler) =
+        AppSetup::with_rotating_key_registry(key_registry, &network_config.helper_origin);
+    if let Some(path) = args.result_signing_key {
+        let signing_key = fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read result signing key {}: {e}", path.display()))?
+            .parse::<ResultSigningKey>()?;
+        setup = setup.with_result_signing_key(signing_key);
+    }
+    if let Some(dir) = args.transcript_dir {
+        fs::create_dir_all(&dir)?;
+        setup = setup.with_transcript_dir(dir);
+    }
+    if args.channel_digests {
+        setup = setup.with_channel_digests();
+    }
+
+    let server_config = ServerConfig {
+        port: args.port,
+        disable_https: args.disable_https,
+        tls: server_tls,
+        hpke_config: mk_encryption,
+    };
+
     let clients = MpcHelperClient::from_conf(&network_config, &identity);
 
     let (transport, server) = HttpTransport::new(
@@ -156,6 +261,10 @@ async fn server(args: ServerArgs) -> Result<(), BoxError> {
         clients,
         Some(handler),
     );
+    let server = match metrics {
+        Some(handle) => server.with_metrics(handle),
+        None => server,
+    };
 
     let _app = setup.connect(transport.clone(), HttpShardTransport);
 
@@ -175,23 +284,95 @@ async fn server(args: ServerArgs) -> Result<(), BoxError> {
         })
         .transpose()?;
 
-    let (_addr, server_handle) = server
-        .start_on(
-            listener,
-            // TODO, trace based on the content of the query.
-            None as Option<()>,
-        )
-        .await;
+    let (_addr, server_handle) = server.start_on(listener, QueryTracing).await;
 
     server_handle.await?;
 
     Ok(())
 }
 
+async fn manifest_modified(key_dir: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(key_dir.join(KEY_MANIFEST_FILE))
+        .await
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+/// Keeps match key decryption keys in sync with the key directory. Keys are reloaded when
+/// the manifest modification time changes or when `SIGHUP` is received. Expired keys are retired
+/// on every check. If reload fails, the helper keeps using the keys it already has.
+fn spawn_key_reloader(
+    registry: Arc<RotatingKeyRegistry>,
+    key_dir: PathBuf,
+    check_interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut hangup = signal(SignalKind::hangup())
+            .map_err(|e| warn!("failed to install SIGHUP handler: {e}"))
+            .ok();
+        let mut interval = tokio::time::interval(check_interval);
+        // first tick completes immediately and keys have just been loaded
+        interval.tick().await;
+        let mut last_modified = manifest_modified(&key_dir).await;
+
+        loop {
+            let forced = tokio::select! {
+                _ = interval.tick() => false,
+                Some(()) = async {
+                    match hangup.as_mut() {
+                        Some(hangup) => hangup.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => true,
+            };
+
+            let modified = manifest_modified(&key_dir).await;
+            if forced || modified != last_modified {
+                match load_key_dir(&key_dir)
+                    .await
+                    .and_then(|keys| registry.replace(keys).map_err(Into::into))
+                {
+                    Ok(()) => {
+                        last_modified = modified;
+                        info!(
+                            "reloaded match key decryption keys from {}: {:?}",
+                            key_dir.display(),
+                            registry.key_ids()
+                        );
+                    }
+                    Err(e) => error!(
+                        "failed to reload keys from {}, keeping the existing ones: {e}",
+                        key_dir.display()
+                    ),
+                }
+            }
+
+            for key_id in registry.retire_expired(SystemTime::now()) {
+                info!("match key decryption key {key_id} expired and has been retired");
+            }
+        }
+    })
+}
+
 #[tokio::main]
 pub async fn main() {
     let args = Args::parse();
-    let _handle = args.logging.setup_logging();
+    let span_sink = match (&args.server.trace_file, &args.server.otlp_endpoint) {
+        (Some(path), _) => Some(SpanSink::File(path.clone())),
+        (None, Some(endpoint)) => Some(SpanSink::Otlp(endpoint.clone())),
+        (None, None) => None,
+    };
+    let span_exporter = span_sink.map(|sink| {
+        let service_name = match args.server.identity {
+            Some(id) => format!("ipa-helper-{id}"),
+            None => String::from("ipa-helper"),
+        };
+        SpanExporter::spawn(service_name, sink).0
+    });
+    // Prometheus recorder is installed by the server, in place of the metrics collector.
+    let _handle = args
+        .logging
+        .setup_logging_with(!args.server.metrics, span_exporter);
 
     let res = match args.command {
         None => server(args.server).await,
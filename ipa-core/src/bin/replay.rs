@@ -0,0 +1,86 @@
+use std::{error::Error, path::PathBuf, time::SystemTime};
+
+use clap::Parser;
+use ipa_core::{
+    cli::Verbosity,
+    config::{hpke_registry, HpkeServerConfig},
+    helpers::{BodyStream, Transcript},
+    report::DEFAULT_HELPER_ORIGIN,
+    test_fixture::{replay_query, ReplayError},
+};
+use tracing::{error, info};
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "replay",
+    about = "Re-runs one helper's share of a query against its recorded transcript"
+)]
+struct Args {
+    #[clap(flatten)]
+    logging: Verbosity,
+
+    /// Transcript recorded by the helper, usually `<query id>-<role>.jsonl`
+    #[arg(long)]
+    transcript: PathBuf,
+
+    /// Input this helper received for the query
+    #[arg(long)]
+    input: PathBuf,
+
+    /// Private key for decrypting match keys
+    #[arg(long)]
+    mk_private_key: Option<PathBuf>,
+
+    /// Directory with private keys for decrypting match keys, described by a `manifest.toml` file
+    #[arg(long, conflicts_with = "mk_private_key")]
+    mk_key_dir: Option<PathBuf>,
+
+    /// Origin the helper used to authenticate reports
+    #[arg(long, default_value = DEFAULT_HELPER_ORIGIN)]
+    helper_origin: String,
+
+    /// Write the replayed query result to this file
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let _handle = args.logging.setup_logging();
+
+    let transcript = Transcript::load(&args.transcript)?;
+    let input = tokio::fs::read(&args.input).await?;
+
+    let mk_encryption = match (args.mk_private_key, args.mk_key_dir) {
+        (Some(private_key_file), None) => Some(HpkeServerConfig::File { private_key_file }),
+        (None, Some(key_dir)) => Some(HpkeServerConfig::Directory { key_dir }),
+        (None, None) => None,
+        (Some(_), Some(_)) => panic!("should have been rejected by clap"),
+    };
+    let key_registry = hpke_registry(mk_encryption.as_ref())
+        .await?
+        .snapshot(SystemTime::now());
+
+    match replay_query(
+        transcript,
+        key_registry,
+        &args.helper_origin,
+        BodyStream::from(input),
+    )
+    .await
+    {
+        Ok(result) => {
+            info!("replay matches {}", args.transcript.display());
+            if let Some(output) = args.output {
+                tokio::fs::write(output, result).await?;
+            }
+            Ok(())
+        }
+        Err(e @ ReplayError::Diverged(_)) => {
+            error!("{e}");
+            std::process::exit(1)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
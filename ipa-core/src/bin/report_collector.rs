@@ -4,26 +4,40 @@ use std::{
     fmt::Debug,
     fs::{File, OpenOptions},
     io,
-    io::{stdout, Write},
+    io::{stdout, BufReader, Write},
+    num::{NonZeroU32, NonZeroUsize},
     ops::Deref,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
+use bytes::Bytes;
 use clap::{Parser, Subcommand};
 use comfy_table::{Cell, Table};
+use futures::{stream, StreamExt, TryStreamExt};
 use hyper::http::uri::Scheme;
 use ipa_core::{
     cli::{
         noise::{apply, ApplyDpArgs},
-        playbook::{make_clients, playbook_oprf_ipa, validate, validate_dp, InputSource},
-        CsvSerializer, IpaQueryResult, Verbosity,
+        playbook::{
+            encode_oprf_inputs, encrypt_oprf_reports, fetch_results, ipa_noise_params,
+            make_clients, make_clients_for_network, merge_histograms, merged_noise_mean_std,
+            partition_by_user, playbook_oprf_ipa, query_digests, query_progress, upload_inputs,
+            validate, validate_dp, wait_for_completion, DetachedQuery, InputSource, ResultKeys,
+        },
+        CliPaths, CsvSerializer, IpaQueryResult, Verbosity,
     },
     config::NetworkConfig,
     ff::{boolean_array::BA32, FieldType},
-    helpers::query::{IpaQueryConfig, QueryConfig, QuerySize, QueryType},
+    helpers::{
+        first_mismatch,
+        query::{IpaQueryConfig, QueryConfig, QuerySize, QueryType},
+        BodyStream, HelperIdentity, StreamCompression,
+    },
     hpke::{KeyRegistry, PublicKeyOnly},
-    net::MpcHelperClient,
-    report::{KeyIdentifier, DEFAULT_KEY_ID},
+    net::{ClientIdentity, MpcHelperClient, QueryInputs},
+    query::QueryProgress,
+    report::KeyIdentifier,
     test_fixture::{
         ipa::{ipa_in_the_clear, CappingOrder, IpaQueryStyle, IpaSecurityModel, TestRawDataRecord},
         EventGenerator, EventGeneratorConfig,
@@ -51,11 +65,21 @@ struct Args {
     #[arg(short, long, default_value_t = 0)]
     wait: usize,
 
+    /// Compression of record streams that helpers send to each other while running queries
+    /// created by this command
+    #[arg(long, value_enum, default_value_t = StreamCompression::None)]
+    compression: StreamCompression,
+
+    /// Number of bit multiplications covered by each zero-knowledge proof that helpers generate
+    /// while running queries created by this command. Helpers use their default if not set
+    #[arg(long)]
+    proof_size: Option<NonZeroUsize>,
+
     #[clap(flatten)]
     input: CommandInput,
 
     /// The destination file for output.
-    #[arg(long, value_name = "FILE")]
+    #[arg(long, value_name = "FILE", global = true)]
     output_file: Option<PathBuf>,
 
     #[command(subcommand)]
@@ -66,6 +90,7 @@ struct Args {
 pub struct CommandInput {
     #[arg(
         long,
+        global = true,
         help = "Read the input from the provided file, instead of standard input"
     )]
     input_file: Option<PathBuf>,
@@ -99,7 +124,111 @@ enum ReportCollectorCommand {
     /// Apply differential privacy noise to IPA inputs
     ApplyDpNoise(ApplyDpArgs),
     /// Execute OPRF IPA in a semi-honest majority setting
-    OprfIpa(IpaQueryConfig),
+    OprfIpa(OprfIpaArgs),
+    /// Create an OPRF IPA query and record it in a state file, without uploading inputs
+    Create(CreateArgs),
+    /// Upload inputs for the query recorded in the state file
+    Upload(StateFileArgs),
+    /// Print the status of the query recorded in the state file on each helper
+    Status(StateFileArgs),
+    /// Wait for the query recorded in the state file to complete
+    Wait(WaitArgs),
+    /// Fetch results of the query recorded in the state file
+    Results(StateFileArgs),
+    /// Secret-share and encrypt reports for each helper, without sending them anywhere
+    Encrypt(EncryptArgs),
+    /// Upload reports produced by `encrypt` for the query recorded in the state file
+    UploadEncrypted(UploadEncryptedArgs),
+    /// Compare digests of the records helpers exchanged while running the query recorded in the
+    /// state file and print the first channel where they disagree
+    Digests(DigestsArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct OprfIpaArgs {
+    #[clap(flatten)]
+    config: IpaQueryConfig,
+
+    /// Split inputs with more records than this into several queries, each covering a
+    /// disjoint set of users
+    #[arg(long)]
+    max_query_size: Option<NonZeroUsize>,
+
+    /// Number of queries to run at the same time when the input is split. Helpers run one
+    /// query at a time for now, so values other than 1 are rejected.
+    #[arg(long, default_value = "1")]
+    max_concurrent_queries: NonZeroUsize,
+}
+
+#[derive(Debug, clap::Args)]
+struct StateFileArgs {
+    /// File that keeps track of the query between report collector invocations
+    #[arg(long, value_name = "FILE")]
+    state_file: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct CreateArgs {
+    #[clap(flatten)]
+    state: StateFileArgs,
+
+    /// Number of records that will be uploaded for this query
+    #[arg(long)]
+    query_size: u32,
+
+    #[clap(flatten)]
+    config: IpaQueryConfig,
+}
+
+#[derive(Debug, clap::Args)]
+struct EncryptArgs {
+    /// Directory to write encrypted reports to, one file per helper
+    #[arg(long, value_name = "DIR")]
+    output_dir: PathBuf,
+
+    /// Encrypt reports in the format that predates report versions, for queries created with
+    /// `--unversioned-reports`
+    #[arg(long)]
+    unversioned_reports: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct UploadEncryptedArgs {
+    #[clap(flatten)]
+    state: StateFileArgs,
+
+    /// Directory with reports produced by `encrypt`
+    #[arg(long, value_name = "DIR")]
+    input_dir: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+struct WaitArgs {
+    #[clap(flatten)]
+    state: StateFileArgs,
+
+    /// Give up if the query does not complete within this many seconds
+    #[arg(long)]
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, clap::Args)]
+struct DigestsArgs {
+    #[clap(flatten)]
+    state: StateFileArgs,
+
+    /// Certificate of one of the helpers. Helpers only share digests with each other, so the
+    /// request must authenticate as one of them
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key for the certificate of the helper
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Helper to claim to be when HTTPS is disabled
+    #[arg(long, default_value = "1", conflicts_with = "tls_cert")]
+    helper: usize,
 }
 
 #[derive(Debug, clap::Args)]
@@ -122,7 +251,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
         Scheme::HTTPS
     };
 
-    let (clients, network) = make_clients(args.network.as_deref(), scheme, args.wait).await;
     match args.action {
         ReportCollectorCommand::GenIpaInputs {
             count,
@@ -130,17 +258,30 @@ async fn main() -> Result<(), Box<dyn Error>> {
             gen_args,
         } => gen_inputs(count, seed, args.output_file, gen_args)?,
         ReportCollectorCommand::ApplyDpNoise(ref dp_args) => apply_dp_noise(&args, dp_args)?,
-        ReportCollectorCommand::OprfIpa(config) => {
+        ReportCollectorCommand::OprfIpa(ref ipa_args) => {
+            let (clients, network) = make_clients(args.network.as_deref(), scheme, args.wait).await;
             ipa(
                 &args,
                 &network,
                 IpaSecurityModel::SemiHonest,
-                config,
+                ipa_args,
                 &clients,
                 IpaQueryStyle::Oprf,
             )
             .await?
         }
+        ReportCollectorCommand::Create(ref create_args) => {
+            create(&args, scheme, create_args).await?;
+        }
+        ReportCollectorCommand::Upload(ref state) => upload(&args, state).await?,
+        ReportCollectorCommand::Status(ref state) => status(&args, state).await?,
+        ReportCollectorCommand::Wait(ref wait_args) => wait(&args, wait_args).await?,
+        ReportCollectorCommand::Results(ref state) => results(&args, state).await?,
+        ReportCollectorCommand::Encrypt(ref encrypt_args) => encrypt(&args, encrypt_args)?,
+        ReportCollectorCommand::UploadEncrypted(ref upload_args) => {
+            upload_encrypted(&args, upload_args).await?;
+        }
+        ReportCollectorCommand::Digests(ref digests_args) => digests(digests_args).await?,
     };
 
     Ok(())
@@ -173,33 +314,45 @@ fn gen_inputs(
 }
 
 #[derive(Default)]
-struct KeyRegistries(Vec<KeyRegistry<PublicKeyOnly>>);
+struct KeyRegistries(Option<[KeyRegistry<PublicKeyOnly>; 3]>);
 
 impl KeyRegistries {
+    /// Builds the registries of helper public keys from `network`. Returns `None` if one of the
+    /// helpers does not have a public key.
     fn init_from(
         &mut self,
         network: &NetworkConfig,
-    ) -> Option<(KeyIdentifier, [&KeyRegistry<PublicKeyOnly>; 3])> {
+    ) -> Result<Option<(KeyIdentifier, [&KeyRegistry<PublicKeyOnly>; 3])>, Box<dyn Error>> {
         // Get the configs, if all three peers have one
-        let configs = network.peers().iter().try_fold(Vec::new(), |acc, peer| {
-            if let (mut vec, Some(hpke_config)) = (acc, peer.hpke_config.as_ref()) {
-                vec.push(hpke_config);
-                Some(vec)
-            } else {
-                None
-            }
-        })?;
+        let [Some(c1), Some(c2), Some(c3)] = network
+            .peers()
+            .each_ref()
+            .map(|peer| peer.hpke_config.as_ref())
+        else {
+            return Ok(None);
+        };
+
+        // Helpers rotate keys in lockstep, so reports for all of them are encrypted using the
+        // same key identifier.
+        let key_id = c1.key_id;
+        if let Some(hpke) = [c2, c3].into_iter().find(|hpke| hpke.key_id != key_id) {
+            return Err(format!(
+                "all helpers must use the same match key identifier, got {key_id} and {}",
+                hpke.key_id
+            )
+            .into());
+        }
 
         // Create key registries
-        self.0 = configs
-            .into_iter()
-            .map(|hpke| KeyRegistry::from_keys([PublicKeyOnly(hpke.public_key.clone())]))
-            .collect::<Vec<KeyRegistry<PublicKeyOnly>>>();
-
-        Some((
-            DEFAULT_KEY_ID,
-            self.0.iter().collect::<Vec<_>>().try_into().ok().unwrap(),
-        ))
+        let [r1, r2, r3] = [c1, c2, c3].map(|hpke| {
+            KeyRegistry::from_identified_keys([(
+                hpke.key_id,
+                PublicKeyOnly(hpke.public_key.clone()),
+            )])
+        });
+        let registries = self.0.insert([r1?, r2?, r3?]);
+
+        Ok(Some((key_id, registries.each_ref())))
     }
 }
 
@@ -207,10 +360,20 @@ async fn ipa(
     args: &Args,
     network: &NetworkConfig,
     security_model: IpaSecurityModel,
-    ipa_query_config: IpaQueryConfig,
+    ipa_args: &OprfIpaArgs,
     helper_clients: &[MpcHelperClient; 3],
     query_style: IpaQueryStyle,
 ) -> Result<(), Box<dyn Error>> {
+    // Helpers keep the state of a single query, there is only one `QueryId`.
+    if ipa_args.max_concurrent_queries.get() > 1 {
+        return Err(format!(
+            "helpers run one query at a time, --max-concurrent-queries {} is not supported",
+            ipa_args.max_concurrent_queries
+        )
+        .into());
+    }
+
+    let ipa_query_config = ipa_args.config;
     let input = InputSource::from(&args.input);
     let query_type: QueryType;
     match (security_model, &query_style) {
@@ -223,16 +386,6 @@ async fn ipa(
     };
 
     let input_rows = input.iter::<TestRawDataRecord>().collect::<Vec<_>>();
-    let query_config = QueryConfig {
-        size: QuerySize::try_from(input_rows.len()).unwrap(),
-        field_type: FieldType::Fp32BitPrime,
-        query_type,
-    };
-    let query_id = helper_clients[0]
-        .create_query(query_config)
-        .await
-        .expect("Unable to create query!");
-
     let expected = {
         let mut r = ipa_in_the_clear(
             &input_rows,
@@ -253,22 +406,55 @@ async fn ipa(
         r
     };
 
+    // Users are never split across partitions, so every partition can be attributed on its
+    // own and the histograms add up to the same result a single query would produce.
+    let input_size = QuerySize::try_from(input_rows.len())?;
+    let partitions = match ipa_args.max_query_size {
+        Some(max_query_size) => partition_by_user(input_rows, max_query_size)?,
+        None => vec![input_rows],
+    };
+    let partition_count = NonZeroU32::new(u32::try_from(partitions.len())?)
+        .ok_or("input must have at least one partition")?;
+    if partition_count.get() > 1 {
+        tracing::info!(
+            "Splitting {input_size} records into {partition_count} queries, running up to {} at a time",
+            ipa_args.max_concurrent_queries
+        );
+    }
+
     let mut key_registries = KeyRegistries::default();
-    let actual = match query_style {
-        IpaQueryStyle::Oprf => {
-            // the value for histogram values (BA32) must be kept in sync with the server-side
-            // implementation, otherwise a runtime reconstruct error will be generated.
-            // see ipa-core/src/query/executor.rs
-            playbook_oprf_ipa::<BA32, _>(
-                input_rows,
-                helper_clients,
-                query_id,
-                ipa_query_config,
-                key_registries.init_from(network),
-            )
-            .await
-        }
+    let encryption = key_registries.init_from(network)?;
+    let mpc_time = Instant::now();
+    let results = stream::iter(partitions)
+        .map(|rows| ipa_partition(args, network, query_type, rows, helper_clients, encryption))
+        .buffer_unordered(ipa_args.max_concurrent_queries.get())
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let actual = IpaQueryResult {
+        input_size,
+        config: ipa_query_config,
+        latency: mpc_time.elapsed(),
+        breakdowns: merge_histograms(
+            &results
+                .into_iter()
+                .map(|result| result.breakdowns)
+                .collect::<Vec<_>>(),
+        ),
+        partitions: partition_count,
     };
+    if ipa_query_config.with_dp != 0 && partition_count.get() > 1 {
+        let (mean, std) = merged_noise_mean_std(
+            &ipa_noise_params(
+                ipa_query_config.epsilon,
+                ipa_query_config.per_user_credit_cap,
+            ),
+            partition_count,
+        );
+        tracing::info!(
+            "Merged histogram carries DP noise from {partition_count} queries: mean {mean:.3}, std {std:.3}"
+        );
+    }
 
     if let Some(ref path) = args.output_file {
         // it will be sad to lose the results if file already exists.
@@ -317,6 +503,7 @@ async fn ipa(
                 actual.breakdowns,
                 ipa_query_config.epsilon,
                 ipa_query_config.per_user_credit_cap,
+                actual.partitions,
             );
         }
     }
@@ -324,6 +511,325 @@ async fn ipa(
     Ok(())
 }
 
+/// Runs IPA over one partition of the input as a separate query.
+async fn ipa_partition(
+    args: &Args,
+    network: &NetworkConfig,
+    query_type: QueryType,
+    rows: Vec<TestRawDataRecord>,
+    helper_clients: &[MpcHelperClient; 3],
+    encryption: Option<(KeyIdentifier, [&KeyRegistry<PublicKeyOnly>; 3])>,
+) -> Result<IpaQueryResult, Box<dyn Error>> {
+    // Ask helpers to encrypt and sign results, if all of them publish their verifying keys.
+    let result_keys = ResultKeys::generate(network, &mut thread_rng());
+    let query_config = QueryConfig {
+        size: QuerySize::try_from(rows.len())?,
+        field_type: FieldType::Fp32BitPrime,
+        query_type,
+        result_key: result_keys.as_ref().map(ResultKeys::encryption_key),
+        compression: args.compression,
+        proof_size: args.proof_size,
+    };
+    let query_id = helper_clients[0].create_query(query_config).await?;
+
+    // the value for histogram values (BA32) must be kept in sync with the server-side
+    // implementation, otherwise a runtime reconstruct error will be generated.
+    // see ipa-core/src/query/executor.rs
+    Ok(playbook_oprf_ipa::<BA32, _>(
+        rows,
+        helper_clients,
+        query_id,
+        query_config,
+        encryption,
+        &network.helper_origin,
+        result_keys.as_ref(),
+    )
+    .await?)
+}
+
+async fn create(
+    args: &Args,
+    scheme: Scheme,
+    create_args: &CreateArgs,
+) -> Result<(), Box<dyn Error>> {
+    let state_file = &create_args.state.state_file;
+    if state_file.exists() {
+        // Don't lose track of a query that may still be running.
+        return Err(format!("query state file {} already exists", state_file.display()).into());
+    }
+
+    let network_toml = args
+        .network
+        .as_deref()
+        .map(std::fs::read_to_string)
+        .transpose()?;
+    let (clients, network) = make_clients(args.network.as_deref(), scheme.clone(), args.wait).await;
+    let query_size = QuerySize::try_from(create_args.query_size)?;
+
+    let result_keys = ResultKeys::generate(&network, &mut thread_rng());
+    let query_config = QueryConfig {
+        size: query_size,
+        field_type: FieldType::Fp32BitPrime,
+        query_type: QueryType::OprfIpa(create_args.config),
+        result_key: result_keys.as_ref().map(ResultKeys::encryption_key),
+        compression: args.compression,
+        proof_size: args.proof_size,
+    };
+    let query_id = clients[0].create_query(query_config).await?;
+
+    DetachedQuery::new(
+        query_id,
+        query_config,
+        network_toml,
+        &scheme,
+        result_keys.as_ref(),
+    )
+    .create(state_file)?;
+    println!("{query_id}");
+
+    Ok(())
+}
+
+/// Restores the query recorded in the state file, together with clients to talk to helpers
+/// that run it.
+async fn load_query(
+    args: &Args,
+    state: &StateFileArgs,
+) -> Result<(DetachedQuery, [MpcHelperClient; 3], NetworkConfig), Box<dyn Error>> {
+    let query = DetachedQuery::load(&state.state_file)?;
+    let (clients, network) =
+        make_clients_for_network(query.network_config()?, query.scheme(), args.wait).await;
+
+    Ok((query, clients, network))
+}
+
+async fn upload(args: &Args, state: &StateFileArgs) -> Result<(), Box<dyn Error>> {
+    let (mut query, clients, network) = load_query(args, state).await?;
+    if query.inputs_uploaded {
+        return Err(format!("inputs for query {} are already uploaded", query.query_id).into());
+    }
+
+    let input_rows = InputSource::from(&args.input)
+        .iter::<TestRawDataRecord>()
+        .collect::<Vec<_>>();
+    if input_rows.len() != usize::from(query.config.size) {
+        return Err(format!(
+            "query {} expects {} records, but {} were provided",
+            query.query_id,
+            query.config.size,
+            input_rows.len()
+        )
+        .into());
+    }
+
+    let mut key_registries = KeyRegistries::default();
+    let inputs = encode_oprf_inputs(
+        &input_rows,
+        &query.ipa_config()?,
+        key_registries.init_from(&network)?,
+        &network.helper_origin,
+    );
+    upload_inputs(inputs, &clients, query.query_id).await?;
+
+    query.inputs_uploaded = true;
+    query.save(&state.state_file)?;
+
+    Ok(())
+}
+
+fn encrypt(args: &Args, encrypt_args: &EncryptArgs) -> Result<(), Box<dyn Error>> {
+    let network = match args.network {
+        Some(ref path) => NetworkConfig::from_toml_str(&std::fs::read_to_string(path)?)?,
+        None => return Err("network configuration with helper public keys is required".into()),
+    };
+    let mut key_registries = KeyRegistries::default();
+    let (key_id, key_registries) = key_registries
+        .init_from(&network)?
+        .ok_or("one or more helpers is missing a public key")?;
+
+    let input_rows = InputSource::from(&args.input)
+        .iter::<TestRawDataRecord>()
+        .collect::<Vec<_>>();
+    let reports = encrypt_oprf_reports(
+        &input_rows,
+        key_id,
+        key_registries,
+        &network.helper_origin,
+        encrypt_args.unversioned_reports,
+    );
+
+    std::fs::create_dir_all(&encrypt_args.output_dir)?;
+    for (id, reports) in HelperIdentity::make_three().into_iter().zip(reports) {
+        let path = encrypt_args.output_dir.helper_encrypted_reports(id);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(&reports))
+            .map_err(|e| {
+                format!(
+                    "Failed to write encrypted reports to {}: {e}",
+                    path.display()
+                )
+            })?;
+    }
+    tracing::info!(
+        "Encrypted {} reports into {}",
+        input_rows.len(),
+        encrypt_args.output_dir.display()
+    );
+
+    Ok(())
+}
+
+async fn upload_encrypted(
+    args: &Args,
+    upload_args: &UploadEncryptedArgs,
+) -> Result<(), Box<dyn Error>> {
+    let (mut query, clients, _) = load_query(args, &upload_args.state).await?;
+    if query.inputs_uploaded {
+        return Err(format!("inputs for query {} are already uploaded", query.query_id).into());
+    }
+    if query.ipa_config()?.plaintext_match_keys {
+        return Err(format!(
+            "query {} expects plaintext match keys, encrypted reports can't be used",
+            query.query_id
+        )
+        .into());
+    }
+
+    let mut reports = Vec::with_capacity(3);
+    for id in HelperIdentity::make_three() {
+        let path = upload_args.input_dir.helper_encrypted_reports(id);
+        let bytes = std::fs::read(&path).map_err(|e| {
+            format!(
+                "Failed to read encrypted reports from {}: {e}",
+                path.display()
+            )
+        })?;
+        reports.push(Bytes::from(bytes));
+    }
+    let reports: [Bytes; 3] = reports
+        .try_into()
+        .map_err(|_| "expected exactly one input per helper")?;
+    let size = QueryInputs::encrypted_reports(reports.clone())?.size();
+    if size != query.config.size {
+        return Err(format!(
+            "query {} expects {} reports, but {} has {size}",
+            query.query_id,
+            query.config.size,
+            upload_args.input_dir.display()
+        )
+        .into());
+    }
+    let inputs = reports.map(BodyStream::new);
+
+    upload_inputs(inputs, &clients, query.query_id).await?;
+
+    query.inputs_uploaded = true;
+    query.save(&upload_args.state.state_file)?;
+
+    Ok(())
+}
+
+async fn digests(args: &DigestsArgs) -> Result<(), Box<dyn Error>> {
+    let query = DetachedQuery::load(&args.state.state_file)?;
+    let network = query.network_config()?.override_scheme(&query.scheme());
+    let identity = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => ClientIdentity::from_pkcs8(
+            &mut BufReader::new(File::open(cert)?),
+            &mut BufReader::new(File::open(key)?),
+        )?,
+        (None, None) => ClientIdentity::Helper(HelperIdentity::try_from(args.helper)?),
+        _ => panic!("should have been rejected by clap"),
+    };
+    let clients = MpcHelperClient::from_conf(&network, &identity);
+
+    let reports = query_digests(&clients, query.query_id).await?;
+    match first_mismatch(&reports) {
+        Some(mismatch) => println!("first mismatch: {mismatch}"),
+        None => println!("records sent and received by helpers match on all channels"),
+    }
+
+    Ok(())
+}
+
+async fn status(args: &Args, state: &StateFileArgs) -> Result<(), Box<dyn Error>> {
+    let (query, clients, _) = load_query(args, state).await?;
+    let statuses = query_progress(&clients, query.query_id).await?;
+
+    let mut table = Table::new();
+    table.set_header(vec![
+        "Helper", "Status", "Stage", "Records", "Elapsed", "Done",
+    ]);
+    for (i, (status, progress)) in statuses.iter().enumerate() {
+        let mut row = vec![format!("{}", i + 1), format!("{status:?}")];
+        if let Some(stage) = progress.as_ref().and_then(QueryProgress::current) {
+            let done = progress
+                .iter()
+                .flat_map(|p| &p.stages)
+                .filter(|s| s.finished)
+                .map(|s| format!("{:?} ({:.1?})", s.stage, s.elapsed))
+                .collect::<Vec<_>>();
+            row.extend([
+                format!("{:?}", stage.stage),
+                stage.records.to_string(),
+                format!("{:.1?}", stage.elapsed),
+                done.join(", "),
+            ]);
+        }
+        table.add_row(row);
+    }
+    println!("{table}");
+
+    Ok(())
+}
+
+async fn wait(args: &Args, wait_args: &WaitArgs) -> Result<(), Box<dyn Error>> {
+    let (query, clients, _) = load_query(args, &wait_args.state).await?;
+    wait_for_completion(
+        &clients,
+        query.query_id,
+        wait_args.timeout.map(Duration::from_secs),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn results(args: &Args, state: &StateFileArgs) -> Result<(), Box<dyn Error>> {
+    let (query, clients, network) = load_query(args, state).await?;
+    let result_keys = query.result_keys(&network)?;
+
+    // the value for histogram values (BA32) must be kept in sync with the server-side
+    // implementation, otherwise a runtime reconstruct error will be generated.
+    // see ipa-core/src/query/executor.rs
+    let breakdowns = fetch_results::<BA32>(
+        &clients,
+        query.query_id,
+        &query.config,
+        result_keys.as_ref(),
+    )
+    .await?;
+
+    let output = IpaQueryResult {
+        input_size: query.config.size,
+        config: query.ipa_config()?,
+        // Query was driven by several invocations, so end-to-end latency is not known here.
+        latency: Duration::ZERO,
+        breakdowns,
+        partitions: NonZeroU32::MIN,
+    };
+    let output = serde_json::to_string_pretty(&output)?;
+    if let Some(ref path) = args.output_file {
+        std::fs::write(path, output)?;
+    } else {
+        println!("{output}");
+    }
+
+    Ok(())
+}
+
 fn apply_dp_noise(args: &Args, dp_args: &ApplyDpArgs) -> Result<(), Box<dyn Error>> {
     let IpaQueryResult { breakdowns, .. } =
         serde_json::from_slice(&InputSource::from(&args.input).to_vec()?)?;
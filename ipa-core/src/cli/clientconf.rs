@@ -9,6 +9,7 @@ use crate::{
     cli::paths::PathExt,
     config::{ClientConfig, HpkeClientConfig, NetworkConfig, PeerConfig},
     error::BoxError,
+    report::DEFAULT_KEY_ID,
 };
 
 #[derive(Debug, Args)]
@@ -63,6 +64,8 @@ pub fn setup(args: ConfGenArgs) -> Result<(), BoxError> {
                 port,
                 tls_cert_file: args.keys_dir.helper_tls_cert(id),
                 mk_public_key_file: args.keys_dir.helper_mk_public_key(id),
+                result_verifying_key_file: Some(args.keys_dir.helper_result_verifying_key(id))
+                    .filter(|path| path.exists()),
             }
         })
         .collect::<Vec<_>>()
@@ -98,6 +101,7 @@ pub struct HelperClientConf<'a> {
     pub(crate) port: u16,
     pub(crate) tls_cert_file: PathBuf,
     pub(crate) mk_public_key_file: PathBuf,
+    pub(crate) result_verifying_key_file: Option<PathBuf>,
 }
 
 /// Generates client configuration file at the requested destination. The destination must exist
@@ -138,6 +142,14 @@ pub fn gen_client_config<'a>(
             String::from("hpke"),
             Value::Table(encode_hpke(mk_public_key)),
         );
+        if let Some(path) = &client_conf.result_verifying_key_file {
+            let verifying_key = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+            peer.insert(
+                String::from("result_verifying_key"),
+                Value::String(verifying_key.trim().to_owned()),
+            );
+        }
         peers.push(peer.into());
     }
 
@@ -165,9 +177,13 @@ pub fn gen_client_config<'a>(
 /// Creates a section in TOML that describes the HPKE configuration for match key encryption.
 fn encode_hpke(public_key: String) -> Table {
     let mut hpke_table = Table::new();
-    // TODO: key registry requires a set of public keys with their "identifier". Right now
-    // we encode only one key
+    // Helpers that rotate keys publish a new config with the current key and its identifier.
+    // Freshly generated configs always start with the default one.
     hpke_table.insert(String::from("public_key"), Value::String(public_key));
+    hpke_table.insert(
+        String::from("key_id"),
+        Value::Integer(i64::from(DEFAULT_KEY_ID)),
+    );
 
     hpke_table
 }
@@ -204,6 +220,13 @@ fn assert_peer_config(expected: &Value, actual: &PeerConfig) {
         expected.get("hpke").expect("hpke section must be present"),
         actual.hpke_config.as_ref(),
     );
+    assert_eq!(
+        expected
+            .get("result_verifying_key")
+            .and_then(toml::Value::as_str)
+            .map(ToOwned::to_owned),
+        actual.result_verifying_key.map(|vk| vk.to_string())
+    );
 }
 
 /// Validates that the resulting [`HpkeClientConfig`] can be read by helper binary correctly.
@@ -217,4 +240,8 @@ fn assert_hpke_config(expected: &Value, actual: Option<&HpkeClientConfig>) {
             .map(ToOwned::to_owned),
         actual.map(|v| hex::encode(v.public_key.to_bytes()))
     );
+    assert_eq!(
+        expected.get("key_id").and_then(toml::Value::as_integer),
+        actual.map(|v| i64::from(v.key_id))
+    );
 }
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{num::NonZeroU32, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
@@ -14,4 +14,12 @@ pub struct QueryResult {
     )]
     pub latency: Duration,
     pub breakdowns: Vec<u32>,
+    /// Number of queries the input was split into. Histograms of all of them are merged into
+    /// `breakdowns`, so it carries DP noise from each query.
+    #[serde(default = "single_query")]
+    pub partitions: NonZeroU32,
+}
+
+fn single_query() -> NonZeroU32 {
+    NonZeroU32::MIN
 }
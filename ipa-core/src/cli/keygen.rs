@@ -13,7 +13,10 @@ use rcgen::{
 };
 use time::{Duration, OffsetDateTime};
 
-use crate::{error::BoxError, hpke::KeyPair};
+use crate::{
+    error::BoxError,
+    hpke::{KeyPair, ResultSigningKey},
+};
 
 #[derive(Debug, Args)]
 #[clap(
@@ -46,6 +49,15 @@ pub struct KeygenArgs {
     /// Writes the generated report private key to the file
     #[arg(long)]
     pub(crate) mk_private_key: PathBuf,
+
+    /// Writes the generated query result signing key to the file
+    #[arg(long, requires = "result_verifying_key")]
+    pub(crate) result_signing_key: Option<PathBuf>,
+
+    /// Writes the generated query result verifying key to the file. Report collectors need it
+    /// in the network configuration to check helper signatures.
+    #[arg(long, requires = "result_signing_key")]
+    pub(crate) result_verifying_key: Option<PathBuf>,
 }
 
 fn create_new<P: AsRef<Path>>(path: P) -> io::Result<File> {
@@ -109,6 +121,23 @@ fn keygen_matchkey<R: Rng + CryptoRng>(args: &KeygenArgs, mut rng: &mut R) -> Re
     Ok(())
 }
 
+/// Generates the key pair used for signing query results, if requested.
+fn keygen_result_signing<R: Rng + CryptoRng>(
+    args: &KeygenArgs,
+    rng: &mut R,
+) -> Result<(), BoxError> {
+    let (Some(sk_path), Some(vk_path)) = (&args.result_signing_key, &args.result_verifying_key)
+    else {
+        return Ok(());
+    };
+    let signing_key = ResultSigningKey::gen(rng);
+
+    create_new(vk_path)?.write_all(signing_key.verifying_key().to_string().as_bytes())?;
+    create_new(sk_path)?.write_all(hex::encode(signing_key.to_bytes()).as_bytes())?;
+
+    Ok(())
+}
+
 /// Generate keys necessary for running a helper service.
 ///
 /// # Errors
@@ -120,5 +149,6 @@ pub fn keygen(args: &KeygenArgs) -> Result<(), BoxError> {
     let mut rng = thread_rng();
     keygen_tls(args, &mut rng)?;
     keygen_matchkey(args, &mut rng)?;
+    keygen_result_signing(args, &mut rng)?;
     Ok(())
 }
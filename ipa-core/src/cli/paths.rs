@@ -1,11 +1,15 @@
 use std::path::Path;
 
-/// Naming conventions for files that store public/private HPKE and TLS keys.
+/// Naming conventions for files that store public/private HPKE and TLS keys, as well as
+/// reports encrypted for each helper.
 pub trait PathExt: ToOwned {
     fn helper_tls_cert<I: Into<u8>>(&self, id: I) -> Self::Owned;
     fn helper_tls_key<I: Into<u8>>(&self, id: I) -> Self::Owned;
     fn helper_mk_public_key<I: Into<u8>>(&self, id: I) -> Self::Owned;
     fn helper_mk_private_key<I: Into<u8>>(&self, id: I) -> Self::Owned;
+    fn helper_result_signing_key<I: Into<u8>>(&self, id: I) -> Self::Owned;
+    fn helper_result_verifying_key<I: Into<u8>>(&self, id: I) -> Self::Owned;
+    fn helper_encrypted_reports<I: Into<u8>>(&self, id: I) -> Self::Owned;
 }
 
 impl PathExt for Path {
@@ -28,4 +32,19 @@ impl PathExt for Path {
         let id = id.into();
         self.join(format!("h{id}_mk.key"))
     }
+
+    fn helper_result_signing_key<I: Into<u8>>(&self, id: I) -> Self::Owned {
+        let id = id.into();
+        self.join(format!("h{id}_result.key"))
+    }
+
+    fn helper_result_verifying_key<I: Into<u8>>(&self, id: I) -> Self::Owned {
+        let id = id.into();
+        self.join(format!("h{id}_result.pub"))
+    }
+
+    fn helper_encrypted_reports<I: Into<u8>>(&self, id: I) -> Self::Owned {
+        let id = id.into();
+        self.join(format!("h{id}_reports.enc"))
+    }
 }
@@ -2,55 +2,174 @@
 use std::{
     cmp::min,
     iter::zip,
+    num::NonZeroU32,
     time::{Duration, Instant},
 };
 
+use bytes::BufMut;
 use futures_util::future::try_join_all;
 use generic_array::GenericArray;
 use rand::rngs::StdRng;
-use rand_core::SeedableRng;
+use rand_core::{CryptoRng, RngCore, SeedableRng};
 use tokio::time::sleep;
 use typenum::Unsigned;
 
 use crate::{
     cli::IpaQueryResult,
+    config::NetworkConfig,
+    error::BoxError,
     ff::{
         boolean_array::{BA20, BA3, BA8},
         Serializable, U128Conversions,
     },
     helpers::{
-        query::{IpaQueryConfig, QueryInput, QuerySize},
-        BodyStream,
+        query::{IpaQueryConfig, QueryConfig, QueryInput, QuerySize, QueryType},
+        BodyStream, DigestReport, HelperIdentity,
+    },
+    hpke::{
+        open_result, KeyPair, OpenResultError, PublicKeyRegistry, ResultBinding,
+        ResultEncryptionKey, ResultVerifyingKey,
     },
-    hpke::PublicKeyRegistry,
     net::MpcHelperClient,
     protocol::{ipa_prf::OPRFIPAInputRow, QueryId},
-    query::QueryStatus,
+    query::{QueryProgress, QueryStatus},
     report::{KeyIdentifier, OprfReport},
-    secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares, SharedValue},
-    test_fixture::{ipa::TestRawDataRecord, Reconstruct},
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+        IntoShares, SharedValue,
+    },
+    test_fixture::ipa::TestRawDataRecord,
 };
 
 type BreakdownKey = BA8;
 type Timestamp = BA20;
 type TriggerValue = BA3;
 
-/// Executes the IPA v3 protocol.
+/// Keys that report collector uses to open query results sealed by helpers.
+pub struct ResultKeys {
+    /// Private part of the key that report collector supplied at query creation.
+    pub decryption_key: KeyPair,
+    /// Helper verifying keys, in the same order as helper clients.
+    pub verifying_keys: [ResultVerifyingKey; 3],
+    pub helper_origin: String,
+}
+
+impl ResultKeys {
+    /// Generates a fresh report collector key, if all helpers in `network` publish their
+    /// result verifying keys. Otherwise, results can't be authenticated and `None` is returned.
+    pub fn generate<R: RngCore + CryptoRng>(network: &NetworkConfig, rng: &mut R) -> Option<Self> {
+        Self::with_decryption_key(network, KeyPair::gen(rng))
+    }
+
+    /// Uses an existing report collector key, for example one that was persisted when the
+    /// query was created. Returns `None` if any of the helpers in `network` does not publish
+    /// its result verifying key.
+    #[must_use]
+    pub fn with_decryption_key(network: &NetworkConfig, decryption_key: KeyPair) -> Option<Self> {
+        let [vk1, vk2, vk3] = network
+            .peers()
+            .each_ref()
+            .map(|peer| peer.result_verifying_key);
+
+        Some(Self {
+            decryption_key,
+            verifying_keys: [vk1?, vk2?, vk3?],
+            helper_origin: network.helper_origin.clone(),
+        })
+    }
+
+    #[must_use]
+    pub fn encryption_key(&self) -> ResultEncryptionKey {
+        ResultEncryptionKey::from(&self.decryption_key)
+    }
+
+    /// Verifies and decrypts results of the query `query_id` created with `config`, received
+    /// from helpers in the same order as helper clients.
+    ///
+    /// ## Errors
+    /// If any of the results can't be authenticated or decrypted.
+    pub fn open(
+        &self,
+        sealed: [Vec<u8>; 3],
+        query_id: QueryId,
+        config: &QueryConfig,
+    ) -> Result<[Vec<u8>; 3], OpenResultError> {
+        let [r1, r2, r3] = sealed;
+        let [h1, h2, h3] = HelperIdentity::make_three();
+        let [vk1, vk2, vk3] = &self.verifying_keys;
+        let open = |sealed: &[u8], helper, vk| {
+            let binding = ResultBinding {
+                helper,
+                helper_origin: &self.helper_origin,
+                query_id,
+                config,
+            };
+            open_result(sealed, &binding, vk, &self.decryption_key)
+        };
+
+        Ok([
+            open(&r1, h1, vk1)?,
+            open(&r2, h2, vk2)?,
+            open(&r3, h3, vk3)?,
+        ])
+    }
+}
+
+/// Executes the IPA v3 protocol for the query `query_id` created with `query_config`. If match
+/// key encryption is requested, reports are encrypted for the given `helper_origin`. If
+/// `result_keys` are provided, the query must have been created with the matching result
+/// encryption key.
+///
+/// ## Errors
+/// If inputs can't be uploaded, the query does not complete or its results can't be fetched.
 ///
 /// ## Panics
-/// If report encryption fails
+/// If report encryption fails or `query_config` is not an IPA query.
 pub async fn playbook_oprf_ipa<HV, KR>(
     records: Vec<TestRawDataRecord>,
     clients: &[MpcHelperClient; 3],
     query_id: QueryId,
-    query_config: IpaQueryConfig,
+    query_config: QueryConfig,
     encryption: Option<(KeyIdentifier, [&KR; 3])>,
-) -> IpaQueryResult
+    helper_origin: &str,
+    result_keys: Option<&ResultKeys>,
+) -> Result<IpaQueryResult, RunQueryError>
 where
     HV: SharedValue + U128Conversions,
     AdditiveShare<HV>: Serializable,
     KR: PublicKeyRegistry,
 {
+    let inputs = encode_oprf_inputs(
+        &records,
+        &ipa_config(&query_config),
+        encryption,
+        helper_origin,
+    );
+    tracing::info!("Starting query for OPRF");
+
+    run_query_and_validate::<HV>(
+        inputs,
+        records.len(),
+        clients,
+        query_id,
+        query_config,
+        result_keys,
+    )
+    .await
+}
+
+/// Secret-shares `records` and serializes the shares into the format helpers expect as query
+/// input. Depending on `query_config`, match keys are sent in the clear or reports are encrypted
+/// with the helper public keys provided in `encryption`.
+///
+/// ## Panics
+/// If report encryption fails or if encryption is requested, but no keys are provided.
+pub fn encode_oprf_inputs<KR: PublicKeyRegistry>(
+    records: &[TestRawDataRecord],
+    query_config: &IpaQueryConfig,
+    encryption: Option<(KeyIdentifier, [&KR; 3])>,
+    helper_origin: &str,
+) -> [BodyStream; 3] {
     let mut buffers: [_; 3] = std::array::from_fn(|_| Vec::new());
     let query_size = records.len();
 
@@ -70,48 +189,106 @@ where
             }
         });
     } else if let Some((key_id, key_registries)) = encryption {
-        const ESTIMATED_AVERAGE_REPORT_SIZE: usize = 80; // TODO: confirm/adjust
-        for buffer in &mut buffers {
-            buffer.reserve(query_size * ESTIMATED_AVERAGE_REPORT_SIZE);
-        }
-
-        let mut rng = StdRng::from_entropy();
-        let shares: [Vec<OprfReport<BreakdownKey, TriggerValue, Timestamp>>; 3] =
-            records.iter().cloned().share();
-        zip(&mut buffers, shares)
-            .zip(key_registries)
-            .for_each(|((buf, shares), key_registry)| {
-                for share in shares {
-                    share
-                        .delimited_encrypt_to(key_id, key_registry, &mut rng, buf)
-                        .unwrap();
-                }
-            });
+        buffers = encrypt_oprf_reports(
+            records,
+            key_id,
+            key_registries,
+            helper_origin,
+            query_config.unversioned_reports,
+        );
     } else {
         panic!(
             "match key encryption was requested, but one or more helpers is missing a public key"
         )
     }
 
-    let inputs = buffers.map(BodyStream::from);
-    tracing::info!("Starting query for OPRF");
+    buffers.map(BodyStream::from)
+}
 
-    run_query_and_validate::<HV>(inputs, query_size, clients, query_id, query_config).await
+/// Secret-shares `records` and encrypts each report share for the corresponding helper, using
+/// its key `key_id` from `key_registries`. Reports are written in the same length-delimited
+/// format user agents use to submit them, one buffer per helper. Reports are prefixed with
+/// [`ReportVersion::LATEST`], unless `unversioned_reports` is set.
+///
+/// [`ReportVersion::LATEST`]: crate::report::ReportVersion::LATEST
+///
+/// ## Panics
+/// If report encryption fails.
+pub fn encrypt_oprf_reports<KR: PublicKeyRegistry>(
+    records: &[TestRawDataRecord],
+    key_id: KeyIdentifier,
+    key_registries: [&KR; 3],
+    helper_origin: &str,
+    unversioned_reports: bool,
+) -> [Vec<u8>; 3] {
+    const ESTIMATED_AVERAGE_REPORT_SIZE: usize = 80; // TODO: confirm/adjust
+    let mut buffers: [_; 3] =
+        std::array::from_fn(|_| Vec::with_capacity(records.len() * ESTIMATED_AVERAGE_REPORT_SIZE));
+
+    let mut rng = StdRng::from_entropy();
+    let shares: [Vec<OprfReport<BreakdownKey, TriggerValue, Timestamp>>; 3] =
+        records.iter().cloned().share();
+    zip(&mut buffers, shares)
+        .zip(key_registries)
+        .for_each(|((buf, shares), key_registry)| {
+            for share in shares {
+                if unversioned_reports {
+                    buf.put_u16_le(share.encrypted_len());
+                    share
+                        .encrypt_to(key_id, key_registry, helper_origin, &mut rng, buf)
+                        .unwrap();
+                } else {
+                    share
+                        .delimited_encrypt_to(key_id, key_registry, helper_origin, &mut rng, buf)
+                        .unwrap();
+                }
+            }
+        });
+
+    buffers
 }
 
+/// Error returned when waiting for query completion does not succeed.
+#[derive(Debug, thiserror::Error)]
+pub enum WaitError {
+    #[error("query did not complete within {0:?}")]
+    Timeout(Duration),
+    #[error(transparent)]
+    Net(#[from] crate::net::Error),
+}
+
+/// Error returned when query results can't be retrieved from helpers or reconstructed.
+#[derive(Debug, thiserror::Error)]
+pub enum FetchResultsError {
+    #[error(transparent)]
+    Net(#[from] crate::net::Error),
+    #[error(transparent)]
+    Open(#[from] OpenResultError),
+    #[error("query results returned by helpers are malformed: {0}")]
+    Malformed(#[source] BoxError),
+}
+
+/// Error returned when running a query from start to finish does not succeed.
+#[derive(Debug, thiserror::Error)]
+pub enum RunQueryError {
+    #[error("failed to upload query inputs: {0}")]
+    Upload(#[source] crate::net::Error),
+    #[error(transparent)]
+    Wait(#[from] WaitError),
+    #[error(transparent)]
+    Fetch(#[from] FetchResultsError),
+}
+
+/// Sends query inputs to all helpers.
+///
+/// ## Errors
+/// If any of the helpers rejects its input.
 #[allow(clippy::disallowed_methods)] // allow try_join_all
-pub async fn run_query_and_validate<HV>(
+pub async fn upload_inputs(
     inputs: [BodyStream; 3],
-    query_size: usize,
     clients: &[MpcHelperClient; 3],
     query_id: QueryId,
-    query_config: IpaQueryConfig,
-) -> IpaQueryResult
-where
-    HV: SharedValue + U128Conversions,
-    AdditiveShare<HV>: Serializable,
-{
-    let mpc_time = Instant::now();
+) -> Result<(), crate::net::Error> {
     try_join_all(
         inputs
             .into_iter()
@@ -123,66 +300,248 @@ where
                 })
             }),
     )
-    .await
-    .unwrap();
+    .await?;
+
+    Ok(())
+}
+
+/// Returns the status of the query, as reported by each helper.
+///
+/// ## Errors
+/// If any of the helpers can't be reached or does not know about this query.
+pub async fn query_status(
+    clients: &[MpcHelperClient; 3],
+    query_id: QueryId,
+) -> Result<[QueryStatus; 3], crate::net::Error> {
+    let [s1, s2, s3] = clients
+        .each_ref()
+        .map(|client| client.query_status(query_id));
+    let (s1, s2, s3) = futures::try_join!(s1, s2, s3)?;
+
+    Ok([s1, s2, s3])
+}
+
+/// Returns the status of the query along with its progress, as reported by each helper.
+/// Progress is only available from helpers that are running the query.
+///
+/// ## Errors
+/// If any of the helpers can't be reached or does not know about this query.
+pub async fn query_progress(
+    clients: &[MpcHelperClient; 3],
+    query_id: QueryId,
+) -> Result<[(QueryStatus, Option<QueryProgress>); 3], crate::net::Error> {
+    let [p1, p2, p3] = clients
+        .each_ref()
+        .map(|client| client.query_progress(query_id));
+    let (p1, p2, p3) = futures::try_join!(p1, p2, p3)?;
+
+    Ok([p1, p2, p3])
+}
+
+/// Returns digests of the records each helper exchanged with other helpers while running the
+/// query. Helpers only share digests with each other, so `clients` must authenticate as one of
+/// the helpers.
+///
+/// ## Errors
+/// If any of the helpers can't be reached or does not keep digests for this query.
+pub async fn query_digests(
+    clients: &[MpcHelperClient; 3],
+    query_id: QueryId,
+) -> Result<[DigestReport; 3], crate::net::Error> {
+    let [d1, d2, d3] = clients
+        .each_ref()
+        .map(|client| client.query_digests(query_id));
+    let (d1, d2, d3) = futures::try_join!(d1, d2, d3)?;
 
+    Ok([d1, d2, d3])
+}
+
+/// Polls helpers until all of them report the query as completed. If `timeout` is set, gives up
+/// once it elapses.
+///
+/// ## Errors
+/// If the query does not complete before `timeout` or if helpers can't be queried.
+pub async fn wait_for_completion(
+    clients: &[MpcHelperClient; 3],
+    query_id: QueryId,
+    timeout: Option<Duration>,
+) -> Result<(), WaitError> {
+    let started = Instant::now();
     let mut delay = Duration::from_millis(125);
     loop {
-        if try_join_all(clients.iter().map(|client| client.query_status(query_id)))
-            .await
-            .unwrap()
-            .into_iter()
-            .all(|status| status == QueryStatus::Completed)
+        let progress = query_progress(clients, query_id).await?;
+        if progress
+            .iter()
+            .all(|(status, _)| *status == QueryStatus::Completed)
+        {
+            return Ok(());
+        }
+        if let Some(stage) = progress
+            .iter()
+            .filter_map(|(_, progress)| progress.as_ref()?.current())
+            .min_by_key(|stage| stage.stage)
         {
-            break;
+            tracing::info!(
+                "query is at {:?} stage with {} records, for {:?}",
+                stage.stage,
+                stage.records,
+                stage.elapsed
+            );
+        }
+
+        if let Some(timeout) = timeout {
+            let elapsed = started.elapsed();
+            if elapsed >= timeout {
+                return Err(WaitError::Timeout(timeout));
+            }
+            delay = min(delay, timeout - elapsed);
         }
 
         sleep(delay).await;
         delay = min(Duration::from_secs(5), delay * 2);
-        // TODO: Add a timeout of some sort. Possibly, add some sort of progress indicator to
-        // the status API so we can check whether the query is making progress.
     }
+}
 
-    // wait until helpers have processed the query and get the results from them
-    let results: [_; 3] = try_join_all(clients.iter().map(|client| client.query_results(query_id)))
-        .await
-        .unwrap()
-        .try_into()
-        .unwrap();
-
-    let results: Vec<HV> = results
-        .map(|bytes| {
-            AdditiveShare::<HV>::from_byte_slice(&bytes)
-                .collect::<Result<Vec<_>, _>>()
-                .unwrap()
-        })
-        .reconstruct();
+/// Returns the IPA part of `config`.
+///
+/// ## Panics
+/// If `config` is not an IPA query.
+fn ipa_config(config: &QueryConfig) -> IpaQueryConfig {
+    let QueryType::OprfIpa(ipa_config) = config.query_type else {
+        panic!("{:?} is not an IPA query", config.query_type);
+    };
 
-    let lat = mpc_time.elapsed();
+    ipa_config
+}
 
-    tracing::info!("Running IPA for {query_size:?} records took {t:?}", t = lat);
-    let mut breakdowns = vec![0; usize::try_from(query_config.max_breakdown_key).unwrap()];
+/// Fetches query results from all helpers, opens them if they were sealed for the report
+/// collector and reconstructs the histogram of `max_breakdown_key` buckets.
+///
+/// ## Errors
+/// If results can't be retrieved or authenticated, or if they are not valid shares of a
+/// histogram for the query configuration.
+///
+/// ## Panics
+/// If `config` is not an IPA query.
+pub async fn fetch_results<HV>(
+    clients: &[MpcHelperClient; 3],
+    query_id: QueryId,
+    config: &QueryConfig,
+    result_keys: Option<&ResultKeys>,
+) -> Result<Vec<u32>, FetchResultsError>
+where
+    HV: SharedValue + U128Conversions,
+    AdditiveShare<HV>: Serializable,
+{
+    let query_config = ipa_config(config);
+    let [r1, r2, r3] = clients
+        .each_ref()
+        .map(|client| client.query_results(query_id));
+    let (r1, r2, r3) = futures::try_join!(r1, r2, r3)?;
+    let results = [r1, r2, r3].map(Vec::from);
+    let results = match result_keys {
+        Some(keys) => keys.open(results, query_id, config)?,
+        None => results,
+    };
+
+    let [s1, s2, s3] = results.map(|bytes| {
+        AdditiveShare::<HV>::from_byte_slice(&bytes)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| FetchResultsError::Malformed(e.into()))
+    });
+    let results = reconstruct_results([s1?, s2?, s3?])?;
+
+    let max_breakdown_key = usize::try_from(query_config.max_breakdown_key).unwrap();
+    let mut breakdowns = vec![0; max_breakdown_key];
     for (breakdown_key, trigger_value) in results.into_iter().enumerate() {
         // TODO: make the data type used consistent with `ipa_in_the_clear`
         // I think using u32 is wrong, we should move to u128
-        if query_config.with_dp == 0 {
-            // otherwise if DP is added trigger_values will not be zero due to noise
-            assert!(
-                breakdown_key < query_config.max_breakdown_key.try_into().unwrap()
-                    || trigger_value == HV::ZERO,
-                "trigger values were attributed to buckets more than max breakdown key"
-            );
+        if breakdown_key < max_breakdown_key {
+            breakdowns[breakdown_key] += u32::try_from(trigger_value.as_u128())
+                .map_err(|e| FetchResultsError::Malformed(e.into()))?;
+        } else if query_config.with_dp == 0 && trigger_value != HV::ZERO {
+            // if DP is added, trigger values beyond max breakdown key are not zero due to noise
+            return Err(FetchResultsError::Malformed(
+                format!(
+                    "trigger values were attributed to bucket {breakdown_key}, \
+                     but max breakdown key is {max_breakdown_key}"
+                )
+                .into(),
+            ));
         }
+    }
 
-        if breakdown_key < query_config.max_breakdown_key.try_into().unwrap() {
-            breakdowns[breakdown_key] += u32::try_from(trigger_value.as_u128()).unwrap();
-        }
+    Ok(breakdowns)
+}
+
+/// Reconstructs values from the shares returned by each helper, checking that the shares are
+/// consistent with each other instead of trusting them.
+fn reconstruct_results<HV: SharedValue>(
+    shares: [Vec<AdditiveShare<HV>>; 3],
+) -> Result<Vec<HV>, FetchResultsError> {
+    let [s1, s2, s3] = shares;
+    if s1.len() != s2.len() || s2.len() != s3.len() {
+        return Err(FetchResultsError::Malformed(
+            format!(
+                "helpers returned {}, {} and {} shares",
+                s1.len(),
+                s2.len(),
+                s3.len()
+            )
+            .into(),
+        ));
     }
 
-    IpaQueryResult {
+    zip(s1, zip(s2, s3))
+        .enumerate()
+        .map(|(i, (s1, (s2, s3)))| {
+            if s1.right() == s2.left() && s2.right() == s3.left() && s3.right() == s1.left() {
+                Ok(s1.left() + s2.left() + s3.left())
+            } else {
+                Err(FetchResultsError::Malformed(
+                    format!("shares of value {i} are inconsistent").into(),
+                ))
+            }
+        })
+        .collect()
+}
+
+/// Uploads inputs, waits for the query to complete and fetches its results.
+///
+/// ## Errors
+/// If any of the steps fails.
+///
+/// ## Panics
+/// If `query_config` is not an IPA query.
+pub async fn run_query_and_validate<HV>(
+    inputs: [BodyStream; 3],
+    query_size: usize,
+    clients: &[MpcHelperClient; 3],
+    query_id: QueryId,
+    query_config: QueryConfig,
+    result_keys: Option<&ResultKeys>,
+) -> Result<IpaQueryResult, RunQueryError>
+where
+    HV: SharedValue + U128Conversions,
+    AdditiveShare<HV>: Serializable,
+{
+    let mpc_time = Instant::now();
+    upload_inputs(inputs, clients, query_id)
+        .await
+        .map_err(RunQueryError::Upload)?;
+    wait_for_completion(clients, query_id, None).await?;
+
+    // wait until helpers have processed the query and get the results from them
+    let breakdowns = fetch_results::<HV>(clients, query_id, &query_config, result_keys).await?;
+
+    let lat = mpc_time.elapsed();
+    tracing::info!("Running IPA for {query_size:?} records took {t:?}", t = lat);
+
+    Ok(IpaQueryResult {
         input_size: QuerySize::try_from(query_size).unwrap(),
-        config: query_config,
+        config: ipa_config(&query_config),
         latency: lat,
         breakdowns,
-    }
+        partitions: NonZeroU32::MIN,
+    })
 }
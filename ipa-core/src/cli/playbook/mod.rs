@@ -3,9 +3,11 @@ mod generator;
 mod input;
 mod ipa;
 mod multiply;
+mod partition;
+mod state;
 
 use core::fmt::Debug;
-use std::{fs, path::Path, time::Duration};
+use std::{fs, num::NonZeroU32, path::Path, time::Duration};
 
 pub use add::secure_add;
 use comfy_table::{Cell, Color, Table};
@@ -14,7 +16,17 @@ pub use input::InputSource;
 pub use multiply::secure_mul;
 use tokio::time::sleep;
 
-pub use self::ipa::playbook_oprf_ipa;
+pub use self::{
+    ipa::{
+        encode_oprf_inputs, encrypt_oprf_reports, fetch_results, playbook_oprf_ipa, query_digests,
+        query_progress, query_status, upload_inputs, wait_for_completion, FetchResultsError,
+        ResultKeys, RunQueryError, WaitError,
+    },
+    partition::{
+        merge_histograms, merged_noise_mean_std, partition_by_user, user_shard, PartitionError,
+    },
+    state::{DetachedQuery, StateFileError},
+};
 use crate::{
     config::{ClientConfig, NetworkConfig, PeerConfig},
     net::{ClientIdentity, MpcHelperClient},
@@ -72,11 +84,31 @@ where
     );
 }
 
-/// Validates that the expected result matches the actual.
+/// DP noise parameters helpers use to add noise to IPA histograms.
+#[must_use]
+pub fn ipa_noise_params(epsilon: f64, per_user_credit_cap: u32) -> NoiseParams {
+    NoiseParams {
+        epsilon,
+        ell_1_sensitivity: per_user_credit_cap.into(),
+        ell_2_sensitivity: per_user_credit_cap.into(),
+        ell_infty_sensitivity: per_user_credit_cap.into(),
+        dimensions: 256.0, // matches the hard coded number of breakdown keys in oprf_ipa.rs/execute
+        ..Default::default()
+    }
+}
+
+/// Validates that the expected result matches the actual. `actual` may be merged from
+/// `partitions` queries, each of which added its own noise.
 ///
 /// ## Panics
 /// If results don't match.
-pub fn validate_dp(expected: Vec<u32>, actual: Vec<u32>, epsilon: f64, per_user_credit_cap: u32) {
+pub fn validate_dp(
+    expected: Vec<u32>,
+    actual: Vec<u32>,
+    epsilon: f64,
+    per_user_credit_cap: u32,
+    partitions: NonZeroU32,
+) {
     let mut expected = expected.into_iter().fuse();
     let mut actual = actual.into_iter().fuse();
     let mut mismatch = Vec::new();
@@ -101,16 +133,8 @@ pub fn validate_dp(expected: Vec<u32>, actual: Vec<u32>, epsilon: f64, per_user_
 
         let next_expected_f64: f64 = next_expected.unwrap().into();
         let actual_expect_f64: f64 = next_actual.unwrap().into();
-        let noise_params = NoiseParams {
-            epsilon,
-            ell_1_sensitivity: per_user_credit_cap.into(),
-            ell_2_sensitivity: per_user_credit_cap.into(),
-            ell_infty_sensitivity: per_user_credit_cap.into(),
-            dimensions: 256.0, // matches the hard coded number of breakdown keys in oprf_ipa.rs/execute
-            ..Default::default()
-        };
-
-        let (mean, std) = crate::protocol::dp::noise_mean_std(&noise_params);
+        let (mean, std) =
+            merged_noise_mean_std(&ipa_noise_params(epsilon, per_user_credit_cap), partitions);
         let same = actual_expect_f64 - mean > next_expected_f64 - 10.0 * std
             && actual_expect_f64 - mean < next_expected_f64 + 10.0 * std;
 
@@ -150,19 +174,22 @@ pub async fn make_clients(
     scheme: Scheme,
     wait: usize,
 ) -> ([MpcHelperClient; 3], NetworkConfig) {
-    let mut wait = wait;
     let network = if let Some(path) = network_path {
         NetworkConfig::from_toml_str(&fs::read_to_string(path).unwrap()).unwrap()
     } else {
-        NetworkConfig {
-            peers: [
-                PeerConfig::new("localhost:3000".parse().unwrap(), None),
-                PeerConfig::new("localhost:3001".parse().unwrap(), None),
-                PeerConfig::new("localhost:3002".parse().unwrap(), None),
-            ],
-            client: ClientConfig::default(),
-        }
+        local_network()
     };
+
+    make_clients_for_network(network, scheme, wait).await
+}
+
+/// Creates 3 clients to talk to MPC helpers in the given `network`.
+pub async fn make_clients_for_network(
+    network: NetworkConfig,
+    scheme: Scheme,
+    wait: usize,
+) -> ([MpcHelperClient; 3], NetworkConfig) {
+    let mut wait = wait;
     let network = network.override_scheme(&scheme);
 
     // Note: This closure is only called when the selected action uses clients.
@@ -176,6 +203,19 @@ pub async fn make_clients(
     (clients, network)
 }
 
+/// Network of three helpers running on the local host, used when no network configuration is
+/// provided.
+fn local_network() -> NetworkConfig {
+    NetworkConfig::new(
+        [
+            PeerConfig::new("localhost:3000".parse().unwrap(), None),
+            PeerConfig::new("localhost:3001".parse().unwrap(), None),
+            PeerConfig::new("localhost:3002".parse().unwrap(), None),
+        ],
+        ClientConfig::default(),
+    )
+}
+
 async fn clients_ready(clients: &[MpcHelperClient; 3]) -> bool {
     clients[0].echo("").await.is_ok()
         && clients[1].echo("").await.is_ok()
@@ -0,0 +1,267 @@
+use std::{
+    collections::HashMap,
+    num::{NonZeroU32, NonZeroUsize},
+};
+
+use crate::{
+    protocol::dp::{noise_mean_std, NoiseParams},
+    test_fixture::ipa::TestRawDataRecord,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum PartitionError {
+    #[error("user {user_id} has {count} records, which exceeds the maximum query size {max}")]
+    UserTooLarge {
+        user_id: u64,
+        count: usize,
+        max: usize,
+    },
+    #[error("unable to split {0} records into queries of at most {1} records")]
+    Unbalanced(usize, usize),
+}
+
+/// Deterministic user-sharding hint. The same user is always assigned to the same shard out of
+/// `shards`, regardless of the platform and across report collector runs.
+#[must_use]
+pub fn user_shard(user_id: u64, shards: NonZeroU32) -> u32 {
+    // splitmix64 finalizer, spreads sequential user ids evenly across shards.
+    let mut z = user_id.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+
+    #[allow(clippy::cast_possible_truncation)] // the remainder is less than `shards`
+    let shard = (z % u64::from(shards.get())) as u32;
+    shard
+}
+
+/// Splits `records` into partitions of at most `max_size` records. All records of a user end up
+/// in the same partition, so per-user capping and attribution within each partition are the same
+/// as over the whole input. Users are placed by [`user_shard`] over the smallest number of shards
+/// that could hold all records. Users that don't fit into their shard go to extra partitions.
+/// Empty partitions are dropped.
+///
+/// ## Errors
+/// If a single user has more than `max_size` records or if there are too many records.
+pub fn partition_by_user(
+    records: Vec<TestRawDataRecord>,
+    max_size: NonZeroUsize,
+) -> Result<Vec<Vec<TestRawDataRecord>>, PartitionError> {
+    let max = max_size.get();
+    if records.len() <= max {
+        return Ok(vec![records]);
+    }
+
+    let mut per_user = HashMap::<u64, usize>::new();
+    for record in &records {
+        *per_user.entry(record.user_id).or_default() += 1;
+    }
+    if let Some((&user_id, &count)) = per_user.iter().find(|&(_, &count)| count > max) {
+        return Err(PartitionError::UserTooLarge {
+            user_id,
+            count,
+            max,
+        });
+    }
+
+    let Some(shards) = u32::try_from(records.len().div_ceil(max))
+        .ok()
+        .and_then(NonZeroU32::new)
+    else {
+        return Err(PartitionError::Unbalanced(records.len(), max));
+    };
+
+    // Hashing is not perfectly balanced, so users that don't fit into their shard are moved to
+    // extra partitions, filled one after another. Users are visited in a fixed order, so the same
+    // input is always split the same way.
+    let mut users = per_user.into_iter().collect::<Vec<_>>();
+    users.sort_unstable();
+    let mut sizes = vec![0usize; shards.get() as usize];
+    let mut user_partition = HashMap::with_capacity(users.len());
+    for (user_id, count) in users {
+        let shard = user_shard(user_id, shards) as usize;
+        let partition = if sizes[shard] + count <= max {
+            shard
+        } else {
+            if sizes.len() == shards.get() as usize || sizes[sizes.len() - 1] + count > max {
+                sizes.push(0);
+            }
+            sizes.len() - 1
+        };
+        sizes[partition] += count;
+        user_partition.insert(user_id, partition);
+    }
+
+    let mut partitions = sizes
+        .into_iter()
+        .map(Vec::with_capacity)
+        .collect::<Vec<_>>();
+    for record in records {
+        partitions[user_partition[&record.user_id]].push(record);
+    }
+    partitions.retain(|partition| !partition.is_empty());
+
+    Ok(partitions)
+}
+
+/// Adds up histograms produced by queries over disjoint partitions of the input.
+///
+/// ## Panics
+/// If histograms have different sizes or a bucket overflows.
+#[must_use]
+pub fn merge_histograms(histograms: &[Vec<u32>]) -> Vec<u32> {
+    let Some(first) = histograms.first() else {
+        return Vec::new();
+    };
+    let mut merged = vec![0u32; first.len()];
+    for histogram in histograms {
+        assert_eq!(
+            merged.len(),
+            histogram.len(),
+            "histograms must have the same number of buckets"
+        );
+        for (total, &value) in merged.iter_mut().zip(histogram) {
+            *total = total
+                .checked_add(value)
+                .expect("merged histogram bucket overflow");
+        }
+    }
+
+    merged
+}
+
+/// Mean and standard deviation of DP noise in a histogram merged from `partitions` queries.
+///
+/// Every query adds its own noise to every bucket, so the merged histogram carries the sum of
+/// independent noise samples. Because users are disjoint across partitions, each user is still
+/// covered by the noise of a single query and the privacy budget is not consumed more than once;
+/// only the accuracy of the merged histogram degrades.
+#[must_use]
+pub fn merged_noise_mean_std(noise_params: &NoiseParams, partitions: NonZeroU32) -> (f64, f64) {
+    let (mean, std) = noise_mean_std(noise_params);
+    let partitions = f64::from(partitions.get());
+
+    (mean * partitions, std * partitions.sqrt())
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::{
+        collections::HashMap,
+        num::{NonZeroU32, NonZeroUsize},
+    };
+
+    use super::{
+        merge_histograms, merged_noise_mean_std, partition_by_user, user_shard, PartitionError,
+    };
+    use crate::{
+        protocol::dp::{noise_mean_std, NoiseParams},
+        test_fixture::ipa::TestRawDataRecord,
+    };
+
+    fn record(user_id: u64) -> TestRawDataRecord {
+        TestRawDataRecord {
+            timestamp: 0,
+            user_id,
+            is_trigger_report: false,
+            breakdown_key: 0,
+            trigger_value: 0,
+        }
+    }
+
+    #[test]
+    fn shard_is_deterministic() {
+        let shards = NonZeroU32::new(7).unwrap();
+        for user_id in 0..1000 {
+            let shard = user_shard(user_id, shards);
+            assert!(shard < 7);
+            assert_eq!(shard, user_shard(user_id, shards));
+        }
+        assert_eq!(0, user_shard(42, NonZeroU32::new(1).unwrap()));
+    }
+
+    #[test]
+    fn small_input_is_not_split() {
+        let records = (0..10).map(record).collect::<Vec<_>>();
+        let partitions = partition_by_user(records, NonZeroUsize::new(10).unwrap()).unwrap();
+        assert_eq!(1, partitions.len());
+        assert_eq!(10, partitions[0].len());
+    }
+
+    #[test]
+    fn users_stay_together() {
+        let records = (0..3000).map(|i| record(i % 500)).collect::<Vec<_>>();
+        let partitions = partition_by_user(records, NonZeroUsize::new(1000).unwrap()).unwrap();
+
+        assert!(partitions.len() >= 3);
+        assert_eq!(3000, partitions.iter().map(Vec::len).sum::<usize>());
+        let mut user_partition = HashMap::new();
+        for (i, partition) in partitions.iter().enumerate() {
+            assert!(partition.len() <= 1000);
+            for r in partition {
+                assert_eq!(i, *user_partition.entry(r.user_id).or_insert(i));
+            }
+        }
+    }
+
+    #[test]
+    fn uneven_users_fit() {
+        // users with 1 to 7 records each
+        let records = (0..1000u64)
+            .flat_map(|user_id| (0..=user_id % 7).map(move |_| record(user_id)))
+            .collect::<Vec<_>>();
+        let split = |records| partition_by_user(records, NonZeroUsize::new(100).unwrap()).unwrap();
+        let partitions = split(records.clone());
+
+        assert_eq!(
+            records.len(),
+            partitions.iter().map(Vec::len).sum::<usize>()
+        );
+        let mut user_partition = HashMap::new();
+        for (i, partition) in partitions.iter().enumerate() {
+            assert!(partition.len() <= 100);
+            for r in partition {
+                assert_eq!(i, *user_partition.entry(r.user_id).or_insert(i));
+            }
+        }
+
+        let users = |partitions: Vec<Vec<TestRawDataRecord>>| {
+            partitions
+                .into_iter()
+                .map(|p| p.into_iter().map(|r| r.user_id).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(users(partitions), users(split(records)));
+    }
+
+    #[test]
+    fn user_too_large() {
+        let records = (0..20).map(|i| record(i / 10)).collect::<Vec<_>>();
+        assert!(matches!(
+            partition_by_user(records, NonZeroUsize::new(5).unwrap()),
+            Err(PartitionError::UserTooLarge {
+                count: 10,
+                max: 5,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn merge() {
+        assert_eq!(
+            vec![5, 7, 9],
+            merge_histograms(&[vec![1, 2, 3], vec![4, 5, 6]])
+        );
+        assert!(merge_histograms(&[]).is_empty());
+    }
+
+    #[test]
+    fn merged_noise() {
+        let params = NoiseParams::default();
+        let (mean, std) = noise_mean_std(&params);
+        let (merged_mean, merged_std) = merged_noise_mean_std(&params, NonZeroU32::new(4).unwrap());
+        assert!((merged_mean - 4.0 * mean).abs() < 1e-9);
+        assert!((merged_std - 2.0 * std).abs() < 1e-9);
+    }
+}
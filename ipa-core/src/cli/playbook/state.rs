@@ -0,0 +1,330 @@
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use hyper::http::uri::Scheme;
+use serde::{Deserialize, Serialize};
+
+use super::{local_network, ResultKeys};
+use crate::{
+    config::NetworkConfig,
+    helpers::query::{IpaQueryConfig, QueryConfig, QueryType},
+    hpke::{Deserializable, IpaPrivateKey, KeyPair},
+    protocol::QueryId,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum StateFileError {
+    #[error("failed to access query state file {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("query state file {path} is malformed: {source}")]
+    Malformed {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("network configuration saved with the query is invalid: {0}")]
+    Network(#[from] crate::config::Error),
+    #[error("result decryption key saved with the query is invalid")]
+    ResultKey,
+    #[error("query results are sealed, but not all helpers publish their result verifying keys")]
+    MissingVerifyingKeys,
+    #[error("query state file describes a {0:?} query, expected an IPA query")]
+    NotIpa(QueryType),
+}
+
+/// Creates a new file that only its owner can read, because query state includes the result
+/// decryption key.
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path)
+}
+
+/// A query that is driven through several report collector invocations.
+///
+/// Everything that is needed to talk to helpers about this query is persisted in a local state
+/// file, so a report collector can be restarted between creating the query, uploading its
+/// inputs and fetching its results.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DetachedQuery {
+    pub query_id: QueryId,
+    /// Configuration the query was created with. Helpers bind sealed results to it, so it must
+    /// be kept exactly as it was sent to them.
+    pub config: QueryConfig,
+    /// Contents of the network configuration file used to create the query. `None` means that
+    /// helpers are running on the local host.
+    pub network: Option<String>,
+    pub https: bool,
+    /// Hex-encoded private key to open query results with, if they are sealed.
+    result_decryption_key: Option<String>,
+    pub inputs_uploaded: bool,
+}
+
+impl DetachedQuery {
+    /// Records the query `query_id` that was created with `config`.
+    #[must_use]
+    pub fn new(
+        query_id: QueryId,
+        config: QueryConfig,
+        network: Option<String>,
+        scheme: &Scheme,
+        result_keys: Option<&ResultKeys>,
+    ) -> Self {
+        Self {
+            query_id,
+            config,
+            network,
+            https: scheme == &Scheme::HTTPS,
+            result_decryption_key: result_keys
+                .map(|keys| hex::encode(keys.decryption_key.sk_bytes())),
+            inputs_uploaded: false,
+        }
+    }
+
+    /// Reads query state from `path`.
+    ///
+    /// ## Errors
+    /// If the file can't be read or its contents are malformed.
+    pub fn load(path: &Path) -> Result<Self, StateFileError> {
+        let bytes = fs::read(path).map_err(|source| StateFileError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        serde_json::from_slice(&bytes).map_err(|source| StateFileError::Malformed {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    /// Writes query state to a new file at `path`. Fails if the file already exists, so the
+    /// state of another query is never lost.
+    ///
+    /// ## Errors
+    /// If the file exists or can't be written.
+    pub fn create(&self, path: &Path) -> Result<(), StateFileError> {
+        let io_err = |source| StateFileError::Io {
+            path: path.to_owned(),
+            source,
+        };
+        let mut file = create_private(path).map_err(io_err)?;
+        file.write_all(self.to_json().as_bytes()).map_err(io_err)
+    }
+
+    /// Replaces query state at `path`. The new state is written next to it first, so an
+    /// interrupted update leaves the previous state intact.
+    ///
+    /// ## Errors
+    /// If the file can't be written.
+    pub fn save(&self, path: &Path) -> Result<(), StateFileError> {
+        let io_err = |source| StateFileError::Io {
+            path: path.to_owned(),
+            source,
+        };
+        let mut tmp = OsString::from(path);
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        // A leftover from an interrupted update may have been created with different permissions.
+        match fs::remove_file(&tmp) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(io_err(e)),
+            _ => {}
+        }
+        let mut file = create_private(&tmp).map_err(io_err)?;
+        file.write_all(self.to_json().as_bytes())
+            .and_then(|()| file.sync_all())
+            .map_err(io_err)?;
+        fs::rename(&tmp, path).map_err(io_err)
+    }
+
+    #[must_use]
+    pub fn scheme(&self) -> Scheme {
+        if self.https {
+            Scheme::HTTPS
+        } else {
+            Scheme::HTTP
+        }
+    }
+
+    /// Returns the configuration of the network this query runs on.
+    ///
+    /// ## Errors
+    /// If the saved network configuration is invalid.
+    pub fn network_config(&self) -> Result<NetworkConfig, StateFileError> {
+        match self.network {
+            Some(ref toml) => Ok(NetworkConfig::from_toml_str(toml)?),
+            None => Ok(local_network()),
+        }
+    }
+
+    /// Returns the keys to open query results with, or `None` if results are not sealed.
+    ///
+    /// ## Errors
+    /// If the saved key is invalid or helpers in `network` no longer publish their verifying
+    /// keys.
+    pub fn result_keys(
+        &self,
+        network: &NetworkConfig,
+    ) -> Result<Option<ResultKeys>, StateFileError> {
+        let Some(ref sk) = self.result_decryption_key else {
+            return Ok(None);
+        };
+        let sk = hex::decode(sk)
+            .ok()
+            .and_then(|sk| IpaPrivateKey::from_bytes(&sk).ok())
+            .ok_or(StateFileError::ResultKey)?;
+
+        ResultKeys::with_decryption_key(network, KeyPair::from(sk))
+            .map(Some)
+            .ok_or(StateFileError::MissingVerifyingKeys)
+    }
+
+    /// Returns the IPA configuration of this query.
+    ///
+    /// ## Errors
+    /// If this is not an IPA query.
+    pub fn ipa_config(&self) -> Result<IpaQueryConfig, StateFileError> {
+        match self.config.query_type {
+            QueryType::OprfIpa(config) => Ok(config),
+            other => Err(StateFileError::NotIpa(other)),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        // Serializing plain data into a string can't fail.
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use hyper::http::uri::Scheme;
+    use rand::thread_rng;
+
+    use super::{DetachedQuery, ResultKeys, StateFileError};
+    use crate::{
+        config::{ClientConfig, NetworkConfig, PeerConfig},
+        ff::FieldType,
+        helpers::{
+            query::{IpaQueryConfig, QueryConfig, QueryType},
+            StreamCompression,
+        },
+        hpke::{ResultSigningKey, ResultVerifyingKey},
+        protocol::QueryId,
+    };
+
+    fn network_with_result_keys() -> NetworkConfig {
+        let mut rng = thread_rng();
+        let peer = |port: u16, vk: ResultVerifyingKey| {
+            let mut peer = PeerConfig::new(format!("localhost:{port}").parse().unwrap(), None);
+            peer.result_verifying_key = Some(vk);
+            peer
+        };
+        let mut vk = || ResultSigningKey::gen(&mut rng).verifying_key();
+        NetworkConfig::new(
+            [peer(3000, vk()), peer(3001, vk()), peer(3002, vk())],
+            ClientConfig::default(),
+        )
+    }
+
+    fn ipa_config(size: u32) -> QueryConfig {
+        QueryConfig::new(
+            QueryType::OprfIpa(IpaQueryConfig::default()),
+            FieldType::Fp32BitPrime,
+            size,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("query.json");
+        let network = network_with_result_keys();
+        let result_keys = ResultKeys::generate(&network, &mut thread_rng()).unwrap();
+
+        let config = ipa_config(100)
+            .with_result_key(result_keys.encryption_key())
+            .with_compression(StreamCompression::Packed)
+            .with_proof_size(NonZeroUsize::new(1 << 17).unwrap())
+            .unwrap();
+        let mut query =
+            DetachedQuery::new(QueryId, config, None, &Scheme::HTTP, Some(&result_keys));
+        query.create(&path).unwrap();
+        assert!(matches!(
+            query.create(&path),
+            Err(StateFileError::Io { .. })
+        ));
+
+        query.inputs_uploaded = true;
+        query.save(&path).unwrap();
+
+        let loaded = DetachedQuery::load(&path).unwrap();
+        assert_eq!(QueryId, loaded.query_id);
+        assert_eq!(config, loaded.config);
+        assert_eq!(IpaQueryConfig::default(), loaded.ipa_config().unwrap());
+        assert_eq!(Scheme::HTTP, loaded.scheme());
+        assert!(loaded.inputs_uploaded);
+
+        let restored = loaded.result_keys(&network).unwrap().unwrap();
+        assert_eq!(
+            result_keys.encryption_key().to_string(),
+            restored.encryption_key().to_string()
+        );
+    }
+
+    #[test]
+    fn save_keeps_file_private() {
+        let dir = tempfile::tempdir().unwrap();
+        // `save` must not clobber a sibling file when the state file name ends with `.tmp`
+        let path = dir.path().join("query.tmp");
+        let query = DetachedQuery::new(QueryId, ipa_config(1), None, &Scheme::HTTP, None);
+        query.create(&path).unwrap();
+        query.save(&path).unwrap();
+        assert_eq!(
+            vec![path.clone()],
+            std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .collect::<Vec<_>>()
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+    }
+
+    #[test]
+    fn missing_verifying_keys() {
+        let network = network_with_result_keys();
+        let result_keys = ResultKeys::generate(&network, &mut thread_rng()).unwrap();
+        let query = DetachedQuery::new(
+            QueryId,
+            ipa_config(1).with_result_key(result_keys.encryption_key()),
+            None,
+            &Scheme::HTTPS,
+            Some(&result_keys),
+        );
+
+        let local = query.network_config().unwrap();
+        assert!(matches!(
+            query.result_keys(&local),
+            Err(StateFileError::MissingVerifyingKeys)
+        ));
+    }
+}
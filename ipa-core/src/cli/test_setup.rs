@@ -65,6 +65,8 @@ pub fn test_setup(args: TestSetupArgs) -> Result<(), BoxError> {
                 tls_expire_after: 365,
                 mk_public_key: args.output_dir.helper_mk_public_key(id),
                 mk_private_key: args.output_dir.helper_mk_private_key(id),
+                result_signing_key: Some(args.output_dir.helper_result_signing_key(id)),
+                result_verifying_key: Some(args.output_dir.helper_result_verifying_key(id)),
             };
 
             keygen(&keygen_args)?;
@@ -74,6 +76,7 @@ pub fn test_setup(args: TestSetupArgs) -> Result<(), BoxError> {
                 port,
                 tls_cert_file: keygen_args.tls_cert,
                 mk_public_key_file: keygen_args.mk_public_key,
+                result_verifying_key_file: keygen_args.result_verifying_key,
             })
         })
         .collect::<Result<Vec<_>, BoxError>>()?
@@ -4,12 +4,14 @@ use clap::Parser;
 use metrics_tracing_context::MetricsLayer;
 use tracing::{info, metadata::LevelFilter, Level};
 use tracing_subscriber::{
-    fmt, fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
+    filter::filter_fn, fmt, fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt,
+    EnvFilter, Layer,
 };
 
 use crate::{
     cli::{install_collector, metric_collector::CollectorHandle},
     error::set_global_panic_hook,
+    telemetry::trace::{SpanExporter, SPAN_TARGET},
 };
 
 #[derive(Debug, Parser)]
@@ -31,20 +33,46 @@ pub struct LoggingHandle {
 impl Verbosity {
     #[must_use]
     pub fn setup_logging(&self) -> LoggingHandle {
+        self.setup(!self.quiet, None)
+    }
+
+    /// Same as [`Self::setup_logging`], but lets the caller skip installing the metrics
+    /// collector, which is needed when the caller installs its own metrics recorder. If
+    /// `span_exporter` is provided, spans that belong to distributed traces are exported with it.
+    #[must_use]
+    pub fn setup_logging_with(
+        &self,
+        collect_metrics: bool,
+        span_exporter: Option<SpanExporter>,
+    ) -> LoggingHandle {
+        self.setup(collect_metrics && !self.quiet, span_exporter)
+    }
+
+    fn setup(&self, collect_metrics: bool, span_exporter: Option<SpanExporter>) -> LoggingHandle {
         let filter_layer = self.log_filter();
         let fmt_layer = fmt::layer()
             .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
             .with_ansi(std::io::stderr().is_terminal())
-            .with_writer(stderr);
+            .with_writer(stderr)
+            .with_filter(filter_fn(|metadata| metadata.target() != SPAN_TARGET));
+        let mut filter = self.log_filter();
+        if span_exporter.is_some() {
+            filter = filter.add_directive(
+                format!("{SPAN_TARGET}=info")
+                    .parse()
+                    .expect("valid filter directive"),
+            );
+        }
 
         tracing_subscriber::registry()
-            .with(self.log_filter())
+            .with(filter)
             .with(fmt_layer)
             .with(MetricsLayer::new())
+            .with(span_exporter)
             .init();
 
         let handle = LoggingHandle {
-            metrics_handle: (!self.quiet).then(install_collector),
+            metrics_handle: collect_metrics.then(install_collector),
         };
         set_global_panic_hook();
 
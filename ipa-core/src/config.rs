@@ -3,9 +3,9 @@ use std::{
     borrow::{Borrow, Cow},
     fmt::{Debug, Formatter},
     iter::Zip,
-    path::PathBuf,
+    path::{Path, PathBuf},
     slice,
-    time::Duration,
+    time::{Duration, UNIX_EPOCH},
 };
 
 use hyper::{http::uri::Scheme, Uri};
@@ -19,9 +19,11 @@ use crate::{
     error::BoxError,
     helpers::HelperIdentity,
     hpke::{
-        Deserializable as _, IpaPrivateKey, IpaPublicKey, KeyRegistry, PrivateKeyOnly,
-        Serializable as _,
+        Deserializable as _, Info, InvalidInfoError, IpaPrivateKey, IpaPublicKey, KeyRegistry,
+        PrivateKeyOnly, ResultVerifyingKey, RotatingKeyRegistry, ScheduledKey, Serializable as _,
+        Validity,
     },
+    report::{KeyIdentifier, DEFAULT_HELPER_ORIGIN, DEFAULT_KEY_ID},
 };
 
 pub type OwnedCertificate = CertificateDer<'static>;
@@ -35,6 +37,8 @@ pub enum Error {
     InvalidUri(#[from] hyper::http::uri::InvalidUri),
     #[error(transparent)]
     IOError(#[from] std::io::Error),
+    #[error(transparent)]
+    InvalidHelperOrigin(#[from] InvalidInfoError),
 }
 
 /// Configuration information describing a helper network.
@@ -51,6 +55,12 @@ pub struct NetworkConfig {
     /// HTTP client configuration.
     #[serde(default)]
     pub client: ClientConfig,
+
+    /// Origin of this helper network. It is authenticated as part of the match key encryption,
+    /// so report collectors must use the same value when encrypting reports for this network.
+    /// Helper networks that use different origins can't decrypt each other's reports.
+    #[serde(default = "default_helper_origin")]
+    pub helper_origin: String,
 }
 
 impl NetworkConfig {
@@ -66,12 +76,17 @@ impl NetworkConfig {
             .add_source(File::from_str(input, FileFormat::Toml))
             .build()?
             .try_deserialize()?;
+        Info::validate_helper_origin(&conf.helper_origin)?;
 
         Ok(conf)
     }
 
     pub fn new(peers: [PeerConfig; 3], client: ClientConfig) -> Self {
-        Self { peers, client }
+        Self {
+            peers,
+            client,
+            helper_origin: default_helper_origin(),
+        }
     }
 
     pub fn peers(&self) -> &[PeerConfig; 3] {
@@ -108,6 +123,10 @@ impl NetworkConfig {
     }
 }
 
+fn default_helper_origin() -> String {
+    DEFAULT_HELPER_ORIGIN.to_string()
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct PeerConfig {
     /// Peer URL
@@ -128,6 +147,11 @@ pub struct PeerConfig {
     /// Match key encryption configuration.
     #[serde(default, rename = "hpke")]
     pub hpke_config: Option<HpkeClientConfig>,
+
+    /// Key that peer uses to sign query results. If all peers specify it, report collectors
+    /// ask helpers to encrypt and sign query results.
+    #[serde(default)]
+    pub result_verifying_key: Option<ResultVerifyingKey>,
 }
 
 impl PeerConfig {
@@ -136,6 +160,7 @@ impl PeerConfig {
             url,
             certificate,
             hpke_config: None,
+            result_verifying_key: None,
         }
     }
 }
@@ -146,12 +171,18 @@ impl PeerConfig {
 pub struct HpkeClientConfig {
     #[serde(deserialize_with = "pk_from_str")]
     pub public_key: IpaPublicKey,
+
+    /// Identifier of the public key. Helpers use it to find the matching private key when
+    /// they rotate keys, see [`RotatingKeyRegistry`].
+    #[serde(default = "default_key_id")]
+    pub key_id: KeyIdentifier,
 }
 
 impl Debug for HpkeClientConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("HpkeClientConfig")
             .field("public_key", &pk_to_str(&self.public_key))
+            .field("key_id", &self.key_id)
             .finish()
     }
 }
@@ -159,10 +190,17 @@ impl Debug for HpkeClientConfig {
 impl HpkeClientConfig {
     #[must_use]
     pub fn new(public_key: IpaPublicKey) -> Self {
-        Self { public_key }
+        Self {
+            public_key,
+            key_id: DEFAULT_KEY_ID,
+        }
     }
 }
 
+fn default_key_id() -> KeyIdentifier {
+    DEFAULT_KEY_ID
+}
+
 /// Reads a Certificate in PEM format using Serde Serialization
 fn certificate_from_pem<'de, D>(deserializer: D) -> Result<Option<OwnedCertificate>, D::Error>
 where
@@ -223,28 +261,112 @@ pub enum HpkeServerConfig {
         // Private key in hex format
         private_key: String,
     },
+    /// Directory with multiple private keys described by the [`KEY_MANIFEST_FILE`] manifest.
+    /// Keys stored there can be rotated while the helper is running.
+    Directory {
+        /// Path to the directory containing the key manifest
+        key_dir: PathBuf,
+    },
+}
+
+/// Name of the manifest file inside the key directory. The manifest lists all private keys
+/// that helper can use to decrypt match keys, for example:
+///
+/// ```toml
+/// [[keys]]
+/// id = 1
+/// private_key_file = "mk_1.key"
+/// not_after = 1735689600
+///
+/// [[keys]]
+/// id = 2
+/// private_key_file = "mk_2.key"
+/// not_before = 1733011200
+/// ```
+///
+/// Key files are resolved relative to the key directory. Validity bounds are optional and
+/// expressed in seconds since the Unix epoch. Helpers only check the manifest modification time
+/// to detect changes, so new key files must be in place before the manifest is updated.
+pub const KEY_MANIFEST_FILE: &str = "manifest.toml";
+
+#[derive(Debug, Deserialize)]
+struct KeyManifest {
+    #[serde(default)]
+    keys: Vec<KeyManifestEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyManifestEntry {
+    id: KeyIdentifier,
+    private_key_file: PathBuf,
+    #[serde(default)]
+    not_before: Option<u64>,
+    #[serde(default)]
+    not_after: Option<u64>,
+}
+
+fn sk_from_str(sk_str: &str) -> Result<IpaPrivateKey, BoxError> {
+    let sk = hex::decode(sk_str.trim())?;
+
+    Ok(IpaPrivateKey::from_bytes(&sk)?)
 }
 
 /// # Errors
 /// If there is a problem with the HPKE configuration.
 pub async fn hpke_registry(
     config: Option<&HpkeServerConfig>,
-) -> Result<KeyRegistry<PrivateKeyOnly>, BoxError> {
+) -> Result<RotatingKeyRegistry, BoxError> {
     let sk_str = match config {
-        None => return Ok(KeyRegistry::<PrivateKeyOnly>::empty()),
-        Some(HpkeServerConfig::Inline { private_key }) => {
-            Cow::Borrowed(private_key.trim().as_bytes())
-        }
+        None => return Ok(RotatingKeyRegistry::empty()),
+        Some(HpkeServerConfig::Inline { private_key }) => Cow::Borrowed(private_key.as_str()),
         Some(HpkeServerConfig::File { private_key_file }) => {
-            Cow::Owned(fs::read_to_string(private_key_file).await?.trim().into())
+            Cow::Owned(fs::read_to_string(private_key_file).await?)
+        }
+        Some(HpkeServerConfig::Directory { key_dir }) => {
+            return Ok(RotatingKeyRegistry::new(load_key_dir(key_dir).await?)?)
         }
     };
 
-    let sk = hex::decode(sk_str)?;
+    Ok(KeyRegistry::from_keys([PrivateKeyOnly(sk_from_str(&sk_str)?)]).into())
+}
+
+/// Reads all private keys listed in the [`KEY_MANIFEST_FILE`] inside `key_dir`.
+///
+/// # Errors
+/// If the manifest or any of the key files can't be read or parsed.
+pub async fn load_key_dir(key_dir: &Path) -> Result<Vec<ScheduledKey>, BoxError> {
+    use config::{Config, File, FileFormat};
+
+    let manifest_path = key_dir.join(KEY_MANIFEST_FILE);
+    let manifest = fs::read_to_string(&manifest_path)
+        .await
+        .map_err(|e| format!("failed to read {}: {e}", manifest_path.display()))?;
+    let manifest: KeyManifest = Config::builder()
+        .add_source(File::from_str(&manifest, FileFormat::Toml))
+        .build()?
+        .try_deserialize()?;
+
+    let mut keys = Vec::with_capacity(manifest.keys.len());
+    for entry in manifest.keys {
+        let key_path = key_dir.join(&entry.private_key_file);
+        let sk_str = fs::read_to_string(&key_path)
+            .await
+            .map_err(|e| format!("failed to read {}: {e}", key_path.display()))?;
+        keys.push(ScheduledKey {
+            key_id: entry.id,
+            private_key: sk_from_str(&sk_str)?,
+            validity: Validity {
+                not_before: entry
+                    .not_before
+                    .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                not_after: entry
+                    .not_after
+                    .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            },
+        });
+    }
 
-    Ok(KeyRegistry::from_keys([PrivateKeyOnly(
-        IpaPrivateKey::from_bytes(&sk)?,
-    )]))
+    Ok(keys)
 }
 
 /// Configuration information for launching an instance of the helper party web service.
@@ -270,6 +392,10 @@ pub trait HyperClientConfigurator {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientConfig {
     pub http_config: HttpClientConfigurator,
+    /// Controls how step streams exchanged with other helpers recover from connection failures
+    /// and how much of their data is buffered.
+    #[serde(default)]
+    pub stream_retry: StreamRetryConfig,
 }
 
 impl Default for ClientConfig {
@@ -288,6 +414,7 @@ impl ClientConfig {
     pub fn configure_http2(conf: Http2Configurator) -> Self {
         Self {
             http_config: HttpClientConfigurator::Http2(conf),
+            stream_retry: StreamRetryConfig::default(),
         }
     }
 
@@ -295,6 +422,53 @@ impl ClientConfig {
     pub fn use_http1() -> Self {
         Self {
             http_config: HttpClientConfigurator::http1(),
+            stream_retry: StreamRetryConfig::default(),
+        }
+    }
+}
+
+/// Retry budget and buffer limits for step streams exchanged with other helpers.
+///
+/// Helpers acknowledge the step data they receive. The sender keeps the data that has not been
+/// acknowledged yet and, if the connection fails, reconnects and resumes the stream from the
+/// first byte that was not acknowledged. The query fails only when the budget is exhausted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamRetryConfig {
+    /// How many times in a row the sender reconnects without getting any data through before it
+    /// gives up. Setting it to zero disables reconnects.
+    pub max_retries: u32,
+    /// Delay before the first reconnect attempt. It is doubled after every failed attempt.
+    #[serde(
+        rename = "initial_backoff_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs"
+    )]
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between reconnect attempts.
+    #[serde(
+        rename = "max_backoff_secs",
+        serialize_with = "crate::serde::duration::to_secs",
+        deserialize_with = "crate::serde::duration::from_secs"
+    )]
+    pub max_backoff: Duration,
+    /// How much data, in bytes, the sender keeps for a stream while waiting for the receiver to
+    /// acknowledge it. Sending stops when this limit is reached until acknowledgements arrive.
+    pub max_unacked_bytes: usize,
+    /// How much data, in bytes, a helper buffers for each step stream it receives until the
+    /// protocol reads it. The helper stops reading and acknowledging the stream while this limit
+    /// is reached.
+    pub max_buffered_bytes: usize,
+}
+
+impl Default for StreamRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            max_unacked_bytes: 16 * 1024 * 1024,
+            max_buffered_bytes: 1024 * 1024,
         }
     }
 }
@@ -416,7 +590,7 @@ impl Debug for Http2Configurator {
 
 #[cfg(all(test, unit_test))]
 mod tests {
-    use std::time::Duration;
+    use std::time::{Duration, UNIX_EPOCH};
 
     use hpke::{kem::X25519HkdfSha256, Kem};
     use hyper::Uri;
@@ -424,9 +598,15 @@ mod tests {
     use rand_core::SeedableRng;
 
     use crate::{
-        config::{ClientConfig, HpkeClientConfig, Http2Configurator, HttpClientConfigurator},
+        config::{
+            hpke_registry, ClientConfig, Error, HpkeClientConfig, HpkeServerConfig,
+            Http2Configurator, HttpClientConfigurator, NetworkConfig, StreamRetryConfig,
+            KEY_MANIFEST_FILE,
+        },
         helpers::HelperIdentity,
+        hpke::{KeyPair, PrivateKeyRegistry, ResultSigningKey},
         net::test::TestConfigBuilder,
+        report::DEFAULT_HELPER_ORIGIN,
     };
 
     const URI_1: &str = "http://localhost:3000";
@@ -453,12 +633,62 @@ mod tests {
         assert_eq!(value3.url, uri3);
     }
 
+    #[test]
+    fn helper_origin() {
+        const PEERS: &str = r#"
+            [[peers]]
+            url = "localhost:3000"
+
+            [[peers]]
+            url = "localhost:3001"
+
+            [[peers]]
+            url = "localhost:3002"
+        "#;
+
+        let conf = NetworkConfig::from_toml_str(PEERS).unwrap();
+        assert_eq!(DEFAULT_HELPER_ORIGIN, conf.helper_origin);
+
+        let conf =
+            NetworkConfig::from_toml_str(&format!("helper_origin = \"helpers.example\"\n{PEERS}"))
+                .unwrap();
+        assert_eq!("helpers.example", conf.helper_origin);
+
+        assert!(matches!(
+            NetworkConfig::from_toml_str(&format!("helper_origin = \"\"\n{PEERS}")),
+            Err(Error::InvalidHelperOrigin(_))
+        ));
+    }
+
+    #[test]
+    fn result_verifying_key() {
+        let vk = ResultSigningKey::gen(&mut StdRng::seed_from_u64(1)).verifying_key();
+        let conf = NetworkConfig::from_toml_str(&format!(
+            r#"
+            [[peers]]
+            url = "localhost:3000"
+            result_verifying_key = "{vk}"
+
+            [[peers]]
+            url = "localhost:3001"
+
+            [[peers]]
+            url = "localhost:3002"
+        "#
+        ))
+        .unwrap();
+
+        let [p1, p2, _] = conf.peers();
+        assert_eq!(Some(vk), p1.result_verifying_key);
+        assert_eq!(None, p2.result_verifying_key);
+    }
+
     #[test]
     fn debug_hpke_client_config() {
         let mut rng = StdRng::seed_from_u64(1);
         let (_, public_key) = X25519HkdfSha256::gen_keypair(&mut rng);
-        let config = HpkeClientConfig { public_key };
-        assert_eq!(format!("{config:?}"), "HpkeClientConfig { public_key: \"2bd9da78f01d8bc6948bbcbe44ec1e7163d05083e267d110cdb2e75d847e3b6f\" }");
+        let config = HpkeClientConfig::new(public_key);
+        assert_eq!(format!("{config:?}"), "HpkeClientConfig { public_key: \"2bd9da78f01d8bc6948bbcbe44ec1e7163d05083e267d110cdb2e75d847e3b6f\", key_id: 0 }");
     }
 
     #[test]
@@ -466,6 +696,7 @@ mod tests {
         fn assert_config_eq(config_str: &str, expected: &ClientConfig) {
             let actual: ClientConfig = serde_json::from_str(config_str).unwrap();
 
+            assert_eq!(expected.stream_retry, actual.stream_retry);
             match (&expected.http_config, &actual.http_config) {
                 (HttpClientConfigurator::Http2(left), HttpClientConfigurator::Http2(right)) => {
                     assert_eq!(left, right);
@@ -500,5 +731,76 @@ mod tests {
                 ping_interval: Some(Duration::from_secs(132)),
             }),
         );
+        assert_config_eq(
+            r#"{ "http_config": { "version": "http1" }, "stream_retry": { "max_retries": 2, "max_backoff_secs": 0.5, "max_buffered_bytes": 65536 } }"#,
+            &ClientConfig {
+                stream_retry: StreamRetryConfig {
+                    max_retries: 2,
+                    max_backoff: Duration::from_millis(500),
+                    max_buffered_bytes: 65536,
+                    ..StreamRetryConfig::default()
+                },
+                ..ClientConfig::use_http1()
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn hpke_key_dir() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let key_dir = tempfile::tempdir().unwrap();
+        for name in ["mk_1.key", "mk_5.key"] {
+            let keypair = KeyPair::gen(&mut rng);
+            std::fs::write(key_dir.path().join(name), hex::encode(keypair.sk_bytes())).unwrap();
+        }
+        std::fs::write(
+            key_dir.path().join(KEY_MANIFEST_FILE),
+            r#"
+            [[keys]]
+            id = 1
+            private_key_file = "mk_1.key"
+            not_after = 100
+
+            [[keys]]
+            id = 5
+            private_key_file = "mk_5.key"
+            not_before = 50
+            "#,
+        )
+        .unwrap();
+
+        let registry = hpke_registry(Some(&HpkeServerConfig::Directory {
+            key_dir: key_dir.path().to_path_buf(),
+        }))
+        .await
+        .unwrap();
+
+        assert_eq!(vec![1, 5], registry.key_ids());
+        let snapshot = registry.snapshot(UNIX_EPOCH + Duration::from_secs(75));
+        assert!(snapshot.private_key(1).is_some());
+        assert!(snapshot.private_key(5).is_some());
+        let snapshot = registry.snapshot(UNIX_EPOCH + Duration::from_secs(100));
+        assert!(snapshot.private_key(1).is_none());
+        assert!(snapshot.private_key(5).is_some());
+    }
+
+    #[tokio::test]
+    async fn hpke_key_dir_missing_key_file() {
+        let key_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            key_dir.path().join(KEY_MANIFEST_FILE),
+            r#"
+            [[keys]]
+            id = 0
+            private_key_file = "does_not_exist.key"
+            "#,
+        )
+        .unwrap();
+
+        hpke_registry(Some(&HpkeServerConfig::Directory {
+            key_dir: key_dir.path().to_path_buf(),
+        }))
+        .await
+        .unwrap_err();
     }
 }
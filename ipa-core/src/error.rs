@@ -8,6 +8,8 @@ use thiserror::Error;
 
 use crate::{
     helpers::{Role, ZeroRecordsError},
+    hpke::CryptError,
+    protocol::context::dzkp_validator::MAX_PROOF_BATCHES,
     report::InvalidReportError,
     sharding::ShardIndex,
     task::JoinError,
@@ -80,8 +82,15 @@ pub enum Error {
     /// error (above, possibly with additional detail in the future), and the rest get this error.
     #[error("Parallel DZKP Validation failed")]
     ParallelDZKPValidationFailed,
+    #[error(
+        "DZKP validator can generate at most {} proofs, proof {0} was requested",
+        MAX_PROOF_BATCHES
+    )]
+    TooManyProofBatches(usize),
     #[error("Inconsistent shares")]
     InconsistentShares,
+    #[error("Shuffle verification failed: {0}")]
+    ShuffleValidationFailed(String),
     #[error("The Masks cannot be set safely, i.e. without deleting non-zero field elements")]
     DZKPMasks,
     #[error("Attempt to operate on zero records")]
@@ -90,6 +99,8 @@ pub enum Error {
     EpsilonOutOfBounds,
     #[error("Missing total records in {0}")]
     MissingTotalRecords(String),
+    #[error("failed to seal query results: {0}")]
+    ResultSealing(CryptError),
 }
 
 impl Default for Error {
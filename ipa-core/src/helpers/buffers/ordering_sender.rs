@@ -439,6 +439,19 @@ impl OrderingSender {
 
         waiting_indices
     }
+
+    /// Returns the number of bytes buffered and not yet taken by the stream.
+    ///
+    /// ## Panics
+    /// If state mutex is poisoned.
+    #[cfg(feature = "stall-detection")]
+    pub fn occupancy(&self) -> crate::helpers::BufferOccupancy {
+        let state = self.state.lock().unwrap();
+        crate::helpers::BufferOccupancy {
+            used: state.buf.len(),
+            capacity: state.buf.capacity(),
+        }
+    }
 }
 
 /// A future for writing item `i` into an `OrderingSender`.
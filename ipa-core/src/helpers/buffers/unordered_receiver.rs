@@ -350,6 +350,20 @@ where
 
         r
     }
+
+    /// Returns the number of outstanding reads, relative to the number of reads this
+    /// receiver can track without overflowing.
+    ///
+    /// ## Panics
+    /// If state mutex is poisoned.
+    #[cfg(feature = "stall-detection")]
+    pub fn occupancy(&self) -> crate::helpers::BufferOccupancy {
+        let state = self.inner.lock().unwrap();
+        crate::helpers::BufferOccupancy {
+            used: state.wakers.iter().filter(|w| w.is_some()).count() + state.overflow_wakers.len(),
+            capacity: state.wakers.len(),
+        }
+    }
 }
 
 impl<S, C> Clone for UnorderedReceiver<S, C>
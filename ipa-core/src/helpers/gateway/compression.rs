@@ -0,0 +1,374 @@
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::error::BoxError;
+
+/// Compression of record streams sent between helpers. It is set per query and applies to all
+/// the channels opened by it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum StreamCompression {
+    /// Batches of records are sent as they are.
+    #[default]
+    None,
+    /// Every batch of records is encoded with the most compact of the built-in codecs: one bit
+    /// per byte for batches that only carry zeros and ones (bit-decomposed values), and run-length
+    /// encoding of zeros for padded data. Batches that do not compress are sent as they are.
+    Packed,
+}
+
+impl StreamCompression {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Packed => "packed",
+        }
+    }
+
+    /// Encodes a batch of records for sending.
+    pub(super) fn encode(self, batch: Vec<u8>) -> Vec<u8> {
+        match self {
+            Self::None => batch,
+            Self::Packed => packed::encode(&batch),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("unknown codec {0}")]
+    UnknownCodec(u8),
+    #[error("frame is corrupted: {0}")]
+    Corrupted(&'static str),
+    #[error("stream ended in the middle of a frame")]
+    Truncated,
+}
+
+/// Decodes a stream of records encoded with [`StreamCompression`].
+pub struct Decoder<S> {
+    inner: S,
+    compression: StreamCompression,
+    max_batch_len: usize,
+    buf: BytesMut,
+    done: bool,
+}
+
+impl<S> Decoder<S> {
+    /// Frames that claim to decode to more than `max_batch_len` bytes are rejected before
+    /// anything is allocated for them. It must be at least the size of the largest batch the
+    /// sender can produce, which is the capacity of its send buffer.
+    pub fn new(inner: S, compression: StreamCompression, max_batch_len: usize) -> Self {
+        Self {
+            inner,
+            compression,
+            max_batch_len,
+            buf: BytesMut::new(),
+            done: false,
+        }
+    }
+}
+
+impl<S: Stream<Item = Result<Bytes, BoxError>> + Unpin> Stream for Decoder<S> {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.compression == StreamCompression::None {
+            return this.inner.poll_next_unpin(cx);
+        }
+
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            match packed::decode(&mut this.buf, this.max_batch_len) {
+                Ok(Some(batch)) => return Poll::Ready(Some(Ok(batch.into()))),
+                Ok(None) => {}
+                Err(e) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+            }
+            match ready!(this.inner.poll_next_unpin(cx)) {
+                Some(Ok(bytes)) => this.buf.extend_from_slice(&bytes),
+                Some(Err(e)) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                None => {
+                    this.done = true;
+                    if !this.buf.is_empty() {
+                        return Poll::Ready(Some(Err(DecodeError::Truncated.into())));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Frame format: codec tag, length of the decoded batch and length of the payload, both as
+/// LEB128 varints, followed by the payload.
+mod packed {
+    use bytes::{Buf, BytesMut};
+
+    use super::DecodeError;
+
+    const RAW: u8 = 0;
+    const BITS: u8 = 1;
+    const ZERO_RUNS: u8 = 2;
+    /// Number of bytes needed to encode any 64 bit value as a varint.
+    const MAX_VARINT_LEN: usize = 10;
+
+    pub fn encode(batch: &[u8]) -> Vec<u8> {
+        let (codec, payload) = if batch.iter().all(|&b| b <= 1) {
+            (BITS, pack_bits(batch))
+        } else {
+            let runs = encode_zero_runs(batch);
+            if runs.len() < batch.len() {
+                (ZERO_RUNS, runs)
+            } else {
+                (RAW, batch.to_vec())
+            }
+        };
+
+        let mut frame = Vec::with_capacity(payload.len() + 11);
+        frame.push(codec);
+        write_varint(&mut frame, batch.len());
+        write_varint(&mut frame, payload.len());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    /// Takes the next frame from `buf` and decodes it. Returns `None` if the frame is not
+    /// complete yet. Frames that decode to more than `max_len` bytes are rejected.
+    pub fn decode(buf: &mut BytesMut, max_len: usize) -> Result<Option<Vec<u8>>, DecodeError> {
+        let Some((&codec, mut rest)) = buf.split_first() else {
+            return Ok(None);
+        };
+        let Some(len) = read_varint(&mut rest)? else {
+            return Ok(None);
+        };
+        if len > max_len {
+            return Err(DecodeError::Corrupted(
+                "batch is larger than the send buffer",
+            ));
+        }
+        let Some(payload_len) = read_varint(&mut rest)? else {
+            return Ok(None);
+        };
+        if rest.len() < payload_len {
+            return Ok(None);
+        }
+        let header_len = buf.len() - rest.len();
+        let payload = &rest[..payload_len];
+
+        let batch = match codec {
+            RAW if payload.len() == len => payload.to_vec(),
+            RAW => return Err(DecodeError::Corrupted("raw batch length mismatch")),
+            BITS => unpack_bits(payload, len)?,
+            ZERO_RUNS => decode_zero_runs(payload, len)?,
+            other => return Err(DecodeError::UnknownCodec(other)),
+        };
+        buf.advance(header_len + payload_len);
+
+        Ok(Some(batch))
+    }
+
+    fn pack_bits(batch: &[u8]) -> Vec<u8> {
+        let mut packed = vec![0; batch.len().div_ceil(8)];
+        for (i, &bit) in batch.iter().enumerate() {
+            packed[i / 8] |= bit << (i % 8);
+        }
+        packed
+    }
+
+    fn unpack_bits(packed: &[u8], len: usize) -> Result<Vec<u8>, DecodeError> {
+        if packed.len() != len.div_ceil(8) {
+            return Err(DecodeError::Corrupted("bit-packed batch length mismatch"));
+        }
+        Ok((0..len).map(|i| (packed[i / 8] >> (i % 8)) & 1).collect())
+    }
+
+    /// Encodes the batch as a sequence of `(zeros, literals)` runs. Each run is the number of
+    /// zeros, the number of literal bytes that follow them, and the literal bytes.
+    fn encode_zero_runs(batch: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let mut rest = batch;
+        while !rest.is_empty() {
+            let zeros = rest.iter().take_while(|&&b| b == 0).count();
+            let literals = rest[zeros..].iter().take_while(|&&b| b != 0).count();
+            write_varint(&mut encoded, zeros);
+            write_varint(&mut encoded, literals);
+            encoded.extend_from_slice(&rest[zeros..zeros + literals]);
+            rest = &rest[zeros + literals..];
+        }
+        encoded
+    }
+
+    fn decode_zero_runs(mut encoded: &[u8], len: usize) -> Result<Vec<u8>, DecodeError> {
+        let mut batch = Vec::with_capacity(len);
+        while !encoded.is_empty() {
+            let zeros = read_varint(&mut encoded)?.ok_or(DecodeError::Corrupted("zero run"))?;
+            let literals =
+                read_varint(&mut encoded)?.ok_or(DecodeError::Corrupted("literal run"))?;
+            if batch.len().saturating_add(zeros).saturating_add(literals) > len
+                || encoded.len() < literals
+            {
+                return Err(DecodeError::Corrupted("run exceeds batch length"));
+            }
+            batch.resize(batch.len() + zeros, 0);
+            batch.extend_from_slice(&encoded[..literals]);
+            encoded = &encoded[literals..];
+        }
+        if batch.len() != len {
+            return Err(DecodeError::Corrupted("run-length batch length mismatch"));
+        }
+        Ok(batch)
+    }
+
+    fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
+        while value >= 0x80 {
+            buf.push(u8::try_from(value & 0x7f).unwrap() | 0x80);
+            value >>= 7;
+        }
+        buf.push(u8::try_from(value).unwrap());
+    }
+
+    /// Reads a varint from the front of `buf`. Returns `None` if `buf` ends before the varint
+    /// does.
+    fn read_varint(buf: &mut &[u8]) -> Result<Option<usize>, DecodeError> {
+        let mut value = 0_usize;
+        for (i, &byte) in buf.iter().enumerate() {
+            if i == MAX_VARINT_LEN {
+                return Err(DecodeError::Corrupted("varint is too long"));
+            }
+            value |= usize::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                *buf = &buf[i + 1..];
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    #[cfg(all(test, unit_test))]
+    mod tests {
+        use bytes::BytesMut;
+
+        use super::{decode, encode, write_varint, DecodeError, BITS, RAW, ZERO_RUNS};
+
+        fn round_trip(batch: &[u8]) -> u8 {
+            let frame = encode(batch);
+            let mut buf = BytesMut::from(&frame[..]);
+            // incomplete frames are not decoded
+            assert_eq!(
+                None,
+                decode(&mut BytesMut::from(&frame[..frame.len() - 1]), batch.len()).unwrap()
+            );
+            assert_eq!(Some(batch.to_vec()), decode(&mut buf, batch.len()).unwrap());
+            assert!(buf.is_empty());
+            frame[0]
+        }
+
+        #[test]
+        fn bits() {
+            let batch = (0..1000).map(|i| u8::from(i % 3 == 0)).collect::<Vec<_>>();
+            assert_eq!(BITS, round_trip(&batch));
+            assert!(encode(&batch).len() < batch.len() / 7);
+        }
+
+        #[test]
+        fn zero_runs() {
+            let mut batch = vec![0; 300];
+            batch.extend([7, 8, 9]);
+            batch.extend([0; 200]);
+            batch.push(10);
+            assert_eq!(ZERO_RUNS, round_trip(&batch));
+            assert!(encode(&batch).len() < 20);
+        }
+
+        #[test]
+        fn incompressible() {
+            let batch = (1..=255).collect::<Vec<u8>>();
+            assert_eq!(RAW, round_trip(&batch));
+        }
+
+        #[test]
+        fn corrupted() {
+            let mut frame = encode(&[1, 0, 1]);
+            frame[0] = 42;
+            assert!(decode(&mut BytesMut::from(&frame[..]), 3).is_err());
+        }
+
+        #[test]
+        fn oversized() {
+            // a few bytes of zero runs claim a huge batch
+            let mut frame = vec![ZERO_RUNS];
+            write_varint(&mut frame, usize::MAX >> 1);
+            write_varint(&mut frame, 10);
+            write_varint(&mut frame, usize::MAX >> 1);
+            write_varint(&mut frame, 0);
+            assert!(matches!(
+                decode(&mut BytesMut::from(&frame[..]), 1024),
+                Err(DecodeError::Corrupted(_))
+            ));
+
+            let frame = encode(&[0; 100]);
+            assert!(decode(&mut BytesMut::from(&frame[..]), 99).is_err());
+            assert_eq!(
+                Some(vec![0; 100]),
+                decode(&mut BytesMut::from(&frame[..]), 100).unwrap()
+            );
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use bytes::Bytes;
+    use futures::{executor::block_on, stream, StreamExt, TryStreamExt};
+
+    use super::{Decoder, StreamCompression};
+    use crate::error::BoxError;
+
+    #[test]
+    fn decodes_frames_split_across_chunks() {
+        let batches = vec![vec![0, 1, 1, 0], vec![0; 64], vec![5, 6, 7]];
+        let encoded = batches
+            .iter()
+            .flat_map(|batch| StreamCompression::Packed.encode(batch.clone()))
+            .collect::<Vec<_>>();
+        // deliver the encoded stream one byte at a time
+        let chunks = encoded
+            .into_iter()
+            .map(|b| Ok::<_, BoxError>(Bytes::from(vec![b])));
+
+        let decoded = block_on(
+            Decoder::new(stream::iter(chunks), StreamCompression::Packed, 64)
+                .map_ok(|b| b.to_vec())
+                .try_collect::<Vec<_>>(),
+        )
+        .unwrap();
+        assert_eq!(batches, decoded);
+    }
+
+    #[test]
+    fn truncated_stream_fails() {
+        let mut frame = StreamCompression::Packed.encode(vec![5, 6, 7]);
+        frame.pop();
+        let mut decoder = Decoder::new(
+            stream::iter([Ok::<_, BoxError>(Bytes::from(frame))]),
+            StreamCompression::Packed,
+            3,
+        );
+        assert!(block_on(decoder.next()).unwrap().is_err());
+    }
+}
@@ -0,0 +1,379 @@
+//! Rolling digests of the records exchanged with other helpers, per channel.
+//!
+//! Every record sent to or received from another helper is added to the digest of its channel.
+//! When helpers compare digests, a channel where the sender's digest of sent records differs from
+//! the receiver's digest of received records points to the gate where their views of the protocol
+//! diverged, see [`first_mismatch`].
+//!
+//! Digests don't depend on the order records are sent or received in, so they can be compared
+//! while a query is still running. Receivers only account for records they have read, so channels
+//! that are still in use may show a mismatch in the number of records.
+//!
+//! Every record goes through a digest, so records are hashed with a cheap non-cryptographic hash
+//! and digests are only kept when helpers are configured to keep them.
+//! Digests are meant to find bugs, not to catch a helper that misbehaves on purpose: such a
+//! helper could report any digest it wants anyway.
+
+use std::fmt::{Display, Formatter};
+
+use dashmap::DashMap;
+use generic_array::GenericArray;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ff::Serializable,
+    helpers::{HelperChannelId, Role},
+    protocol::RecordId,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// Digests of all channels between this helper and other helpers. Cloning it is cheap, all clones
+/// share the same digests.
+#[derive(Clone)]
+pub struct ChannelDigests {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    role: Role,
+    /// Incremented every time a new channel is opened, to keep track of the order of channels.
+    next_seq: AtomicUsize,
+    senders: DashMap<HelperChannelId, Arc<RollingDigest>>,
+    receivers: DashMap<HelperChannelId, Arc<RollingDigest>>,
+}
+
+/// Order-independent digest of records in a single channel. Each record is hashed together with
+/// its record id and the hashes are summed up, separately for each lane.
+pub(super) struct RollingDigest {
+    seq: usize,
+    records: AtomicUsize,
+    lanes: [AtomicU64; 2],
+}
+
+impl RollingDigest {
+    fn new(seq: usize) -> Self {
+        Self {
+            seq,
+            records: AtomicUsize::default(),
+            lanes: [AtomicU64::new(0), AtomicU64::new(0)],
+        }
+    }
+
+    pub(super) fn add<M: Serializable>(&self, record_id: RecordId, msg: &M) {
+        let mut buf = GenericArray::default();
+        msg.serialize(&mut buf);
+        for (lane, seed) in self.lanes.iter().zip(LANE_SEEDS) {
+            lane.fetch_add(hash_record(seed, record_id, &buf), Ordering::Relaxed);
+        }
+        self.records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn report(&self, channel_id: &HelperChannelId) -> ChannelDigest {
+        let digest = self
+            .lanes
+            .iter()
+            .flat_map(|lane| lane.load(Ordering::Relaxed).to_le_bytes())
+            .collect::<Vec<_>>();
+        ChannelDigest {
+            gate: channel_id.gate.as_ref().to_string(),
+            peer: channel_id.peer,
+            seq: self.seq,
+            records: self.records.load(Ordering::Relaxed),
+            digest: hex::encode(digest),
+        }
+    }
+}
+
+/// Seeds that make the hashes of the lanes of [`RollingDigest`] independent.
+const LANE_SEEDS: [u64; 2] = [0x243f_6a88_85a3_08d3, 0x1319_8a2e_0370_7344];
+
+/// Finalizer of `splitmix64`, every input bit affects every output bit.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn hash_record(seed: u64, record_id: RecordId, bytes: &[u8]) -> u64 {
+    let mut hash = mix(seed ^ u64::from(u32::from(record_id)));
+    for chunk in bytes.chunks(8) {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        hash = mix(hash ^ u64::from_le_bytes(word));
+    }
+    mix(hash ^ bytes.len() as u64)
+}
+
+impl ChannelDigests {
+    #[must_use]
+    pub fn new(role: Role) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                role,
+                next_seq: AtomicUsize::default(),
+                senders: DashMap::default(),
+                receivers: DashMap::default(),
+            }),
+        }
+    }
+
+    /// Returns the digest of records sent through the given channel.
+    pub(super) fn sender(&self, channel_id: &HelperChannelId) -> Arc<RollingDigest> {
+        self.get(&self.inner.senders, channel_id)
+    }
+
+    /// Returns the digest of records received from the given channel.
+    pub(super) fn receiver(&self, channel_id: &HelperChannelId) -> Arc<RollingDigest> {
+        self.get(&self.inner.receivers, channel_id)
+    }
+
+    fn get(
+        &self,
+        channels: &DashMap<HelperChannelId, Arc<RollingDigest>>,
+        channel_id: &HelperChannelId,
+    ) -> Arc<RollingDigest> {
+        if let Some(digest) = channels.get(channel_id) {
+            return Arc::clone(&digest);
+        }
+        Arc::clone(&channels.entry(channel_id.clone()).or_insert_with(|| {
+            Arc::new(RollingDigest::new(
+                self.inner.next_seq.fetch_add(1, Ordering::Relaxed),
+            ))
+        }))
+    }
+
+    /// Returns the current digests of all channels.
+    #[must_use]
+    pub fn report(&self) -> DigestReport {
+        let collect = |channels: &DashMap<HelperChannelId, Arc<RollingDigest>>| {
+            let mut digests = channels
+                .iter()
+                .map(|entry| entry.value().report(entry.key()))
+                .collect::<Vec<_>>();
+            digests.sort_by_key(|d| d.seq);
+            digests
+        };
+
+        DigestReport {
+            role: self.inner.role,
+            sent: collect(&self.inner.senders),
+            received: collect(&self.inner.receivers),
+        }
+    }
+}
+
+/// Digests of all channels of one helper, in the order channels were opened.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestReport {
+    pub role: Role,
+    pub sent: Vec<ChannelDigest>,
+    pub received: Vec<ChannelDigest>,
+}
+
+/// Digest of records sent or received through a single channel.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelDigest {
+    pub gate: String,
+    /// Helper on the other side of the channel.
+    pub peer: Role,
+    /// Position of this channel in the order channels were opened by this helper.
+    pub seq: usize,
+    pub records: usize,
+    pub digest: String,
+}
+
+impl ChannelDigest {
+    fn matches(&self, other: &Self) -> bool {
+        self.records == other.records && self.digest == other.digest
+    }
+}
+
+/// A channel where the records sent by one helper differ from the records received by another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DigestMismatch {
+    pub gate: String,
+    pub sender: Role,
+    pub receiver: Role,
+    /// Digest of the sender, or `None` if it did not send anything on this channel.
+    pub sent: Option<ChannelDigest>,
+    /// Digest of the receiver, or `None` if it did not receive anything on this channel.
+    pub received: Option<ChannelDigest>,
+}
+
+impl Display for DigestMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let show = |d: &Option<ChannelDigest>| {
+            d.as_ref().map_or_else(
+                || "nothing".to_string(),
+                |d| format!("{} records ({})", d.records, d.digest),
+            )
+        };
+        write!(
+            f,
+            "{}: {:?} sent {} to {:?}, which received {}",
+            self.gate,
+            self.sender,
+            show(&self.sent),
+            self.receiver,
+            show(&self.received)
+        )
+    }
+}
+
+/// Compares digests reported by helpers and returns the first channel where the sender and the
+/// receiver disagree. Channels are ordered by the time they were opened by the helper that
+/// reported the mismatching digest. Channels between helpers that are not in `reports` are
+/// ignored.
+#[must_use]
+pub fn first_mismatch(reports: &[DigestReport]) -> Option<DigestMismatch> {
+    let find = |role: Role| reports.iter().find(|r| r.role == role);
+    let lookup = |digests: &[ChannelDigest], gate: &str, peer: Role| {
+        digests
+            .iter()
+            .find(|d| d.gate == gate && d.peer == peer)
+            .cloned()
+    };
+
+    let mut mismatches = Vec::new();
+    for report in reports {
+        for sent in &report.sent {
+            let Some(receiver) = find(sent.peer) else {
+                continue;
+            };
+            let received = lookup(&receiver.received, &sent.gate, report.role);
+            if !received.as_ref().is_some_and(|r| r.matches(sent)) {
+                mismatches.push((
+                    sent.seq,
+                    DigestMismatch {
+                        gate: sent.gate.clone(),
+                        sender: report.role,
+                        receiver: sent.peer,
+                        sent: Some(sent.clone()),
+                        received,
+                    },
+                ));
+            }
+        }
+        for received in &report.received {
+            let Some(sender) = find(received.peer) else {
+                continue;
+            };
+            if lookup(&sender.sent, &received.gate, report.role).is_none() {
+                mismatches.push((
+                    received.seq,
+                    DigestMismatch {
+                        gate: received.gate.clone(),
+                        sender: received.peer,
+                        receiver: report.role,
+                        sent: None,
+                        received: Some(received.clone()),
+                    },
+                ));
+            }
+        }
+    }
+
+    mismatches
+        .into_iter()
+        .min_by_key(|(seq, _)| *seq)
+        .map(|(_, mismatch)| mismatch)
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use super::{first_mismatch, ChannelDigests};
+    use crate::{
+        ff::{Fp31, U128Conversions},
+        helpers::{ChannelId, Role},
+        protocol::{Gate, RecordId},
+    };
+
+    fn exchange(
+        sender: &ChannelDigests,
+        receiver: &ChannelDigests,
+        gate: &str,
+        from: Role,
+        to: Role,
+        values: &[u128],
+    ) {
+        let tx = sender.sender(&ChannelId::new(to, Gate::from(gate)));
+        let rx = receiver.receiver(&ChannelId::new(from, Gate::from(gate)));
+        for (i, &v) in values.iter().enumerate() {
+            tx.add(RecordId::from(i), &Fp31::truncate_from(v));
+        }
+        // receivers may read records in any order
+        for (i, &v) in values.iter().enumerate().rev() {
+            rx.add(RecordId::from(i), &Fp31::truncate_from(v));
+        }
+    }
+
+    #[test]
+    fn matching_channels() {
+        let h1 = ChannelDigests::new(Role::H1);
+        let h2 = ChannelDigests::new(Role::H2);
+        exchange(&h1, &h2, "/a", Role::H1, Role::H2, &[1, 2, 3]);
+        exchange(&h2, &h1, "/b", Role::H2, Role::H1, &[4]);
+
+        let reports = [h1.report(), h2.report()];
+        assert_eq!(1, reports[0].sent.len());
+        assert_eq!(3, reports[0].sent[0].records);
+        assert_eq!(reports[0].sent[0].digest, reports[1].received[0].digest);
+        assert_eq!(None, first_mismatch(&reports));
+    }
+
+    #[test]
+    fn digest_depends_on_record_ids() {
+        let h1 = ChannelDigests::new(Role::H1);
+        let h2 = ChannelDigests::new(Role::H2);
+        exchange(&h1, &h2, "/a", Role::H1, Role::H2, &[1, 2]);
+        exchange(&h1, &h2, "/b", Role::H1, Role::H2, &[2, 1]);
+
+        let report = h1.report();
+        assert_ne!(report.sent[0].digest, report.sent[1].digest);
+    }
+
+    #[test]
+    fn finds_first_mismatch() {
+        let h1 = ChannelDigests::new(Role::H1);
+        let h2 = ChannelDigests::new(Role::H2);
+        let h3 = ChannelDigests::new(Role::H3);
+        exchange(&h1, &h2, "/a", Role::H1, Role::H2, &[1, 2]);
+
+        // H2 sees a different record on /b than H3 sent
+        let tx = h3.sender(&ChannelId::new(Role::H2, Gate::from("/b")));
+        let rx = h2.receiver(&ChannelId::new(Role::H3, Gate::from("/b")));
+        tx.add(RecordId::FIRST, &Fp31::truncate_from(5_u128));
+        rx.add(RecordId::FIRST, &Fp31::truncate_from(6_u128));
+
+        // H1 sends on /c, but H3 never reads it
+        h1.sender(&ChannelId::new(Role::H3, Gate::from("/c")))
+            .add(RecordId::FIRST, &Fp31::truncate_from(1_u128));
+
+        let mismatch = first_mismatch(&[h1.report(), h2.report(), h3.report()]).unwrap();
+        assert_eq!("/b", mismatch.gate);
+        assert_eq!((Role::H3, Role::H2), (mismatch.sender, mismatch.receiver));
+        assert_ne!(
+            mismatch.sent.unwrap().digest,
+            mismatch.received.unwrap().digest
+        );
+
+        // without H2, the only remaining mismatch is the channel H3 did not read
+        let mismatch = first_mismatch(&[h1.report(), h3.report()]).unwrap();
+        assert_eq!("/c", mismatch.gate);
+        assert_eq!(None, mismatch.received);
+    }
+
+    #[test]
+    fn report_round_trip() {
+        let h1 = ChannelDigests::new(Role::H1);
+        let h2 = ChannelDigests::new(Role::H2);
+        exchange(&h1, &h2, "/a", Role::H1, Role::H2, &[1, 2, 3]);
+
+        let report = h1.report();
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(report, serde_json::from_str(&json).unwrap());
+    }
+}
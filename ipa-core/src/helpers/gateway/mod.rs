@@ -1,21 +1,32 @@
+mod compression;
+mod digest;
 mod receive;
 mod send;
 #[cfg(feature = "stall-detection")]
 pub(super) mod stall_detection;
+mod stall_report;
+mod transcript;
 mod transport;
 
 use std::num::NonZeroUsize;
 
+use typenum::Unsigned;
+
+pub use compression::StreamCompression;
+pub use digest::{first_mismatch, ChannelDigest, ChannelDigests, DigestMismatch, DigestReport};
 pub(super) use receive::{MpcReceivingEnd, ShardReceivingEnd};
 pub(super) use send::SendingEnd;
 #[cfg(feature = "stall-detection")]
 pub(super) use stall_detection::InstrumentedGateway;
+pub use stall_report::{BufferOccupancy, ChannelReport, StallReport};
+pub use transcript::{Divergence, RecordedStream, Transcript, TranscriptError, TranscriptRecorder};
 pub use transport::RoleResolvingTransport;
 
 use crate::{
     helpers::{
         buffers::UnorderedReceiver,
         gateway::{
+            compression::Decoder,
             receive::{GatewayReceivers, ShardReceiveStream, UR},
             send::GatewaySenders,
             transport::Transports,
@@ -23,7 +34,8 @@ use crate::{
         HelperChannelId, LogErrors, Message, MpcMessage, RecordsStream, Role, RoleAssignment,
         ShardChannelId, TotalRecords, Transport,
     },
-    protocol::QueryId,
+    protocol::{context::dzkp_validator::TARGET_PROOF_SIZE, QueryId},
+    query::ProgressTracker,
     sharding::ShardIndex,
     sync::{Arc, Mutex},
 };
@@ -51,6 +63,8 @@ pub struct Gateway {
     config: GatewayConfig,
     transports: Transports<RoleResolvingTransport, ShardTransportImpl>,
     query_id: QueryId,
+    transcript: Option<Arc<TranscriptRecorder>>,
+    digests: Option<ChannelDigests>,
     #[cfg(feature = "stall-detection")]
     inner: crate::sync::Arc<State>,
     #[cfg(not(feature = "stall-detection"))]
@@ -63,6 +77,7 @@ pub struct State {
     mpc_receivers: GatewayReceivers<Role, UR>,
     shard_senders: GatewaySenders<ShardIndex>,
     shard_receivers: GatewayReceivers<ShardIndex, ShardReceiveStream>,
+    progress: ProgressTracker,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -82,6 +97,17 @@ pub struct GatewayConfig {
     /// 4088 bytes being sent in a batch.
     pub read_size: NonZeroUsize,
 
+    /// Compression of batches sent to other helpers. Streams between shards of the same helper
+    /// are not compressed.
+    pub compression: StreamCompression,
+
+    /// Number of bit multiplications that a single zero-knowledge proof should cover. Malicious
+    /// protocols size the batches they validate based on this value, see
+    /// [`UpgradableContext::target_proof_size`].
+    ///
+    /// [`UpgradableContext::target_proof_size`]: crate::protocol::context::UpgradableContext::target_proof_size
+    pub target_proof_size: NonZeroUsize,
+
     /// Time to wait before checking gateway progress. If no progress has been made between
     /// checks, the gateway is considered to be stalled and will create a report with outstanding
     /// send/receive requests
@@ -98,21 +124,52 @@ impl Gateway {
         mpc_transport: MpcTransportImpl,
         shard_transport: ShardTransportImpl,
     ) -> Self {
+        let mpc = RoleResolvingTransport {
+            roles,
+            inner: mpc_transport,
+        };
         #[allow(clippy::useless_conversion)] // not useless in stall-detection build
         Self {
             query_id,
             config,
             transports: Transports {
-                mpc: RoleResolvingTransport {
-                    roles,
-                    inner: mpc_transport,
-                },
+                mpc,
                 shard: shard_transport,
             },
+            transcript: None,
+            digests: None,
             inner: State::default().into(),
         }
     }
 
+    /// Records all messages exchanged with other helpers through this gateway with the given
+    /// recorder.
+    #[must_use]
+    pub fn with_transcript(mut self, transcript: Arc<TranscriptRecorder>) -> Self {
+        self.transcript = Some(transcript);
+        self
+    }
+
+    /// Keeps digests of the records exchanged with other helpers, per channel, in `digests`. They
+    /// are updated as the query sends and receives records.
+    #[must_use]
+    pub fn with_digests(mut self, digests: ChannelDigests) -> Self {
+        self.digests = Some(digests);
+        self
+    }
+
+    /// Returns digests of the records exchanged with other helpers, if they are kept.
+    #[must_use]
+    pub fn digests(&self) -> Option<&ChannelDigests> {
+        self.digests.as_ref()
+    }
+
+    /// Returns the recorder of messages exchanged with other helpers, if there is one.
+    #[must_use]
+    pub fn transcript(&self) -> Option<&Arc<TranscriptRecorder>> {
+        self.transcript.as_ref()
+    }
+
     #[must_use]
     pub fn role(&self) -> Role {
         self.transports.mpc.identity()
@@ -123,6 +180,17 @@ impl Gateway {
         &self.config
     }
 
+    #[must_use]
+    pub fn query_id(&self) -> QueryId {
+        self.query_id
+    }
+
+    /// Returns the tracker of query progress, which is updated as the query opens new channels.
+    #[must_use]
+    pub fn progress(&self) -> ProgressTracker {
+        self.inner.progress.clone()
+    }
+
     /// Returns a sender suitable for sending data between MPC helpers. The data must be approved
     /// for sending by implementing [`MpcMessage`] trait.
     ///
@@ -153,9 +221,19 @@ impl Gateway {
             self.config,
             self.query_id,
             total_records,
+            || {
+                self.inner
+                    .progress
+                    .observe(channel_id.gate.as_ref(), total_records);
+            },
         );
 
-        send::SendingEnd::new(channel, transport.identity())
+        send::SendingEnd::new(
+            channel,
+            transport.identity(),
+            self.transcript.clone(),
+            self.digests.as_ref().map(|d| d.sender(channel_id)),
+        )
     }
 
     /// Returns a sender for shard-to-shard traffic. This sender is more relaxed compared to one
@@ -174,12 +252,16 @@ impl Gateway {
         let channel = self.inner.shard_senders.get::<M, _>(
             channel_id,
             transport,
-            self.config,
+            GatewayConfig {
+                compression: StreamCompression::None,
+                ..self.config
+            },
             self.query_id,
             total_records,
+            || {},
         );
 
-        send::SendingEnd::new(channel, transport.identity())
+        send::SendingEnd::new(channel, transport.identity(), None, None)
     }
 
     #[must_use]
@@ -190,14 +272,24 @@ impl Gateway {
         receive::MpcReceivingEnd::new(
             channel_id.clone(),
             self.inner.mpc_receivers.get_or_create(channel_id, || {
+                self.inner
+                    .progress
+                    .observe(channel_id.gate.as_ref(), TotalRecords::Indeterminate);
+                let active_work = self.config.active_work();
                 UnorderedReceiver::new(
-                    Box::pin(LogErrors::new(self.transports.mpc.receive(
-                        channel_id.peer,
-                        (self.query_id, channel_id.gate.clone()),
+                    Box::pin(LogErrors::new(Decoder::new(
+                        self.transports
+                            .mpc
+                            .receive(channel_id.peer, (self.query_id, channel_id.gate.clone())),
+                        self.config.compression,
+                        // the peer can't send more than fits into its send buffer at once
+                        active_work.get().saturating_mul(M::Size::USIZE),
                     ))),
-                    self.config.active_work(),
+                    active_work,
                 )
             }),
+            self.transcript.clone(),
+            self.digests.as_ref().map(|d| d.receiver(channel_id)),
         )
     }
 
@@ -234,6 +326,8 @@ impl Default for GatewayConfig {
         Self {
             active: 32768.try_into().unwrap(),
             read_size: 2048.try_into().unwrap(),
+            compression: StreamCompression::None,
+            target_proof_size: TARGET_PROOF_SIZE.try_into().unwrap(),
             // In-memory tests are fast, so progress check intervals can be lower.
             // Real world scenarios currently over-report stalls because of inefficiencies inside
             // infrastructure and actual networking issues. This check is only valuable to report
@@ -267,9 +361,9 @@ mod tests {
 
     use crate::{
         ff::{boolean_array::BA3, Fp31, Fp32BitPrime, Gf2, U128Conversions},
-        helpers::{Direction, GatewayConfig, MpcMessage, Role, SendingEnd},
+        helpers::{Direction, GatewayConfig, MpcMessage, Role, SendingEnd, StreamCompression},
         protocol::{
-            context::{Context, ShardedContext},
+            context::{Context, SemiHonestContext, ShardedContext},
             RecordId,
         },
         secret_sharing::replicated::semi_honest::AdditiveShare,
@@ -319,6 +413,61 @@ mod tests {
             .await;
     }
 
+    #[tokio::test]
+    async fn compressed_streams() {
+        async fn exchange<V: MpcMessage>(ctx: SemiHonestContext<'_>, values: &[V]) -> Vec<V> {
+            let ctx = ctx.set_total_records(values.len());
+            let role = ctx.role();
+            let send_channel = ctx.send_channel::<V>(role.peer(Direction::Right));
+            let recv_channel = ctx.recv_channel::<V>(role.peer(Direction::Left));
+            let (_, received) = try_join(
+                try_join_all(
+                    values
+                        .iter()
+                        .enumerate()
+                        .map(|(i, v)| send_channel.send(i.into(), v)),
+                ),
+                try_join_all((0..values.len()).map(|i| recv_channel.receive(i.into()))),
+            )
+            .await
+            .unwrap();
+
+            received
+        }
+
+        let config = TestWorldConfig {
+            gateway_config: GatewayConfig {
+                compression: StreamCompression::Packed,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let bits = (0..100_u128)
+            .map(|i| Gf2::truncate_from(i % 3))
+            .collect::<Vec<_>>();
+        let sparse = (0..100_u128)
+            .map(|i| Fp32BitPrime::truncate_from(if i % 10 == 0 { i } else { 0 }))
+            .collect::<Vec<_>>();
+
+        let world = TestWorld::new_with(config);
+        let results = world
+            .semi_honest((), |ctx, ()| {
+                let (bits, sparse) = (bits.clone(), sparse.clone());
+                async move {
+                    (
+                        exchange(ctx.narrow("bits"), &bits).await,
+                        exchange(ctx.narrow("sparse"), &sparse).await,
+                    )
+                }
+            })
+            .await;
+
+        for (received_bits, received_sparse) in results {
+            assert_eq!(bits, received_bits);
+            assert_eq!(sparse, received_sparse);
+        }
+    }
+
     #[tokio::test]
     pub async fn handles_reordering() {
         let config = TestWorldConfig {
@@ -13,7 +13,12 @@ use crate::{
     error::BoxError,
     helpers::{
         buffers::{UnorderedReceiver, UnorderedReceiverError},
-        gateway::transport::RoleResolvingTransport,
+        gateway::{
+            compression::Decoder,
+            digest::RollingDigest,
+            transcript::{Flow, TranscriptRecorder},
+            transport::RoleResolvingTransport,
+        },
         transport::SingleRecordStream,
         ChannelId, Error, HelperChannelId, LogErrors, Message, MpcMessage, Role, ShardChannelId,
         ShardTransportImpl, Transport, TransportIdentity,
@@ -31,6 +36,8 @@ use crate::{
 pub struct MpcReceivingEnd<M> {
     channel_id: HelperChannelId,
     unordered_rx: UR,
+    transcript: Option<Arc<TranscriptRecorder>>,
+    digest: Option<Arc<RollingDigest>>,
     _phantom: PhantomData<fn() -> M>,
 }
 
@@ -47,7 +54,7 @@ pub(super) struct GatewayReceivers<I, S> {
 }
 
 pub type UR = UnorderedReceiver<
-    LogErrors<<RoleResolvingTransport as Transport>::RecordsStream, Bytes, BoxError>,
+    LogErrors<Decoder<<RoleResolvingTransport as Transport>::RecordsStream>, Bytes, BoxError>,
     Vec<u8>,
 >;
 
@@ -62,10 +69,17 @@ pub struct ShardReceiveStream(
 );
 
 impl<M: MpcMessage> MpcReceivingEnd<M> {
-    pub(super) fn new(channel_id: HelperChannelId, rx: UR) -> Self {
+    pub(super) fn new(
+        channel_id: HelperChannelId,
+        rx: UR,
+        transcript: Option<Arc<TranscriptRecorder>>,
+        digest: Option<Arc<RollingDigest>>,
+    ) -> Self {
         Self {
             channel_id,
             unordered_rx: rx,
+            transcript,
+            digest,
             _phantom: PhantomData,
         }
     }
@@ -81,7 +95,8 @@ impl<M: MpcMessage> MpcReceivingEnd<M> {
     /// and sent to this helper.
     #[tracing::instrument(level = "trace", "receive", skip_all, fields(i = %record_id, from = ?self.channel_id.peer, gate = ?self.channel_id.gate.as_ref()))]
     pub async fn receive(&self, record_id: RecordId) -> Result<M, Error<Role>> {
-        self.unordered_rx
+        let msg = self
+            .unordered_rx
             .recv::<M, _>(record_id)
             .await
             .map_err(|e| match e {
@@ -93,7 +108,15 @@ impl<M: MpcMessage> MpcReceivingEnd<M> {
                     channel_id: self.channel_id.clone(),
                     inner,
                 },
-            })
+            })?;
+        if let Some(digest) = &self.digest {
+            digest.add(record_id, &msg);
+        }
+        if let Some(transcript) = &self.transcript {
+            transcript.record(Flow::Received, &self.channel_id, record_id, &msg);
+        }
+
+        Ok(msg)
     }
 }
 
@@ -1,28 +1,35 @@
 use std::{
     borrow::Borrow,
-    fmt::Debug,
     marker::PhantomData,
     num::NonZeroUsize,
     pin::Pin,
-    task::{Context, Poll},
+    task::{ready, Context, Poll},
 };
 
 use dashmap::{mapref::entry::Entry, DashMap};
 use futures::Stream;
 #[cfg(all(test, feature = "shuttle"))]
 use shuttle::future as tokio;
+use tracing::Instrument;
 use typenum::Unsigned;
 
 use crate::{
     helpers::{
-        buffers::OrderingSender, routing::RouteId, ChannelId, Error, GatewayConfig, Message,
-        TotalRecords, Transport, TransportIdentity,
+        buffers::OrderingSender,
+        gateway::{
+            digest::RollingDigest,
+            transcript::{Flow, TranscriptRecorder},
+            StreamCompression,
+        },
+        routing::RouteId,
+        ChannelId, Error, GatewayConfig, Message, TotalRecords, Transport, TransportIdentity,
     },
     protocol::{QueryId, RecordId},
     sync::Arc,
     telemetry::{
         labels::{ROLE, STEP},
-        metrics::{BYTES_SENT, RECORDS_SENT},
+        metrics::{BYTES_SENT, RECORDS_SENT, STREAM_BYTES_COMPRESSED, STREAM_BYTES_RAW},
+        trace::SPAN_TARGET,
     },
 };
 
@@ -30,6 +37,8 @@ use crate::{
 pub struct SendingEnd<I: TransportIdentity, M> {
     sender_id: I,
     inner: Arc<GatewaySender<I>>,
+    transcript: Option<Arc<TranscriptRecorder>>,
+    digest: Option<Arc<RollingDigest>>,
     /// This makes this struct [`Send`] even if [`M`] is not [`Sync`].
     _phantom: PhantomData<fn() -> M>,
 }
@@ -47,6 +56,8 @@ pub(super) struct GatewaySender<I> {
 
 struct GatewaySendStream<I> {
     inner: Arc<GatewaySender<I>>,
+    sender_id: I,
+    compression: StreamCompression,
 }
 
 /// Configuration for each [`GatewaySender`]. All values stored here
@@ -123,6 +134,11 @@ impl<I: TransportIdentity> GatewaySender<I> {
         self.total_records
     }
 
+    #[cfg(feature = "stall-detection")]
+    pub fn occupancy(&self) -> crate::helpers::BufferOccupancy {
+        self.ordering_tx.occupancy()
+    }
+
     pub fn is_closed(&self) -> bool {
         self.ordering_tx.is_closed()
     }
@@ -133,10 +149,17 @@ impl<I: TransportIdentity> GatewaySender<I> {
 }
 
 impl<I: TransportIdentity, M: Message> SendingEnd<I, M> {
-    pub(super) fn new(sender: Arc<GatewaySender<I>>, id: I) -> Self {
+    pub(super) fn new(
+        sender: Arc<GatewaySender<I>>,
+        id: I,
+        transcript: Option<Arc<TranscriptRecorder>>,
+        digest: Option<Arc<RollingDigest>>,
+    ) -> Self {
         Self {
             sender_id: id,
             inner: sender,
+            transcript,
+            digest,
             _phantom: PhantomData,
         }
     }
@@ -157,6 +180,12 @@ impl<I: TransportIdentity, M: Message> SendingEnd<I, M> {
         gate = ?self.inner.channel_id.gate.as_ref()
     ))]
     pub async fn send<B: Borrow<M>>(&self, record_id: RecordId, msg: B) -> Result<(), Error<I>> {
+        if let Some(transcript) = &self.transcript {
+            transcript.record(Flow::Sent, &self.inner.channel_id, record_id, msg.borrow());
+        }
+        if let Some(digest) = &self.digest {
+            digest.add(record_id, msg.borrow());
+        }
         let r = self.inner.send(record_id, msg).await;
         metrics::increment_counter!(RECORDS_SENT,
             STEP => self.inner.channel_id.gate.as_ref().to_string(),
@@ -185,7 +214,7 @@ impl<I: TransportIdentity, M: Message> SendingEnd<I, M> {
 
 impl<I: TransportIdentity> GatewaySenders<I> {
     /// Returns a communication channel for the given [`ChannelId`]. If it does not exist, it will
-    /// be created using the provided [`Transport`] implementation.
+    /// be created using the provided [`Transport`] implementation and `on_create` is called.
     pub fn get<M: Message, T: Transport<Identity = I>>(
         &self,
         channel_id: &ChannelId<I>,
@@ -193,6 +222,7 @@ impl<I: TransportIdentity> GatewaySenders<I> {
         config: GatewayConfig,
         query_id: QueryId,
         total_records: TotalRecords, // TODO track children for indeterminate senders
+        on_create: impl FnOnce(),
     ) -> Arc<GatewaySender<I>> {
         assert!(
             total_records.is_specified(),
@@ -208,20 +238,33 @@ impl<I: TransportIdentity> GatewaySenders<I> {
                     channel_id.clone(),
                 );
                 entry.insert(Arc::clone(&sender));
+                on_create();
 
                 tokio::spawn({
                     let ChannelId { peer, gate } = channel_id.clone();
                     let transport = transport.clone();
                     let stream = GatewaySendStream {
                         inner: Arc::clone(&sender),
+                        sender_id: transport.identity(),
+                        compression: config.compression,
                     };
+                    let span = tracing::info_span!(
+                        target: SPAN_TARGET,
+                        "send",
+                        otel.name = %format_args!("send {}", gate.as_ref()),
+                        otel.kind = "client",
+                        gate = gate.as_ref(),
+                        to = ?peer,
+                    );
                     async move {
-                        // TODO(651): In the HTTP case we probably need more robust error handling here.
+                        // HTTP transport resumes streams interrupted by connection failures, so an
+                        // error here means the other helper is gone or rejected the stream.
                         transport
                             .send(peer, (RouteId::Records, query_id, gate), stream)
                             .await
                             .expect("{channel_id:?} receiving end should be accepted by transport");
                     }
+                    .instrument(span)
                 });
 
                 sender
@@ -238,12 +281,36 @@ impl<I: TransportIdentity> GatewaySenders<I> {
     }
 }
 
-impl<I: Debug> Stream for GatewaySendStream<I> {
+impl<I: TransportIdentity> GatewaySendStream<I> {
+    fn compress(&self, batch: Vec<u8>) -> Vec<u8> {
+        if self.compression == StreamCompression::None {
+            return batch;
+        }
+
+        let raw_len = batch.len();
+        let encoded = self.compression.encode(batch);
+        let gate = &self.inner.channel_id.gate;
+        metrics::counter!(STREAM_BYTES_RAW, raw_len as u64,
+            STEP => gate.as_ref().to_string(),
+            ROLE => self.sender_id.as_str(),
+        );
+        metrics::counter!(STREAM_BYTES_COMPRESSED, encoded.len() as u64,
+            STEP => gate.as_ref().to_string(),
+            ROLE => self.sender_id.as_str(),
+        );
+
+        encoded
+    }
+}
+
+impl<I: TransportIdentity> Stream for GatewaySendStream<I> {
     type Item = Vec<u8>;
 
     #[tracing::instrument(level = "trace", name = "send_stream", skip_all, fields(to = ?self.inner.channel_id.peer, gate = ?self.inner.channel_id.gate))]
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::get_mut(self).inner.ordering_tx.take_next(cx)
+        let this = Pin::get_mut(self);
+        let batch = ready!(this.inner.ordering_tx.take_next(cx));
+        Poll::Ready(batch.map(|batch| this.compress(batch)))
     }
 }
 
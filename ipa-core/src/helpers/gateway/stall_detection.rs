@@ -3,7 +3,7 @@ use std::{
     ops::{RangeInclusive, Sub},
 };
 
-pub use gateway::InstrumentedGateway;
+pub use gateway::{InstrumentedGateway, StallObserver};
 
 use crate::sync::{
     atomic::{AtomicUsize, Ordering},
@@ -74,12 +74,17 @@ mod gateway {
     use crate::{
         helpers::{
             gateway::{Gateway, ShardTransportImpl, State},
-            GatewayConfig, HelperChannelId, Message, MpcMessage, MpcReceivingEnd, MpcTransportImpl,
-            Role, RoleAssignment, SendingEnd, ShardChannelId, ShardReceivingEnd, TotalRecords,
+            ChannelDigests, GatewayConfig, HelperChannelId, Message, MpcMessage, MpcReceivingEnd,
+            MpcTransportImpl, Role, RoleAssignment, SendingEnd, ShardChannelId, ShardReceivingEnd,
+            StallReport, TotalRecords, TranscriptRecorder,
         },
         protocol::QueryId,
+        query::ProgressTracker,
         sharding::ShardIndex,
-        sync::Arc,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
     };
 
     pub struct InstrumentedGateway {
@@ -87,6 +92,32 @@ mod gateway {
         // Gateway owns the sequence number associated with it. When it goes out of scope, sn is destroyed
         // and external observers can see that they no longer need to watch it.
         _sn: Arc<AtomicUsize>,
+        // Set by the watcher if no progress has been made since the last check.
+        stalled: Arc<AtomicBool>,
+    }
+
+    /// Builds [`StallReport`]s for a gateway on demand. It does not keep the gateway alive.
+    pub struct StallObserver {
+        gateway: Observed<Weak<State>>,
+        stalled: Arc<AtomicBool>,
+    }
+
+    impl StallObserver {
+        /// Returns the current state of the gateway or `None` if it has been dropped.
+        #[must_use]
+        pub fn report(&self) -> Option<StallReport> {
+            let sequence_number = self.gateway.get_sn().upgrade()?.load(Ordering::Relaxed);
+            let state = self.gateway.inner().upgrade()?;
+            let mut senders = state.mpc_senders.channel_reports();
+            senders.extend(state.shard_senders.channel_reports());
+
+            Some(StallReport {
+                stalled: self.stalled.load(Ordering::Relaxed),
+                sequence_number,
+                senders,
+                receivers: state.mpc_receivers.channel_reports(),
+            })
+        }
     }
 
     impl Observed<InstrumentedGateway> {
@@ -98,9 +129,33 @@ mod gateway {
 
                 #[inline]
                 pub fn config(&self) -> &GatewayConfig;
+
+                #[inline]
+                pub fn query_id(&self) -> QueryId;
+
+                #[inline]
+                pub fn progress(&self) -> ProgressTracker;
+
+                #[inline]
+                pub fn digests(&self) -> Option<&ChannelDigests>;
+
+                #[inline]
+                pub fn transcript(&self) -> Option<&Arc<TranscriptRecorder>>;
             }
         }
 
+        #[must_use]
+        pub fn with_transcript(mut self, transcript: Arc<TranscriptRecorder>) -> Self {
+            self.inner.gateway = self.inner.gateway.with_transcript(transcript);
+            self
+        }
+
+        #[must_use]
+        pub fn with_digests(mut self, digests: ChannelDigests) -> Self {
+            self.inner.gateway = self.inner.gateway.with_digests(digests);
+            self
+        }
+
         #[allow(clippy::let_and_return)]
         pub fn new(
             query_id: QueryId,
@@ -115,6 +170,7 @@ mod gateway {
                 InstrumentedGateway {
                     gateway: Gateway::new(query_id, config, roles, mpc_transport, shard_transport),
                     _sn: version,
+                    stalled: Arc::default(),
                 },
             );
 
@@ -125,12 +181,14 @@ mod gateway {
 
                 tokio::spawn({
                     let gateway = r.to_observed();
+                    let stalled = Arc::clone(&r.inner().stalled);
                     async move {
                         let mut last_sn_seen = 0;
                         loop {
                             ::tokio::time::sleep(config.progress_check_interval).await;
                             let now = gateway.get_sn().upgrade().map(|v| v.load(core::sync::atomic::Ordering::Relaxed));
                             if let Some(now) = now {
+                                stalled.store(now == last_sn_seen, core::sync::atomic::Ordering::Relaxed);
                                 if now == last_sn_seen {
                                     if let Some(state) = gateway.get_state() {
                                         tracing::warn!(sn = now, state = ?state, "Helper is stalled");
@@ -196,6 +254,14 @@ mod gateway {
             )
         }
 
+        #[must_use]
+        pub fn stall_observer(&self) -> StallObserver {
+            StallObserver {
+                gateway: self.to_observed(),
+                stalled: Arc::clone(&self.inner().stalled),
+            }
+        }
+
         pub fn to_observed(&self) -> Observed<Weak<State>> {
             // todo: inner.inner
             Observed::wrap(
@@ -278,7 +344,7 @@ mod receive {
                 receive::{GatewayReceivers, ShardReceiveStream, ShardReceivingEnd, UR},
                 MpcReceivingEnd,
             },
-            ChannelId, Message, MpcMessage, Role, TransportIdentity,
+            ChannelId, ChannelReport, Message, MpcMessage, Role, TransportIdentity,
         },
         protocol::RecordId,
         sharding::ShardIndex,
@@ -334,6 +400,28 @@ mod receive {
         }
     }
 
+    impl GatewayReceivers<Role, UR> {
+        /// Reports channels that have outstanding reads.
+        pub fn channel_reports(&self) -> Vec<ChannelReport> {
+            let mut reports = Vec::new();
+            for entry in &self.inner {
+                let channel = entry.key();
+                if let Some(waiting) = super::to_ranges(entry.value().waiting()).get_state() {
+                    reports.push(ChannelReport {
+                        gate: channel.gate.as_ref().to_string(),
+                        peer: format!("{:?}", channel.peer),
+                        total_records: None,
+                        waiting,
+                        buffer: Some(entry.value().occupancy()),
+                    });
+                }
+            }
+            reports.sort_by(|a, b| (&a.gate, &a.peer).cmp(&(&b.gate, &b.peer)));
+
+            reports
+        }
+    }
+
     impl ObserveState for GatewayReceivers<ShardIndex, ShardReceiveStream> {
         type State = WaitingTasks<ShardIndex>;
 
@@ -364,7 +452,7 @@ mod send {
         helpers::{
             error::Error,
             gateway::send::{GatewaySender, GatewaySenders},
-            ChannelId, Message, TotalRecords, TransportIdentity,
+            ChannelId, ChannelReport, Message, TotalRecords, TransportIdentity,
         },
         protocol::RecordId,
     };
@@ -413,6 +501,31 @@ mod send {
         }
     }
 
+    impl<I: TransportIdentity> GatewaySenders<I> {
+        /// Reports channels that have records waiting to be sent or data in the send buffer.
+        pub fn channel_reports(&self) -> Vec<ChannelReport> {
+            let mut reports = Vec::new();
+            for entry in &self.inner {
+                let channel = entry.key();
+                let sender = entry.value();
+                let buffer = sender.occupancy();
+                let waiting = sender.get_state().unwrap_or_default();
+                if !waiting.is_empty() || buffer.used > 0 {
+                    reports.push(ChannelReport {
+                        gate: channel.gate.as_ref().to_string(),
+                        peer: format!("{:?}", channel.peer),
+                        total_records: sender.total_records().count(),
+                        waiting,
+                        buffer: Some(buffer),
+                    });
+                }
+            }
+            reports.sort_by(|a, b| (&a.gate, &a.peer).cmp(&(&b.gate, &b.peer)));
+
+            reports
+        }
+    }
+
     impl<I: TransportIdentity> ObserveState for GatewaySender<I> {
         type State = Vec<String>;
 
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+/// Snapshot of the gateway state, used to diagnose queries that do not make progress.
+///
+/// Only channels that have records waiting to be sent or received, or data sitting in their
+/// buffers, are included in the report.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StallReport {
+    /// Set if the gateway has not made any progress since the last check made by stall detection.
+    pub stalled: bool,
+    /// Number of send and receive operations performed by the gateway so far.
+    pub sequence_number: usize,
+    pub senders: Vec<ChannelReport>,
+    pub receivers: Vec<ChannelReport>,
+}
+
+/// State of a single channel.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelReport {
+    pub gate: String,
+    /// Helper role or shard index on the other side of the channel.
+    pub peer: String,
+    /// Total number of records in this channel, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_records: Option<usize>,
+    /// Records that are waiting to be sent or received, collapsed into ranges.
+    pub waiting: Vec<String>,
+    /// Occupancy of the channel buffer, if it can be determined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer: Option<BufferOccupancy>,
+}
+
+/// Occupancy of a channel buffer. Send buffers are measured in bytes, receive buffers in the
+/// number of outstanding reads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BufferOccupancy {
+    pub used: usize,
+    pub capacity: usize,
+}
@@ -0,0 +1,634 @@
+//! Transcripts of the records a helper exchanges with other helpers during a query.
+//!
+//! When helpers disagree on a result, the transcript of one helper makes it possible to rerun
+//! that helper's side of the protocol offline. [`TranscriptRecorder`] writes every record sent to
+//! or received from another helper, together with the PRSS seeds of the helper, to a local file.
+//! [`Transcript`] reads it back. Replaying a transcript feeds the recorded records to the helper
+//! and checks what it sends against the recording, reporting the first [`Divergence`].
+//!
+//! The file contains one JSON object per line. Records are stored in the order they were sent or
+//! received, with their gate, peer and record id. Transcripts contain PRSS seeds, so anyone with
+//! access to the file and the transcripts of other helpers can recover secret inputs. Files are
+//! only readable by the user running the helper.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{Display, Formatter},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use generic_array::GenericArray;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ff::Serializable,
+    helpers::{query::QueryConfig, ChannelId, Role, TransportIdentity},
+    protocol::{prss::PrssSeeds, Gate, RecordId},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Flow {
+    Sent,
+    Received,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    Helper {
+        role: Role,
+    },
+    Prss {
+        seeds: PrssSeeds,
+    },
+    Query {
+        config: QueryConfig,
+    },
+    Record {
+        flow: Flow,
+        gate: String,
+        peer: String,
+        record_id: usize,
+        #[serde(with = "hex")]
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TranscriptError {
+    #[error("failed to read transcript: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid transcript entry on line {line}: {source}")]
+    Parse {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error("invalid peer {0} on line {1}")]
+    InvalidPeer(String, usize),
+    #[error("transcript must start with the role of the helper")]
+    MissingRole,
+}
+
+/// The first difference between what a helper sent during a replay and what it sent when the
+/// transcript was recorded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub gate: String,
+    pub peer: Role,
+    pub record_id: RecordId,
+    /// The recorded record, or `None` if it was not sent when the transcript was recorded.
+    pub expected: Option<Vec<u8>>,
+    /// The record sent during the replay, or `None` if it was not sent.
+    pub actual: Option<Vec<u8>>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let show = |v: &Option<Vec<u8>>| {
+            v.as_ref()
+                .map_or_else(|| "nothing".to_string(), hex::encode)
+        };
+        write!(
+            f,
+            "{}: record {} sent to {:?} is {}, but {} was recorded",
+            self.gate,
+            self.record_id,
+            self.peer,
+            show(&self.actual),
+            show(&self.expected)
+        )
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// Records sent or received on one channel.
+#[derive(Debug)]
+pub struct RecordedStream {
+    flow: Flow,
+    pub gate: String,
+    pub peer: Role,
+    pub records: BTreeMap<usize, Vec<u8>>,
+}
+
+/// Transcript of one helper, read from a file written by [`TranscriptRecorder`].
+#[derive(Debug)]
+pub struct Transcript {
+    role: Role,
+    prss: Option<PrssSeeds>,
+    query: Option<QueryConfig>,
+    /// Streams in the order they were first used.
+    streams: Vec<RecordedStream>,
+}
+
+impl Transcript {
+    /// Reads a transcript from the given file.
+    ///
+    /// ## Errors
+    /// If the file can't be read or is not a valid transcript.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TranscriptError> {
+        let mut lines = BufReader::new(File::open(path)?).lines().enumerate();
+        let parse = |line: usize, text: &str| {
+            serde_json::from_str::<Entry>(text).map_err(|source| TranscriptError::Parse {
+                line: line + 1,
+                source,
+            })
+        };
+
+        let role = match lines.next() {
+            Some((i, line)) => match parse(i, &line?)? {
+                Entry::Helper { role } => role,
+                _ => return Err(TranscriptError::MissingRole),
+            },
+            None => return Err(TranscriptError::MissingRole),
+        };
+        let mut transcript = Self {
+            role,
+            prss: None,
+            query: None,
+            streams: Vec::new(),
+        };
+        let mut index = HashMap::new();
+        for (i, line) in lines {
+            match parse(i, &line?)? {
+                Entry::Helper { .. } => return Err(TranscriptError::MissingRole),
+                Entry::Prss { seeds } => transcript.prss = Some(seeds),
+                Entry::Query { config } => transcript.query = Some(config),
+                Entry::Record {
+                    flow,
+                    gate,
+                    peer,
+                    record_id,
+                    data,
+                } => {
+                    let peer = Role::try_from(peer.as_str())
+                        .map_err(|_| TranscriptError::InvalidPeer(peer.clone(), i + 1))?;
+                    let streams = &mut transcript.streams;
+                    let stream = *index.entry((flow, gate.clone(), peer)).or_insert_with(|| {
+                        streams.push(RecordedStream {
+                            flow,
+                            gate,
+                            peer,
+                            records: BTreeMap::new(),
+                        });
+                        streams.len() - 1
+                    });
+                    streams[stream].records.insert(record_id, data);
+                }
+            }
+        }
+
+        Ok(transcript)
+    }
+
+    #[must_use]
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// PRSS seeds of the helper, if they were recorded.
+    #[must_use]
+    pub fn prss(&self) -> Option<&PrssSeeds> {
+        self.prss.as_ref()
+    }
+
+    /// Configuration of the query, if the transcript was written by a helper running it.
+    #[must_use]
+    pub fn query_config(&self) -> Option<&QueryConfig> {
+        self.query.as_ref()
+    }
+
+    /// Streams the helper received from other helpers.
+    pub fn received(&self) -> impl Iterator<Item = &RecordedStream> {
+        self.streams.iter().filter(|s| s.flow == Flow::Received)
+    }
+
+    /// Streams the helper sent to other helpers.
+    pub fn sent(&self) -> impl Iterator<Item = &RecordedStream> {
+        self.streams.iter().filter(|s| s.flow == Flow::Sent)
+    }
+
+    fn find(&self, flow: Flow, gate: &str, peer: Role) -> Option<&RecordedStream> {
+        self.streams
+            .iter()
+            .find(|s| s.flow == flow && s.gate == gate && s.peer == peer)
+    }
+}
+
+/// Writes the records exchanged by a helper to a transcript, or checks them against an existing
+/// transcript when replaying it.
+pub struct TranscriptRecorder {
+    sink: Sink,
+}
+
+enum Sink {
+    File {
+        writer: Mutex<BufWriter<File>>,
+        failed: AtomicBool,
+    },
+    Check(Checker),
+}
+
+struct Checker {
+    expected: Arc<Transcript>,
+    /// Only streams under this gate are expected to be sent again.
+    root: String,
+    seen: Mutex<HashSet<(String, Role, usize)>>,
+    divergence: Mutex<Option<Divergence>>,
+}
+
+impl TranscriptRecorder {
+    /// Creates a transcript file for the helper with the given role. An existing file is
+    /// overwritten. On Unix, a new file is only readable and writable by its owner.
+    ///
+    /// ## Errors
+    /// If the file can't be created.
+    pub fn create<P: AsRef<Path>>(path: P, role: Role) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut writer = BufWriter::new(options.open(path)?);
+        serde_json::to_writer(&mut writer, &Entry::Helper { role })?;
+        writer.write_all(b"\n")?;
+
+        Ok(Self {
+            sink: Sink::File {
+                writer: Mutex::new(writer),
+                failed: AtomicBool::new(false),
+            },
+        })
+    }
+
+    /// Creates a recorder that checks the records sent by a helper against the ones in
+    /// `expected`, for streams under the `root` gate.
+    #[must_use]
+    pub fn check(expected: Arc<Transcript>, root: &Gate) -> Self {
+        Self {
+            sink: Sink::Check(Checker {
+                expected,
+                root: root.as_ref().to_string(),
+                seen: Mutex::default(),
+                divergence: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Records the PRSS seeds of this helper.
+    pub fn record_prss(&self, seeds: &PrssSeeds) {
+        self.write(&Entry::Prss {
+            seeds: seeds.clone(),
+        });
+    }
+
+    /// Records the configuration of the query, so it can be replayed from the transcript alone.
+    pub fn record_query(&self, config: &QueryConfig) {
+        self.write(&Entry::Query { config: *config });
+    }
+
+    /// Returns the PRSS seeds from the transcript being checked. The key exchange at `gate` is not
+    /// repeated during a replay, so the records sent there are not expected again. Always `None`
+    /// for recorders that write transcripts, or if the transcript has no seeds.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    #[must_use]
+    pub fn replayed_prss(&self, gate: &Gate) -> Option<PrssSeeds> {
+        let Sink::Check(checker) = &self.sink else {
+            return None;
+        };
+        let seeds = checker.expected.prss()?;
+        let mut seen = checker.seen.lock().unwrap();
+        for s in checker.expected.sent().filter(|s| s.gate == gate.as_ref()) {
+            seen.extend(s.records.keys().map(|&i| (s.gate.clone(), s.peer, i)));
+        }
+
+        Some(seeds.clone())
+    }
+
+    pub(super) fn record<I: TransportIdentity, M: Serializable>(
+        &self,
+        flow: Flow,
+        channel_id: &ChannelId<I>,
+        record_id: RecordId,
+        msg: &M,
+    ) {
+        let mut buf = GenericArray::default();
+        msg.serialize(&mut buf);
+        let data = buf.to_vec();
+
+        match &self.sink {
+            Sink::File { .. } => self.write(&Entry::Record {
+                flow,
+                gate: channel_id.gate.as_ref().to_string(),
+                peer: channel_id.peer.as_str().to_string(),
+                record_id: usize::from(record_id),
+                data,
+            }),
+            Sink::Check(checker) if flow == Flow::Sent => {
+                let peer = Role::try_from(&*channel_id.peer.as_str())
+                    .expect("transcripts are only checked for MPC channels");
+                checker.check(channel_id.gate.as_ref(), peer, record_id, data);
+            }
+            Sink::Check(_) => {}
+        }
+    }
+
+    /// Returns the first difference between the records sent so far and the transcript being
+    /// checked, or records from the transcript that were not sent. Always `None` for recorders
+    /// that write transcripts.
+    ///
+    /// ## Panics
+    /// If a mutex is poisoned.
+    #[must_use]
+    pub fn divergence(&self) -> Option<Divergence> {
+        let Sink::Check(checker) = &self.sink else {
+            return None;
+        };
+        if let Some(divergence) = checker.divergence.lock().unwrap().clone() {
+            return Some(divergence);
+        }
+
+        let seen = checker.seen.lock().unwrap();
+        checker
+            .expected
+            .sent()
+            .filter(|s| s.gate.starts_with(&checker.root))
+            .find_map(|s| {
+                s.records
+                    .iter()
+                    .find(|(&record_id, _)| !seen.contains(&(s.gate.clone(), s.peer, record_id)))
+                    .map(|(&record_id, data)| Divergence {
+                        gate: s.gate.clone(),
+                        peer: s.peer,
+                        record_id: RecordId::from(record_id),
+                        expected: Some(data.clone()),
+                        actual: None,
+                    })
+            })
+    }
+
+    /// Writes buffered entries to the transcript file.
+    ///
+    /// ## Errors
+    /// If writing to the file fails.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn flush(&self) -> io::Result<()> {
+        match &self.sink {
+            Sink::File { writer, .. } => writer.lock().unwrap().flush(),
+            Sink::Check(_) => Ok(()),
+        }
+    }
+
+    fn write(&self, entry: &Entry) {
+        let Sink::File { writer, failed } = &self.sink else {
+            return;
+        };
+        let mut writer = writer.lock().unwrap();
+        let r = serde_json::to_writer(&mut *writer, entry)
+            .map_err(io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"));
+        // report the first failure only, the transcript is incomplete after that anyway
+        if let Err(e) = r {
+            if !failed.swap(true, Ordering::Relaxed) {
+                tracing::error!("failed to write transcript: {e}");
+            }
+        }
+    }
+}
+
+impl Checker {
+    fn check(&self, gate: &str, peer: Role, record_id: RecordId, actual: Vec<u8>) {
+        let i = usize::from(record_id);
+        self.seen
+            .lock()
+            .unwrap()
+            .insert((gate.to_string(), peer, i));
+        let expected = self
+            .expected
+            .find(Flow::Sent, gate, peer)
+            .and_then(|s| s.records.get(&i));
+        if expected != Some(&actual) {
+            self.divergence
+                .lock()
+                .unwrap()
+                .get_or_insert_with(|| Divergence {
+                    gate: gate.to_string(),
+                    peer,
+                    record_id,
+                    expected: expected.cloned(),
+                    actual: Some(actual),
+                });
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Divergence, Flow, Transcript, TranscriptError, TranscriptRecorder};
+    use crate::{
+        ff::{FieldType, Fp31, U128Conversions},
+        helpers::{
+            query::{QueryConfig, QueryType},
+            ChannelId, Role,
+        },
+        protocol::{prss::PrssSeeds, Gate, RecordId},
+    };
+
+    fn query_config() -> QueryConfig {
+        QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, 2).unwrap()
+    }
+
+    fn channel(gate: &str, peer: Role) -> ChannelId<Role> {
+        ChannelId::new(peer, Gate::from(gate))
+    }
+
+    fn write(path: &std::path::Path) {
+        let recorder = TranscriptRecorder::create(path, Role::H2).unwrap();
+        recorder.record_prss(&PrssSeeds {
+            left: [1; 32],
+            right: [2; 32],
+        });
+        recorder.record_query(&query_config());
+        let a = channel("/a", Role::H3);
+        let b = channel("/b", Role::H1);
+        for i in [1_u128, 0] {
+            recorder.record(
+                Flow::Sent,
+                &a,
+                RecordId::from(usize::try_from(i).unwrap()),
+                &Fp31::truncate_from(i + 10),
+            );
+        }
+        recorder.record(
+            Flow::Received,
+            &b,
+            RecordId::FIRST,
+            &Fp31::truncate_from(5_u128),
+        );
+        recorder.flush().unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transcript.jsonl");
+        write(&path);
+
+        let transcript = Transcript::load(&path).unwrap();
+        assert_eq!(Role::H2, transcript.role());
+        assert_eq!([1; 32], transcript.prss().unwrap().left);
+        assert_eq!(Some(&query_config()), transcript.query_config());
+
+        let sent = transcript.sent().collect::<Vec<_>>();
+        assert_eq!(1, sent.len());
+        assert_eq!(("/a", Role::H3), (sent[0].gate.as_str(), sent[0].peer));
+        assert_eq!(
+            vec![(0, vec![10]), (1, vec![11])],
+            sent[0]
+                .records
+                .iter()
+                .map(|(&i, v)| (i, v.clone()))
+                .collect::<Vec<_>>()
+        );
+
+        let received = transcript.received().collect::<Vec<_>>();
+        assert_eq!(1, received.len());
+        assert_eq!(Some(&vec![5]), received[0].records.get(&0));
+    }
+
+    #[test]
+    fn invalid_transcripts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transcript.jsonl");
+
+        std::fs::write(&path, "").unwrap();
+        assert!(matches!(
+            Transcript::load(&path),
+            Err(TranscriptError::MissingRole)
+        ));
+
+        std::fs::write(&path, "{\"helper\":{\"role\":\"H1\"}}\nnot json\n").unwrap();
+        assert!(matches!(
+            Transcript::load(&path),
+            Err(TranscriptError::Parse { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn check() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transcript.jsonl");
+        write(&path);
+        let transcript = Arc::new(Transcript::load(&path).unwrap());
+        let a = channel("/a", Role::H3);
+
+        // same records
+        let checker = TranscriptRecorder::check(Arc::clone(&transcript), &Gate::from("/"));
+        checker.record(
+            Flow::Sent,
+            &a,
+            RecordId::FIRST,
+            &Fp31::truncate_from(10_u128),
+        );
+        checker.record(
+            Flow::Sent,
+            &a,
+            RecordId::from(1),
+            &Fp31::truncate_from(11_u128),
+        );
+        assert_eq!(None, checker.divergence());
+
+        // missing record
+        let checker = TranscriptRecorder::check(Arc::clone(&transcript), &Gate::from("/"));
+        checker.record(
+            Flow::Sent,
+            &a,
+            RecordId::FIRST,
+            &Fp31::truncate_from(10_u128),
+        );
+        assert_eq!(
+            Some(Divergence {
+                gate: "/a".to_string(),
+                peer: Role::H3,
+                record_id: RecordId::from(1),
+                expected: Some(vec![11]),
+                actual: None,
+            }),
+            checker.divergence()
+        );
+
+        // different record, received records are not checked
+        let checker = TranscriptRecorder::check(Arc::clone(&transcript), &Gate::from("/"));
+        checker.record(
+            Flow::Received,
+            &channel("/b", Role::H1),
+            RecordId::FIRST,
+            &Fp31::truncate_from(6_u128),
+        );
+        checker.record(
+            Flow::Sent,
+            &a,
+            RecordId::FIRST,
+            &Fp31::truncate_from(12_u128),
+        );
+        checker.record(
+            Flow::Sent,
+            &a,
+            RecordId::from(1),
+            &Fp31::truncate_from(11_u128),
+        );
+        assert_eq!(
+            Some(Divergence {
+                gate: "/a".to_string(),
+                peer: Role::H3,
+                record_id: RecordId::FIRST,
+                expected: Some(vec![10]),
+                actual: Some(vec![12]),
+            }),
+            checker.divergence()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transcript.jsonl");
+        write(&path);
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+    }
+
+    #[test]
+    fn replayed_prss() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transcript.jsonl");
+        write(&path);
+        let transcript = Arc::new(Transcript::load(&path).unwrap());
+
+        let recorder =
+            TranscriptRecorder::create(dir.path().join("other.jsonl"), Role::H2).unwrap();
+        assert!(recorder.replayed_prss(&Gate::from("/a")).is_none());
+
+        // records of the key exchange are not expected when seeds are replayed
+        let checker = TranscriptRecorder::check(transcript, &Gate::from("/"));
+        let seeds = checker.replayed_prss(&Gate::from("/a")).unwrap();
+        assert_eq!(([1; 32], [2; 32]), (seeds.left, seeds.right));
+        assert_eq!(None, checker.divergence());
+    }
+}
@@ -57,8 +57,13 @@ mod gateway_exports {
 pub use gateway::GatewayConfig;
 // TODO: this type should only be available within infra. Right now several infra modules
 // are exposed at the root level. That makes it impossible to have a proper hierarchy here.
+#[cfg(feature = "stall-detection")]
+pub use gateway::stall_detection::StallObserver;
 pub use gateway::{
-    MpcTransportError, MpcTransportImpl, RoleResolvingTransport, ShardTransportImpl,
+    first_mismatch, BufferOccupancy, ChannelDigest, ChannelDigests, ChannelReport, DigestMismatch,
+    DigestReport, Divergence, MpcTransportError, MpcTransportImpl, RecordedStream,
+    RoleResolvingTransport, ShardTransportImpl, StallReport, StreamCompression, Transcript,
+    TranscriptError, TranscriptRecorder,
 };
 pub use gateway_exports::{Gateway, MpcReceivingEnd, SendingEnd, ShardReceivingEnd};
 pub use prss_protocol::negotiate as negotiate_prss;
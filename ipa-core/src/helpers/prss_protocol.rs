@@ -7,7 +7,8 @@ use crate::{
     protocol::{prss, Gate, RecordId},
 };
 
-/// Establish the prss endpoint by exchanging public keys with the other helpers.
+/// Establish the prss endpoint by exchanging public keys with the other helpers. When the gateway
+/// replays a transcript, the recorded seeds are used instead.
 /// # Errors
 /// if communication with other helpers fails
 pub async fn negotiate<R: RngCore + CryptoRng>(
@@ -15,6 +16,10 @@ pub async fn negotiate<R: RngCore + CryptoRng>(
     gate: &Gate,
     rng: &mut R,
 ) -> Result<prss::Endpoint, Error<Role>> {
+    if let Some(seeds) = gateway.transcript().and_then(|t| t.replayed_prss(gate)) {
+        return Ok(prss::Endpoint::from_seeds(&seeds));
+    }
+
     // setup protocol to exchange PRSS public keys. This protocol sends one message per peer.
     // Each message contains this helper's public key. At the end of this protocol, all helpers
     // have completed key exchange and each of them have established a shared secret with each peer.
@@ -39,5 +44,13 @@ pub async fn negotiate<R: RngCore + CryptoRng>(
     )
     .await?;
 
-    Ok(ep_setup.setup(&recv_left_pk, &recv_right_pk))
+    // the seeds are only revealed when the helper keeps a transcript of this query, so it can be
+    // replayed later.
+    Ok(if let Some(transcript) = gateway.transcript() {
+        let (endpoint, seeds) = ep_setup.setup_with_seeds(&recv_left_pk, &recv_right_pk);
+        transcript.record_prss(&seeds);
+        endpoint
+    } else {
+        ep_setup.setup(&recv_left_pk, &recv_right_pk)
+    })
 }
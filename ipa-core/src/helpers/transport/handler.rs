@@ -7,12 +7,13 @@ use serde_json::json;
 use crate::{
     error::BoxError,
     helpers::{
-        query::PrepareQuery, transport::routing::Addr, BodyStream, HelperIdentity,
-        TransportIdentity,
+        query::PrepareQuery, transport::routing::Addr, BodyStream, DigestReport, HelperIdentity,
+        StallReport, TransportIdentity,
     },
     query::{
-        NewQueryError, PrepareQueryError, ProtocolResult, QueryCompletionError, QueryInputError,
-        QueryStatus, QueryStatusError,
+        NewQueryError, PrepareQueryError, ProtocolResult, QueryCompletionError, QueryDigestsError,
+        QueryInputError, QueryKillError, QueryProgress, QueryStallReportError, QueryStatus,
+        QueryStatusError,
     },
     sync::{Arc, Mutex, Weak},
 };
@@ -135,6 +136,27 @@ impl From<QueryStatus> for HelperResponse {
     }
 }
 
+impl From<(QueryStatus, Option<QueryProgress>)> for HelperResponse {
+    fn from((status, progress): (QueryStatus, Option<QueryProgress>)) -> Self {
+        let v = serde_json::to_vec(&json!({"status": status, "progress": progress})).unwrap();
+        Self { body: v }
+    }
+}
+
+impl From<StallReport> for HelperResponse {
+    fn from(value: StallReport) -> Self {
+        let v = serde_json::to_vec(&value).unwrap();
+        Self { body: v }
+    }
+}
+
+impl From<DigestReport> for HelperResponse {
+    fn from(value: DigestReport) -> Self {
+        let v = serde_json::to_vec(&value).unwrap();
+        Self { body: v }
+    }
+}
+
 impl<R: AsRef<dyn ProtocolResult>> From<R> for HelperResponse {
     fn from(value: R) -> Self {
         let v = value.as_ref().to_bytes();
@@ -156,6 +178,12 @@ pub enum Error {
     #[error(transparent)]
     QueryStatus(#[from] QueryStatusError),
     #[error(transparent)]
+    QueryKill(#[from] QueryKillError),
+    #[error(transparent)]
+    QueryStallReport(#[from] QueryStallReportError),
+    #[error(transparent)]
+    QueryDigests(#[from] QueryDigestsError),
+    #[error(transparent)]
     DeserializationFailure(#[from] serde_json::Error),
     #[error("MalformedRequest: {0}")]
     BadRequest(BoxError),
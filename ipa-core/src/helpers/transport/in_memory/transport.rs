@@ -119,7 +119,10 @@ impl<I: TransportIdentity> InMemoryTransport<I> {
                             | RouteId::PrepareQuery
                             | RouteId::QueryInput
                             | RouteId::QueryStatus
-                            | RouteId::CompleteQuery => {
+                            | RouteId::CompleteQuery
+                            | RouteId::KillQuery
+                            | RouteId::QueryStallReport
+                            | RouteId::QueryDigests => {
                                 handler
                                     .as_ref()
                                     .expect("Handler is set")
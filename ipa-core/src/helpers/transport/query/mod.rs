@@ -10,9 +10,10 @@ use crate::{
     ff::FieldType,
     helpers::{
         transport::{routing::RouteId, BodyStream, NoQueryId, NoStep},
-        GatewayConfig, RoleAssignment, RouteParams,
+        GatewayConfig, RoleAssignment, RouteParams, StreamCompression,
     },
-    protocol::QueryId,
+    hpke::ResultEncryptionKey,
+    protocol::{context::dzkp_validator::MIN_PROOF_SIZE, QueryId},
 };
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Serialize)]
@@ -84,12 +85,48 @@ pub struct QueryConfig {
     pub size: QuerySize,
     pub field_type: FieldType,
     pub query_type: QueryType,
+    /// If set, helpers encrypt query results to this key and sign them, instead of returning
+    /// plaintext shares.
+    #[serde(default)]
+    pub result_key: Option<ResultEncryptionKey>,
+    /// Compression of the record streams helpers send to each other while running this query.
+    #[serde(default)]
+    pub compression: StreamCompression,
+    /// Number of bit multiplications covered by each zero-knowledge proof in malicious protocols.
+    /// Larger proofs need more memory, smaller ones more rounds of communication. Helpers use
+    /// their default proof size if this is not set. It can't be smaller than [`MIN_PROOF_SIZE`].
+    #[serde(default, deserialize_with = "deserialize_proof_size")]
+    pub proof_size: Option<NonZeroUsize>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum QueryConfigError {
     #[error(transparent)]
     BadQuerySize(#[from] BadQuerySizeError),
+    #[error("proof size {0} is smaller than the minimum of {}", MIN_PROOF_SIZE)]
+    ProofSizeTooSmall(NonZeroUsize),
+}
+
+/// Checks that `proof_size` is large enough for helpers to accept it.
+///
+/// ## Errors
+/// If `proof_size` is smaller than [`MIN_PROOF_SIZE`].
+pub fn check_proof_size(proof_size: NonZeroUsize) -> Result<NonZeroUsize, QueryConfigError> {
+    if proof_size.get() < MIN_PROOF_SIZE {
+        Err(QueryConfigError::ProofSizeTooSmall(proof_size))
+    } else {
+        Ok(proof_size)
+    }
+}
+
+fn deserialize_proof_size<'de, D>(deserializer: D) -> Result<Option<NonZeroUsize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<NonZeroUsize>::deserialize(deserializer)?
+        .map(check_proof_size)
+        .transpose()
+        .map_err(serde::de::Error::custom)
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -158,6 +195,10 @@ impl From<&QueryConfig> for GatewayConfig {
         );
         // we set active to be at least 2, so unwrap is fine.
         config.active = NonZeroUsize::new(active).unwrap();
+        config.compression = value.compression;
+        if let Some(proof_size) = value.proof_size {
+            config.target_proof_size = proof_size;
+        }
 
         config
     }
@@ -180,8 +221,34 @@ impl QueryConfig {
             size: size.try_into()?,
             field_type,
             query_type,
+            result_key: None,
+            compression: StreamCompression::None,
+            proof_size: None,
         })
     }
+
+    /// Requests helpers to encrypt query results to `result_key`.
+    #[must_use]
+    pub fn with_result_key(mut self, result_key: ResultEncryptionKey) -> Self {
+        self.result_key = Some(result_key);
+        self
+    }
+
+    /// Requests helpers to compress the record streams they send to each other.
+    #[must_use]
+    pub fn with_compression(mut self, compression: StreamCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Requests helpers to cover `proof_size` bit multiplications with each zero-knowledge proof.
+    ///
+    /// ## Errors
+    /// If `proof_size` is smaller than [`MIN_PROOF_SIZE`].
+    pub fn with_proof_size(mut self, proof_size: NonZeroUsize) -> Result<Self, QueryConfigError> {
+        self.proof_size = Some(check_proof_size(proof_size)?);
+        Ok(self)
+    }
 }
 
 impl RouteParams<RouteId, QueryId, NoStep> for &PrepareQuery {
@@ -277,6 +344,16 @@ pub struct IpaQueryConfig {
     #[cfg_attr(feature = "clap", arg(long))]
     #[serde(default)]
     pub plaintext_match_keys: bool,
+
+    /// If true, input reports are in the format that predates report versions. They are not
+    /// prefixed with a version and are decrypted as [`ReportVersion::V1`] reports. Otherwise,
+    /// every report must be prefixed with one of the [`ReportVersion::SUPPORTED`] versions.
+    ///
+    /// [`ReportVersion::V1`]: crate::report::ReportVersion::V1
+    /// [`ReportVersion::SUPPORTED`]: crate::report::ReportVersion::SUPPORTED
+    #[cfg_attr(feature = "clap", arg(long))]
+    #[serde(default)]
+    pub unversioned_reports: bool,
 }
 
 impl Default for IpaQueryConfig {
@@ -289,6 +366,7 @@ impl Default for IpaQueryConfig {
             with_dp: 1,
             epsilon: 5.0,
             plaintext_match_keys: false,
+            unversioned_reports: false,
         }
     }
 }
@@ -317,6 +395,7 @@ impl IpaQueryConfig {
             epsilon,
             // dp_params,
             plaintext_match_keys: false,
+            unversioned_reports: false,
         }
     }
 
@@ -340,6 +419,7 @@ impl IpaQueryConfig {
             with_dp,
             epsilon,
             plaintext_match_keys: false,
+            unversioned_reports: false,
         }
     }
 }
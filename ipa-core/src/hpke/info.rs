@@ -2,6 +2,16 @@ use crate::report::{Epoch, EventType, KeyIdentifier, NonAsciiStringError};
 
 const DOMAIN: &str = "private-attribution";
 
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidInfoError {
+    #[error(
+        "bad helper origin \"{0}\": it must be a non-empty ASCII string without NUL characters"
+    )]
+    HelperOrigin(String),
+    #[error("bad site_domain: {0}")]
+    SiteDomain(#[from] NonAsciiStringError),
+}
+
 /// Represents the [`info`] part of the receiver context, that is: application specific data
 /// for each encryption.
 ///
@@ -23,22 +33,23 @@ impl<'a> Info<'a> {
     /// Creates a new instance.
     ///
     /// ## Errors
-    /// if helper or site origin is not a valid ASCII string.
+    /// if helper origin is not valid (see [`validate_helper_origin`]) or site origin is not
+    /// a valid ASCII string.
+    ///
+    /// [`validate_helper_origin`]: Self::validate_helper_origin
     pub fn new(
         key_id: KeyIdentifier,
         epoch: Epoch,
         event_type: EventType,
         helper_origin: &'a str,
         site_domain: &'a str,
-    ) -> Result<Self, NonAsciiStringError> {
+    ) -> Result<Self, InvalidInfoError> {
         // If the types of errors returned from this function change, then the validation in
         // `EncryptedReport::from_bytes` may need to change as well.
-        if !helper_origin.is_ascii() {
-            return Err(helper_origin.into());
-        }
+        Self::validate_helper_origin(helper_origin)?;
 
         if !site_domain.is_ascii() {
-            return Err(site_domain.into());
+            return Err(NonAsciiStringError::from(site_domain).into());
         }
 
         Ok(Self {
@@ -50,6 +61,23 @@ impl<'a> Info<'a> {
         })
     }
 
+    /// Checks that `helper_origin` can be used to build the info string. Helper origin identifies
+    /// the helper network, so ciphertexts created for one network can't be opened by another.
+    /// It must be a non-empty ASCII string and must not include the NUL character, because
+    /// it is used as a delimiter.
+    ///
+    /// ## Errors
+    /// If `helper_origin` does not satisfy the requirements above.
+    pub fn validate_helper_origin(helper_origin: &str) -> Result<(), InvalidInfoError> {
+        if helper_origin.is_empty() || !helper_origin.is_ascii() || helper_origin.contains('\0') {
+            return Err(InvalidInfoError::HelperOrigin(
+                helper_origin.escape_default().to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
     /// Converts this instance into an owned byte slice that can further be used to create HPKE
     /// sender or receiver context.
     pub(super) fn to_bytes(&self) -> Box<[u8]> {
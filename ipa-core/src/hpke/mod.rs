@@ -14,11 +14,19 @@ use typenum::U16;
 
 mod info;
 mod registry;
+mod result;
+mod rotation;
 
-pub use info::Info;
+pub use info::{Info, InvalidInfoError};
 pub use registry::{
-    KeyPair, KeyRegistry, PrivateKeyOnly, PrivateKeyRegistry, PublicKeyOnly, PublicKeyRegistry,
+    KeyPair, KeyRegistry, KeyRegistryError, PrivateKeyOnly, PrivateKeyRegistry, PublicKeyOnly,
+    PublicKeyRegistry,
 };
+pub use result::{
+    open_result, seal_result, BadVerifyingKey, InvalidKeyError, OpenResultError, ResultBinding,
+    ResultEncryptionKey, ResultSigningKey, ResultVerifyingKey,
+};
+pub use rotation::{RotatingKeyRegistry, ScheduledKey, Validity};
 
 use crate::{
     ff::{GaloisField, Serializable as IpaSerializable},
@@ -85,7 +93,7 @@ impl From<io::Error> for CryptError {
 
 /// Opens the given ciphertext in place by first obtaining the secret key from `key_registry`
 /// using epoch and key from the `info` parameter and then applying [`HPKE decryption`]
-/// to the provided ciphertext.
+/// to the provided ciphertext. `aad` must match the associated data provided at encryption time.
 ///
 /// This function mutates the provided ciphertext slice and replaces it with the plaintext obtained
 /// after opening the ciphertext. The result will contain a pointer to the plaintext slice.
@@ -101,6 +109,7 @@ pub fn open_in_place<'a, R: PrivateKeyRegistry>(
     enc: &[u8],
     ciphertext: &'a mut [u8],
     info: &Info,
+    aad: &[u8],
 ) -> Result<&'a [u8], CryptError> {
     let key_id = info.key_id;
     let info = info.to_bytes();
@@ -117,7 +126,7 @@ pub fn open_in_place<'a, R: PrivateKeyRegistry>(
         &encap_key,
         &info,
         ct,
-        &[],
+        aad,
         &tag,
     )?;
 
@@ -134,12 +143,15 @@ pub(crate) type Ciphertext<'a> = (
     AeadTag<IpaAead>,
 );
 
+/// Seals `plaintext` in place, authenticating both `info` and `aad`.
+///
 /// ## Errors
 /// If the match key cannot be sealed for any reason.
 pub(crate) fn seal_in_place<'a, R: CryptoRng + RngCore, K: PublicKeyRegistry>(
     key_registry: &K,
     plaintext: &'a mut [u8],
     info: &'a Info,
+    aad: &[u8],
     rng: &mut R,
 ) -> Result<Ciphertext<'a>, CryptError> {
     let key_id = info.key_id;
@@ -153,7 +165,7 @@ pub(crate) fn seal_in_place<'a, R: CryptoRng + RngCore, K: PublicKeyRegistry>(
         pk_r,
         &info,
         plaintext,
-        &[],
+        aad,
         rng,
     )?;
 
@@ -171,7 +183,10 @@ mod tests {
 
     use crate::{
         ff::{Gf40Bit, Serializable as IpaSerializable},
-        hpke::{open_in_place, seal_in_place, CryptError, Info, IpaAead, KeyPair, KeyRegistry},
+        hpke::{
+            open_in_place, seal_in_place, CryptError, Info, InvalidInfoError, IpaAead, KeyPair,
+            KeyRegistry,
+        },
         report::{Epoch, EventType, KeyIdentifier},
         secret_sharing::replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
     };
@@ -234,6 +249,7 @@ mod tests {
                 &self.registry,
                 plaintext.as_mut_slice(),
                 &info,
+                &[],
                 &mut self.rng,
             )
             .unwrap();
@@ -282,7 +298,7 @@ mod tests {
                 Self::SITE_DOMAIN,
             )
             .unwrap();
-            open_in_place(&self.registry, &enc.enc, enc.ct.as_mut(), &info)?;
+            open_in_place(&self.registry, &enc.enc, enc.ct.as_mut(), &info, &[])?;
 
             // TODO: fix once array split is a thing.
             Ok(XorReplicated::deserialize_infallible(
@@ -312,6 +328,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn invalid_helper_origin() {
+        for origin in ["", "foo\0bar", "h\u{e9}lper"] {
+            assert!(matches!(
+                Info::new(0, 0, EventType::Source, origin, "bar"),
+                Err(InvalidInfoError::HelperOrigin(_))
+            ));
+        }
+    }
+
     #[test]
     fn decrypt_happy_case() {
         let rng = StdRng::from_seed([1_u8; 32]);
@@ -467,7 +493,7 @@ mod tests {
                     _ => panic!("bad test setup: only 5 fields can be corrupted, asked to corrupt: {corrupted_info_field}")
                 };
 
-                open_in_place(&suite.registry, &encryption.enc, &mut encryption.ct, &info).unwrap_err();
+                open_in_place(&suite.registry, &encryption.enc, &mut encryption.ct, &info, &[]).unwrap_err();
             }
         }
     }
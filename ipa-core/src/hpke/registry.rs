@@ -7,8 +7,8 @@ use super::{IpaPrivateKey, IpaPublicKey, KeyIdentifier};
 /// A pair of secret key and public key. Public keys used by UA to encrypt the data towards helpers
 /// secret keys used by helpers to open the ciphertexts. Each helper needs access to both
 pub struct KeyPair {
-    pk: IpaPublicKey,
-    sk: IpaPrivateKey,
+    pub(super) pk: IpaPublicKey,
+    pub(super) sk: IpaPrivateKey,
 }
 
 impl From<(IpaPrivateKey, IpaPublicKey)> for KeyPair {
@@ -20,6 +20,13 @@ impl From<(IpaPrivateKey, IpaPublicKey)> for KeyPair {
     }
 }
 
+impl From<IpaPrivateKey> for KeyPair {
+    fn from(sk: IpaPrivateKey) -> Self {
+        let pk = <super::IpaKem as hpke::Kem>::sk_to_pk(&sk);
+        Self { pk, sk }
+    }
+}
+
 impl KeyPair {
     pub fn gen<R: rand::RngCore + rand::CryptoRng>(mut r: &mut R) -> Self {
         <super::IpaKem as hpke::Kem>::gen_keypair(&mut r).into()
@@ -81,8 +88,18 @@ pub trait PrivateKeyRegistry: Send + Sync + 'static {
 }
 
 /// A registry that holds all the keys available for helper/UA to use.
+///
+/// Keys are indexed by their [`KeyIdentifier`]. Identifiers do not need to be contiguous, which
+/// allows helpers to retire old keys and introduce new ones without renumbering the keys that are
+/// still in use.
 pub struct KeyRegistry<K> {
-    keys: Box<[K]>,
+    keys: Box<[Option<K>]>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyRegistryError {
+    #[error("Key {0} is specified more than once")]
+    DuplicateKey(KeyIdentifier),
 }
 
 impl<K> KeyRegistry<K> {
@@ -93,28 +110,69 @@ impl<K> KeyRegistry<K> {
         Self { keys: Box::new([]) }
     }
 
+    /// Creates a registry from the given keys. The position of each key in `pairs` becomes
+    /// its identifier.
     pub fn from_keys<const N: usize, I: Into<K>>(pairs: [I; N]) -> Self {
         Self {
             keys: pairs
                 .into_iter()
-                .map(Into::into)
+                .map(|k| Some(k.into()))
                 .collect::<Vec<_>>()
                 .into_boxed_slice(),
         }
     }
 
-    fn key(&self, key_id: KeyIdentifier) -> Option<&K> {
-        match key_id as usize {
-            key_id if key_id < self.keys.len() => Some(&self.keys[key_id]),
-            _ => None,
+    /// Creates a registry from keys with explicitly assigned identifiers.
+    ///
+    /// ## Errors
+    /// If the same identifier is used for more than one key.
+    pub fn from_identified_keys<I: Into<K>, T: IntoIterator<Item = (KeyIdentifier, I)>>(
+        keys: T,
+    ) -> Result<Self, KeyRegistryError> {
+        let mut slots = Vec::new();
+        for (key_id, key) in keys {
+            let idx = usize::from(key_id);
+            if slots.len() <= idx {
+                slots.resize_with(idx + 1, || None);
+            }
+            if slots[idx].replace(key.into()).is_some() {
+                return Err(KeyRegistryError::DuplicateKey(key_id));
+            }
         }
+
+        Ok(Self {
+            keys: slots.into_boxed_slice(),
+        })
+    }
+
+    /// Returns the identifiers of all keys stored in this registry, in ascending order.
+    pub fn key_ids(&self) -> impl Iterator<Item = KeyIdentifier> + '_ {
+        self.keys
+            .iter()
+            .enumerate()
+            .filter_map(|(id, k)| k.as_ref().and(KeyIdentifier::try_from(id).ok()))
+    }
+
+    /// Consumes this registry and returns all keys along with their identifiers.
+    pub(super) fn into_identified_keys(self) -> impl Iterator<Item = (KeyIdentifier, K)> {
+        self.keys
+            .into_vec()
+            .into_iter()
+            .enumerate()
+            .filter_map(|(id, k)| Some((KeyIdentifier::try_from(id).ok()?, k?)))
+    }
+
+    fn key(&self, key_id: KeyIdentifier) -> Option<&K> {
+        self.keys.get(usize::from(key_id)).and_then(Option::as_ref)
     }
 }
 
 impl KeyRegistry<KeyPair> {
     #[cfg(any(test, feature = "test-fixture"))]
     pub fn random<R: rand::RngCore + rand::CryptoRng>(keys_count: usize, r: &mut R) -> Self {
-        let keys = (0..keys_count).map(|_| KeyPair::gen(r)).collect::<Vec<_>>();
+        let keys = (0..keys_count)
+            .map(|_| Some(KeyPair::gen(r)))
+            .collect::<Vec<_>>();
 
         Self {
             keys: keys.into_boxed_slice(),
@@ -221,4 +279,37 @@ mod tests {
             decrypt(private_registry.private_key(0).unwrap(), &ct_payload).unwrap_err()
         );
     }
+
+    #[test]
+    fn sparse_key_ids() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let registry = KeyRegistry::<KeyPair>::from_identified_keys([
+            (3, KeyPair::gen(&mut rng)),
+            (7, KeyPair::gen(&mut rng)),
+        ])
+        .unwrap();
+
+        assert_eq!(vec![3, 7], registry.key_ids().collect::<Vec<_>>());
+        assert!(registry.public_key(0).is_none());
+        assert!(registry.private_key(5).is_none());
+        assert!(registry.private_key(200).is_none());
+
+        let pt = b"This is a plaintext.";
+        let ct_payload = encrypt(registry.public_key(7).unwrap(), pt, &mut rng);
+        assert_eq!(
+            Ok(pt.to_vec()),
+            decrypt(registry.private_key(7).unwrap(), &ct_payload)
+        );
+    }
+
+    #[test]
+    fn duplicate_key_ids() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let r = KeyRegistry::<KeyPair>::from_identified_keys([
+            (1, KeyPair::gen(&mut rng)),
+            (1, KeyPair::gen(&mut rng)),
+        ]);
+
+        assert!(matches!(r, Err(KeyRegistryError::DuplicateKey(1))));
+    }
 }
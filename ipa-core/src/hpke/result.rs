@@ -0,0 +1,624 @@
+//! Encryption and authentication of query results.
+//!
+//! When report collector asks for it, each helper encrypts its share of the query results
+//! to the public key that report collector supplied when it created the query, and signs
+//! the ciphertext with its own signing key. Sealed results can be relayed through untrusted
+//! storage: only the report collector can open them, and it can verify which helper produced
+//! them before reconstructing the shares.
+//!
+//! Sealed result layout:
+//! ```text
+//! helper identity (1 byte) | encapsulated key (32 bytes) | ciphertext | tag (16 bytes) | signature (64 bytes)
+//! ```
+//! The signature covers all bytes that precede it, as well as the helper origin, the query id
+//! and the parts of the query configuration that determine its results, so a sealed result can't
+//! be passed off as the result of another query.
+
+use std::{
+    fmt::{Debug, Display, Formatter},
+    num::NonZeroU32,
+    str::FromStr,
+};
+
+use ed25519_dalek::{Signer, Verifier};
+use hpke::{
+    aead::AeadTag, single_shot_open_in_place_detached, single_shot_seal_in_place_detached, OpModeR,
+    OpModeS,
+};
+use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use typenum::Unsigned;
+
+use super::{
+    CryptError, Deserializable, EncapsulationSize, IpaAead, IpaKdf, IpaKem, IpaPublicKey, KeyPair,
+    Serializable,
+};
+use crate::{
+    ff::FieldType,
+    helpers::{
+        query::{QueryConfig, QueryType},
+        HelperIdentity,
+    },
+    protocol::QueryId,
+};
+
+const DOMAIN: &str = "private-attribution-result";
+
+/// Version of the query configuration encoding that sealed results are bound to. It must change
+/// whenever [`encode_config`] does.
+const CONFIG_ENCODING_VERSION: u8 = 1;
+
+const SIGNATURE_LEN: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidKeyError {
+    #[error("key must be a hex string: {0}")]
+    Hex(#[from] hex::FromHexError),
+    #[error("key must be {expected} bytes long, got {actual}")]
+    Length { expected: usize, actual: usize },
+}
+
+fn key_from_hex<const N: usize>(s: &str) -> Result<[u8; N], InvalidKeyError> {
+    let bytes = hex::decode(s.trim())?;
+    <[u8; N]>::try_from(bytes.as_slice()).map_err(|_| InvalidKeyError::Length {
+        expected: N,
+        actual: bytes.len(),
+    })
+}
+
+/// Public key that report collector supplies at query creation. Helpers encrypt query results
+/// to this key.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct ResultEncryptionKey([u8; 32]);
+
+impl ResultEncryptionKey {
+    fn public_key(&self) -> Result<IpaPublicKey, CryptError> {
+        Ok(IpaPublicKey::from_bytes(&self.0)?)
+    }
+}
+
+impl From<&KeyPair> for ResultEncryptionKey {
+    fn from(value: &KeyPair) -> Self {
+        Self(value.pk.to_bytes().into())
+    }
+}
+
+impl Debug for ResultEncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ResultEncryptionKey({self})")
+    }
+}
+
+impl Display for ResultEncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for ResultEncryptionKey {
+    type Err = InvalidKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(key_from_hex(s)?))
+    }
+}
+
+impl Serialize for ResultEncryptionKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ResultEncryptionKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Key that helper uses to sign query results.
+pub struct ResultSigningKey(ed25519_dalek::SigningKey);
+
+impl ResultSigningKey {
+    pub fn gen<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        Self(ed25519_dalek::SigningKey::generate(rng))
+    }
+
+    #[must_use]
+    pub fn verifying_key(&self) -> ResultVerifyingKey {
+        ResultVerifyingKey(self.0.verifying_key())
+    }
+
+    /// Returns the secret key bytes.
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+}
+
+impl Debug for ResultSigningKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // never print the secret key
+        write!(f, "ResultSigningKey({})", self.verifying_key())
+    }
+}
+
+impl FromStr for ResultSigningKey {
+    type Err = InvalidKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(ed25519_dalek::SigningKey::from_bytes(&key_from_hex(
+            s,
+        )?)))
+    }
+}
+
+/// Public counterpart of [`ResultSigningKey`]. Report collectors use it to verify that results
+/// were produced by a given helper.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct ResultVerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl ResultVerifyingKey {
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+}
+
+impl Debug for ResultVerifyingKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ResultVerifyingKey({self})")
+    }
+}
+
+impl Display for ResultVerifyingKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.0.as_bytes()))
+    }
+}
+
+impl FromStr for ResultVerifyingKey {
+    type Err = BadVerifyingKey;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = key_from_hex(s)?;
+        Ok(Self(
+            ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+                .map_err(|_| BadVerifyingKey::NotOnCurve)?,
+        ))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BadVerifyingKey {
+    #[error(transparent)]
+    Format(#[from] InvalidKeyError),
+    #[error("verifying key is not a valid Ed25519 point")]
+    NotOnCurve,
+}
+
+impl Serialize for ResultVerifyingKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ResultVerifyingKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OpenResultError {
+    #[error("sealed result is too short: {0}, expected length at least: {1}")]
+    Length(usize, usize),
+    #[error("sealed result was produced by helper {actual:?}, expected {expected:?}")]
+    WrongHelper {
+        expected: HelperIdentity,
+        actual: u8,
+    },
+    #[error("sealed result signature is not valid")]
+    Signature,
+    #[error(transparent)]
+    Crypt(#[from] CryptError),
+}
+
+/// Everything that a sealed result is bound to, besides its contents. Report collector must
+/// supply the same values that the helper used to seal the result in order to open it.
+#[derive(Copy, Clone, Debug)]
+pub struct ResultBinding<'a> {
+    /// Helper that produced the result.
+    pub helper: HelperIdentity,
+    pub helper_origin: &'a str,
+    pub query_id: QueryId,
+    /// Configuration of the query, as the report collector created it.
+    pub config: &'a QueryConfig,
+}
+
+fn info(helper_origin: &str, helper: HelperIdentity) -> Vec<u8> {
+    let mut info = Vec::with_capacity(DOMAIN.len() + helper_origin.len() + 3);
+    info.extend_from_slice(DOMAIN.as_bytes());
+    info.push(0);
+    info.extend_from_slice(helper_origin.as_bytes());
+    info.push(0);
+    info.push(helper.into());
+
+    info
+}
+
+/// Encodes the parts of `config` that determine query results. Fields are written in a fixed
+/// order, using fixed-width little-endian integers, so the encoding does not depend on how
+/// configuration is serialized anywhere else. Settings that only change how helpers run the
+/// query, like stream compression and proof size, are left out.
+fn encode_config(config: &QueryConfig) -> Vec<u8> {
+    let mut out = vec![CONFIG_ENCODING_VERSION];
+    out.extend_from_slice(&u32::from(config.size).to_le_bytes());
+    out.push(match config.field_type {
+        #[cfg(any(test, feature = "weak-field"))]
+        FieldType::Fp31 => 1,
+        FieldType::Fp32BitPrime => 2,
+    });
+    let query_type = config.query_type.as_ref();
+    out.push(u8::try_from(query_type.len()).expect("query type names are short"));
+    out.extend_from_slice(query_type.as_bytes());
+    match config.query_type {
+        #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
+        QueryType::TestMultiply | QueryType::TestAddInPrimeField => {}
+        QueryType::OprfIpa(ipa_config) => {
+            for value in [
+                ipa_config.per_user_credit_cap,
+                ipa_config.max_breakdown_key,
+                ipa_config
+                    .attribution_window_seconds
+                    .map_or(0, NonZeroU32::get),
+                ipa_config.num_multi_bits,
+                ipa_config.with_dp,
+            ] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            out.extend_from_slice(&ipa_config.epsilon.to_le_bytes());
+            out.push(ipa_config.plaintext_match_keys.into());
+            out.push(ipa_config.unversioned_reports.into());
+        }
+    }
+    match config.result_key {
+        Some(key) => {
+            out.push(1);
+            out.extend_from_slice(&key.0);
+        }
+        None => out.push(0),
+    }
+
+    out
+}
+
+fn signed_message(binding: &ResultBinding, body: &[u8]) -> Vec<u8> {
+    let config = encode_config(binding.config);
+    let query_id = binding.query_id.as_ref();
+    let mut msg = Vec::with_capacity(
+        DOMAIN.len() + binding.helper_origin.len() + query_id.len() + config.len() + body.len() + 4,
+    );
+    msg.extend_from_slice(DOMAIN.as_bytes());
+    msg.push(0);
+    msg.extend_from_slice(binding.helper_origin.as_bytes());
+    msg.push(0);
+    msg.extend_from_slice(query_id.as_bytes());
+    msg.push(0);
+    msg.extend_from_slice(&config);
+    msg.push(0);
+    msg.extend_from_slice(body);
+
+    msg
+}
+
+/// Encrypts `results` to the report collector key `recipient` and signs them with
+/// `signing_key` on behalf of the helper in `binding`.
+///
+/// ## Errors
+/// If `recipient` is not a valid public key or encryption fails.
+pub fn seal_result<R: CryptoRng + RngCore>(
+    results: &[u8],
+    binding: &ResultBinding,
+    recipient: &ResultEncryptionKey,
+    signing_key: &ResultSigningKey,
+    rng: &mut R,
+) -> Result<Vec<u8>, CryptError> {
+    let pk_r = recipient.public_key()?;
+    let mut ciphertext = results.to_vec();
+    let (encap_key, tag) = single_shot_seal_in_place_detached::<IpaAead, IpaKdf, IpaKem, _>(
+        &OpModeS::Base,
+        &pk_r,
+        &info(binding.helper_origin, binding.helper),
+        &mut ciphertext,
+        &[],
+        rng,
+    )?;
+
+    let mut out = Vec::with_capacity(
+        1 + EncapsulationSize::USIZE
+            + ciphertext.len()
+            + AeadTag::<IpaAead>::size()
+            + SIGNATURE_LEN,
+    );
+    out.push(binding.helper.into());
+    out.extend_from_slice(&encap_key.to_bytes());
+    out.extend_from_slice(&ciphertext);
+    out.extend_from_slice(&tag.to_bytes());
+    let signature = signing_key.0.sign(&signed_message(binding, &out));
+    out.extend_from_slice(&signature.to_bytes());
+
+    Ok(out)
+}
+
+/// Verifies that `sealed` was produced by the helper in `binding` for the query in `binding`, and
+/// decrypts it using the report collector key `recipient`.
+///
+/// ## Errors
+/// If the result was produced by a different helper, the signature does not match
+/// `verifying_key` and `binding` or the result cannot be decrypted.
+pub fn open_result(
+    sealed: &[u8],
+    binding: &ResultBinding,
+    verifying_key: &ResultVerifyingKey,
+    recipient: &KeyPair,
+) -> Result<Vec<u8>, OpenResultError> {
+    let min_len = 1 + EncapsulationSize::USIZE + AeadTag::<IpaAead>::size() + SIGNATURE_LEN;
+    if sealed.len() < min_len {
+        return Err(OpenResultError::Length(sealed.len(), min_len));
+    }
+    if sealed[0] != u8::from(binding.helper) {
+        return Err(OpenResultError::WrongHelper {
+            expected: binding.helper,
+            actual: sealed[0],
+        });
+    }
+
+    let (body, signature) = sealed.split_at(sealed.len() - SIGNATURE_LEN);
+    let signature =
+        ed25519_dalek::Signature::from_slice(signature).map_err(|_| OpenResultError::Signature)?;
+    verifying_key
+        .0
+        .verify(&signed_message(binding, body), &signature)
+        .map_err(|_| OpenResultError::Signature)?;
+
+    let (encap_key, ciphertext) = body[1..].split_at(EncapsulationSize::USIZE);
+    let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - AeadTag::<IpaAead>::size());
+    let encap_key =
+        <IpaKem as hpke::Kem>::EncappedKey::from_bytes(encap_key).map_err(CryptError::from)?;
+    let tag = AeadTag::<IpaAead>::from_bytes(tag).map_err(CryptError::from)?;
+
+    let mut plaintext = ciphertext.to_vec();
+    single_shot_open_in_place_detached::<_, IpaKdf, IpaKem>(
+        &OpModeR::Base,
+        &recipient.sk,
+        &encap_key,
+        &info(binding.helper_origin, binding.helper),
+        &mut plaintext,
+        &[],
+        &tag,
+    )
+    .map_err(CryptError::from)?;
+
+    Ok(plaintext)
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use rand::{rngs::StdRng, thread_rng};
+    use rand_core::SeedableRng;
+
+    use std::num::NonZeroUsize;
+
+    use super::{
+        encode_config, open_result, seal_result, OpenResultError, ResultBinding,
+        ResultEncryptionKey, ResultSigningKey, ResultVerifyingKey,
+    };
+    use crate::{
+        ff::FieldType,
+        helpers::{
+            query::{IpaQueryConfig, QueryConfig, QueryType},
+            HelperIdentity, StreamCompression,
+        },
+        hpke::{CryptError, KeyPair},
+        protocol::QueryId,
+    };
+
+    const ORIGIN: &str = "helpers.example.com";
+
+    fn config(size: u32) -> QueryConfig {
+        QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, size).unwrap()
+    }
+
+    #[test]
+    fn seal_open_roundtrip() {
+        let mut rng = thread_rng();
+        let rc_keys = KeyPair::gen(&mut rng);
+        let signing_key = ResultSigningKey::gen(&mut rng);
+        let [h1, ..] = HelperIdentity::make_three();
+        let config = config(1);
+        let binding = ResultBinding {
+            helper: h1,
+            helper_origin: ORIGIN,
+            query_id: QueryId,
+            config: &config,
+        };
+
+        let sealed = seal_result(
+            b"shares",
+            &binding,
+            &ResultEncryptionKey::from(&rc_keys),
+            &signing_key,
+            &mut rng,
+        )
+        .unwrap();
+
+        assert_eq!(
+            b"shares".as_slice(),
+            open_result(&sealed, &binding, &signing_key.verifying_key(), &rc_keys).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let mut rng = thread_rng();
+        let rc_keys = KeyPair::gen(&mut rng);
+        let signing_key = ResultSigningKey::gen(&mut rng);
+        let [h1, h2, _] = HelperIdentity::make_three();
+        let vk = signing_key.verifying_key();
+        let config = config(1);
+        let binding = ResultBinding {
+            helper: h1,
+            helper_origin: ORIGIN,
+            query_id: QueryId,
+            config: &config,
+        };
+
+        let sealed = seal_result(
+            &[1, 2, 3, 4],
+            &binding,
+            &ResultEncryptionKey::from(&rc_keys),
+            &signing_key,
+            &mut rng,
+        )
+        .unwrap();
+
+        let mut tampered = sealed.clone();
+        tampered[40] ^= 1;
+        assert!(matches!(
+            open_result(&tampered, &binding, &vk, &rc_keys),
+            Err(OpenResultError::Signature)
+        ));
+        assert!(matches!(
+            open_result(
+                &sealed,
+                &ResultBinding {
+                    helper: h2,
+                    ..binding
+                },
+                &vk,
+                &rc_keys
+            ),
+            Err(OpenResultError::WrongHelper { .. })
+        ));
+        assert!(matches!(
+            open_result(
+                &sealed,
+                &ResultBinding {
+                    helper_origin: "other.example.com",
+                    ..binding
+                },
+                &vk,
+                &rc_keys
+            ),
+            Err(OpenResultError::Signature)
+        ));
+        // results of one query can't be passed off as results of another one
+        assert!(matches!(
+            open_result(
+                &sealed,
+                &ResultBinding {
+                    config: &config(2),
+                    ..binding
+                },
+                &vk,
+                &rc_keys
+            ),
+            Err(OpenResultError::Signature)
+        ));
+        assert!(matches!(
+            open_result(
+                &sealed,
+                &binding,
+                &ResultSigningKey::gen(&mut rng).verifying_key(),
+                &rc_keys
+            ),
+            Err(OpenResultError::Signature)
+        ));
+        assert!(matches!(
+            open_result(&sealed, &binding, &vk, &KeyPair::gen(&mut rng)),
+            Err(OpenResultError::Crypt(CryptError::Other))
+        ));
+        assert!(matches!(
+            open_result(&sealed[..10], &binding, &vk, &rc_keys),
+            Err(OpenResultError::Length(10, _))
+        ));
+    }
+
+    #[test]
+    fn binds_to_result_determining_config() {
+        let mut rng = thread_rng();
+        let rc_keys = KeyPair::gen(&mut rng);
+        let signing_key = ResultSigningKey::gen(&mut rng);
+        let vk = signing_key.verifying_key();
+        let [h1, ..] = HelperIdentity::make_three();
+        let config = QueryConfig::new(
+            QueryType::OprfIpa(IpaQueryConfig::default()),
+            FieldType::Fp32BitPrime,
+            10,
+        )
+        .unwrap();
+        let binding = ResultBinding {
+            helper: h1,
+            helper_origin: ORIGIN,
+            query_id: QueryId,
+            config: &config,
+        };
+        let sealed = seal_result(
+            b"shares",
+            &binding,
+            &ResultEncryptionKey::from(&rc_keys),
+            &signing_key,
+            &mut rng,
+        )
+        .unwrap();
+        let open = |config: &QueryConfig| {
+            open_result(&sealed, &ResultBinding { config, ..binding }, &vk, &rc_keys)
+        };
+
+        // how helpers ran the query does not change its results
+        let tuned = config
+            .with_compression(StreamCompression::Packed)
+            .with_proof_size(NonZeroUsize::new(1 << 17).unwrap())
+            .unwrap();
+        assert_eq!(b"shares".as_slice(), open(&tuned).unwrap());
+
+        let other_cap = QueryConfig {
+            query_type: QueryType::OprfIpa(IpaQueryConfig {
+                per_user_credit_cap: 16,
+                ..IpaQueryConfig::default()
+            }),
+            ..config
+        };
+        assert!(matches!(open(&other_cap), Err(OpenResultError::Signature)));
+        let other_key = config.with_result_key(ResultEncryptionKey::from(&rc_keys));
+        assert!(matches!(open(&other_key), Err(OpenResultError::Signature)));
+    }
+
+    /// Sealed results must keep opening after unrelated changes to how query configuration is
+    /// serialized, so the encoding they are bound to is fixed.
+    #[test]
+    fn config_encoding_is_stable() {
+        // version, size, field type, query type and no result key
+        let expected = [&[1, 2, 0, 0, 0, 1, 13][..], b"test-multiply", &[0]].concat();
+        assert_eq!(expected, encode_config(&config(2)));
+    }
+
+    #[test]
+    fn keys_from_str() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let signing_key = ResultSigningKey::gen(&mut rng);
+        let parsed = hex::encode(signing_key.to_bytes())
+            .parse::<ResultSigningKey>()
+            .unwrap();
+        assert_eq!(signing_key.verifying_key(), parsed.verifying_key());
+
+        let vk = signing_key.verifying_key();
+        assert_eq!(vk, vk.to_string().parse::<ResultVerifyingKey>().unwrap());
+
+        let ek = ResultEncryptionKey::from(&KeyPair::gen(&mut rng));
+        assert_eq!(ek, ek.to_string().parse::<ResultEncryptionKey>().unwrap());
+        assert!("abcd".parse::<ResultEncryptionKey>().is_err());
+    }
+}
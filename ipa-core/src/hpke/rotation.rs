@@ -0,0 +1,317 @@
+use std::{
+    fmt::{Debug, Formatter},
+    time::SystemTime,
+};
+
+use super::{IpaPrivateKey, KeyIdentifier, KeyRegistry, KeyRegistryError, PrivateKeyOnly};
+use crate::sync::{Arc, Mutex};
+
+/// Period of time during which a key can be used to open ciphertexts. Both bounds are optional,
+/// a missing bound means that key validity is not limited in that direction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Validity {
+    /// Key can't be used before this instant.
+    pub not_before: Option<SystemTime>,
+    /// Key can't be used at or after this instant.
+    pub not_after: Option<SystemTime>,
+}
+
+impl Validity {
+    #[must_use]
+    pub fn contains(&self, t: SystemTime) -> bool {
+        !self.not_before.is_some_and(|not_before| t < not_before) && !self.is_expired(t)
+    }
+
+    #[must_use]
+    pub fn is_expired(&self, t: SystemTime) -> bool {
+        self.not_after.is_some_and(|not_after| not_after <= t)
+    }
+}
+
+/// Private key that helper uses to open match keys along with its identifier and the time
+/// window during which it can be used.
+#[derive(Clone)]
+pub struct ScheduledKey {
+    pub key_id: KeyIdentifier,
+    pub private_key: IpaPrivateKey,
+    pub validity: Validity,
+}
+
+impl Debug for ScheduledKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // never print the private key
+        f.debug_struct("ScheduledKey")
+            .field("key_id", &self.key_id)
+            .field("validity", &self.validity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Private key registry that supports key rotation.
+///
+/// Unlike [`KeyRegistry`], the set of keys stored here can be replaced while the helper is
+/// running. Each key has a [`Validity`] window attached to it. Queries never use this registry
+/// directly, instead they obtain an immutable [`snapshot`] that includes only the keys valid at
+/// the time query started. That makes rotation invisible to queries that are already running.
+///
+/// [`snapshot`]: Self::snapshot
+pub struct RotatingKeyRegistry {
+    keys: Mutex<Vec<ScheduledKey>>,
+}
+
+impl Debug for RotatingKeyRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RotatingKeyRegistry{:?}", self.keys.lock().unwrap())
+    }
+}
+
+impl Default for RotatingKeyRegistry {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl From<KeyRegistry<PrivateKeyOnly>> for RotatingKeyRegistry {
+    fn from(registry: KeyRegistry<PrivateKeyOnly>) -> Self {
+        Self {
+            keys: Mutex::new(
+                registry
+                    .into_identified_keys()
+                    .map(|(key_id, sk)| ScheduledKey {
+                        key_id,
+                        private_key: sk.0,
+                        validity: Validity::default(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl RotatingKeyRegistry {
+    #[must_use]
+    pub fn empty() -> Self {
+        Self {
+            keys: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Creates a new registry with the given set of keys.
+    ///
+    /// ## Errors
+    /// If the same key identifier is used more than once.
+    pub fn new(keys: Vec<ScheduledKey>) -> Result<Self, KeyRegistryError> {
+        Self::validate(&keys)?;
+        Ok(Self {
+            keys: Mutex::new(keys),
+        })
+    }
+
+    /// Replaces all keys in this registry with `keys`. Queries that already obtained
+    /// a snapshot of this registry are not affected.
+    ///
+    /// ## Errors
+    /// If the same key identifier is used more than once. Existing keys are kept in this case.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn replace(&self, keys: Vec<ScheduledKey>) -> Result<(), KeyRegistryError> {
+        Self::validate(&keys)?;
+        *self.keys.lock().unwrap() = keys;
+
+        Ok(())
+    }
+
+    /// Removes all keys that expired at `now` and returns their identifiers.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    pub fn retire_expired(&self, now: SystemTime) -> Vec<KeyIdentifier> {
+        let mut retired = Vec::new();
+        self.keys.lock().unwrap().retain(|key| {
+            let expired = key.validity.is_expired(now);
+            if expired {
+                retired.push(key.key_id);
+            }
+            !expired
+        });
+
+        retired
+    }
+
+    /// Returns identifiers of all keys stored in this registry, including keys that are not
+    /// yet valid.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned.
+    #[must_use]
+    pub fn key_ids(&self) -> Vec<KeyIdentifier> {
+        let mut ids = self
+            .keys
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|key| key.key_id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        ids
+    }
+
+    /// Returns an immutable registry containing only the keys that are valid at `now`.
+    ///
+    /// ## Panics
+    /// If the mutex is poisoned. Key identifiers are validated every time keys are set, so
+    /// building the snapshot can't fail otherwise.
+    #[must_use]
+    pub fn snapshot(&self, now: SystemTime) -> Arc<KeyRegistry<PrivateKeyOnly>> {
+        let keys = self.keys.lock().unwrap();
+        let registry = KeyRegistry::from_identified_keys(
+            keys.iter()
+                .filter(|key| key.validity.contains(now))
+                .map(|key| (key.key_id, PrivateKeyOnly(key.private_key.clone()))),
+        )
+        .expect("key identifiers are unique");
+
+        Arc::new(registry)
+    }
+
+    fn validate(keys: &[ScheduledKey]) -> Result<(), KeyRegistryError> {
+        let mut seen = [false; 1 << KeyIdentifier::BITS];
+        for key in keys {
+            let seen = &mut seen[usize::from(key.key_id)];
+            if *seen {
+                return Err(KeyRegistryError::DuplicateKey(key.key_id));
+            }
+            *seen = true;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use hpke::Kem;
+    use rand::rngs::StdRng;
+    use rand_core::SeedableRng;
+
+    use super::{RotatingKeyRegistry, ScheduledKey, Validity};
+    use crate::hpke::{IpaKem, KeyRegistry, KeyRegistryError, PrivateKeyOnly, PrivateKeyRegistry};
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn key(key_id: u8, not_before: Option<u64>, not_after: Option<u64>) -> ScheduledKey {
+        let mut rng = StdRng::seed_from_u64(key_id.into());
+        let (private_key, _) = IpaKem::gen_keypair(&mut rng);
+        ScheduledKey {
+            key_id,
+            private_key,
+            validity: Validity {
+                not_before: not_before.map(at),
+                not_after: not_after.map(at),
+            },
+        }
+    }
+
+    #[test]
+    fn validity() {
+        let validity = Validity {
+            not_before: Some(at(10)),
+            not_after: Some(at(20)),
+        };
+        assert!(!validity.contains(at(9)));
+        assert!(validity.contains(at(10)));
+        assert!(validity.contains(at(19)));
+        assert!(!validity.contains(at(20)));
+        assert!(!validity.is_expired(at(19)));
+        assert!(validity.is_expired(at(20)));
+
+        assert!(Validity::default().contains(at(0)));
+        assert!(!Validity::default().is_expired(at(u64::from(u32::MAX))));
+    }
+
+    #[test]
+    fn snapshot_includes_valid_keys_only() {
+        let registry = RotatingKeyRegistry::new(vec![
+            key(0, None, Some(100)),
+            key(1, Some(50), Some(200)),
+            key(2, Some(150), None),
+        ])
+        .unwrap();
+
+        let snapshot = registry.snapshot(at(10));
+        assert_eq!(vec![0], snapshot.key_ids().collect::<Vec<_>>());
+
+        let snapshot = registry.snapshot(at(75));
+        assert_eq!(vec![0, 1], snapshot.key_ids().collect::<Vec<_>>());
+        assert!(snapshot.private_key(2).is_none());
+
+        let snapshot = registry.snapshot(at(175));
+        assert_eq!(vec![1, 2], snapshot.key_ids().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn replace_does_not_affect_snapshots() {
+        let registry = RotatingKeyRegistry::new(vec![key(0, None, None)]).unwrap();
+        let before = registry.snapshot(at(0));
+
+        registry.replace(vec![key(1, None, None)]).unwrap();
+        let after = registry.snapshot(at(0));
+
+        assert!(before.private_key(0).is_some());
+        assert!(before.private_key(1).is_none());
+        assert!(after.private_key(0).is_none());
+        assert!(after.private_key(1).is_some());
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        assert!(matches!(
+            RotatingKeyRegistry::new(vec![key(3, None, None), key(3, Some(1), None)]),
+            Err(KeyRegistryError::DuplicateKey(3))
+        ));
+
+        let registry = RotatingKeyRegistry::new(vec![key(0, None, None)]).unwrap();
+        registry
+            .replace(vec![key(1, None, None), key(1, None, None)])
+            .unwrap_err();
+        assert_eq!(vec![0], registry.key_ids());
+    }
+
+    #[test]
+    fn retire_expired() {
+        let registry = RotatingKeyRegistry::new(vec![
+            key(0, None, Some(100)),
+            key(1, None, Some(200)),
+            key(2, Some(300), None),
+        ])
+        .unwrap();
+
+        assert!(registry.retire_expired(at(50)).is_empty());
+        assert_eq!(vec![0], registry.retire_expired(at(100)));
+        assert_eq!(vec![1, 2], registry.key_ids());
+        assert_eq!(vec![1], registry.retire_expired(at(1000)));
+        assert_eq!(vec![2], registry.key_ids());
+    }
+
+    #[test]
+    fn from_fixed_registry() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let (sk, _) = IpaKem::gen_keypair(&mut rng);
+        let registry =
+            RotatingKeyRegistry::from(KeyRegistry::<PrivateKeyOnly>::from_keys([PrivateKeyOnly(
+                sk,
+            )]));
+
+        assert_eq!(vec![0], registry.key_ids());
+        assert!(registry
+            .snapshot(SystemTime::now())
+            .private_key(0)
+            .is_some());
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    cmp::min,
     collections::HashMap,
     future::Future,
     io::{self, BufRead},
@@ -11,7 +12,7 @@ use axum::{
     body::Body,
     http::uri::{self, Parts, Scheme},
 };
-use bytes::Bytes;
+use bytes::{Buf, Bytes, BytesMut};
 use futures::{stream::StreamExt, Stream};
 use http_body_util::BodyExt;
 use hyper::{header::HeaderName, http::HeaderValue, Request, Response, StatusCode, Uri};
@@ -22,19 +23,24 @@ use hyper_util::{
 };
 use pin_project::pin_project;
 use rustls::RootCertStore;
+use tokio::time::sleep;
 use tracing::error;
 
 use crate::{
     config::{
         ClientConfig, HyperClientConfigurator, NetworkConfig, OwnedCertificate, OwnedPrivateKey,
-        PeerConfig,
+        PeerConfig, StreamRetryConfig,
     },
     helpers::{
         query::{PrepareQuery, QueryConfig, QueryInput},
-        HelperIdentity,
+        DigestReport, HelperIdentity, StallReport,
+    },
+    net::{
+        http_serde, server::HTTP_CLIENT_ID_HEADER, step_stream::OutboundStream, Error,
+        CRYPTO_PROVIDER,
     },
-    net::{http_serde, server::HTTP_CLIENT_ID_HEADER, Error, CRYPTO_PROVIDER},
     protocol::{Gate, QueryId},
+    telemetry::trace::{TraceContext, TRACEPARENT},
 };
 
 #[derive(Default)]
@@ -155,6 +161,7 @@ pub struct MpcHelperClient {
     scheme: uri::Scheme,
     authority: uri::Authority,
     auth_header: Option<(HeaderName, HeaderValue)>,
+    stream_retry: StreamRetryConfig,
 }
 
 impl MpcHelperClient {
@@ -247,7 +254,13 @@ impl MpcHelperClient {
                 None,
             )
         };
-        Self::new_internal(peer_config.url, connector, auth_header, client_config)
+        Self::new_internal(
+            peer_config.url,
+            connector,
+            auth_header,
+            client_config,
+            client_config.stream_retry.clone(),
+        )
     }
 
     #[must_use]
@@ -256,6 +269,7 @@ impl MpcHelperClient {
         connector: HttpsConnector<HttpConnector>,
         auth_header: Option<(HeaderName, HeaderValue)>,
         conf: &C,
+        stream_retry: StreamRetryConfig,
     ) -> Self {
         let mut builder = Client::builder(TokioExecutor::new());
         // the following timer is necessary for http2, in particular for any timeouts
@@ -275,6 +289,7 @@ impl MpcHelperClient {
             scheme,
             authority,
             auth_header,
+            stream_retry,
         }
     }
 
@@ -282,6 +297,11 @@ impl MpcHelperClient {
         if let Some((k, v)) = self.auth_header.clone() {
             req.headers_mut().insert(k, v);
         }
+        if let Some(context) = TraceContext::current() {
+            if let Ok(value) = HeaderValue::try_from(context.to_string()) {
+                req.headers_mut().insert(TRACEPARENT, value);
+            }
+        }
         ResponseFuture {
             authority: &self.authority,
             inner: self.client.request(req),
@@ -380,59 +400,237 @@ impl MpcHelperClient {
     /// Sends a batch of messages associated with a query's step to another helper. Messages are a
     /// contiguous block of records. Also includes [`crate::protocol::RecordId`] information and
     /// [`crate::helpers::network::ChannelId`].
+    ///
+    /// The future completes once the other helper acknowledges all the data. If the connection
+    /// fails, the stream is resumed from the last acknowledged byte, within the budget set by
+    /// [`StreamRetryConfig`].
+    ///
+    /// [`StreamRetryConfig`]: crate::config::StreamRetryConfig
     /// # Errors
-    /// If the request has illegal arguments, or fails to deliver to helper
-    /// # Panics
-    /// If messages size > max u32 (unlikely)
-    pub fn step<S: Stream<Item = Vec<u8>> + Send + 'static>(
+    /// If the request has illegal arguments, is rejected by the other helper, or the data could
+    /// not be delivered within the retry budget.
+    pub async fn step<S: Stream<Item = Vec<u8>> + Send + 'static>(
         &self,
         query_id: QueryId,
         gate: &Gate,
         data: S,
-    ) -> Result<ResponseFuture, Error> {
-        let data = data.map(|v| Ok::<bytes::Bytes, Error>(Bytes::from(v)));
-        let body = axum::body::Body::from_stream(data);
-        let req = http_serde::query::step::Request::new(query_id, gate.clone(), body);
-        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
-        Ok(self.request(req))
+    ) -> Result<(), Error> {
+        let retry = &self.stream_retry;
+        let stream = OutboundStream::new(data, retry.max_unacked_bytes);
+        let mut backoff = retry.initial_backoff;
+        let mut failures = 0;
+        loop {
+            let (offset, body) = stream.reconnect();
+            let req = http_serde::query::step::Request::new(
+                query_id,
+                gate.clone(),
+                offset,
+                Body::from_stream(body),
+            );
+            let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+            let error = match self.request(req).await {
+                Ok(resp) if resp.status().is_success() => {
+                    match Self::read_acks(resp, &stream).await {
+                        Ok(()) if stream.is_complete() => return Ok(()),
+                        Ok(()) => Error::StreamInterrupted {
+                            dest: self.authority.to_string(),
+                            inner: "stream closed before all data was acknowledged".into(),
+                        },
+                        Err(e) => e,
+                    }
+                }
+                Ok(resp) if resp.status().is_server_error() => Error::from_failed_resp(resp).await,
+                // the other helper is not going to accept this request if it is sent again
+                Ok(resp) => return Err(Error::from_failed_resp(resp).await),
+                Err(e) => e,
+            };
+
+            if stream.acked() > offset {
+                failures = 0;
+                backoff = retry.initial_backoff;
+            }
+            failures += 1;
+            if failures > retry.max_retries {
+                return Err(error);
+            }
+            tracing::warn!(
+                "step stream {gate:?} to {} failed, reconnecting in {backoff:?}: {error}",
+                self.authority
+            );
+            sleep(backoff).await;
+            backoff = min(retry.max_backoff, backoff * 2);
+        }
+    }
+
+    /// Reads offsets acknowledged by the other helper until it closes the response.
+    async fn read_acks(
+        resp: ResponseFromEndpoint<'_>,
+        stream: &OutboundStream,
+    ) -> Result<(), Error> {
+        let dest = resp.endpoint();
+        let mut body = resp.into_body().into_data_stream();
+        let mut buf = BytesMut::new();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|e| Error::StreamInterrupted {
+                dest: dest.clone(),
+                inner: e.into(),
+            })?;
+            buf.extend_from_slice(&chunk);
+            while buf.len() >= http_serde::query::step::ACK_LEN {
+                stream.ack(buf.get_u64());
+            }
+        }
+        Ok(())
     }
 
     /// Retrieve the status of a query.
     ///
     /// ## Errors
     /// If the request has illegal arguments, or fails to deliver to helper
-    #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))]
     pub async fn query_status(
         &self,
         query_id: QueryId,
     ) -> Result<crate::query::QueryStatus, Error> {
+        Ok(self.query_progress(query_id).await?.0)
+    }
+
+    /// Retrieve the status of a query along with its progress, if the query is running.
+    ///
+    /// ## Errors
+    /// If the request has illegal arguments, or fails to deliver to helper
+    pub async fn query_progress(
+        &self,
+        query_id: QueryId,
+    ) -> Result<
+        (
+            crate::query::QueryStatus,
+            Option<crate::query::QueryProgress>,
+        ),
+        Error,
+    > {
         let req = http_serde::query::status::Request::new(query_id);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
 
         let resp = self.request(req).await?;
         if resp.status().is_success() {
             let bytes = Self::response_to_bytes(resp).await?;
-            let http_serde::query::status::ResponseBody { status } =
+            let http_serde::query::status::ResponseBody { status, progress } =
                 serde_json::from_slice(&bytes)?;
-            Ok(status)
+            Ok((status, progress))
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
+    /// Retrieve the stall-detection report for a running query. The server only answers if this
+    /// client authenticates as one of the helpers.
+    ///
+    /// ## Errors
+    /// If the request has illegal arguments, or fails to deliver to helper
+    pub async fn stall_report(&self, query_id: QueryId) -> Result<StallReport, Error> {
+        let req = http_serde::admin::stall_report::Request::new(query_id);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = Self::response_to_bytes(resp).await?;
+            Ok(serde_json::from_slice(&bytes)?)
         } else {
             Err(Error::from_failed_resp(resp).await)
         }
     }
 
-    /// Wait for completion of the query and pull the results of this query. This is a blocking
-    /// API so it is not supposed to be used outside of CLI context.
+    /// Retrieve digests of the records a helper exchanged with other helpers while running a
+    /// query. The server only answers if this client authenticates as one of the helpers.
     ///
     /// ## Errors
     /// If the request has illegal arguments, or fails to deliver to helper
-    #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))]
-    pub async fn query_results(&self, query_id: QueryId) -> Result<bytes::Bytes, Error> {
+    pub async fn query_digests(&self, query_id: QueryId) -> Result<DigestReport, Error> {
+        let req = http_serde::admin::digests::Request::new(query_id);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+
+        let resp = self.request(req).await?;
+        if resp.status().is_success() {
+            let bytes = Self::response_to_bytes(resp).await?;
+            Ok(serde_json::from_slice(&bytes)?)
+        } else {
+            Err(Error::from_failed_resp(resp).await)
+        }
+    }
+
+    /// Wait for completion of the query and pull the results of this query. The response does
+    /// not arrive until the query is completed, callers that can't wait indefinitely should
+    /// bound it with a timeout.
+    ///
+    /// ## Errors
+    /// If the request has illegal arguments, or fails to deliver to helper
+    pub async fn query_results(&self, query_id: QueryId) -> Result<Bytes, Error> {
+        use futures::TryStreamExt;
+
+        let dest = self.authority.to_string();
+        let results = self
+            .query_results_bytes(query_id)
+            .await?
+            .try_fold(bytes::BytesMut::new(), |mut acc, chunk| {
+                acc.extend_from_slice(&chunk);
+                std::future::ready(Ok(acc))
+            })
+            .await
+            .map_err(|inner| Error::InvalidResultsStream { dest, inner })?;
+
+        Ok(results.freeze())
+    }
+
+    /// Wait for completion of the query and stream its results back as typed shares. Records
+    /// are yielded as soon as enough bytes for them arrive from the helper. Like
+    /// [`Self::query_results`], this waits for the query to complete.
+    ///
+    /// Query results are expected to be a sequence of `T` records, this does not hold for
+    /// results sealed for the report collector.
+    ///
+    /// ## Errors
+    /// If the request has illegal arguments, or fails to deliver to helper. The returned
+    /// stream fails if the download is truncated or the results can't be parsed as `T`.
+    pub async fn query_results_stream<T: crate::ff::Serializable>(
+        &self,
+        query_id: QueryId,
+    ) -> Result<
+        crate::helpers::SingleRecordStream<T, impl crate::helpers::BytesStream + 'static>,
+        Error,
+    > {
+        Ok(crate::helpers::SingleRecordStream::new(
+            self.query_results_bytes(query_id).await?,
+        ))
+    }
+
+    /// Terminates the query on this helper, aborting it if it is running.
+    ///
+    /// ## Errors
+    /// If the request has illegal arguments, or fails to deliver to helper. Helpers respond with
+    /// [`StatusCode::NOT_FOUND`] if they don't know about this query.
+    pub async fn kill_query(&self, query_id: QueryId) -> Result<(), Error> {
+        let req = http_serde::query::kill::Request::new(query_id);
+        let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
+        let resp = self.request(req).await?;
+        Self::resp_ok(resp).await
+    }
+
+    /// Requests query results and strips the length-delimited framing from the response body.
+    async fn query_results_bytes(
+        &self,
+        query_id: QueryId,
+    ) -> Result<impl crate::helpers::BytesStream + 'static, Error> {
+        use futures::TryStreamExt;
+
         let req = http_serde::query::results::Request::new(query_id);
         let req = req.try_into_http_request(self.scheme.clone(), self.authority.clone())?;
         let resp = self.request(req).await?;
         if resp.status().is_success() {
-            let body = resp.into_body().collect().await?.to_bytes();
-            Ok(body)
+            let body = resp
+                .into_body()
+                .into_data_stream()
+                .map_err(crate::error::BoxError::from);
+            Ok(http_serde::query::results::decode_frames(body))
         } else {
             Err(Error::from_failed_resp(resp).await)
         }
@@ -457,15 +655,18 @@ pub(crate) mod tests {
         task::Poll,
     };
 
-    use futures::stream::{once, poll_immediate};
+    use futures::{
+        stream::{once, poll_immediate},
+        TryStreamExt,
+    };
     use ipa_step::StepNarrow;
 
     use super::*;
     use crate::{
         ff::{FieldType, Fp31},
         helpers::{
-            make_owned_handler, query::QueryType::TestMultiply, BytesStream, HelperResponse,
-            RequestHandler, RoleAssignment, Transport, MESSAGE_PAYLOAD_SIZE_BYTES,
+            make_owned_handler, query::QueryType::TestMultiply, routing::RouteId, BytesStream,
+            HelperResponse, RequestHandler, RoleAssignment, Transport, MESSAGE_PAYLOAD_SIZE_BYTES,
         },
         net::test::TestServer,
         query::ProtocolResult,
@@ -486,6 +687,7 @@ pub(crate) mod tests {
                 .unwrap(),
             certificate: None,
             hpke_config: None,
+            result_verifying_key: None,
         };
         let client =
             MpcHelperClient::new(&ClientConfig::default(), peer_config, ClientIdentity::None);
@@ -611,6 +813,71 @@ pub(crate) mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn kill() {
+        let handler = move || {
+            make_owned_handler(move |addr, _| async move {
+                assert!(matches!(addr.route, RouteId::KillQuery));
+                assert_eq!(addr.query_id, Some(QueryId));
+
+                Ok(HelperResponse::ok())
+            })
+        };
+
+        test_query_command(
+            |client| async move { client.kill_query(QueryId).await.unwrap() },
+            handler,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn stall_report() {
+        let handler = move || {
+            make_owned_handler(move |addr, _| async move {
+                assert!(matches!(addr.route, RouteId::QueryStallReport));
+                assert_eq!(addr.query_id, Some(QueryId));
+
+                Ok(HelperResponse::from(StallReport {
+                    stalled: true,
+                    sequence_number: 7,
+                    ..StallReport::default()
+                }))
+            })
+        };
+
+        let report = test_query_command(
+            |client| async move { client.stall_report(QueryId).await.unwrap() },
+            handler,
+        )
+        .await;
+        assert!(report.stalled);
+        assert_eq!(7, report.sequence_number);
+    }
+
+    #[tokio::test]
+    async fn query_digests() {
+        let handler = move || {
+            make_owned_handler(move |addr, _| async move {
+                assert!(matches!(addr.route, RouteId::QueryDigests));
+                assert_eq!(addr.query_id, Some(QueryId));
+
+                Ok(HelperResponse::from(DigestReport {
+                    role: crate::helpers::Role::H3,
+                    sent: Vec::new(),
+                    received: Vec::new(),
+                }))
+            })
+        };
+
+        let report = test_query_command(
+            |client| async move { client.query_digests(QueryId).await.unwrap() },
+            handler,
+        )
+        .await;
+        assert_eq!(crate::helpers::Role::H3, report.role);
+    }
+
     #[tokio::test]
     async fn input() {
         let expected_query_id = QueryId;
@@ -645,18 +912,15 @@ pub(crate) mod tests {
         let expected_step = Gate::default().narrow(&TestExecutionStep::Iter(0));
         let expected_payload = vec![7u8; MESSAGE_PAYLOAD_SIZE_BYTES];
 
-        let resp = client
+        client
             .step(
                 expected_query_id,
                 &expected_step,
                 once(ready(expected_payload.clone())),
             )
-            .unwrap()
             .await
             .unwrap();
 
-        MpcHelperClient::resp_ok(resp).await.unwrap();
-
         let mut stream = Arc::clone(&transport)
             .receive(HelperIdentity::ONE, (QueryId, expected_step.clone()))
             .into_bytes_stream();
@@ -695,4 +959,40 @@ pub(crate) mod tests {
                 .to_bytes()
         );
     }
+
+    #[tokio::test]
+    async fn results_stream() {
+        let expected_results = (0..50_000u128)
+            .map(|i| {
+                Replicated::from((
+                    Fp31::try_from(i % 31).unwrap(),
+                    Fp31::try_from((i + 1) % 31).unwrap(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        let handler = {
+            let expected_results = expected_results.clone();
+            move || {
+                let expected_results = expected_results.clone();
+                make_owned_handler(move |_, _| {
+                    let results: Box<dyn ProtocolResult> = Box::new(expected_results.clone());
+                    async move { Ok(HelperResponse::from(results)) }
+                })
+            }
+        };
+        let results = test_query_command(
+            |client| async move {
+                client
+                    .query_results_stream::<Replicated<Fp31>>(QueryId)
+                    .await
+                    .unwrap()
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap()
+            },
+            handler,
+        )
+        .await;
+        assert_eq!(expected_results, results);
+    }
 }
@@ -59,6 +59,18 @@ pub enum Error {
     },
     #[error("{error}")]
     Application { code: StatusCode, error: BoxError },
+    #[error("step stream to {dest} was interrupted: {inner}")]
+    StreamInterrupted {
+        dest: String,
+        #[source]
+        inner: BoxError,
+    },
+    #[error("malformed query results stream from {dest}: {inner}")]
+    InvalidResultsStream {
+        dest: String,
+        #[source]
+        inner: BoxError,
+    },
 }
 
 impl Error {
@@ -152,7 +164,9 @@ impl IntoResponse for Error {
             | Self::HyperHttpPassthrough(_)
             | Self::FailedHttpRequest { .. }
             | Self::InvalidUri(_)
-            | Self::MissingExtension(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            | Self::MissingExtension(_)
+            | Self::InvalidResultsStream { .. }
+            | Self::StreamInterrupted { .. } => StatusCode::INTERNAL_SERVER_ERROR,
 
             Self::Application { code, .. } => code,
         };
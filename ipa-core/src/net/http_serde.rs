@@ -11,8 +11,8 @@
 //! [`crate::net::server::handlers`]. This module provides functions to accept
 //! requests for each of the server APIs.
 //!
-//! This module is organized into the submodules "echo" and "query" for their
-//! respective APIs. Each module might have a Request struct used by the client
+//! This module is organized into the submodules "echo", "query" and "admin" for
+//! their respective APIs. Each module might have a Request struct used by the client
 //! to provide request parameters using [`crate::transport`] types.
 
 type OutgoingRequest = Result<hyper::Request<axum::body::Body>, crate::net::Error>;
@@ -68,8 +68,134 @@ pub mod echo {
     pub const AXUM_PATH: &str = "/echo";
 }
 
+pub mod metrics {
+    pub const AXUM_PATH: &str = "/metrics";
+}
+
+/// Diagnostic APIs. These are only available to clients that authenticate as one of the helpers.
+pub mod admin {
+    pub const BASE_AXUM_PATH: &str = "/admin";
+
+    pub mod stall_report {
+        use crate::{
+            helpers::{routing::RouteId, NoStep, RouteParams},
+            protocol::QueryId,
+        };
+
+        #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+        pub struct Request {
+            pub query_id: QueryId,
+        }
+
+        impl RouteParams<RouteId, QueryId, NoStep> for Request {
+            type Params = String;
+
+            fn resource_identifier(&self) -> RouteId {
+                RouteId::QueryStallReport
+            }
+
+            fn query_id(&self) -> QueryId {
+                self.query_id
+            }
+
+            fn gate(&self) -> NoStep {
+                NoStep
+            }
+
+            fn extra(&self) -> Self::Params {
+                serde_json::to_string(self).unwrap()
+            }
+        }
+
+        impl Request {
+            pub fn new(query_id: QueryId) -> Self {
+                Self { query_id }
+            }
+
+            pub fn try_into_http_request(
+                self,
+                scheme: axum::http::uri::Scheme,
+                authority: axum::http::uri::Authority,
+            ) -> crate::net::http_serde::OutgoingRequest {
+                let uri = axum::http::uri::Uri::builder()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(format!(
+                        "{}/query/{}/stall",
+                        crate::net::http_serde::admin::BASE_AXUM_PATH,
+                        self.query_id.as_ref()
+                    ))
+                    .build()?;
+                Ok(hyper::Request::get(uri).body(axum::body::Body::empty())?)
+            }
+        }
+
+        pub const AXUM_PATH: &str = "/query/:query_id/stall";
+    }
+
+    pub mod digests {
+        use crate::{
+            helpers::{routing::RouteId, NoStep, RouteParams},
+            protocol::QueryId,
+        };
+
+        #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+        pub struct Request {
+            pub query_id: QueryId,
+        }
+
+        impl RouteParams<RouteId, QueryId, NoStep> for Request {
+            type Params = String;
+
+            fn resource_identifier(&self) -> RouteId {
+                RouteId::QueryDigests
+            }
+
+            fn query_id(&self) -> QueryId {
+                self.query_id
+            }
+
+            fn gate(&self) -> NoStep {
+                NoStep
+            }
+
+            fn extra(&self) -> Self::Params {
+                serde_json::to_string(self).unwrap()
+            }
+        }
+
+        impl Request {
+            pub fn new(query_id: QueryId) -> Self {
+                Self { query_id }
+            }
+
+            pub fn try_into_http_request(
+                self,
+                scheme: axum::http::uri::Scheme,
+                authority: axum::http::uri::Authority,
+            ) -> crate::net::http_serde::OutgoingRequest {
+                let uri = axum::http::uri::Uri::builder()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(format!(
+                        "{}/query/{}/digests",
+                        crate::net::http_serde::admin::BASE_AXUM_PATH,
+                        self.query_id.as_ref()
+                    ))
+                    .build()?;
+                Ok(hyper::Request::get(uri).body(axum::body::Body::empty())?)
+            }
+        }
+
+        pub const AXUM_PATH: &str = "/query/:query_id/digests";
+    }
+}
+
 pub mod query {
-    use std::fmt::{Display, Formatter};
+    use std::{
+        fmt::{Display, Formatter},
+        num::NonZeroUsize,
+    };
 
     use async_trait::async_trait;
     use axum::{
@@ -81,7 +207,11 @@ pub mod query {
 
     use crate::{
         ff::FieldType,
-        helpers::query::{QueryConfig, QuerySize, QueryType},
+        helpers::{
+            query::{check_proof_size, QueryConfig, QuerySize, QueryType},
+            StreamCompression,
+        },
+        hpke::ResultEncryptionKey,
         net::Error,
     };
 
@@ -110,12 +240,23 @@ pub mod query {
                 size: QuerySize,
                 field_type: FieldType,
                 query_type: String,
+                result_key: Option<ResultEncryptionKey>,
+                #[serde(default)]
+                compression: StreamCompression,
+                proof_size: Option<NonZeroUsize>,
             }
             let Query(QueryTypeParam {
                 size,
                 field_type,
                 query_type,
+                result_key,
+                compression,
+                proof_size,
             }) = req.extract().await?;
+            let proof_size = proof_size
+                .map(check_proof_size)
+                .transpose()
+                .map_err(|e| Error::BadQueryString(e.into()))?;
 
             let query_type = match query_type.as_str() {
                 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
@@ -132,6 +273,9 @@ pub mod query {
                 size,
                 field_type,
                 query_type,
+                result_key,
+                compression,
+                proof_size,
             }))
         }
     }
@@ -145,6 +289,15 @@ pub mod query {
                 f = self.field_type,
                 size = self.size
             )?;
+            if let Some(result_key) = self.result_key {
+                write!(f, "&result_key={result_key}")?;
+            }
+            if self.compression != StreamCompression::None {
+                write!(f, "&compression={}", self.compression.as_str())?;
+            }
+            if let Some(proof_size) = self.proof_size {
+                write!(f, "&proof_size={proof_size}")?;
+            }
             match self.query_type {
                 #[cfg(any(test, feature = "test-fixture", feature = "cli"))]
                 QueryType::TestMultiply | QueryType::TestAddInPrimeField => Ok(()),
@@ -159,6 +312,10 @@ pub mod query {
                         write!(f, "&plaintext_match_keys=true")?;
                     }
 
+                    if config.unversioned_reports {
+                        write!(f, "&unversioned_reports=true")?;
+                    }
+
                     if let Some(window) = config.attribution_window_seconds {
                         write!(f, "&attribution_window_seconds={}", window.get())?;
                     }
@@ -283,6 +440,65 @@ pub mod query {
         pub const AXUM_PATH: &str = "/:query_id";
     }
 
+    pub mod kill {
+        use serde::{Deserialize, Serialize};
+
+        use crate::{
+            helpers::{routing::RouteId, NoStep, RouteParams},
+            protocol::QueryId,
+        };
+
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        pub struct Request {
+            pub query_id: QueryId,
+        }
+
+        impl RouteParams<RouteId, QueryId, NoStep> for Request {
+            type Params = String;
+
+            fn resource_identifier(&self) -> RouteId {
+                RouteId::KillQuery
+            }
+
+            fn query_id(&self) -> QueryId {
+                self.query_id
+            }
+
+            fn gate(&self) -> NoStep {
+                NoStep
+            }
+
+            fn extra(&self) -> Self::Params {
+                serde_json::to_string(self).unwrap()
+            }
+        }
+
+        impl Request {
+            pub fn new(query_id: QueryId) -> Self {
+                Self { query_id }
+            }
+
+            pub fn try_into_http_request(
+                self,
+                scheme: axum::http::uri::Scheme,
+                authority: axum::http::uri::Authority,
+            ) -> crate::net::http_serde::OutgoingRequest {
+                let uri = axum::http::uri::Uri::builder()
+                    .scheme(scheme)
+                    .authority(authority)
+                    .path_and_query(format!(
+                        "{}/{}/kill",
+                        crate::net::http_serde::query::BASE_AXUM_PATH,
+                        self.query_id.as_ref()
+                    ))
+                    .build()?;
+                Ok(hyper::Request::post(uri).body(axum::body::Body::empty())?)
+            }
+        }
+
+        pub const AXUM_PATH: &str = "/:query_id/kill";
+    }
+
     pub mod input {
         use axum::{body::Body, http::uri};
         use hyper::header::CONTENT_TYPE;
@@ -327,27 +543,40 @@ pub mod query {
     }
 
     pub mod step {
-        use axum::{body::Body, http::uri};
+        use axum::{
+            body::Body,
+            http::{uri, HeaderName},
+        };
 
         use crate::{
             net::{http_serde::query::BASE_AXUM_PATH, Error},
             protocol::{Gate, QueryId},
         };
 
+        /// Offset of the first byte of the request body within the step stream. Requests
+        /// without this header start the stream.
+        pub static OFFSET_HEADER: HeaderName = HeaderName::from_static("x-ipa-stream-offset");
+
+        /// Receivers acknowledge step data by streaming back the total number of bytes received,
+        /// each encoded as a big-endian `u64`.
+        pub const ACK_LEN: usize = std::mem::size_of::<u64>();
+
         // When this type is used on the client side, `B` is `hyper::Body`. When this type
         // is used on the server side, `B` can be any body type supported by axum.
         #[derive(Debug)]
         pub struct Request<B> {
             pub query_id: QueryId,
             pub gate: Gate,
+            pub offset: u64,
             pub body: B,
         }
 
         impl<B> Request<B> {
-            pub fn new(query_id: QueryId, gate: Gate, body: B) -> Self {
+            pub fn new(query_id: QueryId, gate: Gate, offset: u64, body: B) -> Self {
                 Self {
                     query_id,
                     gate,
+                    offset,
                     body,
                 }
             }
@@ -370,7 +599,9 @@ pub mod query {
                         self.gate.as_ref()
                     ))
                     .build()?;
-                Ok(hyper::Request::post(uri).body(self.body)?)
+                Ok(hyper::Request::post(uri)
+                    .header(&OFFSET_HEADER, self.offset)
+                    .body(self.body)?)
             }
         }
 
@@ -383,7 +614,7 @@ pub mod query {
         use crate::{
             helpers::{routing::RouteId, HelperResponse, NoStep, RouteParams},
             protocol::QueryId,
-            query::QueryStatus,
+            query::{QueryProgress, QueryStatus},
         };
 
         #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -412,12 +643,10 @@ pub mod query {
         }
 
         impl Request {
-            #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))] // needed because client is blocking; remove when non-blocking
             pub fn new(query_id: QueryId) -> Self {
                 Self { query_id }
             }
 
-            #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))] // needed because client is blocking; remove when non-blocking
             pub fn try_into_http_request(
                 self,
                 scheme: axum::http::uri::Scheme,
@@ -439,6 +668,9 @@ pub mod query {
         #[derive(Clone, Debug, Serialize, Deserialize)]
         pub struct ResponseBody {
             pub status: QueryStatus,
+            /// Present only while the query is running.
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub progress: Option<QueryProgress>,
         }
 
         impl From<HelperResponse> for ResponseBody {
@@ -451,11 +683,90 @@ pub mod query {
     }
 
     pub mod results {
+        use std::cmp::min;
+
+        use bytes::Bytes;
+        use futures::{stream, Stream};
+
         use crate::{
+            error::BoxError,
             helpers::{routing::RouteId, NoStep, RouteParams},
             protocol::QueryId,
         };
 
+        /// Maximum size of the payload carried by a single frame of the results stream. Frame
+        /// lengths are encoded as `u16`, so this can't exceed [`u16::MAX`].
+        pub const MAX_FRAME_LEN: usize = 32 * 1024;
+        const _: () = assert!(MAX_FRAME_LEN <= u16::MAX as usize);
+
+        /// Encodes query results as a stream of length-delimited frames.
+        ///
+        /// Each frame is a `u16` little-endian length followed by at most [`MAX_FRAME_LEN`]
+        /// bytes of results. The stream always ends with an empty frame, which lets the
+        /// receiving side tell a complete download apart from a truncated one.
+        ///
+        /// Frames are produced as the stream is polled. Lengths and payloads are yielded as
+        /// separate chunks, so payloads share memory with `results` instead of being copied.
+        pub fn encode_frames(
+            results: Bytes,
+        ) -> impl Stream<Item = Result<Bytes, BoxError>> + Send + 'static {
+            let mut rest = Some(results);
+            let payloads = std::iter::from_fn(move || {
+                let mut results = rest.take()?;
+                let payload = results.split_to(min(MAX_FRAME_LEN, results.len()));
+                if !payload.is_empty() {
+                    rest = Some(results);
+                }
+                Some(payload)
+            });
+            stream::iter(payloads.flat_map(|payload| {
+                let len = u16::try_from(payload.len()).expect("frame fits into MAX_FRAME_LEN");
+                [Bytes::copy_from_slice(&len.to_le_bytes()), payload]
+                    .into_iter()
+                    .filter(|chunk| !chunk.is_empty())
+                    .map(Ok)
+            }))
+        }
+
+        /// Decodes a stream produced by [`encode_frames`] back into chunks of results.
+        ///
+        /// Frame boundaries of the input stream do not need to match the frames themselves.
+        /// The returned stream fails if the input ends before the terminating empty frame or
+        /// if there is data after it.
+        pub fn decode_frames<S: crate::helpers::BytesStream>(
+            body: S,
+        ) -> impl Stream<Item = Result<Bytes, BoxError>> + Send {
+            use std::{future::ready, io};
+
+            use futures::{StreamExt, TryStreamExt};
+
+            use crate::helpers::LengthDelimitedStream;
+
+            let frames = LengthDelimitedStream::<Bytes, _>::new(body)
+                .map_ok(|frames| stream::iter(frames.into_iter().map(Ok)))
+                .try_flatten()
+                .boxed();
+            stream::try_unfold((frames, false), |(mut frames, finished)| async move {
+                match frames.try_next().await? {
+                    Some(_) if finished => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected data after the end of query results",
+                    )),
+                    Some(frame) => {
+                        let finished = frame.is_empty();
+                        Ok(Some((frame, (frames, finished))))
+                    }
+                    None if finished => Ok(None),
+                    None => Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "query results stream ended before the end marker",
+                    )),
+                }
+            })
+            .try_filter(|frame| ready(!frame.is_empty()))
+            .map_err(BoxError::from)
+        }
+
         #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
         pub struct Request {
             pub query_id: QueryId,
@@ -482,12 +793,10 @@ pub mod query {
         }
 
         impl Request {
-            #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))] // needed because client is blocking; remove when non-blocking
             pub fn new(query_id: QueryId) -> Self {
                 Self { query_id }
             }
 
-            #[cfg(any(all(test, not(feature = "shuttle")), feature = "cli"))] // needed because client is blocking; remove when non-blocking
             pub fn try_into_http_request(
                 self,
                 scheme: axum::http::uri::Scheme,
@@ -507,5 +816,82 @@ pub mod query {
         }
 
         pub const AXUM_PATH: &str = "/:query_id/complete";
+
+        #[cfg(all(test, unit_test))]
+        mod tests {
+            use bytes::Bytes;
+            use futures::{stream, StreamExt, TryStreamExt};
+
+            use super::{decode_frames, encode_frames, MAX_FRAME_LEN};
+            use crate::error::BoxError;
+
+            async fn roundtrip(results: &[u8], split_at: usize) -> Result<Vec<u8>, BoxError> {
+                let encoded = encode_frames(Bytes::copy_from_slice(results))
+                    .try_fold(Vec::new(), |mut acc, frame| async move {
+                        acc.extend_from_slice(&frame);
+                        Ok(acc)
+                    })
+                    .await
+                    .unwrap();
+                decode(encoded, split_at).await
+            }
+
+            async fn decode(encoded: Vec<u8>, split_at: usize) -> Result<Vec<u8>, BoxError> {
+                let chunks = encoded
+                    .chunks(split_at)
+                    .map(|chunk| Ok::<_, BoxError>(Bytes::copy_from_slice(chunk)))
+                    .collect::<Vec<_>>();
+                decode_frames(stream::iter(chunks))
+                    .try_fold(Vec::new(), |mut acc, chunk| async move {
+                        acc.extend_from_slice(&chunk);
+                        Ok(acc)
+                    })
+                    .await
+            }
+
+            #[tokio::test]
+            async fn empty_results() {
+                let frames = encode_frames(Bytes::new()).collect::<Vec<_>>().await;
+                assert_eq!(1, frames.len());
+                assert_eq!(&[0, 0], frames[0].as_ref().unwrap().as_ref());
+                assert_eq!(Vec::<u8>::new(), roundtrip(&[], 1).await.unwrap());
+            }
+
+            #[tokio::test]
+            async fn multiple_frames() {
+                let results = (0..2 * MAX_FRAME_LEN + 17)
+                    .map(|i| u8::try_from(i % 251).unwrap())
+                    .collect::<Vec<_>>();
+                let results_bytes = Bytes::from(results.clone());
+                let chunks = encode_frames(results_bytes.clone())
+                    .map(Result::unwrap)
+                    .collect::<Vec<_>>()
+                    .await;
+                // length and payload of 3 frames, followed by the length of the empty one
+                assert_eq!(7, chunks.len());
+                // payloads are not copied
+                assert_eq!(results_bytes.as_ptr(), chunks[1].as_ptr());
+                for split_at in [1, 3, 1000, MAX_FRAME_LEN + 2, 3 * MAX_FRAME_LEN] {
+                    assert_eq!(results, roundtrip(&results, split_at).await.unwrap());
+                }
+            }
+
+            #[tokio::test]
+            async fn truncated() {
+                let encoded = vec![3, 0, 1, 2, 3];
+                let err = decode(encoded, 2).await.unwrap_err();
+                assert!(err.to_string().contains("end marker"), "{err}");
+
+                let encoded = vec![3, 0, 1, 2];
+                assert!(decode(encoded, 2).await.is_err());
+            }
+
+            #[tokio::test]
+            async fn trailing_data() {
+                let encoded = vec![1, 0, 1, 0, 0, 1, 0, 2];
+                let err = decode(encoded, 3).await.unwrap_err();
+                assert!(err.to_string().contains("after the end"), "{err}");
+            }
+        }
     }
 }
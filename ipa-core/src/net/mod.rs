@@ -12,14 +12,19 @@ use crate::config::{OwnedCertificate, OwnedPrivateKey};
 mod client;
 mod error;
 mod http_serde;
+mod report_collector;
 mod server;
+mod step_stream;
 #[cfg(all(test, not(feature = "shuttle")))]
 pub mod test;
 mod transport;
 
 pub use client::{ClientIdentity, MpcHelperClient};
 pub use error::Error;
-pub use server::{MpcHelperServer, TracingSpanMaker};
+pub use report_collector::{
+    QueryHandle, QueryInputs, ReportCollector, ReportCollectorError, RetryPolicy,
+};
+pub use server::{MpcHelperServer, QueryTracing, TracingSpanMaker};
 pub use transport::{HttpShardTransport, HttpTransport};
 
 pub const APPLICATION_JSON: &str = "application/json";
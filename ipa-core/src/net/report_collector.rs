@@ -0,0 +1,788 @@
+use std::{
+    cmp::min,
+    future::Future,
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use hyper::StatusCode;
+use rand::thread_rng;
+use tokio::time::{sleep, timeout};
+
+use crate::{
+    config::NetworkConfig,
+    error::BoxError,
+    ff::{FieldType, Serializable},
+    helpers::{
+        query::{IpaQueryConfig, QueryConfig, QueryInput, QuerySize, QueryType},
+        BodyStream, HelperIdentity, StreamCompression,
+    },
+    hpke::{
+        open_result, KeyPair, OpenResultError, ResultBinding, ResultEncryptionKey,
+        ResultVerifyingKey,
+    },
+    net::{ClientIdentity, Error, MpcHelperClient},
+    protocol::QueryId,
+    query::QueryStatus,
+    secret_sharing::{
+        replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+        SharedValue,
+    },
+    utils::array::zip3,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReportCollectorError {
+    #[error("{operation} did not complete within {timeout:?}")]
+    Timeout {
+        operation: &'static str,
+        timeout: Duration,
+    },
+    #[error(transparent)]
+    Net(#[from] Error),
+    #[error("failed to open query results: {0}")]
+    OpenResult(#[from] OpenResultError),
+    #[error("query inputs are malformed: {0}")]
+    BadInputs(String),
+    #[error("failed to reconstruct query results: {0}")]
+    Reconstruct(BoxError),
+}
+
+/// How [`ReportCollector`] deals with slow or unreachable helpers.
+///
+/// Requests that don't change query state (status, cancellation) are retried on any connection
+/// failure or timeout. Requests that do (query creation, input upload, results) are retried only if the
+/// connection to the helper could not be established, so a helper never sees them twice.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// How many times a failed request is retried before giving up.
+    pub max_retries: u32,
+    /// Delay before the first retry. It is doubled after every attempt.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between retries.
+    pub max_backoff: Duration,
+    /// Time limit for requests that helpers answer right away: query creation, status and
+    /// cancellation.
+    pub request_timeout: Duration,
+    /// Time limit for input uploads and results downloads. Results are only sent once the
+    /// query completes, so this must account for the query running time. `None` means no limit.
+    pub transfer_timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            transfer_timeout: None,
+        }
+    }
+}
+
+/// Inputs of a query, one buffer per helper, in the same order as helpers in the network
+/// configuration.
+pub struct QueryInputs {
+    size: QuerySize,
+    shares: [Bytes; 3],
+}
+
+impl QueryInputs {
+    #[must_use]
+    pub fn new(size: QuerySize, shares: [Bytes; 3]) -> Self {
+        Self { size, shares }
+    }
+
+    /// Length-delimited encrypted reports, one buffer per helper. The query size is the number
+    /// of reports, which must be the same for every helper.
+    ///
+    /// ## Errors
+    /// If a buffer does not end on a report boundary or helpers got different number of reports.
+    pub fn encrypted_reports(shares: [Bytes; 3]) -> Result<Self, ReportCollectorError> {
+        let [c1, c2, c3] = shares
+            .each_ref()
+            .map(|reports| count_delimited_reports(reports));
+        let (Some(c1), Some(c2), Some(c3)) = (c1, c2, c3) else {
+            return Err(ReportCollectorError::BadInputs(
+                "encrypted reports are truncated".into(),
+            ));
+        };
+        if c1 != c2 || c2 != c3 {
+            return Err(ReportCollectorError::BadInputs(format!(
+                "helpers got different number of reports: {c1}, {c2} and {c3}"
+            )));
+        }
+        let size =
+            QuerySize::try_from(c1).map_err(|e| ReportCollectorError::BadInputs(e.to_string()))?;
+
+        Ok(Self::new(size, shares))
+    }
+
+    #[must_use]
+    pub fn size(&self) -> QuerySize {
+        self.size
+    }
+}
+
+/// Counts reports in a buffer of length-delimited encrypted reports. Returns `None` if the
+/// buffer does not end on a report boundary.
+fn count_delimited_reports(mut buf: &[u8]) -> Option<usize> {
+    let mut count = 0;
+    while !buf.is_empty() {
+        let (len, rest) = buf.split_first_chunk::<2>()?;
+        buf = rest.get(usize::from(u16::from_le_bytes(*len))..)?;
+        count += 1;
+    }
+
+    Some(count)
+}
+
+/// A query submitted by [`ReportCollector`]. Keeps the key that query results are sealed to
+/// and the query configuration they are bound to, so it must be kept around until results are
+/// retrieved.
+pub struct QueryHandle {
+    query_id: QueryId,
+    config: QueryConfig,
+    result_key: Option<KeyPair>,
+}
+
+impl QueryHandle {
+    #[must_use]
+    pub fn query_id(&self) -> QueryId {
+        self.query_id
+    }
+}
+
+/// Drives queries on a helper network on behalf of a report collector.
+///
+/// This is the library counterpart of the `report_collector` binary: it creates queries,
+/// uploads inputs, tracks and cancels queries and reconstructs query results. If all helpers
+/// publish their result verifying keys, every query asks helpers to seal results to a fresh
+/// key and results are authenticated before they are reconstructed.
+pub struct ReportCollector {
+    clients: [MpcHelperClient; 3],
+    helper_origin: String,
+    verifying_keys: Option<[ResultVerifyingKey; 3]>,
+    policy: RetryPolicy,
+    compression: StreamCompression,
+    proof_size: Option<NonZeroUsize>,
+}
+
+impl ReportCollector {
+    #[must_use]
+    pub fn new(network: &NetworkConfig, identity: &ClientIdentity) -> Self {
+        let [vk1, vk2, vk3] = network
+            .peers()
+            .each_ref()
+            .map(|peer| peer.result_verifying_key);
+
+        Self {
+            clients: MpcHelperClient::from_conf(network, identity),
+            helper_origin: network.helper_origin.clone(),
+            verifying_keys: vk1.zip(vk2).zip(vk3).map(|((a, b), c)| [a, b, c]),
+            policy: RetryPolicy::default(),
+            compression: StreamCompression::None,
+            proof_size: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Requests helpers to compress the record streams they exchange while running queries
+    /// submitted by this report collector.
+    #[must_use]
+    pub fn with_compression(mut self, compression: StreamCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Requests helpers to cover `proof_size` bit multiplications with each zero-knowledge proof
+    /// they generate while running queries submitted by this report collector.
+    #[must_use]
+    pub fn with_proof_size(mut self, proof_size: NonZeroUsize) -> Self {
+        self.proof_size = Some(proof_size);
+        self
+    }
+
+    /// Creates an IPA query and uploads its inputs.
+    ///
+    /// ## Errors
+    /// See [`Self::submit`].
+    pub async fn submit_ipa(
+        &self,
+        config: IpaQueryConfig,
+        inputs: QueryInputs,
+    ) -> Result<QueryHandle, ReportCollectorError> {
+        self.submit(QueryType::OprfIpa(config), FieldType::Fp32BitPrime, inputs)
+            .await
+    }
+
+    /// Creates a query and uploads its inputs to helpers. If the upload fails, the query is
+    /// cancelled, so helpers are ready to accept a new one.
+    ///
+    /// ## Errors
+    /// If helpers reject the query or its inputs, or if they can't be reached.
+    pub async fn submit(
+        &self,
+        query_type: QueryType,
+        field_type: FieldType,
+        inputs: QueryInputs,
+    ) -> Result<QueryHandle, ReportCollectorError> {
+        let result_key = self
+            .verifying_keys
+            .is_some()
+            .then(|| KeyPair::gen(&mut thread_rng()));
+        let query_config = QueryConfig {
+            size: inputs.size,
+            field_type,
+            query_type,
+            result_key: result_key.as_ref().map(ResultEncryptionKey::from),
+            compression: self.compression,
+            proof_size: self.proof_size,
+        };
+
+        let query_id = self
+            .with_retries(
+                "query creation",
+                Some(self.policy.request_timeout),
+                is_connect_error,
+                || self.clients[0].create_query(query_config),
+            )
+            .await?;
+        let handle = QueryHandle {
+            query_id,
+            config: query_config,
+            result_key,
+        };
+
+        if let Err(e) = self.upload(query_id, inputs.shares).await {
+            if let Err(cancel_err) = self.kill(query_id).await {
+                tracing::warn!("failed to cancel {query_id:?} after upload error: {cancel_err}");
+            }
+            return Err(e);
+        }
+
+        Ok(handle)
+    }
+
+    /// Returns the status of the query, as reported by each helper.
+    ///
+    /// ## Errors
+    /// If any of the helpers can't be reached or does not know about this query.
+    pub async fn status(
+        &self,
+        handle: &QueryHandle,
+    ) -> Result<[QueryStatus; 3], ReportCollectorError> {
+        let [s1, s2, s3] = self.clients.each_ref().map(|client| {
+            self.with_retries(
+                "query status",
+                Some(self.policy.request_timeout),
+                is_any_connection_error,
+                move || client.query_status(handle.query_id),
+            )
+        });
+        let (s1, s2, s3) = futures::try_join!(s1, s2, s3)?;
+
+        Ok([s1, s2, s3])
+    }
+
+    /// Polls helpers until all of them report the query as completed.
+    ///
+    /// ## Errors
+    /// If the query does not complete before `timeout` or if helpers can't be queried.
+    pub async fn wait(
+        &self,
+        handle: &QueryHandle,
+        timeout: Duration,
+    ) -> Result<(), ReportCollectorError> {
+        let started = Instant::now();
+        let mut delay = self.policy.initial_backoff;
+        loop {
+            if self
+                .status(handle)
+                .await?
+                .into_iter()
+                .all(|status| status == QueryStatus::Completed)
+            {
+                return Ok(());
+            }
+
+            let elapsed = started.elapsed();
+            if elapsed >= timeout {
+                return Err(ReportCollectorError::Timeout {
+                    operation: "query execution",
+                    timeout,
+                });
+            }
+            sleep(min(delay, timeout - elapsed)).await;
+            delay = min(self.policy.max_backoff, delay * 2);
+        }
+    }
+
+    /// Terminates the query on all helpers. Helpers that don't know about this query are
+    /// skipped, so it is safe to cancel a query that failed to start on some of them.
+    ///
+    /// ## Errors
+    /// If any of the helpers can't be reached or refuses to terminate the query.
+    pub async fn cancel(&self, handle: QueryHandle) -> Result<(), ReportCollectorError> {
+        self.kill(handle.query_id).await
+    }
+
+    /// Waits for the query to complete, downloads results from all helpers and reconstructs
+    /// them as a vector of `T`. Results can be retrieved only once, helpers forget about the
+    /// query after that.
+    ///
+    /// ## Errors
+    /// If results can't be retrieved, authenticated or reconstructed.
+    pub async fn results<T>(&self, handle: &QueryHandle) -> Result<Vec<T>, ReportCollectorError>
+    where
+        T: SharedValue,
+        AdditiveShare<T>: Serializable,
+    {
+        let [r1, r2, r3] = self.clients.each_ref().map(|client| {
+            self.with_retries(
+                "results download",
+                self.policy.transfer_timeout,
+                is_connect_error,
+                move || client.query_results(handle.query_id),
+            )
+        });
+        let (r1, r2, r3) = futures::try_join!(r1, r2, r3)?;
+        let results = [r1, r2, r3];
+
+        let results = match (&handle.result_key, &self.verifying_keys) {
+            (Some(result_key), Some(verifying_keys)) => {
+                let [r1, r2, r3] =
+                    zip3(results, zip3(HelperIdentity::make_three(), *verifying_keys)).map(
+                        |(sealed, (identity, vk))| {
+                            let binding = ResultBinding {
+                                helper: identity,
+                                helper_origin: &self.helper_origin,
+                                query_id: handle.query_id,
+                                config: &handle.config,
+                            };
+                            open_result(&sealed, &binding, &vk, result_key)
+                        },
+                    );
+                [r1?, r2?, r3?]
+            }
+            _ => results.map(Vec::from),
+        };
+
+        let [s1, s2, s3] = results.map(|bytes| {
+            AdditiveShare::<T>::from_byte_slice(&bytes)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| ReportCollectorError::Reconstruct(e.into()))
+        });
+
+        reconstruct([s1?, s2?, s3?])
+    }
+
+    #[allow(clippy::disallowed_methods)] // allow try_join_all
+    async fn upload(
+        &self,
+        query_id: QueryId,
+        shares: [Bytes; 3],
+    ) -> Result<(), ReportCollectorError> {
+        futures::future::try_join_all(self.clients.iter().zip(shares).map(|(client, share)| {
+            self.with_retries(
+                "input upload",
+                self.policy.transfer_timeout,
+                is_connect_error,
+                move || {
+                    client.query_input(QueryInput {
+                        query_id,
+                        input_stream: BodyStream::new(share.clone()),
+                    })
+                },
+            )
+        }))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn kill(&self, query_id: QueryId) -> Result<(), ReportCollectorError> {
+        let [k1, k2, k3] = self.clients.each_ref().map(|client| {
+            self.with_retries(
+                "query cancellation",
+                Some(self.policy.request_timeout),
+                is_any_connection_error,
+                move || async move {
+                    match client.kill_query(query_id).await {
+                        Err(Error::FailedHttpRequest {
+                            status: StatusCode::NOT_FOUND,
+                            ..
+                        }) => Ok(()),
+                        r => r,
+                    }
+                },
+            )
+        });
+        futures::try_join!(k1, k2, k3)?;
+
+        Ok(())
+    }
+
+    /// Runs `request` until it succeeds, fails with an error that `retriable` rejects, or the
+    /// retry budget runs out. Every attempt is bounded by `timeout`.
+    async fn with_retries<T, F, Fut>(
+        &self,
+        operation: &'static str,
+        timeout_after: Option<Duration>,
+        retriable: fn(&ReportCollectorError) -> bool,
+        mut request: F,
+    ) -> Result<T, ReportCollectorError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut backoff = self.policy.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            let result = match timeout_after {
+                Some(timeout_after) => timeout(timeout_after, request())
+                    .await
+                    .map_err(|_| ReportCollectorError::Timeout {
+                        operation,
+                        timeout: timeout_after,
+                    })
+                    .and_then(|r| r.map_err(Into::into)),
+                None => request().await.map_err(Into::into),
+            };
+            match result {
+                Err(e) if attempt < self.policy.max_retries && retriable(&e) => {
+                    attempt += 1;
+                    tracing::warn!("{operation} failed, retrying in {backoff:?}: {e}");
+                    sleep(backoff).await;
+                    backoff = min(self.policy.max_backoff, backoff * 2);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// The request did not reach the helper.
+fn is_connect_error(e: &ReportCollectorError) -> bool {
+    matches!(e, ReportCollectorError::Net(Error::ConnectError { inner, .. }) if inner.is_connect())
+}
+
+/// The request or the response to it was lost, or the helper did not answer in time.
+fn is_any_connection_error(e: &ReportCollectorError) -> bool {
+    matches!(
+        e,
+        ReportCollectorError::Net(Error::ConnectError { .. })
+            | ReportCollectorError::Timeout { .. }
+    )
+}
+
+/// Reconstructs values from replicated shares, checking that helpers agree on every share
+/// they have in common.
+fn reconstruct<T: SharedValue>(
+    shares: [Vec<AdditiveShare<T>>; 3],
+) -> Result<Vec<T>, ReportCollectorError> {
+    let [s1, s2, s3] = shares;
+    if s1.len() != s2.len() || s2.len() != s3.len() {
+        return Err(ReportCollectorError::Reconstruct(
+            format!(
+                "helpers returned different number of results: {}, {} and {}",
+                s1.len(),
+                s2.len(),
+                s3.len()
+            )
+            .into(),
+        ));
+    }
+
+    s1.into_iter()
+        .zip(s2)
+        .zip(s3)
+        .enumerate()
+        .map(|(i, ((s1, s2), s3))| {
+            if s1.right() != s2.left() || s2.right() != s3.left() || s3.right() != s1.left() {
+                return Err(ReportCollectorError::Reconstruct(
+                    format!("helpers disagree on the shares of result {i}").into(),
+                ));
+            }
+            Ok(s1.left() + s2.left() + s3.left())
+        })
+        .collect()
+}
+
+#[cfg(all(test, web_test, descriptive_gate))]
+mod tests {
+    use std::{
+        future::pending,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use bytes::Bytes;
+    use generic_array::GenericArray;
+    use typenum::Unsigned;
+
+    use super::{QueryHandle, QueryInputs, ReportCollector, ReportCollectorError, RetryPolicy};
+    use crate::{
+        config::{ClientConfig, NetworkConfig, PeerConfig},
+        ff::{FieldType, Fp31, Serializable, U128Conversions},
+        helpers::{
+            make_owned_handler,
+            query::{QueryConfig, QuerySize, QueryType::TestMultiply},
+            ApiError, HelperResponse,
+        },
+        net::{
+            test::{TestConfigBuilder, TestServer},
+            transport::tests::make_helpers,
+            ClientIdentity, Error,
+        },
+        protocol::QueryId,
+        query::QueryStatus,
+        secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
+    };
+
+    fn fast_retries() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+            request_timeout: Duration::from_secs(5),
+            transfer_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+
+    fn multiply_inputs(a: u128, b: u128) -> QueryInputs {
+        const SZ: usize = <AdditiveShare<Fp31> as Serializable>::Size::USIZE;
+
+        let (a, b) = (Fp31::truncate_from(a), Fp31::truncate_from(b));
+        let shares = (a, b).share().map(|(a, b)| {
+            let mut vec = vec![0u8; 2 * SZ];
+            a.serialize(GenericArray::from_mut_slice(&mut vec[..SZ]));
+            b.serialize(GenericArray::from_mut_slice(&mut vec[SZ..]));
+            Bytes::from(vec)
+        });
+
+        QueryInputs::new(QuerySize::try_from(1).unwrap(), shares)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn submit_wait_results() {
+        let mut conf = TestConfigBuilder::with_open_ports()
+            .with_disable_https_option(true)
+            .build();
+        let _helpers = make_helpers(
+            conf.sockets.take().unwrap(),
+            conf.servers,
+            &conf.network,
+            conf.disable_https,
+        )
+        .await;
+        let rc = ReportCollector::new(&conf.network, &ClientIdentity::None)
+            .with_retry_policy(fast_retries());
+
+        let handle = rc
+            .submit(TestMultiply, FieldType::Fp31, multiply_inputs(4, 5))
+            .await
+            .unwrap();
+        rc.wait(&handle, Duration::from_secs(10)).await.unwrap();
+        assert_eq!(
+            [QueryStatus::Completed; 3],
+            rc.status(&handle).await.unwrap()
+        );
+        assert_eq!(
+            vec![Fp31::truncate_from(20_u128)],
+            rc.results::<Fp31>(&handle).await.unwrap()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn cancel_frees_helpers() {
+        let mut conf = TestConfigBuilder::with_open_ports()
+            .with_disable_https_option(true)
+            .build();
+        let _helpers = make_helpers(
+            conf.sockets.take().unwrap(),
+            conf.servers,
+            &conf.network,
+            conf.disable_https,
+        )
+        .await;
+        let rc = ReportCollector::new(&conf.network, &ClientIdentity::None)
+            .with_retry_policy(fast_retries());
+
+        let handle = rc
+            .submit(TestMultiply, FieldType::Fp31, multiply_inputs(2, 3))
+            .await
+            .unwrap();
+        let cancelled_id = handle.query_id();
+        rc.cancel(handle).await.unwrap();
+
+        // helpers forgot about the cancelled query and accept a new one
+        let handle = rc
+            .submit(TestMultiply, FieldType::Fp31, multiply_inputs(4, 5))
+            .await
+            .unwrap();
+        assert_eq!(cancelled_id, handle.query_id());
+        assert_eq!(
+            vec![Fp31::truncate_from(20_u128)],
+            rc.results::<Fp31>(&handle).await.unwrap()
+        );
+    }
+
+    fn network_at(port: u16) -> NetworkConfig {
+        let peer = || PeerConfig::new(format!("http://localhost:{port}").parse().unwrap(), None);
+        NetworkConfig::new([peer(), peer(), peer()], ClientConfig::default())
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_retries() {
+        // nothing listens on this port once the listener is dropped
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let rc = ReportCollector::new(&network_at(port), &ClientIdentity::None)
+            .with_retry_policy(fast_retries());
+
+        let err = rc
+            .submit(TestMultiply, FieldType::Fp31, multiply_inputs(1, 1))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ReportCollectorError::Net(Error::ConnectError { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn status_retries_timeouts() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = make_owned_handler({
+            let calls = Arc::clone(&calls);
+            move |_addr, _data| {
+                // only the first request hangs, the retry is answered
+                let first = calls.fetch_add(1, Ordering::Relaxed) == 0;
+                async move {
+                    if first {
+                        pending::<()>().await;
+                    }
+                    Ok(HelperResponse::from(QueryStatus::Completed))
+                }
+            }
+        });
+        let TestServer { addr, .. } = TestServer::builder()
+            .disable_https()
+            .with_request_handler(handler)
+            .build()
+            .await;
+        let rc = ReportCollector::new(&network_at(addr.port()), &ClientIdentity::None)
+            .with_retry_policy(RetryPolicy {
+                request_timeout: Duration::from_millis(50),
+                ..fast_retries()
+            });
+        let handle = QueryHandle {
+            query_id: QueryId,
+            config: QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap(),
+            result_key: None,
+        };
+
+        assert_eq!(
+            [QueryStatus::Completed; 3],
+            rc.status(&handle).await.unwrap()
+        );
+        assert_eq!(4, calls.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn request_timeout() {
+        let handler =
+            make_owned_handler(|_addr, _data| pending::<Result<HelperResponse, ApiError>>());
+        let TestServer { addr, .. } = TestServer::builder()
+            .disable_https()
+            .with_request_handler(handler)
+            .build()
+            .await;
+        let rc = ReportCollector::new(&network_at(addr.port()), &ClientIdentity::None)
+            .with_retry_policy(RetryPolicy {
+                request_timeout: Duration::from_millis(50),
+                ..fast_retries()
+            });
+
+        let err = rc
+            .submit(TestMultiply, FieldType::Fp31, multiply_inputs(1, 1))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ReportCollectorError::Timeout {
+                operation: "query creation",
+                ..
+            }
+        ));
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod unit_tests {
+    use bytes::Bytes;
+
+    use super::{count_delimited_reports, reconstruct, QueryInputs, ReportCollectorError};
+    use crate::{
+        ff::{Field, Fp31, U128Conversions},
+        secret_sharing::{
+            replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+            IntoShares,
+        },
+    };
+
+    #[test]
+    fn reconstruct_checks_consistency() {
+        let value = Fp31::truncate_from(7_u128);
+        let [s1, s2, s3] = value.share();
+        assert_eq!(
+            vec![value],
+            reconstruct([vec![s1.clone()], vec![s2.clone()], vec![s3.clone()]]).unwrap()
+        );
+
+        let tampered = AdditiveShare::new(s2.left() + Fp31::ONE, s2.right());
+        assert!(matches!(
+            reconstruct([vec![s1.clone()], vec![tampered], vec![s3.clone()]]),
+            Err(ReportCollectorError::Reconstruct(_))
+        ));
+        assert!(matches!(
+            reconstruct([vec![s1], vec![], vec![s3]]),
+            Err(ReportCollectorError::Reconstruct(_))
+        ));
+    }
+
+    #[test]
+    fn encrypted_reports_size() {
+        let reports = Bytes::from_static(&[2, 0, 1, 1, 1, 0, 1]);
+        assert_eq!(Some(2), count_delimited_reports(&reports));
+        assert_eq!(None, count_delimited_reports(&reports[..4]));
+
+        let inputs =
+            QueryInputs::encrypted_reports([reports.clone(), reports.clone(), reports.clone()])
+                .unwrap();
+        assert_eq!(2, usize::from(inputs.size()));
+
+        assert!(matches!(
+            QueryInputs::encrypted_reports([reports.clone(), reports.clone(), reports.slice(..3)]),
+            Err(ReportCollectorError::BadInputs(_))
+        ));
+        assert!(matches!(
+            QueryInputs::encrypted_reports([reports.clone(), reports.clone(), reports.slice(..4)]),
+            Err(ReportCollectorError::BadInputs(_))
+        ));
+    }
+}
@@ -0,0 +1,507 @@
+//! Fault injection for helper HTTP servers, to test how helpers deal with unreliable networks
+//! over real sockets.
+//!
+//! [`FaultInjector`] is installed on the receiving side of a connection. It holds a list of
+//! faults, each with a [`Target`] that selects the requests it applies to by route, gate and the
+//! roles of the helpers on both ends. A fault either applies to the request as a whole, or to a
+//! single chunk of the request body, for example a batch of records sent on a step stream.
+//!
+//! Roles are those of the latest query created on the helpers the injector is installed on: the
+//! helper that receives the `create_query` request is `H1`, the one to its right is `H2`.
+//!
+//! | Fault                   | Request                     | Body chunk                           |
+//! |-------------------------|-----------------------------|--------------------------------------|
+//! | [`Fault::Delay`]        | handled after the delay     | delivered after the delay            |
+//! | [`Fault::Drop`]         | rejected with 503           | silently discarded                   |
+//! | [`Fault::Duplicate`]    | -                           | delivered twice                      |
+//! | [`Fault::Reorder`]      | -                           | delivered after the next chunk       |
+//! | [`Fault::Corrupt`]      | -                           | lowest bit of the first byte flipped |
+//! | [`Fault::Disconnect`]   | -                           | body fails, as if the connection broke |
+//!
+//! HTTP/2 does not preserve chunk boundaries, so chunks seen by the server may not match what
+//! the client sent. Tests that target chunks other than the first should account for that.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, TryStreamExt};
+use hyper::StatusCode;
+use pin_project::pin_project;
+use tokio::time::{sleep, Sleep};
+
+use super::{ClientIdentity, QueryTracing};
+use crate::{
+    error::BoxError,
+    helpers::{routing::RouteId, HelperIdentity, Role, RoleAssignment},
+    protocol::Gate,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    Delay(Duration),
+    Drop,
+    Duplicate,
+    Reorder,
+    Corrupt,
+    Disconnect,
+}
+
+/// Selects requests, or chunks of their bodies, that a [`Fault`] applies to. Unset fields match
+/// any request.
+#[derive(Clone, Debug)]
+pub struct Target {
+    route: Option<RouteId>,
+    gate: Option<Gate>,
+    from: Option<Role>,
+    to: Option<Role>,
+    chunk: Option<usize>,
+    times: usize,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self {
+            route: None,
+            gate: None,
+            from: None,
+            to: None,
+            chunk: None,
+            times: 1,
+        }
+    }
+}
+
+impl Target {
+    /// Targets step streams sent on `gate`.
+    #[must_use]
+    pub fn step(gate: &Gate) -> Self {
+        Self {
+            route: Some(RouteId::Records),
+            gate: Some(gate.clone()),
+            ..Self::default()
+        }
+    }
+
+    /// Targets query API calls, or step streams on any gate if `route` is [`RouteId::Records`].
+    #[must_use]
+    pub fn route(route: RouteId) -> Self {
+        Self {
+            route: Some(route),
+            ..Self::default()
+        }
+    }
+
+    /// Only matches requests sent by the helper in role `from`. Requests from report collectors
+    /// are not sent by any helper.
+    #[must_use]
+    pub fn from(mut self, from: Role) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    /// Only matches requests received by the helper in role `to`.
+    #[must_use]
+    pub fn to(mut self, to: Role) -> Self {
+        self.to = Some(to);
+        self
+    }
+
+    /// Applies the fault to the chunk of the request body with the given index, instead of the
+    /// whole request.
+    #[must_use]
+    pub fn chunk(mut self, index: usize) -> Self {
+        self.chunk = Some(index);
+        self
+    }
+
+    /// Applies the fault to the first `times` matching requests. The default is 1.
+    #[must_use]
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = times;
+        self
+    }
+
+    fn matches(&self, route: RouteId, gate: Option<&str>, from: Option<Role>, to: Role) -> bool {
+        self.times > 0
+            && self.route.map_or(true, |r| r == route)
+            && self
+                .gate
+                .as_ref()
+                .map_or(true, |g| gate == Some(g.as_ref()))
+            && self.from.map_or(true, |f| from == Some(f))
+            && self.to.map_or(true, |t| t == to)
+    }
+}
+
+/// Injects faults into requests received by helper servers. Clones share the same list of
+/// faults, so one injector can be installed on all helpers.
+#[derive(Clone)]
+pub struct FaultInjector {
+    faults: Arc<Mutex<Vec<(Target, Fault)>>>,
+    roles: Arc<Mutex<RoleAssignment>>,
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self {
+            faults: Arc::default(),
+            roles: Arc::new(Mutex::new(
+                RoleAssignment::new(HelperIdentity::make_three()),
+            )),
+        }
+    }
+}
+
+impl FaultInjector {
+    /// ## Panics
+    /// If `fault` only applies to body chunks and `target` does not select one.
+    pub fn inject(&self, target: Target, fault: Fault) {
+        assert!(
+            target.chunk.is_some() || matches!(fault, Fault::Delay(_) | Fault::Drop),
+            "{fault:?} only applies to body chunks"
+        );
+        self.faults.lock().unwrap().push((target, fault));
+    }
+
+    /// Returns the number of faults that are yet to be injected.
+    #[must_use]
+    pub fn pending(&self) -> usize {
+        self.faults
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(target, _)| target.times)
+            .sum()
+    }
+
+    /// Installs this injector on the router of the helper server with the given identity.
+    pub(super) fn layer(&self, router: Router, identity: HelperIdentity) -> Router {
+        router.layer(middleware::from_fn_with_state(
+            (self.clone(), identity),
+            inject,
+        ))
+    }
+
+    /// Assigns roles the way the query processor does for queries created on `leader`.
+    fn set_leader(&self, leader: HelperIdentity) {
+        let [right, left] = leader.others();
+        *self.roles.lock().unwrap() =
+            RoleAssignment::try_from([(leader, Role::H1), (right, Role::H2), (left, Role::H3)])
+                .unwrap();
+    }
+
+    /// Takes the faults that apply to a request, consuming one use of each matching target.
+    fn take(
+        &self,
+        route: RouteId,
+        gate: Option<&str>,
+        from: Option<HelperIdentity>,
+        to: HelperIdentity,
+    ) -> (Option<Fault>, HashMap<usize, Fault>) {
+        let (from, to) = {
+            let roles = self.roles.lock().unwrap();
+            (from.map(|id| roles.role(id)), roles.role(to))
+        };
+        let mut request_fault = None;
+        let mut chunk_faults = HashMap::new();
+        for (target, fault) in self.faults.lock().unwrap().iter_mut() {
+            if !target.matches(route, gate, from, to) {
+                continue;
+            }
+            match target.chunk {
+                Some(index) if !chunk_faults.contains_key(&index) => {
+                    chunk_faults.insert(index, *fault);
+                }
+                None if request_fault.is_none() => request_fault = Some(*fault),
+                _ => continue,
+            }
+            target.times -= 1;
+        }
+
+        (request_fault, chunk_faults)
+    }
+}
+
+async fn inject(
+    State((faults, identity)): State<(FaultInjector, HelperIdentity)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(route) = QueryTracing::route(request.method(), request.uri().path()) else {
+        return next.run(request).await;
+    };
+    if route.id == RouteId::ReceiveQuery {
+        faults.set_leader(identity);
+    }
+    let from = request.extensions().get::<ClientIdentity>().map(|id| id.0);
+    let (request_fault, chunk_faults) = faults.take(route.id, route.gate, from, identity);
+
+    match request_fault {
+        Some(Fault::Drop) => return StatusCode::SERVICE_UNAVAILABLE.into_response(),
+        Some(Fault::Delay(delay)) => sleep(delay).await,
+        _ => {}
+    }
+    if chunk_faults.is_empty() {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    let body = FaultyBody {
+        inner: body.into_data_stream().map_err(BoxError::from),
+        faults: chunk_faults,
+        index: 0,
+        delay: None,
+        ready: VecDeque::new(),
+        held: None,
+        failed: false,
+    };
+    next.run(Request::from_parts(parts, Body::from_stream(body)))
+        .await
+}
+
+/// Request body that applies faults to the chunks of the inner body.
+#[pin_project]
+struct FaultyBody<S> {
+    #[pin]
+    inner: S,
+    faults: HashMap<usize, Fault>,
+    index: usize,
+    #[pin]
+    delay: Option<Sleep>,
+    /// Chunks that are ready to be delivered.
+    ready: VecDeque<Bytes>,
+    /// Chunk that is delivered after the next one.
+    held: Option<Bytes>,
+    failed: bool,
+}
+
+impl<S: Stream<Item = Result<Bytes, BoxError>>> Stream for FaultyBody<S> {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if let Some(delay) = this.delay.as_mut().as_pin_mut() {
+                ready!(delay.poll(cx));
+                this.delay.set(None);
+            }
+            if let Some(chunk) = this.ready.pop_front() {
+                return Poll::Ready(Some(Ok(chunk)));
+            }
+            if *this.failed {
+                return Poll::Ready(None);
+            }
+
+            let chunk = match ready!(this.inner.as_mut().poll_next(cx)) {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(this.held.take().map(Ok)),
+            };
+            let index = *this.index;
+            *this.index += 1;
+
+            match this.faults.remove(&index) {
+                None => this.ready.push_back(chunk),
+                Some(Fault::Delay(delay)) => {
+                    this.ready.push_back(chunk);
+                    this.delay.set(Some(sleep(delay)));
+                }
+                Some(Fault::Drop) => {}
+                Some(Fault::Duplicate) => {
+                    this.ready.push_back(chunk.clone());
+                    this.ready.push_back(chunk);
+                }
+                Some(Fault::Reorder) => {
+                    *this.held = Some(chunk);
+                    continue;
+                }
+                Some(Fault::Corrupt) => {
+                    let mut corrupted = BytesMut::from(&chunk[..]);
+                    if let Some(b) = corrupted.first_mut() {
+                        *b ^= 1;
+                    }
+                    this.ready.push_back(corrupted.freeze());
+                }
+                Some(Fault::Disconnect) => {
+                    *this.failed = true;
+                    return Poll::Ready(Some(Err(format!(
+                        "connection broken by fault injector at chunk {index}"
+                    )
+                    .into())));
+                }
+            }
+            if let Some(held) = this.held.take() {
+                this.ready.push_back(held);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bytes::Bytes;
+    use futures::{stream, StreamExt};
+
+    use super::{Fault, FaultInjector, FaultyBody, Target};
+    use crate::{
+        error::BoxError,
+        helpers::{routing::RouteId, HelperIdentity, Role},
+        protocol::Gate,
+    };
+
+    fn apply(faults: &[(usize, Fault)], chunks: &[&'static [u8]]) -> Vec<Result<Vec<u8>, ()>> {
+        let body = FaultyBody {
+            inner: stream::iter(
+                chunks
+                    .iter()
+                    .map(|&c| Ok::<_, BoxError>(Bytes::from_static(c)))
+                    .collect::<Vec<_>>(),
+            ),
+            faults: faults.iter().copied().collect(),
+            index: 0,
+            delay: None,
+            ready: Default::default(),
+            held: None,
+            failed: false,
+        };
+
+        futures::executor::block_on(
+            body.map(|r| r.map(|b| b.to_vec()).map_err(|_| ()))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn chunk_faults() {
+        let chunks: &[&[u8]] = &[&[0, 1], &[2, 3], &[4, 5]];
+        assert_eq!(
+            vec![Ok(vec![0, 1]), Ok(vec![4, 5])],
+            apply(&[(1, Fault::Drop)], chunks)
+        );
+        assert_eq!(
+            vec![
+                Ok(vec![0, 1]),
+                Ok(vec![0, 1]),
+                Ok(vec![2, 3]),
+                Ok(vec![4, 5])
+            ],
+            apply(&[(0, Fault::Duplicate)], chunks)
+        );
+        assert_eq!(
+            vec![Ok(vec![2, 3]), Ok(vec![0, 1]), Ok(vec![4, 5])],
+            apply(&[(0, Fault::Reorder)], chunks)
+        );
+        assert_eq!(
+            vec![Ok(vec![0, 1]), Ok(vec![2, 3]), Ok(vec![4, 5])],
+            apply(&[(2, Fault::Reorder)], chunks)
+        );
+        assert_eq!(
+            vec![Ok(vec![0, 1]), Ok(vec![3, 3]), Ok(vec![4, 5])],
+            apply(&[(1, Fault::Corrupt)], chunks)
+        );
+        assert_eq!(
+            vec![Ok(vec![0, 1]), Err(())],
+            apply(&[(1, Fault::Disconnect)], chunks)
+        );
+    }
+
+    #[tokio::test]
+    async fn delays_chunks() {
+        let delay = Duration::from_millis(50);
+        let body = FaultyBody {
+            inner: stream::iter([Ok::<_, BoxError>(Bytes::from_static(&[0]))]),
+            faults: [(0, Fault::Delay(delay))].into_iter().collect(),
+            index: 0,
+            delay: None,
+            ready: Default::default(),
+            held: None,
+            failed: false,
+        };
+
+        let start = Instant::now();
+        assert_eq!(1, body.collect::<Vec<_>>().await.len());
+        assert!(start.elapsed() >= delay);
+    }
+
+    #[test]
+    fn targets() {
+        let gate = Gate::from("a");
+        let faults = FaultInjector::default();
+        faults.inject(
+            Target::step(&gate)
+                .from(Role::H1)
+                .to(Role::H2)
+                .chunk(0)
+                .times(2),
+            Fault::Corrupt,
+        );
+        faults.inject(Target::route(RouteId::QueryInput), Fault::Drop);
+        assert_eq!(3, faults.pending());
+
+        let take = |route, gate, from| faults.take(route, gate, from, HelperIdentity::TWO);
+        assert_eq!(
+            (None, Default::default()),
+            take(RouteId::Records, Some("b"), Some(HelperIdentity::ONE))
+        );
+        assert_eq!(
+            (None, Default::default()),
+            take(RouteId::Records, Some("a"), Some(HelperIdentity::THREE))
+        );
+        assert_eq!(
+            (None, [(0, Fault::Corrupt)].into_iter().collect()),
+            take(RouteId::Records, Some("a"), Some(HelperIdentity::ONE))
+        );
+        assert_eq!(
+            (Some(Fault::Drop), Default::default()),
+            take(RouteId::QueryInput, None, None)
+        );
+        assert_eq!(1, faults.pending());
+        assert_eq!(
+            (None, Default::default()),
+            take(RouteId::QueryInput, None, None)
+        );
+    }
+
+    #[test]
+    fn targets_follow_roles() {
+        let faults = FaultInjector::default();
+        faults.inject(
+            Target::route(RouteId::Records).from(Role::H1).to(Role::H2),
+            Fault::Drop,
+        );
+
+        // H1 is THREE, H2 is ONE and H3 is TWO
+        faults.set_leader(HelperIdentity::THREE);
+        let take = |from, to| faults.take(RouteId::Records, Some("a"), Some(from), to);
+        assert_eq!(
+            (None, Default::default()),
+            take(HelperIdentity::ONE, HelperIdentity::TWO)
+        );
+        assert_eq!(
+            (Some(Fault::Drop), Default::default()),
+            take(HelperIdentity::THREE, HelperIdentity::ONE)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate only applies to body chunks")]
+    fn request_faults_are_limited() {
+        FaultInjector::default().inject(Target::route(RouteId::QueryInput), Fault::Duplicate);
+    }
+}
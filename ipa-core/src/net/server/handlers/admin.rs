@@ -0,0 +1,202 @@
+use axum::{extract::Path, routing::get, Extension, Json, Router};
+use hyper::StatusCode;
+use tower::layer::layer_fn;
+
+use crate::{
+    helpers::{ApiError, BodyStream, DigestReport, StallReport, Transport},
+    net::{
+        http_serde::admin::{
+            digests,
+            stall_report::{self, Request},
+        },
+        server::{handlers::query::HelperAuthentication, ClientIdentity, Error},
+        HttpTransport,
+    },
+    protocol::QueryId,
+    query::{QueryDigestsError, QueryStallReportError},
+    sync::Arc,
+};
+
+async fn stall_report(
+    transport: Extension<Arc<HttpTransport>>,
+    _: Extension<ClientIdentity>, // require that client is an authenticated helper
+    Path(query_id): Path<QueryId>,
+) -> Result<Json<StallReport>, Error> {
+    let req = Request { query_id };
+    let transport = Transport::clone_ref(&*transport);
+    match transport.dispatch(req, BodyStream::empty()).await {
+        Ok(resp) => Ok(Json(resp.try_into_owned().map_err(|e| {
+            Error::application(StatusCode::INTERNAL_SERVER_ERROR, e)
+        })?)),
+        Err(err @ ApiError::QueryStallReport(QueryStallReportError::NoSuchQuery(_))) => {
+            Err(Error::application(StatusCode::NOT_FOUND, err))
+        }
+        Err(err @ ApiError::QueryStallReport(QueryStallReportError::NotRunning { .. })) => {
+            Err(Error::application(StatusCode::CONFLICT, err))
+        }
+        Err(err @ ApiError::QueryStallReport(QueryStallReportError::Unavailable)) => {
+            Err(Error::application(StatusCode::SERVICE_UNAVAILABLE, err))
+        }
+        Err(err) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, err)),
+    }
+}
+
+async fn query_digests(
+    transport: Extension<Arc<HttpTransport>>,
+    _: Extension<ClientIdentity>, // require that client is an authenticated helper
+    Path(query_id): Path<QueryId>,
+) -> Result<Json<DigestReport>, Error> {
+    let req = digests::Request::new(query_id);
+    let transport = Transport::clone_ref(&*transport);
+    match transport.dispatch(req, BodyStream::empty()).await {
+        Ok(resp) => Ok(Json(resp.try_into_owned().map_err(|e| {
+            Error::application(StatusCode::INTERNAL_SERVER_ERROR, e)
+        })?)),
+        Err(err @ ApiError::QueryDigests(QueryDigestsError::NoSuchQuery(_))) => {
+            Err(Error::application(StatusCode::NOT_FOUND, err))
+        }
+        Err(err @ ApiError::QueryDigests(QueryDigestsError::Disabled)) => {
+            Err(Error::application(StatusCode::SERVICE_UNAVAILABLE, err))
+        }
+        Err(err) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, err)),
+    }
+}
+
+/// Construct router for diagnostic APIs. Access is limited to clients that authenticate as one
+/// of the helpers.
+pub fn router(transport: Arc<HttpTransport>) -> Router {
+    Router::new()
+        .route(stall_report::AXUM_PATH, get(stall_report))
+        .route(digests::AXUM_PATH, get(query_digests))
+        .layer(Extension(transport))
+        .layer(layer_fn(HelperAuthentication::new))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use axum::{
+        body::Body,
+        http::uri::{Authority, Scheme},
+    };
+    use hyper::StatusCode;
+
+    use crate::{
+        helpers::{
+            make_owned_handler,
+            routing::{Addr, RouteId},
+            BodyStream, BufferOccupancy, ChannelDigest, ChannelReport, DigestReport,
+            HelperIdentity, HelperResponse, Role, StallReport,
+        },
+        net::{
+            http_serde,
+            server::{
+                handlers::query::test_helpers::{assert_fails_with, assert_success_with},
+                ClientIdentity,
+            },
+        },
+        protocol::QueryId,
+    };
+
+    fn request(client_id: Option<ClientIdentity>) -> hyper::Request<Body> {
+        let mut req = http_serde::admin::stall_report::Request::new(QueryId)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        if let Some(client_id) = client_id {
+            req.extensions_mut().insert(client_id);
+        }
+        req
+    }
+
+    #[tokio::test]
+    async fn stall_report() {
+        let report = StallReport {
+            stalled: true,
+            sequence_number: 42,
+            senders: vec![ChannelReport {
+                gate: String::from("/protocol/a"),
+                peer: String::from("H2"),
+                total_records: Some(10),
+                waiting: vec![String::from("[3..9]")],
+                buffer: Some(BufferOccupancy {
+                    used: 12,
+                    capacity: 64,
+                }),
+            }],
+            receivers: Vec::new(),
+        };
+        let handler = make_owned_handler({
+            let report = report.clone();
+            move |addr: Addr<HelperIdentity>, _data: BodyStream| {
+                let report = report.clone();
+                async move {
+                    let RouteId::QueryStallReport = addr.route else {
+                        panic!("unexpected call");
+                    };
+                    assert_eq!(addr.query_id, Some(QueryId));
+                    Ok(HelperResponse::from(report))
+                }
+            }
+        });
+
+        let body =
+            assert_success_with(request(Some(ClientIdentity(HelperIdentity::TWO))), handler).await;
+        let resp: StallReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report, resp);
+    }
+
+    #[tokio::test]
+    async fn requires_authentication() {
+        assert_fails_with(request(None), StatusCode::UNAUTHORIZED).await;
+    }
+
+    fn digests_request(client_id: Option<ClientIdentity>) -> hyper::Request<Body> {
+        let mut req = http_serde::admin::digests::Request::new(QueryId)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        if let Some(client_id) = client_id {
+            req.extensions_mut().insert(client_id);
+        }
+        req
+    }
+
+    #[tokio::test]
+    async fn digests() {
+        let report = DigestReport {
+            role: Role::H2,
+            sent: vec![ChannelDigest {
+                gate: String::from("/protocol/a"),
+                peer: Role::H3,
+                seq: 0,
+                records: 10,
+                digest: String::from("00112233445566778899aabbccddeeff"),
+            }],
+            received: Vec::new(),
+        };
+        let handler = make_owned_handler({
+            let report = report.clone();
+            move |addr: Addr<HelperIdentity>, _data: BodyStream| {
+                let report = report.clone();
+                async move {
+                    let RouteId::QueryDigests = addr.route else {
+                        panic!("unexpected call");
+                    };
+                    assert_eq!(addr.query_id, Some(QueryId));
+                    Ok(HelperResponse::from(report))
+                }
+            }
+        });
+
+        let body = assert_success_with(
+            digests_request(Some(ClientIdentity(HelperIdentity::ONE))),
+            handler,
+        )
+        .await;
+        let resp: DigestReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report, resp);
+    }
+
+    #[tokio::test]
+    async fn digests_require_authentication() {
+        assert_fails_with(digests_request(None), StatusCode::UNAUTHORIZED).await;
+    }
+}
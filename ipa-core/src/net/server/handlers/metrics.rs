@@ -0,0 +1,95 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::header::CONTENT_TYPE,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Router,
+};
+
+use crate::{
+    net::http_serde,
+    telemetry::{
+        labels::{ROUTE, STATUS},
+        metrics::HTTP_REQUEST_DURATION,
+        prometheus::PrometheusHandle,
+    },
+};
+
+/// Content type of the Prometheus text exposition format.
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+#[allow(clippy::unused_async)] // needs to be async for axum handler
+async fn handler(handle: Extension<PrometheusHandle>) -> impl IntoResponse {
+    ([(CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], handle.render())
+}
+
+/// Records the time it takes to produce a response. Requests are labeled with the route
+/// template rather than the actual path, to keep the number of series bounded.
+///
+/// For streaming requests, this only includes the time until the response head is sent.
+pub async fn track_latency(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(String::new, |path| path.as_str().to_owned());
+    let start = Instant::now();
+    let response = next.run(request).await;
+    metrics::histogram!(HTTP_REQUEST_DURATION, start.elapsed().as_secs_f64(),
+        ROUTE => route,
+        STATUS => response.status().as_str().to_owned(),
+    );
+
+    response
+}
+
+pub fn router(handle: PrometheusHandle) -> Router {
+    Router::new()
+        .route(http_serde::metrics::AXUM_PATH, get(handler))
+        .layer(Extension(handle))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use hyper::{Request, StatusCode};
+    use metrics::{Key, Label, Recorder};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::telemetry::{
+        labels::ROLE,
+        metrics::RECORDS_SENT,
+        prometheus::{PrometheusConfig, PrometheusRecorder},
+    };
+
+    #[tokio::test]
+    async fn renders_metrics() {
+        // Recorder is not installed globally, to avoid interfering with other tests.
+        let recorder = PrometheusRecorder::new(PrometheusConfig::default());
+        recorder
+            .register_counter(&Key::from_parts(RECORDS_SENT, vec![Label::new(ROLE, "H1")]))
+            .increment(7);
+
+        let response = router(recorder.handle())
+            .oneshot(
+                Request::builder()
+                    .uri(http_serde::metrics::AXUM_PATH)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROMETHEUS_CONTENT_TYPE);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            "# TYPE ipa_records_sent_total counter\nipa_records_sent_total{role=\"H1\"} 7\n",
+            std::str::from_utf8(&body).unwrap()
+        );
+    }
+}
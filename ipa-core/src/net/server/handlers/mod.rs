@@ -1,18 +1,31 @@
+mod admin;
 mod echo;
+mod metrics;
 mod query;
 
-use axum::Router;
+use axum::{middleware, Router};
 
 use crate::{
     net::{http_serde, HttpTransport},
     sync::Arc,
+    telemetry::prometheus::PrometheusHandle,
 };
 
-pub fn router(transport: Arc<HttpTransport>) -> Router {
-    echo::router().nest(
-        http_serde::query::BASE_AXUM_PATH,
-        Router::new()
-            .merge(query::query_router(Arc::clone(&transport)))
-            .merge(query::h2h_router(transport)),
-    )
+pub fn router(transport: Arc<HttpTransport>, metrics: Option<PrometheusHandle>) -> Router {
+    let router = echo::router()
+        .nest(
+            http_serde::query::BASE_AXUM_PATH,
+            Router::new()
+                .merge(query::query_router(Arc::clone(&transport)))
+                .merge(query::h2h_router(Arc::clone(&transport))),
+        )
+        .nest(http_serde::admin::BASE_AXUM_PATH, admin::router(transport))
+        // Only applies to the routes above, so requests to unknown paths and metric scrapes
+        // are not counted.
+        .route_layer(middleware::from_fn(metrics::track_latency));
+
+    match metrics {
+        Some(handle) => router.merge(metrics::router(handle)),
+        None => router,
+    }
 }
@@ -35,13 +35,14 @@ pub fn router(transport: Arc<HttpTransport>) -> Router {
 
 #[cfg(all(test, unit_test))]
 mod tests {
-    use std::num::NonZeroU32;
+    use std::num::{NonZeroU32, NonZeroUsize};
 
     use axum::body::Body;
     use hyper::{
         http::uri::{Authority, Scheme},
         StatusCode,
     };
+    use rand::thread_rng;
 
     use crate::{
         ff::FieldType,
@@ -49,8 +50,9 @@ mod tests {
             make_owned_handler,
             query::{IpaQueryConfig, PrepareQuery, QueryConfig, QueryType},
             routing::RouteId,
-            HelperResponse, Role, RoleAssignment,
+            HelperResponse, Role, RoleAssignment, StreamCompression,
         },
+        hpke::{KeyPair, ResultEncryptionKey},
         net::{
             http_serde,
             server::handlers::query::test_helpers::{assert_fails_with, assert_success_with},
@@ -86,6 +88,38 @@ mod tests {
         create_test(QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, 1).unwrap()).await;
     }
 
+    #[tokio::test]
+    async fn create_test_with_result_key() {
+        let rc_keys = KeyPair::gen(&mut thread_rng());
+        create_test(
+            QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, 1)
+                .unwrap()
+                .with_result_key(ResultEncryptionKey::from(&rc_keys)),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_with_compression() {
+        create_test(
+            QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, 1)
+                .unwrap()
+                .with_compression(StreamCompression::Packed),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn create_test_with_proof_size() {
+        create_test(
+            QueryConfig::new(QueryType::TestMultiply, FieldType::Fp31, 1)
+                .unwrap()
+                .with_proof_size(NonZeroUsize::new(1_000_000).unwrap())
+                .unwrap(),
+        )
+        .await;
+    }
+
     #[tokio::test]
     async fn create_test_ipa_no_attr_window() {
         create_test(
@@ -98,6 +132,7 @@ mod tests {
                     with_dp: 0,
                     epsilon: 5.0,
                     plaintext_match_keys: true,
+                    unversioned_reports: false,
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -119,6 +154,7 @@ mod tests {
                     with_dp: 1,
                     epsilon: 5.0,
                     plaintext_match_keys: true,
+                    unversioned_reports: false,
                 }),
                 FieldType::Fp32BitPrime,
                 1,
@@ -141,7 +177,11 @@ mod tests {
                 with_dp: 0,
                 epsilon: 5.0,
                 plaintext_match_keys: true,
+                unversioned_reports: true,
             }),
+            result_key: None,
+            compression: StreamCompression::None,
+            proof_size: None,
         })
         .await;
     }
@@ -196,6 +236,24 @@ mod tests {
         assert_fails_with(req.into(), StatusCode::UNPROCESSABLE_ENTITY).await;
     }
 
+    #[tokio::test]
+    async fn malformed_result_key() {
+        let req = OverrideMulReq {
+            query_type: format!("{}&result_key=abcd", QueryType::TEST_MULTIPLY_STR),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
+
+    #[tokio::test]
+    async fn proof_size_too_small() {
+        let req = OverrideMulReq {
+            query_type: format!("{}&proof_size=1", QueryType::TEST_MULTIPLY_STR),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::UNPROCESSABLE_ENTITY).await;
+    }
+
     #[tokio::test]
     async fn malformed_query_type_mul() {
         let req = OverrideMulReq {
@@ -0,0 +1,110 @@
+use axum::{extract::Path, routing::post, Extension, Router};
+use hyper::StatusCode;
+
+use crate::{
+    helpers::{ApiError, BodyStream, Transport},
+    net::{
+        http_serde::query::kill::{self, Request},
+        server::Error,
+        HttpTransport,
+    },
+    protocol::QueryId,
+    query::QueryKillError,
+    sync::Arc,
+};
+
+async fn handler(
+    transport: Extension<Arc<HttpTransport>>,
+    Path(query_id): Path<QueryId>,
+) -> Result<(), Error> {
+    let req = Request { query_id };
+    let transport = Transport::clone_ref(&*transport);
+    match transport.dispatch(req, BodyStream::empty()).await {
+        Ok(_) => Ok(()),
+        Err(err @ ApiError::QueryKill(QueryKillError::NoSuchQuery(_))) => {
+            Err(Error::application(StatusCode::NOT_FOUND, err))
+        }
+        Err(err @ ApiError::QueryKill(QueryKillError::StateError { .. })) => {
+            Err(Error::application(StatusCode::CONFLICT, err))
+        }
+        Err(err) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, err)),
+    }
+}
+
+pub fn router(transport: Arc<HttpTransport>) -> Router {
+    Router::new()
+        .route(kill::AXUM_PATH, post(handler))
+        .layer(Extension(transport))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use axum::{
+        body::Body,
+        http::uri::{Authority, Scheme},
+    };
+    use hyper::StatusCode;
+
+    use crate::{
+        helpers::{
+            make_owned_handler,
+            routing::{Addr, RouteId},
+            BodyStream, HelperIdentity, HelperResponse,
+        },
+        net::{
+            http_serde,
+            server::handlers::query::test_helpers::{assert_fails_with, assert_success_with},
+            test::TestServer,
+        },
+        protocol::QueryId,
+        query::QueryKillError,
+    };
+
+    fn kill_request() -> hyper::Request<Body> {
+        http_serde::query::kill::Request::new(QueryId)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn kill_test() {
+        let handler = make_owned_handler(
+            move |addr: Addr<HelperIdentity>, _data: BodyStream| async move {
+                let RouteId::KillQuery = addr.route else {
+                    panic!("unexpected call");
+                };
+                assert_eq!(addr.query_id, Some(QueryId));
+                Ok(HelperResponse::ok())
+            },
+        );
+
+        assert_success_with(kill_request(), handler).await;
+    }
+
+    #[tokio::test]
+    async fn no_such_query() {
+        let handler = make_owned_handler(
+            move |_addr: Addr<HelperIdentity>, _data: BodyStream| async move {
+                Err(QueryKillError::NoSuchQuery(QueryId).into())
+            },
+        );
+        let test_server = TestServer::builder()
+            .with_request_handler(handler)
+            .build()
+            .await;
+
+        let resp = test_server.server.handle_req(kill_request()).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[tokio::test]
+    async fn malformed_query_id() {
+        let uri = format!(
+            "http://localhost{}/not-a-query-id/kill",
+            http_serde::query::BASE_AXUM_PATH,
+        );
+        let req = hyper::Request::post(uri).body(Body::empty()).unwrap();
+
+        assert_fails_with(req, StatusCode::BAD_REQUEST).await;
+    }
+}
@@ -1,4 +1,5 @@
-use axum::{extract::Path, routing::get, Extension, Router};
+use axum::{body::Body, extract::Path, routing::get, Extension, Router};
+use bytes::Bytes;
 use hyper::StatusCode;
 
 use crate::{
@@ -13,15 +14,24 @@ use crate::{
 };
 
 /// Handles the completion of the query by blocking the sender until query is completed.
+///
+/// Results are streamed back as length-delimited frames, see [`http_serde::query::results::encode_frames`].
+///
+/// Only the encoding is streamed. The query completes with the whole result as a single
+/// [`HelperResponse`] body, so this handler holds the full result in memory while sending it.
+/// The response body is not copied again when it is split into frames.
+///
+/// [`HelperResponse`]: crate::helpers::HelperResponse
 async fn handler(
     transport: Extension<Arc<HttpTransport>>,
     Path(query_id): Path<QueryId>,
-) -> Result<Vec<u8>, Error> {
+) -> Result<Body, Error> {
     let req = Request { query_id };
-    // TODO: we may be able to stream the response
     let transport = Transport::clone_ref(&*transport);
     match transport.dispatch(req, BodyStream::empty()).await {
-        Ok(resp) => Ok(resp.into_body()),
+        Ok(resp) => Ok(Body::from_stream(
+            http_serde::query::results::encode_frames(Bytes::from(resp.into_body())),
+        )),
         Err(e) => Err(Error::application(StatusCode::INTERNAL_SERVER_ERROR, e)),
     }
 }
@@ -34,14 +44,17 @@ pub fn router(transport: Arc<HttpTransport>) -> Router {
 
 #[cfg(all(test, unit_test))]
 mod tests {
+    use std::future::ready;
 
     use axum::{
         body::Body,
         http::uri::{Authority, Scheme},
     };
+    use futures::{stream::once, TryStreamExt};
     use hyper::StatusCode;
 
     use crate::{
+        error::BoxError,
         ff::Fp31,
         helpers::{
             make_owned_handler,
@@ -81,7 +94,13 @@ mod tests {
             .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
             .unwrap();
         let resp_body = assert_success_with(req, req_handler).await;
-        assert_eq!(resp_body, expected_results.to_bytes());
+        let results =
+            http_serde::query::results::decode_frames(once(ready(Ok::<_, BoxError>(resp_body))))
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .concat();
+        assert_eq!(results, expected_results.to_bytes());
     }
 
     struct OverrideReq {
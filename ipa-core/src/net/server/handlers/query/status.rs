@@ -32,6 +32,8 @@ pub fn router(transport: Arc<HttpTransport>) -> Router {
 
 #[cfg(all(test, unit_test))]
 mod tests {
+    use std::time::Duration;
+
     use axum::{
         body::Body,
         http::uri::{Authority, Scheme},
@@ -49,7 +51,7 @@ mod tests {
             server::handlers::query::test_helpers::{assert_fails_with, assert_success_with},
         },
         protocol::QueryId,
-        query::QueryStatus,
+        query::{QueryProgress, QueryStage, QueryStatus, StageProgress},
     };
 
     #[tokio::test]
@@ -74,6 +76,33 @@ mod tests {
         assert_success_with(req, handler).await;
     }
 
+    #[tokio::test]
+    async fn status_with_progress() {
+        let progress = QueryProgress {
+            stages: vec![StageProgress {
+                stage: QueryStage::Sorting,
+                records: 100,
+                elapsed: Duration::from_millis(1500),
+                finished: false,
+            }],
+        };
+        let handler = make_owned_handler({
+            let progress = progress.clone();
+            move |_addr: Addr<HelperIdentity>, _data: BodyStream| {
+                let progress = progress.clone();
+                async move { Ok(HelperResponse::from((QueryStatus::Running, Some(progress)))) }
+            }
+        });
+
+        let req = http_serde::query::status::Request::new(QueryId)
+            .try_into_http_request(Scheme::HTTP, Authority::from_static("localhost"))
+            .unwrap();
+        let body = assert_success_with(req, handler).await;
+        let resp: http_serde::query::status::ResponseBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(QueryStatus::Running, resp.status);
+        assert_eq!(Some(progress), resp.progress);
+    }
+
     struct OverrideReq {
         query_id: String,
     }
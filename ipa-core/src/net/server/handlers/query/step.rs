@@ -1,4 +1,6 @@
-use axum::{extract::Path, routing::post, Extension, Router};
+use axum::{body::Body, extract::Path, http::HeaderMap, routing::post, Extension, Router};
+use bytes::Bytes;
+use futures::TryStreamExt;
 
 use crate::{
     helpers::{BodyStream, Transport},
@@ -17,11 +19,18 @@ async fn handler(
     transport: Extension<Arc<HttpTransport>>,
     from: Extension<ClientIdentity>,
     Path((query_id, gate)): Path<(QueryId, Gate)>,
+    headers: HeaderMap,
     body: BodyStream,
-) -> Result<(), Error> {
+) -> Result<Body, Error> {
+    let offset: u64 = match headers.get(&http_serde::query::step::OFFSET_HEADER) {
+        Some(value) => value.to_str()?.parse()?,
+        None => 0,
+    };
     let transport = Transport::clone_ref(&*transport);
-    transport.receive_stream(query_id, gate, **from, body);
-    Ok(())
+    let acks = transport.receive_stream(query_id, gate, **from, offset, body)?;
+    Ok(Body::from_stream(acks.map_ok(|received| {
+        Bytes::copy_from_slice(&received.to_be_bytes())
+    })))
 }
 
 pub fn router(transport: Arc<HttpTransport>) -> Router {
@@ -35,12 +44,16 @@ mod tests {
     use std::task::Poll;
 
     use axum::body::Body;
-    use futures::{stream::poll_immediate, StreamExt};
+    use futures::{
+        stream::{self, poll_immediate},
+        StreamExt,
+    };
     use hyper::StatusCode;
     use ipa_step::StepNarrow;
 
     use super::*;
     use crate::{
+        error::BoxError,
         helpers::{HelperIdentity, MESSAGE_PAYLOAD_SIZE_BYTES},
         net::{
             server::handlers::query::test_helpers::{assert_fails_with, MaybeExtensionExt},
@@ -51,6 +64,18 @@ mod tests {
 
     const DATA_LEN: usize = 3;
 
+    /// Reads the offsets acknowledged by the server. Reading them is what delivers the request
+    /// body to the transport.
+    async fn acks(resp: axum::response::Response) -> Vec<u64> {
+        assert_eq!(StatusCode::OK, resp.status());
+        axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .chunks_exact(http_serde::query::step::ACK_LEN)
+            .map(|ack| u64::from_be_bytes(ack.try_into().unwrap()))
+            .collect()
+    }
+
     #[tokio::test]
     async fn step() {
         let payload = vec![213; DATA_LEN * MESSAGE_PAYLOAD_SIZE_BYTES];
@@ -63,7 +88,8 @@ mod tests {
 
         let step = Gate::default().narrow("test");
 
-        test_server.server.handle_req(req.into()).await;
+        let resp = test_server.server.handle_req(req.into()).await;
+        assert_eq!(vec![0, payload.len() as u64], acks(resp).await);
 
         let mut stream = Arc::clone(&test_server.transport)
             .receive(HelperIdentity::TWO, (QueryId, step))
@@ -75,11 +101,79 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn resume() {
+        let test_server = TestServer::builder().build().await;
+        let mut stream = Arc::clone(&test_server.transport)
+            .receive(
+                HelperIdentity::ONE,
+                (QueryId, Gate::default().narrow("test")),
+            )
+            .into_bytes_stream();
+
+        let resp = test_server
+            .server
+            .handle_req(
+                OverrideReq {
+                    payload: vec![1, 2, 3, 4],
+                    interrupted: true,
+                    ..Default::default()
+                }
+                .into(),
+            )
+            .await;
+        assert!(axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .is_err());
+
+        // the connection failure is not visible to the protocol
+        assert_eq!(
+            poll_immediate(&mut stream).next().await,
+            Some(Poll::Ready(vec![1, 2, 3, 4]))
+        );
+        assert_eq!(
+            poll_immediate(&mut stream).next().await,
+            Some(Poll::Pending)
+        );
+
+        // the sender did not get the last acknowledgement, so it sends the last two bytes again
+        let resp = test_server
+            .server
+            .handle_req(
+                OverrideReq {
+                    offset: Some(2),
+                    payload: vec![3, 4, 5, 6],
+                    ..Default::default()
+                }
+                .into(),
+            )
+            .await;
+        assert_eq!(vec![4, 6], acks(resp).await);
+
+        assert_eq!(
+            poll_immediate(&mut stream).next().await,
+            Some(Poll::Ready(vec![5, 6]))
+        );
+        assert_eq!(poll_immediate(&mut stream).next().await, None);
+    }
+
+    #[tokio::test]
+    async fn resume_unknown_stream_fails() {
+        let req = OverrideReq {
+            offset: Some(1),
+            ..Default::default()
+        };
+        assert_fails_with(req.into(), StatusCode::CONFLICT).await;
+    }
+
     struct OverrideReq {
         client_id: Option<ClientIdentity>,
         query_id: String,
         gate: Gate,
+        offset: Option<u64>,
         payload: Vec<u8>,
+        /// Fail the request body after sending the payload, as if the connection was lost.
+        interrupted: bool,
     }
 
     impl From<OverrideReq> for hyper::Request<Body> {
@@ -90,10 +184,19 @@ mod tests {
                 val.query_id,
                 val.gate.as_ref()
             );
-            hyper::Request::post(uri)
-                .maybe_extension(val.client_id)
-                .body(Body::from(val.payload))
-                .unwrap()
+            let mut req = hyper::Request::post(uri).maybe_extension(val.client_id);
+            if let Some(offset) = val.offset {
+                req = req.header(&http_serde::query::step::OFFSET_HEADER, offset);
+            }
+            let body = if val.interrupted {
+                Body::from_stream(stream::iter([
+                    Ok(Bytes::from(val.payload)),
+                    Err(BoxError::from("connection reset")),
+                ]))
+            } else {
+                Body::from(val.payload)
+            };
+            req.body(body).unwrap()
         }
     }
 
@@ -103,7 +206,9 @@ mod tests {
                 client_id: Some(ClientIdentity(HelperIdentity::ONE)),
                 query_id: QueryId.as_ref().to_string(),
                 gate: Gate::default().narrow("test"),
+                offset: None,
                 payload: vec![1; DATA_LEN * MESSAGE_PAYLOAD_SIZE_BYTES],
+                interrupted: false,
             }
         }
     }
@@ -1,4 +1,6 @@
 mod config;
+#[cfg(all(test, not(feature = "shuttle")))]
+pub(crate) mod faults;
 mod handlers;
 
 use std::{
@@ -30,7 +32,7 @@ use futures::{
     future::{ready, BoxFuture, Either, Ready},
     Future, FutureExt,
 };
-use hyper::{body::Incoming, header::HeaderName, Request};
+use hyper::{body::Incoming, header::HeaderName, Method, Request};
 use metrics::increment_counter;
 use rustls::{server::WebPkiClientVerifier, RootCertStore};
 use rustls_pki_types::CertificateDer;
@@ -44,18 +46,27 @@ use tracing::{error, Span};
 use crate::{
     config::{NetworkConfig, OwnedCertificate, OwnedPrivateKey, ServerConfig, TlsConfig},
     error::BoxError,
-    helpers::HelperIdentity,
+    helpers::{routing::RouteId, HelperIdentity},
     net::{
-        parse_certificate_and_private_key_bytes, server::config::HttpServerConfig, Error,
-        HttpTransport, CRYPTO_PROVIDER,
+        http_serde, parse_certificate_and_private_key_bytes, server::config::HttpServerConfig,
+        Error, HttpTransport, CRYPTO_PROVIDER,
     },
     sync::Arc,
     task::JoinHandle,
-    telemetry::metrics::{web::RequestProtocolVersion, REQUESTS_RECEIVED},
+    telemetry::{
+        metrics::{web::RequestProtocolVersion, REQUESTS_RECEIVED},
+        prometheus::PrometheusHandle,
+        trace::{SPAN_TARGET, TRACEPARENT},
+    },
 };
 
 pub trait TracingSpanMaker: Send + Sync + Clone + 'static {
     fn make_span(&self) -> Span;
+
+    /// Creates the span for an incoming request. By default, the request is ignored.
+    fn make_request_span<B>(&self, _request: &Request<B>) -> Span {
+        self.make_span()
+    }
 }
 
 impl<T: TracingSpanMaker> TracingSpanMaker for Option<T> {
@@ -66,6 +77,99 @@ impl<T: TracingSpanMaker> TracingSpanMaker for Option<T> {
             tracing::trace_span!("")
         }
     }
+
+    fn make_request_span<B>(&self, request: &Request<B>) -> Span {
+        if let Some(h) = self {
+            h.make_request_span(request)
+        } else {
+            tracing::trace_span!("")
+        }
+    }
+}
+
+/// Creates a span for every query API request, named after the query and the gate of the
+/// `step` stream. The span continues the trace of the remote caller if the request carries
+/// the `traceparent` header. Query creation requests without it start a new trace.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueryTracing;
+
+/// Query API request, as seen by [`QueryTracing`].
+#[derive(Debug, PartialEq, Eq)]
+struct QueryRoute<'a> {
+    id: RouteId,
+    name: Cow<'a, str>,
+    query_id: Option<&'a str>,
+    gate: Option<&'a str>,
+}
+
+impl QueryTracing {
+    fn route<'a>(method: &Method, path: &'a str) -> Option<QueryRoute<'a>> {
+        let path = match path.strip_prefix(http_serde::query::BASE_AXUM_PATH)? {
+            "" | "/" => "",
+            path => path.strip_prefix('/')?,
+        };
+        if path.is_empty() {
+            return (method == Method::POST).then_some(QueryRoute {
+                id: RouteId::ReceiveQuery,
+                name: Cow::Borrowed("create_query"),
+                query_id: None,
+                gate: None,
+            });
+        }
+
+        let (query_id, rest) = path.split_once('/').unwrap_or((path, ""));
+        if let Some(gate) = rest.strip_prefix("step/") {
+            return (method == Method::POST).then(|| QueryRoute {
+                id: RouteId::Records,
+                name: Cow::Owned(format!("step {gate}")),
+                query_id: Some(query_id),
+                gate: Some(gate),
+            });
+        }
+        let (id, name) = match (method, rest) {
+            (&Method::POST, "") => (RouteId::PrepareQuery, "prepare_query"),
+            (&Method::GET, "") => (RouteId::QueryStatus, "query_status"),
+            (&Method::POST, "kill") => (RouteId::KillQuery, "kill"),
+            (&Method::POST, "input") => (RouteId::QueryInput, "input"),
+            (&Method::GET, "complete") => (RouteId::CompleteQuery, "results"),
+            _ => return None,
+        };
+
+        Some(QueryRoute {
+            id,
+            name: Cow::Borrowed(name),
+            query_id: Some(query_id),
+            gate: None,
+        })
+    }
+}
+
+impl TracingSpanMaker for QueryTracing {
+    fn make_span(&self) -> Span {
+        tracing::trace_span!("")
+    }
+
+    fn make_request_span<B>(&self, request: &Request<B>) -> Span {
+        let Some(route) = Self::route(request.method(), request.uri().path()) else {
+            return self.make_span();
+        };
+        let traceparent = request
+            .headers()
+            .get(TRACEPARENT)
+            .and_then(|v| v.to_str().ok())
+            // an empty value starts a new trace
+            .or(route.query_id.is_none().then_some(""));
+
+        tracing::info_span!(
+            target: SPAN_TARGET,
+            "request",
+            otel.name = %route.name,
+            otel.kind = "server",
+            query_id = route.query_id,
+            gate = route.gate,
+            traceparent,
+        )
+    }
 }
 
 impl TracingSpanMaker for () {
@@ -81,6 +185,9 @@ pub struct MpcHelperServer {
     transport: Arc<HttpTransport>,
     config: ServerConfig,
     network_config: NetworkConfig,
+    metrics: Option<PrometheusHandle>,
+    #[cfg(all(test, not(feature = "shuttle")))]
+    faults: Option<faults::FaultInjector>,
 }
 
 impl MpcHelperServer {
@@ -93,11 +200,48 @@ impl MpcHelperServer {
             transport,
             config,
             network_config,
+            metrics: None,
+            #[cfg(all(test, not(feature = "shuttle")))]
+            faults: None,
         }
     }
 
+    /// Serves metrics collected by the given recorder on the `/metrics` endpoint.
+    #[must_use]
+    pub fn with_metrics(mut self, handle: PrometheusHandle) -> Self {
+        self.metrics = Some(handle);
+        self
+    }
+
+    /// Injects faults into requests received by this server.
+    #[cfg(all(test, not(feature = "shuttle")))]
+    #[must_use]
+    pub fn with_fault_injector(mut self, faults: faults::FaultInjector) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
     fn router(&self) -> Router {
-        handlers::router(Arc::clone(&self.transport))
+        self.inject_faults(handlers::router(
+            Arc::clone(&self.transport),
+            self.metrics.clone(),
+        ))
+    }
+
+    #[cfg(all(test, not(feature = "shuttle")))]
+    fn inject_faults(&self, router: Router) -> Router {
+        match &self.faults {
+            Some(faults) => {
+                faults.layer(router, crate::helpers::Transport::identity(&self.transport))
+            }
+            None => router,
+        }
+    }
+
+    #[cfg(not(all(test, not(feature = "shuttle"))))]
+    #[allow(clippy::unused_self)]
+    fn inject_faults(&self, router: Router) -> Router {
+        router
     }
 
     #[cfg(all(test, unit_test))]
@@ -134,7 +278,9 @@ impl MpcHelperServer {
 
         let svc = self.router().layer(
             TraceLayer::new_for_http()
-                .make_span_with(move |_request: &hyper::Request<_>| tracing.make_span())
+                .make_span_with(move |request: &hyper::Request<_>| {
+                    tracing.make_request_span(request)
+                })
                 .on_request(|request: &hyper::Request<_>, _: &Span| {
                     increment_counter!(RequestProtocolVersion::from(request.version()));
                     increment_counter!(REQUESTS_RECEIVED);
@@ -729,3 +875,79 @@ mod e2e_tests {
         );
     }
 }
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use hyper::Method;
+
+    use super::QueryTracing;
+    use crate::helpers::routing::RouteId;
+
+    type Route<'a> = (RouteId, String, Option<&'a str>, Option<&'a str>);
+
+    fn route(method: Method, path: &str) -> Option<Route<'_>> {
+        QueryTracing::route(&method, path).map(|r| (r.id, r.name.into_owned(), r.query_id, r.gate))
+    }
+
+    #[test]
+    fn query_routes() {
+        assert_eq!(
+            Some((RouteId::ReceiveQuery, "create_query".to_owned(), None, None)),
+            route(Method::POST, "/query")
+        );
+        assert_eq!(
+            Some((
+                RouteId::PrepareQuery,
+                "prepare_query".to_owned(),
+                Some("0"),
+                None
+            )),
+            route(Method::POST, "/query/0")
+        );
+        assert_eq!(
+            Some((
+                RouteId::QueryStatus,
+                "query_status".to_owned(),
+                Some("0"),
+                None
+            )),
+            route(Method::GET, "/query/0")
+        );
+        assert_eq!(
+            Some((RouteId::KillQuery, "kill".to_owned(), Some("0"), None)),
+            route(Method::POST, "/query/0/kill")
+        );
+        assert_eq!(
+            Some((RouteId::QueryInput, "input".to_owned(), Some("0"), None)),
+            route(Method::POST, "/query/0/input")
+        );
+        assert_eq!(
+            Some((
+                RouteId::CompleteQuery,
+                "results".to_owned(),
+                Some("0"),
+                None
+            )),
+            route(Method::GET, "/query/0/complete")
+        );
+        assert_eq!(
+            Some((
+                RouteId::Records,
+                "step /protocol/a".to_owned(),
+                Some("0"),
+                Some("/protocol/a")
+            )),
+            route(Method::POST, "/query/0/step//protocol/a")
+        );
+    }
+
+    #[test]
+    fn other_routes() {
+        assert_eq!(None, route(Method::GET, "/query"));
+        assert_eq!(None, route(Method::POST, "/queryx"));
+        assert_eq!(None, route(Method::GET, "/echo"));
+        assert_eq!(None, route(Method::GET, "/metrics"));
+        assert_eq!(None, route(Method::GET, "/query/0/step/a"));
+        assert_eq!(None, route(Method::POST, "/query/0/unknown"));
+    }
+}
@@ -0,0 +1,508 @@
+//! Resumable step streams between helpers.
+//!
+//! Step data for a gate is sent as a single long-running HTTP request, so a transient network
+//! error would fail the whole query. To survive it, the receiving helper acknowledges data as it
+//! arrives by streaming back the total number of bytes received on the stream, and the sending
+//! helper keeps the data that has not been acknowledged yet. When the request fails, the sender
+//! makes a new one with [`OFFSET_HEADER`] set to the first byte it did not get an acknowledgement
+//! for. The receiver drops the data it already has and appends the rest to the stream the gateway
+//! reads from, so protocols do not see the interruption.
+//!
+//! Data is acknowledged once the receiver buffers it, not once the gateway reads it. To keep the
+//! buffer bounded, the receiver stops reading the request body while the buffer holds
+//! [`max_buffered_bytes`] or more, which in turn stops acknowledgements and makes the sender wait.
+//!
+//! [`OFFSET_HEADER`]: crate::net::http_serde::query::step::OFFSET_HEADER
+//! [`max_buffered_bytes`]: crate::config::StreamRetryConfig::max_buffered_bytes
+
+use std::{
+    cmp::min,
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    pin::Pin,
+    task::{ready, Context, Poll, Waker},
+};
+
+use bytes::{Buf, Bytes};
+use futures::{Stream, StreamExt};
+use hyper::StatusCode;
+use pin_project::pin_project;
+
+use crate::{
+    error::BoxError,
+    helpers::{BodyStream, HelperIdentity},
+    net::Error,
+    protocol::{Gate, QueryId},
+    sync::{Arc, Mutex},
+};
+
+type StreamKey = (QueryId, HelperIdentity, Gate);
+
+/// Step streams received by this helper, kept so that senders can resume them.
+pub struct InboundStreams {
+    // TODO(615): like record streams, these need to be keyed by query once helpers run more
+    // than one query at a time.
+    streams: Mutex<HashMap<StreamKey, Arc<Mutex<InboundState>>>>,
+    max_buffered: usize,
+}
+
+#[derive(Default)]
+struct InboundState {
+    /// Number of bytes received so far, over all connections.
+    received: u64,
+    /// Data received, but not read by the gateway yet.
+    buffer: VecDeque<Bytes>,
+    /// Number of bytes in `buffer`.
+    buffered: usize,
+    /// Set once the sender completes the stream.
+    finished: bool,
+    /// Incremented every time the sender connects. Only the latest connection adds data.
+    connection: u64,
+    /// Wakes the gateway, waiting for data.
+    waker: Option<Waker>,
+    /// Wakes the connection, waiting for the gateway to make room in `buffer`.
+    body_waker: Option<Waker>,
+}
+
+impl InboundState {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_body(&mut self) {
+        if let Some(waker) = self.body_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl InboundStreams {
+    /// Creates an empty set of streams, each buffering up to about `max_buffered` bytes that
+    /// the gateway has not read yet.
+    #[must_use]
+    pub fn new(max_buffered: usize) -> Self {
+        Self {
+            streams: Mutex::default(),
+            max_buffered,
+        }
+    }
+
+    /// Connects a request body that carries the data of stream `key` starting at byte `offset`.
+    ///
+    /// Returns the stream to hand to the gateway if this is the first connection for `key`, and
+    /// the acknowledgements to send back on this connection.
+    ///
+    /// ## Errors
+    /// If there is no stream to resume, or `offset` is past the data received on it.
+    pub fn connect(
+        &self,
+        key: StreamKey,
+        offset: u64,
+        body: BodyStream,
+    ) -> Result<(Option<InboundStream>, StreamAcks), Error> {
+        let (state, is_new) = match self.streams.lock().unwrap().entry(key) {
+            Entry::Occupied(entry) => (Arc::clone(entry.get()), false),
+            Entry::Vacant(entry) if offset == 0 => (Arc::clone(entry.insert(Arc::default())), true),
+            Entry::Vacant(_) => {
+                return Err(Error::application(
+                    StatusCode::CONFLICT,
+                    format!("cannot resume unknown stream at offset {offset}"),
+                ))
+            }
+        };
+
+        let acks = {
+            let mut inner = state.lock().unwrap();
+            if offset > inner.received {
+                return Err(Error::application(
+                    StatusCode::CONFLICT,
+                    format!(
+                        "cannot resume stream at offset {offset}, only {} bytes were received",
+                        inner.received
+                    ),
+                ));
+            }
+            inner.connection += 1;
+            // previous connection may be waiting for room in the buffer, it must fail now
+            inner.wake_body();
+            StreamAcks {
+                state: Arc::clone(&state),
+                max_buffered: self.max_buffered,
+                connection: inner.connection,
+                skip: inner.received - offset,
+                initial: Some(inner.received),
+                done: false,
+                body,
+            }
+        };
+
+        Ok((is_new.then(|| InboundStream(state)), acks))
+    }
+
+    pub fn clear(&self) {
+        self.streams.lock().unwrap().clear();
+    }
+}
+
+/// Step data as seen by the gateway. Unlike the request body, it does not fail when the
+/// connection does, it waits for the sender to reconnect instead.
+pub struct InboundStream(Arc<Mutex<InboundState>>);
+
+impl Stream for InboundStream {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.0.lock().unwrap();
+        if let Some(bytes) = state.buffer.pop_front() {
+            state.buffered -= bytes.len();
+            state.wake_body();
+            Poll::Ready(Some(Ok(bytes)))
+        } else if state.finished {
+            Poll::Ready(None)
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// Acknowledgements sent back to the sender of a step stream. Every item is the total number
+/// of bytes received on the stream.
+///
+/// Polling this stream is what reads the request body, so data reaches the gateway only while
+/// acknowledgements are being sent. The body is not read while the gateway has
+/// `max_buffered` bytes or more to read.
+#[pin_project]
+pub struct StreamAcks {
+    state: Arc<Mutex<InboundState>>,
+    max_buffered: usize,
+    connection: u64,
+    /// Bytes at the start of this connection that were already received on an earlier one.
+    skip: u64,
+    /// Lets the sender know where the stream is, before any data arrives on this connection.
+    initial: Option<u64>,
+    done: bool,
+    #[pin]
+    body: BodyStream,
+}
+
+impl Stream for StreamAcks {
+    type Item = Result<u64, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if let Some(received) = this.initial.take() {
+            return Poll::Ready(Some(Ok(received)));
+        }
+        if *this.done {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            {
+                let mut state = this.state.lock().unwrap();
+                if state.connection != *this.connection {
+                    *this.done = true;
+                    return Poll::Ready(Some(Err("superseded by a newer connection".into())));
+                }
+                if state.buffered >= *this.max_buffered {
+                    state.body_waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+            let next = ready!(this.body.as_mut().poll_next(cx));
+            let mut state = this.state.lock().unwrap();
+            if state.connection != *this.connection {
+                *this.done = true;
+                return Poll::Ready(Some(Err("superseded by a newer connection".into())));
+            }
+            match next {
+                Some(Ok(mut bytes)) => {
+                    let skip = min(*this.skip, bytes.len() as u64);
+                    *this.skip -= skip;
+                    bytes.advance(usize::try_from(skip).unwrap());
+                    if bytes.is_empty() {
+                        continue;
+                    }
+                    state.received += bytes.len() as u64;
+                    state.buffered += bytes.len();
+                    state.buffer.push_back(bytes);
+                    state.wake();
+                    return Poll::Ready(Some(Ok(state.received)));
+                }
+                Some(Err(e)) => {
+                    *this.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                None => {
+                    *this.done = true;
+                    if *this.skip > 0 {
+                        return Poll::Ready(Some(Err(
+                            "stream ended before the data received earlier".into(),
+                        )));
+                    }
+                    state.finished = true;
+                    state.wake();
+                    return Poll::Ready(None);
+                }
+            }
+        }
+    }
+}
+
+/// Step data sent to another helper, kept until the receiver acknowledges it so it can be sent
+/// again if the connection fails.
+#[derive(Clone)]
+pub struct OutboundStream(Arc<Mutex<OutboundState>>);
+
+struct OutboundState {
+    source: Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>,
+    source_done: bool,
+    /// Data that was taken from the source, but is not acknowledged yet.
+    unacked: VecDeque<Bytes>,
+    /// Offset of the first byte in `unacked`.
+    base: u64,
+    /// Offset of the byte following the last one taken from the source.
+    end: u64,
+    /// Highest offset acknowledged by the receiver.
+    acked: u64,
+    /// Offset of the next byte to send over the current connection.
+    sent: u64,
+    /// Incremented every time the sender reconnects. Bodies of earlier connections fail when
+    /// polled.
+    connection: u64,
+    max_unacked: u64,
+    waker: Option<Waker>,
+}
+
+impl OutboundState {
+    /// Drops data that the receiver has and that the current connection has already sent.
+    fn trim(&mut self) {
+        let to = min(self.acked, self.sent);
+        while self.base < to {
+            let front = self.unacked.front_mut().unwrap();
+            let len = front.len() as u64;
+            if self.base + len <= to {
+                self.unacked.pop_front();
+                self.base += len;
+            } else {
+                front.advance(usize::try_from(to - self.base).unwrap());
+                self.base = to;
+            }
+        }
+    }
+
+    /// Returns the unsent part of the chunk that contains `self.sent`.
+    fn replay(&self) -> Bytes {
+        let mut start = self.base;
+        for chunk in &self.unacked {
+            let len = chunk.len() as u64;
+            if self.sent < start + len {
+                return chunk.slice(usize::try_from(self.sent - start).unwrap()..);
+            }
+            start += len;
+        }
+        unreachable!(
+            "offset {} is past the end of unacknowledged data",
+            self.sent
+        )
+    }
+}
+
+impl OutboundStream {
+    pub fn new<S: Stream<Item = Vec<u8>> + Send + 'static>(source: S, max_unacked: usize) -> Self {
+        Self(Arc::new(Mutex::new(OutboundState {
+            source: Box::pin(source),
+            source_done: false,
+            unacked: VecDeque::new(),
+            base: 0,
+            end: 0,
+            acked: 0,
+            sent: 0,
+            connection: 0,
+            max_unacked: max_unacked as u64,
+            waker: None,
+        })))
+    }
+
+    /// Starts sending the stream over a new connection. Returns the offset of the first byte
+    /// the receiver is missing and the request body that carries the data from there.
+    pub fn reconnect(&self) -> (u64, ReplayBody) {
+        let mut state = self.0.lock().unwrap();
+        state.connection += 1;
+        state.sent = state.acked;
+        state.trim();
+        // body of the previous connection, if it is still polled, must fail now
+        state.wake();
+
+        (
+            state.acked,
+            ReplayBody {
+                stream: self.clone(),
+                connection: state.connection,
+            },
+        )
+    }
+
+    /// Records the number of bytes received by the other side.
+    pub fn ack(&self, offset: u64) {
+        let mut state = self.0.lock().unwrap();
+        if offset > state.acked {
+            state.acked = offset;
+            state.trim();
+            state.wake();
+        }
+    }
+
+    pub fn acked(&self) -> u64 {
+        self.0.lock().unwrap().acked
+    }
+
+    /// Returns `true` once all the data has been received by the other side.
+    pub fn is_complete(&self) -> bool {
+        let state = self.0.lock().unwrap();
+        state.source_done && state.acked == state.end
+    }
+}
+
+/// Request body for a single connection. It first sends the data that was not acknowledged on
+/// the previous connections and then moves on to the data not sent yet.
+pub struct ReplayBody {
+    stream: OutboundStream,
+    connection: u64,
+}
+
+impl Stream for ReplayBody {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.stream.0.lock().unwrap();
+        if state.connection != self.connection {
+            // An error, rather than the end of the stream, so the receiver does not take the
+            // partial body for the complete one.
+            return Poll::Ready(Some(Err("superseded by a newer connection".into())));
+        }
+
+        if state.sent < state.end {
+            let bytes = state.replay();
+            state.sent += bytes.len() as u64;
+            return Poll::Ready(Some(Ok(bytes)));
+        }
+        if state.source_done {
+            return Poll::Ready(None);
+        }
+        if state.end - state.base >= state.max_unacked {
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        match ready!(state.source.poll_next_unpin(cx)) {
+            Some(data) => {
+                let bytes = Bytes::from(data);
+                state.end += bytes.len() as u64;
+                state.sent = state.end;
+                state.unacked.push_back(bytes.clone());
+                Poll::Ready(Some(Ok(bytes)))
+            }
+            None => {
+                state.source_done = true;
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use futures::{
+        executor::block_on,
+        stream::{self, poll_immediate},
+        StreamExt,
+    };
+
+    use super::{InboundStreams, OutboundStream};
+    use crate::{
+        helpers::{BodyStream, HelperIdentity},
+        protocol::{Gate, QueryId},
+    };
+
+    fn chunks() -> Vec<Vec<u8>> {
+        vec![vec![0, 1, 2], vec![3, 4], vec![5, 6, 7, 8]]
+    }
+
+    #[test]
+    fn replays_unacknowledged_data() {
+        let stream = OutboundStream::new(stream::iter(chunks()), 1024);
+
+        let (offset, body) = stream.reconnect();
+        assert_eq!(0, offset);
+        let sent = block_on(
+            body.take(2)
+                .map(|b| b.unwrap().to_vec())
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(vec![vec![0, 1, 2], vec![3, 4]], sent);
+
+        stream.ack(1);
+        let (offset, body) = stream.reconnect();
+        assert_eq!(1, offset);
+        let sent = block_on(body.map(|b| b.unwrap().to_vec()).collect::<Vec<_>>());
+        assert_eq!(vec![vec![1, 2], vec![3, 4], vec![5, 6, 7, 8]], sent);
+
+        assert!(!stream.is_complete());
+        stream.ack(9);
+        assert!(stream.is_complete());
+    }
+
+    #[test]
+    fn superseded_body_fails() {
+        let stream = OutboundStream::new(stream::iter(chunks()), 1024);
+
+        let (_, mut old) = stream.reconnect();
+        let (_, mut new) = stream.reconnect();
+        assert!(block_on(old.next()).unwrap().is_err());
+        assert_eq!(
+            vec![0, 1, 2],
+            block_on(new.next()).unwrap().unwrap().to_vec()
+        );
+    }
+
+    #[test]
+    fn waits_for_acknowledgements() {
+        let stream = OutboundStream::new(stream::iter(chunks()), 4);
+
+        let (_, mut body) = stream.reconnect();
+        let mut body = poll_immediate(&mut body);
+        assert!(block_on(body.next()).unwrap().is_ready());
+        assert!(block_on(body.next()).unwrap().is_ready());
+        assert!(block_on(body.next()).unwrap().is_pending());
+
+        stream.ack(3);
+        assert!(block_on(body.next()).unwrap().is_ready());
+    }
+
+    #[test]
+    fn stops_reading_when_buffer_is_full() {
+        let streams = InboundStreams::new(4);
+        let body = BodyStream::from_bytes_stream(stream::iter(
+            chunks()
+                .into_iter()
+                .map(|chunk| Ok(bytes::Bytes::from(chunk))),
+        ));
+        let (inbound, mut acks) = streams
+            .connect((QueryId, HelperIdentity::ONE, Gate::default()), 0, body)
+            .unwrap();
+        let mut inbound = inbound.unwrap();
+        let mut acks = poll_immediate(&mut acks);
+
+        // initial acknowledgement, then 3 and 5 bytes buffered
+        assert_eq!(0, block_on(acks.next()).unwrap().unwrap().unwrap());
+        assert_eq!(3, block_on(acks.next()).unwrap().unwrap().unwrap());
+        assert_eq!(5, block_on(acks.next()).unwrap().unwrap().unwrap());
+        assert!(block_on(acks.next()).unwrap().is_pending());
+
+        assert_eq!(vec![0, 1, 2], block_on(inbound.next()).unwrap().unwrap());
+        assert_eq!(9, block_on(acks.next()).unwrap().unwrap().unwrap());
+    }
+}
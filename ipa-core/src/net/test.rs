@@ -29,6 +29,8 @@ use crate::{
     test_fixture::metrics::MetricsHandle,
 };
 
+pub use super::server::faults::{Fault, FaultInjector, Target};
+
 pub const DEFAULT_TEST_PORTS: [u16; 3] = [3000, 3001, 3002];
 
 pub struct TestConfig {
@@ -173,17 +175,17 @@ impl TestConfigBuilder {
                         .unwrap(),
                     ))
                 },
+                result_verifying_key: None,
             })
             .collect::<Vec<_>>()
             .try_into()
             .unwrap();
-        let network = NetworkConfig {
+        let network = NetworkConfig::new(
             peers,
-            client: self
-                .use_http1
+            self.use_http1
                 .then(ClientConfig::use_http1)
                 .unwrap_or_default(),
-        };
+        );
         let servers = if self.disable_https {
             ports.map(|ports| server_config_insecure_http(ports, !self.disable_matchkey_encryption))
         } else {
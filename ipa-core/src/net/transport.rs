@@ -6,7 +6,7 @@ use std::{
 };
 
 use async_trait::async_trait;
-use futures::{Stream, TryFutureExt};
+use futures::Stream;
 use pin_project::{pin_project, pinned_drop};
 
 use crate::{
@@ -18,7 +18,12 @@ use crate::{
         NoResourceIdentifier, NoStep, QueryIdBinding, ReceiveRecords, RequestHandler, RouteParams,
         StepBinding, StreamCollection, Transport,
     },
-    net::{client::MpcHelperClient, error::Error, MpcHelperServer},
+    net::{
+        client::MpcHelperClient,
+        error::Error,
+        step_stream::{InboundStreams, StreamAcks},
+        MpcHelperServer,
+    },
     protocol::{Gate, QueryId},
     sharding::ShardIndex,
     sync::Arc,
@@ -32,6 +37,7 @@ pub struct HttpTransport {
     // TODO(615): supporting multiple queries likely require a hashmap here. It will be ok if we
     // only allow one query at a time.
     record_streams: StreamCollection<HelperIdentity, BodyStream>,
+    inbound_streams: InboundStreams,
     handler: Option<HandlerRef>,
 }
 
@@ -68,7 +74,12 @@ impl HttpTransport {
         clients: [MpcHelperClient; 3],
         handler: Option<HandlerRef>,
     ) -> (Arc<Self>, MpcHelperServer) {
-        let transport = Self::new_internal(identity, clients, handler);
+        let transport = Self::new_internal(
+            identity,
+            clients,
+            handler,
+            network_config.client.stream_retry.max_buffered_bytes,
+        );
         let server = MpcHelperServer::new(Arc::clone(&transport), server_config, network_config);
         (transport, server)
     }
@@ -77,12 +88,14 @@ impl HttpTransport {
         identity: HelperIdentity,
         clients: [MpcHelperClient; 3],
         handler: Option<HandlerRef>,
+        max_buffered_bytes: usize,
     ) -> Arc<Self> {
         Arc::new(Self {
             identity,
             clients,
             handler,
             record_streams: StreamCollection::default(),
+            inbound_streams: InboundStreams::new(max_buffered_bytes),
         })
     }
 
@@ -125,6 +138,7 @@ impl HttpTransport {
         impl<F: Future> PinnedDrop for ClearOnDrop<F> {
             fn drop(self: Pin<&mut Self>) {
                 self.transport.record_streams.clear();
+                self.transport.inbound_streams.clear();
             }
         }
 
@@ -135,7 +149,7 @@ impl HttpTransport {
             .expect("A Handler should be set by now")
             .handle(Addr::from_route(None, req), body);
 
-        if let RouteId::CompleteQuery = route_id {
+        if let RouteId::CompleteQuery | RouteId::KillQuery = route_id {
             ClearOnDrop {
                 transport: Arc::clone(&self),
                 inner: r,
@@ -146,18 +160,34 @@ impl HttpTransport {
         }
     }
 
-    /// Connect an inbound stream of MPC record data.
+    /// Connect an inbound stream of MPC record data, which carries data starting at byte
+    /// `offset` of the stream.
+    ///
+    /// This is called by peer helpers via the HTTP server. A helper that lost the connection
+    /// resumes the stream by connecting again with the offset of the first byte that was not
+    /// acknowledged. The returned stream reads `stream` and produces acknowledgements for the
+    /// sender, data is delivered to the protocol only while it is polled.
     ///
-    /// This is called by peer helpers via the HTTP server.
+    /// ## Errors
+    /// If the stream can't be resumed at `offset`.
     pub fn receive_stream(
         self: Arc<Self>,
         query_id: QueryId,
         gate: Gate,
         from: HelperIdentity,
+        offset: u64,
         stream: BodyStream,
-    ) {
-        self.record_streams
-            .add_stream((query_id, from, gate), stream);
+    ) -> Result<StreamAcks, Error> {
+        let (inbound, acks) =
+            self.inbound_streams
+                .connect((query_id, from, gate.clone()), offset, stream)?;
+        if let Some(inbound) = inbound {
+            self.record_streams.add_stream(
+                (query_id, from, gate),
+                BodyStream::from_bytes_stream(inbound),
+            );
+        }
+        Ok(acks)
     }
 }
 
@@ -194,14 +224,9 @@ impl Transport for Arc<HttpTransport> {
                     .expect("query_id required when sending records");
                 let step =
                     <Option<Gate>>::from(route.gate()).expect("step required when sending records");
-                let resp_future = self.clients[dest].step(query_id, &step, data)?;
                 // we don't need to spawn a task here. Gateway's sender interface already does that
                 // so this can just poll this future.
-                resp_future
-                    .map_err(Into::into)
-                    .and_then(MpcHelperClient::resp_ok)
-                    .await?;
-                Ok(())
+                self.clients[dest].step(query_id, &step, data).await
             }
             RouteId::PrepareQuery => {
                 let req = serde_json::from_str(route.extra().borrow()).unwrap();
@@ -210,7 +235,10 @@ impl Transport for Arc<HttpTransport> {
             evt @ (RouteId::QueryInput
             | RouteId::ReceiveQuery
             | RouteId::QueryStatus
-            | RouteId::CompleteQuery) => {
+            | RouteId::CompleteQuery
+            | RouteId::KillQuery
+            | RouteId::QueryStallReport
+            | RouteId::QueryDigests) => {
                 unimplemented!(
                     "attempting to send client-specific request {evt:?} to another helper"
                 )
@@ -267,13 +295,22 @@ impl Transport for HttpShardTransport {
 }
 
 #[cfg(all(test, web_test, descriptive_gate))]
-mod tests {
-    use std::{iter::zip, net::TcpListener, task::Poll};
+pub(crate) mod tests {
+    use std::{
+        iter::zip,
+        net::TcpListener,
+        task::Poll,
+        time::{Duration, Instant},
+    };
 
     use bytes::Bytes;
-    use futures::stream::{poll_immediate, StreamExt};
+    use futures::{
+        future::ready,
+        stream::{poll_immediate, StreamExt},
+    };
     use futures_util::future::{join_all, try_join_all};
     use generic_array::GenericArray;
+    use hyper::StatusCode;
     use once_cell::sync::Lazy;
     use tokio::sync::mpsc::channel;
     use tokio_stream::wrappers::ReceiverStream;
@@ -283,12 +320,21 @@ mod tests {
     use crate::{
         config::{NetworkConfig, ServerConfig},
         ff::{FieldType, Fp31, Serializable},
-        helpers::query::{QueryInput, QueryType::TestMultiply},
+        helpers::{
+            query::{QueryInput, QueryType::TestMultiply},
+            Role,
+        },
         net::{
             client::ClientIdentity,
-            test::{get_test_identity, TestConfig, TestConfigBuilder, TestServer},
+            test::{
+                get_test_identity, Fault, FaultInjector, Target, TestConfig, TestConfigBuilder,
+                TestServer,
+            },
+        },
+        secret_sharing::{
+            replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
+            IntoShares,
         },
-        secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
         test_fixture::Reconstruct,
         AppSetup, HelperApp,
     };
@@ -306,7 +352,10 @@ mod tests {
         let body = BodyStream::from_bytes_stream(ReceiverStream::new(rx));
 
         // Register the stream with the transport (normally called by step data HTTP API handler)
-        Arc::clone(&transport).receive_stream(QueryId, STEP.clone(), HelperIdentity::TWO, body);
+        let mut acks = Arc::clone(&transport)
+            .receive_stream(QueryId, STEP.clone(), HelperIdentity::TWO, 0, body)
+            .unwrap();
+        assert_eq!(0, acks.next().await.unwrap().unwrap());
 
         // Request step data reception (normally called by protocol)
         let mut stream = Arc::clone(&transport)
@@ -321,6 +370,7 @@ mod tests {
 
         // send and verify first chunk
         tx.send(Ok(expected_chunk1.clone().into())).await.unwrap();
+        assert_eq!(4, acks.next().await.unwrap().unwrap());
 
         assert_eq!(
             poll_immediate(&mut stream).next().await,
@@ -329,6 +379,7 @@ mod tests {
 
         // send and verify second chunk
         tx.send(Ok(expected_chunk2.clone().into())).await.unwrap();
+        assert_eq!(8, acks.next().await.unwrap().unwrap());
 
         assert_eq!(
             poll_immediate(&mut stream).next().await,
@@ -336,13 +387,81 @@ mod tests {
         );
     }
 
-    // TODO(651): write a test for an error while reading the body (after error handling is finalized)
+    #[tokio::test]
+    async fn receive_stream_resumes_after_error() {
+        let (tx, rx) = channel::<Result<Bytes, Box<dyn std::error::Error + Send + Sync>>>(1);
+
+        let TestServer { transport, .. } = TestServer::default().await;
+
+        let mut acks = Arc::clone(&transport)
+            .receive_stream(
+                QueryId,
+                STEP.clone(),
+                HelperIdentity::TWO,
+                0,
+                BodyStream::from_bytes_stream(ReceiverStream::new(rx)),
+            )
+            .unwrap();
+        let mut stream = Arc::clone(&transport)
+            .receive(HelperIdentity::TWO, (QueryId, STEP.clone()))
+            .into_bytes_stream();
+
+        tx.send(Ok(vec![0u8, 1, 2].into())).await.unwrap();
+        tx.send(Err("connection reset".into())).await.unwrap();
+        let acks_before_error = acks.by_ref().take_while(|ack| ready(ack.is_ok()));
+        assert_eq!(
+            vec![0, 3],
+            acks_before_error
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+                .await
+        );
+
+        // resuming past the data received so far is not allowed
+        assert!(Arc::clone(&transport)
+            .receive_stream(
+                QueryId,
+                STEP.clone(),
+                HelperIdentity::TWO,
+                4,
+                BodyStream::from(vec![4u8]),
+            )
+            .is_err());
+
+        let acks = Arc::clone(&transport)
+            .receive_stream(
+                QueryId,
+                STEP.clone(),
+                HelperIdentity::TWO,
+                2,
+                BodyStream::from(vec![2u8, 3, 4]),
+            )
+            .unwrap();
+        assert_eq!(
+            vec![3, 5],
+            acks.map(Result::unwrap).collect::<Vec<_>>().await
+        );
+
+        let received = stream.collect::<Vec<_>>().await.concat();
+        assert_eq!(vec![0, 1, 2, 3, 4], received);
+    }
+
+    pub(crate) async fn make_helpers(
+        sockets: [TcpListener; 3],
+        server_config: [ServerConfig; 3],
+        network_config: &NetworkConfig,
+        disable_https: bool,
+    ) -> [HelperApp; 3] {
+        make_helpers_with_faults(sockets, server_config, network_config, disable_https, None).await
+    }
 
-    async fn make_helpers(
+    /// Same as [`make_helpers`], with `faults` injected into requests received by all helpers.
+    pub(crate) async fn make_helpers_with_faults(
         sockets: [TcpListener; 3],
         server_config: [ServerConfig; 3],
         network_config: &NetworkConfig,
         disable_https: bool,
+        faults: Option<&FaultInjector>,
     ) -> [HelperApp; 3] {
         join_all(
             zip(HelperIdentity::make_three(), zip(sockets, server_config)).map(
@@ -361,6 +480,10 @@ mod tests {
                         clients,
                         Some(handler),
                     );
+                    let server = match faults {
+                        Some(faults) => server.with_fault_injector(faults.clone()),
+                        None => server,
+                    };
                     server.start_on(Some(socket), ()).await;
 
                     setup.connect(transport, HttpShardTransport)
@@ -403,25 +526,38 @@ mod tests {
     }
 
     async fn test_multiply(clients: &[MpcHelperClient; 3]) {
-        const SZ: usize = <AdditiveShare<Fp31> as Serializable>::Size::USIZE;
+        let query_id = create_multiply_query(clients).await;
+        let a = Fp31::try_from(4u128).unwrap();
+        let b = Fp31::try_from(5u128).unwrap();
+        assert_eq!(
+            Fp31::try_from(20u128).unwrap(),
+            multiply(clients, query_id, (a, b).share()).await
+        );
+    }
 
-        // send a create query command
-        let leader_client = &clients[0];
+    async fn create_multiply_query(clients: &[MpcHelperClient; 3]) -> QueryId {
+        // the helper that receives the query is H1 and H2 is the one to its right, so roles
+        // follow helper identities
         let create_data = QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap();
+        clients[0].create_query(create_data).await.unwrap()
+    }
 
-        // create query
-        let query_id = leader_client.create_query(create_data).await.unwrap();
-
-        // send input
-        let a = Fp31::try_from(4u128).unwrap();
-        let b = Fp31::try_from(5u128).unwrap();
+    fn multiply_input(a: AdditiveShare<Fp31>, b: AdditiveShare<Fp31>) -> BodyStream {
+        const SZ: usize = <AdditiveShare<Fp31> as Serializable>::Size::USIZE;
+        let mut vec = vec![0u8; 2 * SZ];
+        a.serialize(GenericArray::from_mut_slice(&mut vec[..SZ]));
+        b.serialize(GenericArray::from_mut_slice(&mut vec[SZ..]));
+        BodyStream::from(vec)
+    }
 
-        let helper_shares = (a, b).share().map(|(a, b)| {
-            let mut vec = vec![0u8; 2 * SZ];
-            a.serialize(GenericArray::from_mut_slice(&mut vec[..SZ]));
-            b.serialize(GenericArray::from_mut_slice(&mut vec[SZ..]));
-            BodyStream::from(vec)
-        });
+    /// Sends the shares of `a` and `b` in `shares` to the helpers as the input of `query_id` and
+    /// returns the product.
+    async fn multiply(
+        clients: &[MpcHelperClient; 3],
+        query_id: QueryId,
+        shares: [(AdditiveShare<Fp31>, AdditiveShare<Fp31>); 3],
+    ) -> Fp31 {
+        let helper_shares = shares.map(|(a, b)| multiply_input(a, b));
 
         let mut handle_resps = Vec::with_capacity(helper_shares.len());
         for (i, input_stream) in helper_shares.into_iter().enumerate() {
@@ -440,8 +576,7 @@ mod tests {
         .await
         .try_into()
         .unwrap();
-        let res = result.reconstruct();
-        assert_eq!(Fp31::try_from(20u128).unwrap(), res[0]);
+        result.reconstruct()[0]
     }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -457,4 +592,139 @@ mod tests {
         let conf = TestConfigBuilder::with_open_ports().build();
         test_three_helpers(conf).await;
     }
+
+    /// Starts three helpers over HTTP, with `faults` injected into requests received by all of
+    /// them, and returns clients that talk to them as a report collector.
+    async fn start_helpers_with_faults(
+        faults: &FaultInjector,
+    ) -> ([MpcHelperClient; 3], [HelperApp; 3]) {
+        let mut conf = TestConfigBuilder::with_open_ports()
+            .with_disable_https_option(true)
+            .build();
+        let clients = MpcHelperClient::from_conf(&conf.network, &ClientIdentity::None);
+        let helpers = make_helpers_with_faults(
+            conf.sockets.take().unwrap(),
+            conf.servers,
+            &conf.network,
+            conf.disable_https,
+            Some(faults),
+        )
+        .await;
+
+        (clients, helpers)
+    }
+
+    async fn test_three_helpers_with_faults(faults: &FaultInjector) {
+        let (clients, _helpers) = start_helpers_with_faults(faults).await;
+        test_multiply(&clients).await;
+        assert_eq!(0, faults.pending());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn step_streams_survive_faults() {
+        let faults = FaultInjector::default();
+        faults.inject(
+            Target::route(RouteId::Records).from(Role::H1).to(Role::H2),
+            Fault::Drop,
+        );
+        faults.inject(
+            Target::route(RouteId::Records)
+                .from(Role::H2)
+                .to(Role::H3)
+                .chunk(0),
+            Fault::Disconnect,
+        );
+        faults.inject(
+            Target::route(RouteId::Records).from(Role::H3).to(Role::H1),
+            Fault::Delay(Duration::from_millis(100)),
+        );
+
+        test_three_helpers_with_faults(&faults).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn step_stream_stall() {
+        const STALL: Duration = Duration::from_millis(500);
+        let faults = FaultInjector::default();
+        faults.inject(
+            Target::route(RouteId::Records).to(Role::H2).chunk(0),
+            Fault::Delay(STALL),
+        );
+
+        let start = Instant::now();
+        test_three_helpers_with_faults(&faults).await;
+        assert!(start.elapsed() >= STALL, "{:?}", start.elapsed());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn corrupted_query_input() {
+        let faults = FaultInjector::default();
+        faults.inject(
+            Target::route(RouteId::QueryInput).to(Role::H1).chunk(0),
+            Fault::Corrupt,
+        );
+        let (clients, _helpers) = start_helpers_with_faults(&faults).await;
+
+        // H1 and H3 hold a = 4 and b = 5 and H2 holds zeros. Corruption turns the share of `a`
+        // held by H1 into 5, which the semi-honest protocol does not detect.
+        let zero = Fp31::try_from(0u128).unwrap();
+        let a = Fp31::try_from(4u128).unwrap();
+        let b = Fp31::try_from(5u128).unwrap();
+        let shares = [
+            (AdditiveShare::new(a, zero), AdditiveShare::new(b, zero)),
+            (AdditiveShare::ZERO, AdditiveShare::ZERO),
+            (AdditiveShare::new(zero, a), AdditiveShare::new(zero, b)),
+        ];
+        let query_id = create_multiply_query(&clients).await;
+        assert_eq!(
+            Fp31::try_from(25u128).unwrap(),
+            multiply(&clients, query_id, shares).await
+        );
+        assert_eq!(0, faults.pending());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn query_api_faults() {
+        let faults = FaultInjector::default();
+        faults.inject(
+            Target::route(RouteId::ReceiveQuery).to(Role::H1),
+            Fault::Drop,
+        );
+        let (clients, _helpers) = start_helpers_with_faults(&faults).await;
+
+        let query_config = QueryConfig::new(TestMultiply, FieldType::Fp31, 1).unwrap();
+        assert!(matches!(
+            clients[0].create_query(query_config).await,
+            Err(Error::FailedHttpRequest { status, .. }) if status == StatusCode::SERVICE_UNAVAILABLE
+        ));
+
+        test_multiply(&clients).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn query_input_error() {
+        let faults = FaultInjector::default();
+        faults.inject(Target::route(RouteId::QueryInput).to(Role::H2), Fault::Drop);
+        let (clients, _helpers) = start_helpers_with_faults(&faults).await;
+
+        let query_id = create_multiply_query(&clients).await;
+        let a = Fp31::try_from(4u128).unwrap();
+        let b = Fp31::try_from(5u128).unwrap();
+        let shares = (a, b).share();
+
+        // the helper rejects the input, and accepts it when it is sent again
+        let (a_share, b_share) = shares[1].clone();
+        let input = QueryInput {
+            query_id,
+            input_stream: multiply_input(a_share, b_share),
+        };
+        assert!(matches!(
+            clients[1].query_input(input).await,
+            Err(Error::FailedHttpRequest { status, .. }) if status == StatusCode::SERVICE_UNAVAILABLE
+        ));
+        assert_eq!(
+            Fp31::try_from(20u128).unwrap(),
+            multiply(&clients, query_id, shares).await
+        );
+    }
 }
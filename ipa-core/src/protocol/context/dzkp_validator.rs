@@ -2,6 +2,7 @@ use std::{
     cmp::{self, min},
     collections::BTreeMap,
     fmt::Debug,
+    mem,
 };
 
 use async_trait::async_trait;
@@ -16,7 +17,7 @@ use tokio::sync::watch;
 use crate::{
     error::{BoxError, Error},
     ff::{Fp61BitPrime, U128Conversions},
-    helpers::{stream::TryFlattenItersExt, TotalRecords},
+    helpers::TotalRecords,
     protocol::{
         context::{
             dzkp_field::{DZKPBaseField, UVTupleBlock},
@@ -49,6 +50,32 @@ const BIT_ARRAY_SHIFT: usize = BIT_ARRAY_LEN.ilog2() as usize;
 
 pub const TARGET_PROOF_SIZE: usize = 50_000_000;
 
+/// Smallest number of multiplications per proof that a query can request. Smaller proofs add
+/// rounds of communication without saving a meaningful amount of memory.
+pub const MIN_PROOF_SIZE: usize = 1 << 16;
+
+/// Maximum number of proofs a single validator can generate. Each proof uses its own
+/// [`ValidationChunk`] step, so this must match the step count declared for it.
+///
+/// [`ValidationChunk`]: crate::protocol::context::step::ZeroKnowledgeProofValidateStep::ValidationChunk
+pub const MAX_PROOF_BATCHES: usize = 256;
+
+/// Checks that `total_records`, split into batches of `records_per_batch` records, can be
+/// validated with at most [`MAX_PROOF_BATCHES`] proofs. Protocols call this before creating a
+/// validator, so that a proof size too small for the input fails the query upfront instead of
+/// after all but the last proofs were generated.
+///
+/// ## Errors
+/// [`Error::TooManyProofBatches`] with the index of the last proof if more proofs are needed.
+pub fn check_proof_batches(records_per_batch: usize, total_records: usize) -> Result<(), Error> {
+    let batches = total_records.div_ceil(cmp::max(1, records_per_batch));
+    if batches > MAX_PROOF_BATCHES {
+        Err(Error::TooManyProofBatches(batches - 1))
+    } else {
+        Ok(())
+    }
+}
+
 /// `MultiplicationInputsBlock` is a block of fixed size of intermediate values
 /// that occur duringa multiplication.
 /// These values need to be verified since there might have been malicious behavior.
@@ -240,22 +267,24 @@ impl<'a> SegmentEntry<'a> {
 /// If there are more multiplications, it will cause a panic!
 /// `multiplication_bit_size` is the bit size of a single multiplication. The size will be consistent
 /// across all multiplications of a gate.
-/// `is_empty` keeps track of whether any value has been added
 #[derive(Clone, Debug)]
 struct MultiplicationInputsBatch {
     first_record: RecordId,
     last_record: RecordId,
     max_multiplications: usize,
     multiplication_bit_size: usize,
-    is_empty: bool,
     vec: Vec<MultiplicationInputsBlock>,
 }
 
 impl MultiplicationInputsBatch {
-    /// Creates a new store.
-    /// `first_record` and `last_record` are initialized to `0`. The size of the allocated vector is
+    /// Creates a new store for records starting at `first_record`.
+    /// `last_record` is initialized to `first_record`. The size of the allocated vector is
     /// `ceil((max_multiplications * multiplication_bit_size) / BIT_ARRAY_LEN)`.
-    fn new(max_multiplications: usize, multiplication_bit_size: usize) -> Self {
+    fn new(
+        first_record: RecordId,
+        max_multiplications: usize,
+        multiplication_bit_size: usize,
+    ) -> Self {
         // We should probably check that max_multiplications * multiplication_bit_size does
         // not exceed TARGET_PROOF_SIZE, or at least does not exceed it by much. But for now,
         // it is actually convenient that we don't -- we use TARGET_PROOF_SIZE as the limit
@@ -269,11 +298,10 @@ impl MultiplicationInputsBatch {
             max_multiplications * multiplication_bit_size,
         );
         Self {
-            first_record: RecordId::FIRST,
-            last_record: RecordId::FIRST,
+            first_record,
+            last_record: first_record,
             max_multiplications,
             multiplication_bit_size,
-            is_empty: false,
             vec: Vec::with_capacity((capacity_bits + BIT_ARRAY_MASK) >> BIT_ARRAY_SHIFT),
         }
     }
@@ -284,11 +312,9 @@ impl MultiplicationInputsBatch {
         self.vec.len() * 256
     }
 
-    /// `increment_record_ids` increments the current batch to the next set of records.
-    /// it maintains all the allocated memory and increments the `RecordIds` as follows:
-    /// It sets `last_record` and `first_record` to the record that follows `last_record`.
-    fn increment_record_ids(&mut self) {
-        // measure the amount of records stored using metrics
+    /// `record_metrics` measures the amount of records stored in this batch, compared to the
+    /// amount it was allocated for. It is called once the batch has been validated.
+    fn record_metrics(&self) {
         // currently, MultiplicationInputsBatch does not store the Gate information, maybe we should store it here
         // such that we can add it to the metrics counter
         metrics::increment_counter!(DZKP_BATCH_INCREMENTS,
@@ -296,17 +322,11 @@ impl MultiplicationInputsBatch {
             ACTUAL_AMOUNT => (usize::from(self.last_record)-usize::from(self.first_record)+1usize).to_string(),
             UNIT_SIZE => self.multiplication_bit_size.to_string(),
         );
-
-        self.last_record += 1;
-        self.first_record = self.last_record;
-
-        // set it to empty
-        self.is_empty = true;
     }
 
     /// returns whether the store is empty
     fn is_empty(&self) -> bool {
-        self.is_empty
+        self.vec.is_empty()
     }
 
     /// `insert_segment` allows to include a new segment in `MultiplicationInputsBatch`.
@@ -473,22 +493,17 @@ impl MultiplicationInputsBatch {
 /// Corresponds to `AccumulatorState` of the MAC based malicious validator.
 #[derive(Debug)]
 struct Batch {
+    first_record: RecordId,
     max_multiplications_per_gate: usize,
     inner: BTreeMap<Gate, MultiplicationInputsBatch>,
-    validation_result: watch::Sender<bool>,
-    pending_count: usize,
-    pending_records: BitVec,
 }
 
 impl Batch {
-    fn new(max_multiplications_per_gate: usize) -> Self {
-        let (validation_result, _) = watch::channel::<bool>(false);
+    fn new(first_record: RecordId, max_multiplications_per_gate: usize) -> Self {
         Self {
+            first_record,
             max_multiplications_per_gate,
             inner: BTreeMap::<Gate, MultiplicationInputsBatch>::default(),
-            validation_result,
-            pending_count: 0,
-            pending_records: bitvec![0; max_multiplications_per_gate],
         }
     }
 
@@ -502,7 +517,11 @@ impl Batch {
         self.inner
             .entry(gate)
             .or_insert_with(|| {
-                MultiplicationInputsBatch::new(self.max_multiplications_per_gate, segment.len())
+                MultiplicationInputsBatch::new(
+                    self.first_record,
+                    self.max_multiplications_per_gate,
+                    segment.len(),
+                )
             })
             .insert_segment(record_id, segment);
     }
@@ -516,16 +535,12 @@ impl Batch {
             .sum()
     }
 
-    /// This function should only be called by `validate`!
-    ///
-    /// Updates all `MultiplicationInputsBatch` in map by incrementing the record ids to next chunk
-    ///
-    /// ## Panics
-    /// Panics when `MultiplicationInputsBatch` panics, i.e. when `segment_size` is `None`
-    fn increment_record_ids(&mut self) {
+    /// Records the usage of all `MultiplicationInputsBatch` in map, see
+    /// [`MultiplicationInputsBatch::record_metrics`].
+    fn record_metrics(&self) {
         self.inner
-            .values_mut()
-            .for_each(MultiplicationInputsBatch::increment_record_ids);
+            .values()
+            .for_each(MultiplicationInputsBatch::record_metrics);
     }
 
     /// `get_field_values_prover` converts a `Batch` into an iterator over field values
@@ -561,22 +576,127 @@ impl Batch {
     }
 }
 
+/// A `Batch` that is being filled, along with the records that requested its validation through
+/// [`DZKPValidator::validate_record`].
+#[derive(Debug)]
+struct BatchState {
+    batch: Batch,
+    pending_count: usize,
+    pending_records: BitVec,
+    validation_result: watch::Sender<bool>,
+}
+
+impl BatchState {
+    fn new(first_record: RecordId, records_per_batch: usize) -> Self {
+        let (validation_result, _) = watch::channel::<bool>(false);
+        Self {
+            batch: Batch::new(first_record, records_per_batch),
+            pending_count: 0,
+            pending_records: bitvec![0; records_per_batch],
+            validation_result,
+        }
+    }
+}
+
+/// Result of requesting validation of a record.
+enum Validate {
+    /// Other records of the batch are still pending, wait for the batch to be validated.
+    Wait(watch::Receiver<bool>),
+    /// All records of the batch with the given index are ready, validate it now.
+    Now(usize, BatchState),
+}
+
+/// `Batcher` holds all batches of a validator that have not been validated yet.
+///
+/// Batch `i` collects the multiplications of records `i * records_per_batch` up to
+/// `(i + 1) * records_per_batch`. Because each batch is stored separately, records of the next
+/// batch can be computed while the previous batch is being validated. Once a batch is taken out
+/// for validation, a new batch with the same index is created if more multiplications are pushed
+/// for its records.
+#[derive(Debug)]
+struct Batcher {
+    records_per_batch: usize,
+    batches: BTreeMap<usize, BatchState>,
+}
+
+impl Batcher {
+    fn new(records_per_batch: usize) -> Self {
+        assert_ne!(
+            records_per_batch, 0,
+            "batches must hold at least one record"
+        );
+        Self {
+            records_per_batch,
+            batches: BTreeMap::default(),
+        }
+    }
+
+    fn batch_index(&self, record_id: RecordId) -> usize {
+        usize::from(record_id) / self.records_per_batch
+    }
+
+    fn batch(&mut self, batch_index: usize) -> &mut BatchState {
+        let records_per_batch = self.records_per_batch;
+        self.batches.entry(batch_index).or_insert_with(|| {
+            BatchState::new(
+                RecordId::from(batch_index * records_per_batch),
+                records_per_batch,
+            )
+        })
+    }
+
+    fn push(&mut self, gate: Gate, record_id: RecordId, segment: Segment) {
+        let batch_index = self.batch_index(record_id);
+        self.batch(batch_index).batch.push(gate, record_id, segment);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.batches.values().all(|state| state.batch.is_empty())
+    }
+
+    /// Marks `record_id` as ready for validation. Once all records of its batch are ready, the
+    /// batch is removed from this `Batcher` and returned to the caller that has to validate it.
+    /// Every batch except the last one holds `records_per_batch` records, the last one holds the
+    /// remainder of `total_records`.
+    fn validate_record(&mut self, record_id: RecordId, total_records: usize) -> Validate {
+        let batch_index = self.batch_index(record_id);
+        let first_record = batch_index * self.records_per_batch;
+        let total_count = min(self.records_per_batch, total_records - first_record);
+        let state = self.batch(batch_index);
+        state
+            .pending_records
+            .set(usize::from(record_id) - first_record, true);
+        state.pending_count += 1;
+        if state.pending_count == total_count {
+            assert!(state.pending_records[0..total_count].all());
+            Validate::Now(batch_index, self.batches.remove(&batch_index).unwrap())
+        } else {
+            Validate::Wait(state.validation_result.subscribe())
+        }
+    }
+
+    /// Removes all batches from this `Batcher`, in the order of their indices.
+    fn take_all(&mut self) -> Vec<BatchState> {
+        mem::take(&mut self.batches).into_values().collect()
+    }
+}
+
 /// Corresponds to `MaliciousAccumulator` of the MAC based malicious validator.
 #[derive(Clone, Debug)]
 pub struct DZKPBatch {
-    inner: Weak<Mutex<Batch>>,
+    inner: Weak<Mutex<Batcher>>,
 }
 
 impl DZKPBatch {
-    /// pushes values of a record, i.e. segment, to a `Batch`
+    /// pushes values of a record, i.e. segment, to the `Batch` of that record
     ///
     /// ## Panics
     /// Panics when mutex is poisoned or `segments` have different lengths within `gate`
     pub fn push(&self, gate: Gate, record_id: RecordId, segment: Segment) {
         let arc_mutex = self.inner.upgrade().unwrap();
         // LOCK BEGIN
-        let mut batch = arc_mutex.lock().unwrap();
-        batch.push(gate, record_id, segment);
+        let mut batcher = arc_mutex.lock().unwrap();
+        batcher.push(gate, record_id, segment);
         // LOCK END
     }
 
@@ -618,6 +738,7 @@ pub trait DZKPValidator: Clone + Send + Sync {
     /// to `DZKPBatch`.
     /// Currently only allows `Fp61BitPrime` and is not generic over `DZKPBaseFields`.
     ///
+    /// All batches that hold multiplications are validated together, using a single proof.
     /// `context_counter` allows to create distinct contexts
     /// when calling validate multiple times for the same base context.
     async fn validate_chunk(&self, chunk_counter: usize) -> Result<(), Error>;
@@ -629,9 +750,15 @@ pub trait DZKPValidator: Clone + Send + Sync {
     /// validation. Once `validate_record` has been called for all records in the batch, the
     /// batch is verified, and all of the `validate_record` futures complete.
     ///
+    /// Records are assigned to batches by their id, each batch holding the number of records
+    /// the validator was created with. Batches are validated independently of each other, with
+    /// a separate proof for each, so records of the next batch can be computed while the
+    /// proof for the previous batch is generated and verified.
+    ///
     /// This API may only be used when the number of records per batch is the same for every
     /// step submitting intermediates to this validator. It also requires that `set_total_records`
-    /// is set appropriately on the context that is used to create the validator.
+    /// is set appropriately on the context that is used to create the validator. It should not
+    /// be mixed with [`Self::validate_chunk`] on the same validator.
     async fn validate_record(&self, record_id: RecordId) -> Result<(), Error>;
 
     /// `is_verified` checks that there are no `MultiplicationInputs` that have not been verified
@@ -641,31 +768,21 @@ pub trait DZKPValidator: Clone + Send + Sync {
     /// Errors when there are `MultiplicationInputs` that have not been verified.
     fn is_verified(&self) -> Result<(), Error>;
 
-    /// `validated_seq_join` in this trait is a validated version of `seq_join`. It splits the input stream into `chunks` where
-    /// the `chunk_size` is specified as part of the input. Each `chunk` is a vector that is independently
-    /// verified using `validator.validate()`, which uses DZKPs. Once the validation fails,
-    /// the output stream will return an error.
+    /// `validated_seq_join` in this trait is a validated version of `seq_join`. The `i`-th
+    /// future of `source` must compute record `i`, its output is released once the batch of that
+    /// record has been validated with [`Self::validate_record`]. Once the validation fails, the
+    /// output stream will return an error.
     ///
+    /// Futures of the next batch keep running while the proof for the previous batch is
+    /// generated and verified. The same requirements as for [`Self::validate_record`] apply.
     fn validated_seq_join<'st, S, F, O>(
         &'st self,
-        chunk_size: usize,
         source: S,
     ) -> impl Stream<Item = Result<O, Error>> + 'st
     where
         S: Stream<Item = F> + Send + 'st,
         F: Future<Output = O> + Send + 'st,
-        O: Send + Sync + 'static,
-    {
-        // chunk_size is undefined in the semi-honest setting, set it to 10, ideally it would be 1
-        // but there is some overhead
-        seq_join::<'st, S, F, O>(self.context().active_work(), source)
-            .chunks(chunk_size)
-            .enumerate()
-            .then(move |(context_counter, chunk)| {
-                self.validate_chunk(context_counter).map_ok(|()| chunk)
-            })
-            .try_flatten_iters()
-    }
+        O: Send + Sync + 'static;
 }
 
 #[derive(Clone)]
@@ -700,13 +817,25 @@ impl<'a, B: ShardBinding> DZKPValidator for SemiHonestDZKPValidator<'a, B> {
     fn is_verified(&self) -> Result<(), Error> {
         Ok(())
     }
+
+    fn validated_seq_join<'st, S, F, O>(
+        &'st self,
+        source: S,
+    ) -> impl Stream<Item = Result<O, Error>> + 'st
+    where
+        S: Stream<Item = F> + Send + 'st,
+        F: Future<Output = O> + Send + 'st,
+        O: Send + Sync + 'static,
+    {
+        seq_join(self.context.active_work(), source).map(Ok)
+    }
 }
 
 /// `MaliciousDZKPValidator` corresponds to pub struct `Malicious` and implements the trait `DZKPValidator`
 /// The implementation of `validate` of the `DZKPValidator` trait depends on generic `DF`
 #[derive(Clone)]
 pub struct MaliciousDZKPValidator<'a> {
-    batch_ref: Arc<Mutex<Batch>>,
+    batcher: Arc<Mutex<Batcher>>,
     protocol_ctx: MaliciousDZKPUpgraded<'a>,
     validate_ctx: Base<'a>,
 }
@@ -719,128 +848,26 @@ impl<'a> DZKPValidator for MaliciousDZKPValidator<'a> {
         self.protocol_ctx.clone()
     }
 
+    /// ## Errors
+    /// If validation fails or `context_counter` is not less than [`MAX_PROOF_BATCHES`].
+    ///
     /// ## Panics
-    /// Panics when `context_counter` exceeds 256
-    /// or when `usize` to `u128` conversion fails.
+    /// Panics when `usize` to `u128` conversion fails.
     async fn validate_chunk(&self, context_counter: usize) -> Result<(), Error> {
-        assert!(context_counter <= 256);
-
-        // set up context for this chunk
-        let chunk_ctx = self
-            .validate_ctx
-            .narrow(&Step::ValidationChunk(context_counter));
-        let proof_ctx = chunk_ctx.narrow(&Step::GenerateProof);
-
-        let (
-            my_batch_left_shares,
-            shares_of_batch_from_left_prover,
-            p_mask_from_right_prover,
-            q_mask_from_left_prover,
-        ) = {
-            // LOCK BEGIN
-            let batch = self.batch_ref.lock().unwrap();
-            if batch.is_empty() {
-                return Ok(());
-            }
-
-            // generate BatchToVerify
-            ProofBatch::generate(&proof_ctx, batch.get_field_values_prover())
-            // LOCK END
-        };
-
-        let chunk_batch = BatchToVerify::generate_batch_to_verify(
-            proof_ctx,
-            my_batch_left_shares,
-            shares_of_batch_from_left_prover,
-            p_mask_from_right_prover,
-            q_mask_from_left_prover,
-        )
-        .await;
-
-        // generate challenges
-        let (challenges_for_left_prover, challenges_for_right_prover) = chunk_batch
-            .generate_challenges(chunk_ctx.narrow(&Step::Challenge))
-            .await;
-
-        let (sum_of_uv, p_r_right_prover, q_r_left_prover) = {
-            // LOCK BEGIN
-            let mut batch = self.batch_ref.lock().unwrap();
-
-            // get number of multiplications
-            let m = batch.get_number_of_multiplications();
-            tracing::info!("validating {m} multiplications");
-            debug_assert_eq!(
-                m,
-                batch
-                    .get_field_values_prover::<Fp61BitPrime>()
-                    .flat_map(|(u_array, v_array)| {
-                        u_array.into_iter().zip(v_array).map(|(u, v)| u * v)
-                    })
-                    .count()
-                    / 4,
-                "Number of multiplications is counted incorrectly"
-            );
-            let sum_of_uv = Fp61BitPrime::truncate_from(u128::try_from(m).unwrap())
-                * Fp61BitPrime::MINUS_ONE_HALF;
-
-            let (p_r_right_prover, q_r_left_prover) = chunk_batch.compute_p_and_q_r(
-                &challenges_for_left_prover,
-                &challenges_for_right_prover,
-                batch.get_field_values_from_right_prover(),
-                batch.get_field_values_from_left_prover(),
-            );
-            // update which empties batch_list and increments offsets to next chunk
-            batch.increment_record_ids();
-
-            (sum_of_uv, p_r_right_prover, q_r_left_prover)
-            // LOCK END
-        };
-
-        // verify BatchToVerify, return result
-        let result = chunk_batch
-            .verify(
-                chunk_ctx.narrow(&Step::VerifyProof),
-                sum_of_uv,
-                p_r_right_prover,
-                q_r_left_prover,
-                &challenges_for_left_prover,
-                &challenges_for_right_prover,
-            )
-            .await;
-
-        self.batch_ref
-            .lock()
-            .unwrap()
-            .validation_result
-            .send_replace(result.is_ok());
-
-        result
+        let states = self.batcher.lock().unwrap().take_all();
+        self.validate_states(context_counter, states).await
     }
 
     async fn validate_record(&self, record_id: RecordId) -> Result<(), Error> {
-        enum Validate {
-            Wait(watch::Receiver<bool>),
-            Now,
-        }
-
         let TotalRecords::Specified(total_records) = self.protocol_ctx.total_records() else {
             return Err(Error::MissingTotalRecords(String::from("validate_record")));
         };
 
-        let validate = {
-            let mut batch = self.batch_ref.lock().unwrap();
-            batch.pending_records.set(usize::from(record_id), true);
-            batch.pending_count += 1;
-            // TODO: adjust for multi-batch
-            let total_count = min(batch.max_multiplications_per_gate, total_records.get());
-            if batch.pending_count == total_count {
-                assert!(batch.pending_records[0..total_count].all());
-                Validate::Now
-            } else {
-                Validate::Wait(batch.validation_result.subscribe())
-            }
-            // Release the batch lock.
-        };
+        let validate = self
+            .batcher
+            .lock()
+            .unwrap()
+            .validate_record(record_id, total_records.get());
         match validate {
             Validate::Wait(mut validation_result_rx) => {
                 validation_result_rx
@@ -857,10 +884,9 @@ impl<'a> DZKPValidator for MaliciousDZKPValidator<'a> {
                     Err(Error::ParallelDZKPValidationFailed)
                 }
             }
-            Validate::Now => {
-                let index = 0; // TODO: need to update this when batching logic is fixed
+            Validate::Now(index, state) => {
                 tracing::debug!("validating batch {index}");
-                self.validate_chunk(index).await
+                self.validate_states(index, vec![state]).await
             }
         }
     }
@@ -871,30 +897,176 @@ impl<'a> DZKPValidator for MaliciousDZKPValidator<'a> {
     /// ## Errors
     /// Errors when there are `MultiplicationInputs` that have not been verified.
     fn is_verified(&self) -> Result<(), Error> {
-        if self.batch_ref.lock().unwrap().is_empty() {
+        if self.batcher.lock().unwrap().is_empty() {
             Ok(())
         } else {
             Err(Error::ContextUnsafe(format!("{:?}", self.protocol_ctx)))
         }
     }
+
+    fn validated_seq_join<'st, S, F, O>(
+        &'st self,
+        source: S,
+    ) -> impl Stream<Item = Result<O, Error>> + 'st
+    where
+        S: Stream<Item = F> + Send + 'st,
+        F: Future<Output = O> + Send + 'st,
+        O: Send + Sync + 'static,
+    {
+        // Records of one batch wait for its proof while up to a batch of the following records
+        // is released by `seq_join` and marked ready for validation.
+        let in_flight = self
+            .batcher
+            .lock()
+            .unwrap()
+            .records_per_batch
+            .saturating_mul(2);
+        seq_join(self.protocol_ctx.active_work(), source)
+            .enumerate()
+            .map(move |(i, output)| self.validate_record(RecordId::from(i)).map_ok(|()| output))
+            .buffered(in_flight)
+    }
 }
 
 impl<'a> MaliciousDZKPValidator<'a> {
+    /// Creates a validator that collects the multiplications of `max_multiplications_per_gate`
+    /// records in each batch. Validating more than [`MAX_PROOF_BATCHES`] batches fails, use
+    /// [`check_proof_batches`] to reject such inputs before running the protocol.
+    ///
+    /// ## Panics
+    /// If `max_multiplications_per_gate` is zero.
     #[must_use]
     pub fn new(ctx: MaliciousContext<'a>, max_multiplications_per_gate: usize) -> Self {
-        let list = Batch::new(max_multiplications_per_gate);
-        let batch_list = Arc::new(Mutex::new(list));
+        let batcher = Arc::new(Mutex::new(Batcher::new(max_multiplications_per_gate)));
         let dzkp_batch = DZKPBatch {
-            inner: Arc::downgrade(&batch_list),
+            inner: Arc::downgrade(&batcher),
         };
         let validate_ctx = ctx.narrow(&Step::DZKPValidate).validator_context();
         let protocol_ctx = ctx.dzkp_upgrade(&Step::DZKPMaliciousProtocol, dzkp_batch);
         Self {
-            batch_ref: batch_list,
+            batcher,
             protocol_ctx,
             validate_ctx,
         }
     }
+
+    /// Validates the batches of `states` and notifies the records waiting for them of the result.
+    async fn validate_states(
+        &self,
+        context_counter: usize,
+        states: Vec<BatchState>,
+    ) -> Result<(), Error> {
+        let (batches, senders): (Vec<_>, Vec<_>) = states
+            .into_iter()
+            .map(|state| (state.batch, state.validation_result))
+            .unzip();
+
+        let result = self.validate_batches(context_counter, &batches).await;
+        for sender in senders {
+            sender.send_replace(result.is_ok());
+        }
+
+        result
+    }
+
+    /// Proves and verifies the multiplications of `batches` with a single proof.
+    ///
+    /// ## Errors
+    /// If validation fails or `context_counter` is not less than [`MAX_PROOF_BATCHES`].
+    ///
+    /// ## Panics
+    /// Panics when `usize` to `u128` conversion fails.
+    async fn validate_batches(
+        &self,
+        context_counter: usize,
+        batches: &[Batch],
+    ) -> Result<(), Error> {
+        if context_counter >= MAX_PROOF_BATCHES {
+            return Err(Error::TooManyProofBatches(context_counter));
+        }
+
+        if batches.iter().all(Batch::is_empty) {
+            return Ok(());
+        }
+
+        // set up context for this chunk
+        let chunk_ctx = self
+            .validate_ctx
+            .narrow(&Step::ValidationChunk(context_counter));
+        let proof_ctx = chunk_ctx.narrow(&Step::GenerateProof);
+
+        // generate BatchToVerify
+        let (
+            my_batch_left_shares,
+            shares_of_batch_from_left_prover,
+            p_mask_from_right_prover,
+            q_mask_from_left_prover,
+        ) = ProofBatch::generate(
+            &proof_ctx,
+            batches
+                .iter()
+                .flat_map(Batch::get_field_values_prover::<Fp61BitPrime>),
+        );
+
+        let chunk_batch = BatchToVerify::generate_batch_to_verify(
+            proof_ctx,
+            my_batch_left_shares,
+            shares_of_batch_from_left_prover,
+            p_mask_from_right_prover,
+            q_mask_from_left_prover,
+        )
+        .await;
+
+        // generate challenges
+        let (challenges_for_left_prover, challenges_for_right_prover) = chunk_batch
+            .generate_challenges(chunk_ctx.narrow(&Step::Challenge))
+            .await;
+
+        // get number of multiplications
+        let m = batches
+            .iter()
+            .map(Batch::get_number_of_multiplications)
+            .sum::<usize>();
+        tracing::info!("validating {m} multiplications");
+        debug_assert_eq!(
+            m,
+            batches
+                .iter()
+                .flat_map(Batch::get_field_values_prover::<Fp61BitPrime>)
+                .flat_map(|(u_array, v_array)| {
+                    u_array.into_iter().zip(v_array).map(|(u, v)| u * v)
+                })
+                .count()
+                / 4,
+            "Number of multiplications is counted incorrectly"
+        );
+        let sum_of_uv =
+            Fp61BitPrime::truncate_from(u128::try_from(m).unwrap()) * Fp61BitPrime::MINUS_ONE_HALF;
+
+        let (p_r_right_prover, q_r_left_prover) = chunk_batch.compute_p_and_q_r(
+            &challenges_for_left_prover,
+            &challenges_for_right_prover,
+            batches
+                .iter()
+                .flat_map(Batch::get_field_values_from_right_prover::<Fp61BitPrime>),
+            batches
+                .iter()
+                .flat_map(Batch::get_field_values_from_left_prover::<Fp61BitPrime>),
+        );
+        batches.iter().for_each(Batch::record_metrics);
+
+        // verify BatchToVerify, return result
+        chunk_batch
+            .verify(
+                chunk_ctx.narrow(&Step::VerifyProof),
+                sum_of_uv,
+                p_r_right_prover,
+                q_r_left_prover,
+                &challenges_for_left_prover,
+                &challenges_for_right_prover,
+            )
+            .await
+    }
 }
 
 impl<'a> Drop for MaliciousDZKPValidator<'a> {
@@ -907,8 +1079,8 @@ impl<'a> Drop for MaliciousDZKPValidator<'a> {
 mod tests {
     use std::{
         iter::{repeat, repeat_with, zip},
-        mem,
         num::NonZeroUsize,
+        pin::pin,
     };
 
     use bitvec::{order::Lsb0, prelude::BitArray, vec::BitVec};
@@ -925,8 +1097,8 @@ mod tests {
             context::{
                 dzkp_field::{DZKPCompatibleField, BLOCK_SIZE},
                 dzkp_validator::{
-                    Batch, DZKPValidator, Segment, SegmentEntry, Step, BIT_ARRAY_LEN,
-                    TARGET_PROOF_SIZE,
+                    check_proof_batches, Batch, DZKPValidator, Segment, SegmentEntry, Step,
+                    BIT_ARRAY_LEN, MAX_PROOF_BATCHES, TARGET_PROOF_SIZE,
                 },
                 Context, DZKPContext, UpgradableContext,
             },
@@ -937,6 +1109,7 @@ mod tests {
             Vectorizable,
         },
         seq_join::seq_join,
+        sync::atomic::{AtomicUsize, Ordering},
         test_fixture::{join3v, Reconstruct, Runner, TestWorld},
     };
 
@@ -1006,11 +1179,159 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn dzkp_malicious_batches() {
+        const COUNT: usize = 30;
+        const RECORDS_PER_BATCH: usize = 4;
+        let mut rng = thread_rng();
+
+        let original_inputs = repeat_with(|| rng.gen())
+            .take(COUNT)
+            .collect::<Vec<Boolean>>();
+
+        // 29 records are validated in 8 batches, the last one holds a single record. All records
+        // are started at once, so later batches are computed while earlier ones are validated.
+        let [res0, res1, res2] = TestWorld::default()
+            .malicious(
+                original_inputs.clone().into_iter(),
+                |ctx, input_shares| async move {
+                    let v = ctx
+                        .set_total_records(COUNT - 1)
+                        .dzkp_validator(RECORDS_PER_BATCH);
+                    let m_ctx = v.context().narrow(&Step::DZKPMaliciousProtocol);
+
+                    let m_results = seq_join(
+                        NonZeroUsize::new(COUNT).unwrap(),
+                        iter(
+                            zip(input_shares.clone(), input_shares.into_iter().skip(1))
+                                .enumerate()
+                                .map(|(i, (a_malicious, b_malicious))| {
+                                    let m_ctx = m_ctx.clone();
+                                    let v = &v;
+                                    async move {
+                                        let record_id = RecordId::from(i);
+                                        let result = a_malicious
+                                            .multiply(&b_malicious, m_ctx, record_id)
+                                            .await?;
+                                        v.validate_record(record_id).await?;
+                                        Ok::<_, Error>(result)
+                                    }
+                                }),
+                        ),
+                    )
+                    .try_collect::<Vec<_>>()
+                    .await?;
+                    m_ctx.is_verified().unwrap();
+                    v.is_verified().unwrap();
+                    Ok::<_, Error>(m_results)
+                },
+            )
+            .await
+            .map(Result::unwrap);
+
+        for i in 0..COUNT - 1 {
+            let expected = original_inputs[i] * original_inputs[i + 1];
+            let actual = [res0[i].clone(), res1[i].clone(), res2[i].clone()].reconstruct();
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[tokio::test]
+    async fn validated_seq_join_overlaps_batches() {
+        const COUNT: usize = 32;
+        const RECORDS_PER_BATCH: usize = 4;
+        let mut rng = thread_rng();
+
+        let original_inputs = repeat_with(|| rng.gen())
+            .take(COUNT)
+            .collect::<Vec<Boolean>>();
+
+        let results = TestWorld::default()
+            .malicious(
+                original_inputs.clone().into_iter(),
+                |ctx, input_shares| async move {
+                    let v = ctx
+                        .set_total_records(COUNT - 1)
+                        .dzkp_validator(RECORDS_PER_BATCH);
+                    let m_ctx = v.context().narrow(&Step::DZKPMaliciousProtocol);
+                    let started = AtomicUsize::new(0);
+
+                    let mut outputs = pin!(v.validated_seq_join(iter(
+                        zip(input_shares.clone(), input_shares.into_iter().skip(1))
+                            .enumerate()
+                            .map(|(i, (a_malicious, b_malicious))| {
+                                let m_ctx = m_ctx.clone();
+                                let started = &started;
+                                async move {
+                                    started.fetch_add(1, Ordering::Relaxed);
+                                    a_malicious
+                                        .multiply(&b_malicious, m_ctx, RecordId::from(i))
+                                        .await
+                                        .unwrap()
+                                }
+                            }),
+                    )));
+                    // The first output is released once the first batch is validated. By then,
+                    // records of the following batch must have been started.
+                    let first = outputs.next().await.unwrap().unwrap();
+                    let started_before_first = started.load(Ordering::Relaxed);
+                    let mut m_results = vec![first];
+                    m_results.extend(outputs.try_collect::<Vec<_>>().await.unwrap());
+                    v.is_verified().unwrap();
+                    (started_before_first, m_results)
+                },
+            )
+            .await;
+
+        for (started_before_first, _) in &results {
+            assert!(
+                *started_before_first > RECORDS_PER_BATCH,
+                "only {started_before_first} records started before the first batch was validated"
+            );
+        }
+        let [(_, res0), (_, res1), (_, res2)] = results;
+        for i in 0..COUNT - 1 {
+            let expected = original_inputs[i] * original_inputs[i + 1];
+            let actual = [res0[i].clone(), res1[i].clone(), res2[i].clone()].reconstruct();
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn proof_batches_are_bounded() {
+        assert!(check_proof_batches(10, 100).is_ok());
+        assert!(check_proof_batches(1, MAX_PROOF_BATCHES).is_ok());
+        assert!(check_proof_batches(2, 2 * MAX_PROOF_BATCHES).is_ok());
+        assert!(matches!(
+            check_proof_batches(1, MAX_PROOF_BATCHES + 1),
+            Err(Error::TooManyProofBatches(MAX_PROOF_BATCHES))
+        ));
+        assert!(matches!(
+            check_proof_batches(0, MAX_PROOF_BATCHES + 1),
+            Err(Error::TooManyProofBatches(MAX_PROOF_BATCHES))
+        ));
+    }
+
+    #[tokio::test]
+    async fn validator_bounds_proof_count() {
+        const COUNT: usize = 10 * MAX_PROOF_BATCHES;
+        TestWorld::default()
+            .malicious((), |ctx, ()| async move {
+                // batches are not grown to fit the records into fewer proofs
+                let v = ctx.set_total_records(COUNT).dzkp_validator(1);
+                assert_eq!(1, v.batcher.lock().unwrap().records_per_batch);
+                assert!(matches!(
+                    v.validate_chunk(MAX_PROOF_BATCHES).await,
+                    Err(Error::TooManyProofBatches(MAX_PROOF_BATCHES))
+                ));
+            })
+            .await;
+    }
+
     /// test for testing `validated_seq_join`
     /// similar to `complex_circuit` in `validator.rs`
     async fn complex_circuit_dzkp(
         count: usize,
-        chunk_size: usize,
         max_multiplications_per_gate: usize,
     ) -> Result<(), Error> {
         let world = TestWorld::default();
@@ -1032,34 +1353,32 @@ mod tests {
         let h3_shares: Vec<Replicated<Boolean>> =
             shared_inputs.iter().map(|x| x[2].clone()).collect();
 
-        // todo(DM): change to malicious when proof batching is fixed
         let futures = world
-            .contexts()
+            .malicious_contexts()
             .into_iter()
             .zip([h1_shares.clone(), h2_shares.clone(), h3_shares.clone()])
             .map(|(ctx, input_shares)| async move {
-                let v = ctx.dzkp_validator(max_multiplications_per_gate);
+                let v = ctx
+                    .set_total_records(count - 1)
+                    .dzkp_validator(max_multiplications_per_gate);
                 // test whether narrow works
                 let m_ctx = v.context().narrow(&Step::DZKPMaliciousProtocol);
 
                 let m_results = v
-                    .validated_seq_join(
-                        chunk_size,
-                        iter(
-                            zip(
-                                repeat(m_ctx.set_total_records(count - 1)).enumerate(),
-                                zip(input_shares.iter(), input_shares.iter().skip(1)),
-                            )
-                            .map(
-                                |((i, ctx), (a_malicious, b_malicious))| async move {
-                                    a_malicious
-                                        .multiply(b_malicious, ctx, RecordId::from(i))
-                                        .await
-                                        .unwrap()
-                                },
-                            ),
+                    .validated_seq_join(iter(
+                        zip(
+                            repeat(m_ctx.clone()).enumerate(),
+                            zip(input_shares.iter(), input_shares.iter().skip(1)),
+                        )
+                        .map(
+                            |((i, ctx), (a_malicious, b_malicious))| async move {
+                                a_malicious
+                                    .multiply(b_malicious, ctx, RecordId::from(i))
+                                    .await
+                                    .unwrap()
+                            },
                         ),
-                    )
+                    ))
                     .try_collect::<Vec<_>>()
                     .await?;
                 // check whether verification was successful
@@ -1075,28 +1394,27 @@ mod tests {
             .into_iter()
             .zip([h1_shares, h2_shares, h3_shares])
             .map(|(ctx, input_shares)| async move {
-                let v = ctx.dzkp_validator(max_multiplications_per_gate);
+                let v = ctx
+                    .set_total_records(count - 1)
+                    .dzkp_validator(max_multiplications_per_gate);
                 // test whether narrow works
                 let m_ctx = v.context().narrow(&Step::DZKPMaliciousProtocol);
 
                 let m_results = v
-                    .validated_seq_join(
-                        chunk_size,
-                        iter(
-                            zip(
-                                repeat(m_ctx.set_total_records(count - 1)).enumerate(),
-                                zip(input_shares.iter(), input_shares.iter().skip(1)),
-                            )
-                            .map(
-                                |((i, ctx), (a_malicious, b_malicious))| async move {
-                                    a_malicious
-                                        .multiply(b_malicious, ctx, RecordId::from(i))
-                                        .await
-                                        .unwrap()
-                                },
-                            ),
+                    .validated_seq_join(iter(
+                        zip(
+                            repeat(m_ctx.clone()).enumerate(),
+                            zip(input_shares.iter(), input_shares.iter().skip(1)),
+                        )
+                        .map(
+                            |((i, ctx), (a_malicious, b_malicious))| async move {
+                                a_malicious
+                                    .multiply(b_malicious, ctx, RecordId::from(i))
+                                    .await
+                                    .unwrap()
+                            },
                         ),
-                    )
+                    ))
                     .try_collect::<Vec<_>>()
                     .await?;
                 v.is_verified().unwrap();
@@ -1130,16 +1448,16 @@ mod tests {
     }
 
     prop_compose! {
-        fn arb_count_and_chunk()((log_count, log_chunk_size, log_multiplication_amount) in select(&[(5,5,5),(7,5,5),(5,7,8)])) -> (usize, usize, usize) {
-            (1usize<<log_count, 1usize<<log_chunk_size, 1usize<<log_multiplication_amount)
+        fn arb_count_and_chunk()((log_count, log_multiplication_amount) in select(&[(5,5),(7,5),(5,8)])) -> (usize, usize) {
+            (1usize<<log_count, 1usize<<log_multiplication_amount)
         }
     }
 
     proptest! {
         #[test]
-        fn test_complex_circuit_dzkp((count, chunk_size, multiplication_amount) in arb_count_and_chunk()){
+        fn test_complex_circuit_dzkp((count, multiplication_amount) in arb_count_and_chunk()){
             let future = async {
-            let _ = complex_circuit_dzkp(count, chunk_size, multiplication_amount).await;
+            let _ = complex_circuit_dzkp(count, multiplication_amount).await;
         };
         tokio::runtime::Runtime::new().unwrap().block_on(future);
         }
@@ -1148,7 +1466,7 @@ mod tests {
     #[test]
     fn batch_allocation_small() {
         const SIZE: usize = 1;
-        let mut batch = Batch::new(SIZE);
+        let mut batch = Batch::new(RecordId::FIRST, SIZE);
         let zero = Boolean::ZERO;
         let zero_vec: <Boolean as Vectorizable<1>>::Array = zero.into_array();
         let segment_entry = <Boolean as DZKPCompatibleField<1>>::as_segment_entry(&zero_vec);
@@ -1170,7 +1488,7 @@ mod tests {
     #[test]
     fn batch_allocation_big() {
         const SIZE: usize = 2 * TARGET_PROOF_SIZE;
-        let mut batch = Batch::new(SIZE);
+        let mut batch = Batch::new(RecordId::FIRST, SIZE);
         let zero = Boolean::ZERO;
         let zero_vec: <Boolean as Vectorizable<1>>::Array = zero.into_array();
         let segment_entry = <Boolean as DZKPCompatibleField<1>>::as_segment_entry(&zero_vec);
@@ -1198,7 +1516,7 @@ mod tests {
     #[test]
     fn batch_fill() {
         const SIZE: usize = 10;
-        let mut batch = Batch::new(SIZE);
+        let mut batch = Batch::new(RecordId::FIRST, SIZE);
         let zero = Boolean::ZERO;
         let zero_vec: <Boolean as Vectorizable<1>>::Array = zero.into_array();
         let segment_entry = <Boolean as DZKPCompatibleField<1>>::as_segment_entry(&zero_vec);
@@ -1225,7 +1543,7 @@ mod tests {
     )]
     fn batch_overflow() {
         const SIZE: usize = 10;
-        let mut batch = Batch::new(SIZE);
+        let mut batch = Batch::new(RecordId::FIRST, SIZE);
         let zero = Boolean::ZERO;
         let zero_vec: <Boolean as Vectorizable<1>>::Array = zero.into_array();
         let segment_entry = <Boolean as DZKPCompatibleField<1>>::as_segment_entry(&zero_vec);
@@ -1368,13 +1686,13 @@ mod tests {
         // test for small and large segments, i.e. 8bit and 512 bit
         for segment_size in [8usize, 512usize] {
             // generate batch for the prover
-            let mut batch_prover = Batch::new(1024 / segment_size);
+            let mut batch_prover = Batch::new(RecordId::FIRST, 1024 / segment_size);
 
             // generate batch for the verifier on the left of the prover
-            let mut batch_left = Batch::new(1024 / segment_size);
+            let mut batch_left = Batch::new(RecordId::FIRST, 1024 / segment_size);
 
             // generate batch for the verifier on the right of the prover
-            let mut batch_right = Batch::new(1024 / segment_size);
+            let mut batch_right = Batch::new(RecordId::FIRST, 1024 / segment_size);
 
             // fill the batches with random values
             populate_batch(
@@ -1450,10 +1768,9 @@ mod tests {
                     .unwrap();
 
                 // LOCK BEGIN
-                let mut batch = validator.batch_ref.lock().unwrap();
+                let mut batcher = validator.batcher.lock().unwrap();
 
-                let max_mult = batch.max_multiplications_per_gate;
-                mem::replace(&mut *batch, Batch::new(max_mult))
+                batcher.take_all().pop().unwrap().batch
             })
             .await;
 
@@ -164,6 +164,10 @@ impl<'a> UpgradableContext for Context<'a> {
     fn dzkp_validator(self, max_multiplications_per_gate: usize) -> Self::DZKPValidator {
         MaliciousDZKPValidator::new(self, max_multiplications_per_gate)
     }
+
+    fn target_proof_size(&self) -> usize {
+        self.inner.target_proof_size()
+    }
 }
 
 impl<'a> SeqJoin for Context<'a> {
@@ -114,6 +114,11 @@ pub trait UpgradableContext: Context {
     type DZKPValidator: DZKPValidator;
 
     fn dzkp_validator(self, max_multiplications_per_gate: usize) -> Self::DZKPValidator;
+
+    /// Number of bit multiplications that a single zero-knowledge proof should cover, as
+    /// configured for the query. Protocols use it to choose how many records are validated
+    /// together by their [`DZKPValidator`].
+    fn target_proof_size(&self) -> usize;
 }
 
 #[async_trait]
@@ -216,6 +221,10 @@ impl<'a, B: ShardBinding> Base<'a, B> {
             sharding,
         }
     }
+
+    fn target_proof_size(&self) -> usize {
+        self.inner.gateway.config().target_proof_size.get()
+    }
 }
 
 impl ShardedContext for Base<'_, Sharded> {
@@ -692,8 +701,12 @@ mod tests {
             .per_step(&metrics_step.narrow("seq-prss-0"), 6 * 3);
 
         for role in Role::all() {
-            records_sent_assert.per_helper(role, input_size);
-            bytes_sent_assert.per_helper(role, field_size * input_size);
+            records_sent_assert
+                .per_helper(role, input_size)
+                .per_step_and_helper(&metrics_step, role, input_size);
+            bytes_sent_assert
+                .per_helper(role, field_size * input_size)
+                .per_step_and_helper(&metrics_step, role, field_size * input_size);
             indexed_prss_assert.per_helper(role, input_size);
             seq_prss_assert.per_helper(role, 6 * input_size);
         }
@@ -158,6 +158,10 @@ impl<'a, B: ShardBinding> UpgradableContext for Context<'a, B> {
     fn dzkp_validator(self, _max_multiplications_per_gate: usize) -> Self::DZKPValidator {
         Self::DZKPValidator::new(self.inner)
     }
+
+    fn target_proof_size(&self) -> usize {
+        self.inner.target_proof_size()
+    }
 }
 
 impl<'a, B: ShardBinding> SeqJoin for Context<'a, B> {
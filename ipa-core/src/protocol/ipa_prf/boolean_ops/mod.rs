@@ -4,6 +4,7 @@ mod multiplication;
 mod share_conversion_aby;
 pub(crate) mod step;
 pub use share_conversion_aby::{
-    convert_to_fp25519, expand_shared_array_in_place, extract_from_shared_array,
+    conversion_records_per_batch, convert_to_fp25519, expand_shared_array_in_place,
+    extract_from_shared_array,
 };
 pub mod sigmoid;
@@ -1,4 +1,4 @@
-use std::{convert::Infallible, iter::zip, ops::Neg};
+use std::{cmp::max, convert::Infallible, iter::zip, ops::Neg};
 
 use crate::{
     error::{Error, UnwrapInfallible},
@@ -25,6 +25,18 @@ use crate::{
     },
 };
 
+/// Number of multiplications performed by [`convert_to_fp25519`] for each converted value: it
+/// adds two 256-bit integers twice, which takes one multiplication per bit.
+const MULTIPLICATIONS_PER_CONVERSION: usize = 2 * 256;
+
+/// Returns the number of records, each converting `NC` values, that [`convert_to_fp25519`] should
+/// record into a single DZKP batch for the proof to have about `target_proof_size`
+/// multiplications. This is the value to create the validator with.
+#[must_use]
+pub fn conversion_records_per_batch<const NC: usize>(target_proof_size: usize) -> usize {
+    max(1, target_proof_size / (NC * MULTIPLICATIONS_PER_CONVERSION))
+}
+
 /// share conversion
 /// from Boolean array of size n to integer mod p, where p is modulus of elliptic curve field `Fp25519`
 /// We follow the ABY3 (`https://eprint.iacr.org/2018/403.pdf`)
@@ -92,6 +104,10 @@ use crate::{
 /// dimension. Most of this routine works at `NC`. The final packing in `output_shares` converts to
 /// a vector of (NC / NP) shares, each with dimension `NP`.
 ///
+/// `y` is revealed once `dzkp_validator` has validated the batch `record_id` belongs to, so all
+/// records converted with the same validator share their proofs. Use
+/// [`conversion_records_per_batch`] to size the batches.
+///
 /// # Errors
 /// Propagates Errors from Integer Subtraction, Partial Reveal and Validate
 /// # Panics
@@ -390,7 +406,7 @@ mod tests {
         helpers::{repeat_n, stream::process_slice_by_chunks},
         protocol::{
             context::UpgradableContext,
-            ipa_prf::{CONV_CHUNK, CONV_PROOF_CHUNK, PRF_CHUNK},
+            ipa_prf::{CONV_CHUNK, PRF_CHUNK},
         },
         rand::thread_rng,
         secret_sharing::SharedValue,
@@ -423,8 +439,10 @@ mod tests {
 
             let [res0, res1, res2] = world
                 .semi_honest(records.into_iter(), |ctx, records| async move {
+                    let records_per_batch =
+                        conversion_records_per_batch::<CONV_CHUNK>(ctx.target_proof_size());
                     let c_ctx = ctx.set_total_records((COUNT + CONV_CHUNK - 1) / CONV_CHUNK);
-                    let validator = &c_ctx.dzkp_validator(CONV_PROOF_CHUNK);
+                    let validator = &c_ctx.dzkp_validator(records_per_batch);
                     let m_ctx = validator.context();
                     seq_join(
                         m_ctx.active_work(),
@@ -461,13 +479,11 @@ mod tests {
     #[test]
     fn test_malicious_convert_to_fp25519() {
         run(|| async move {
-            // TODO: use something like the commented parameters, when proof batching is fixed, to
-            // exercise it. Ideally PROOF_CHUNK could be more than 1, but the test is pretty slow.
-            //const COUNT: usize = CONV_CHUNK * PROOF_CHUNK * 2 + 1;
-            //const PROOF_CHUNK: usize = 1;
-            const COUNT: usize = CONV_CHUNK + 1;
+            // Two full batches of two records each, and a partial batch with a single record
+            // that is not fully populated.
+            const PROOF_CHUNK: usize = 2;
+            const COUNT: usize = CONV_CHUNK * PROOF_CHUNK * 2 + 1;
             const TOTAL_RECORDS: usize = (COUNT + CONV_CHUNK - 1) / CONV_CHUNK;
-            const PROOF_CHUNK: usize = TOTAL_RECORDS;
 
             let world = TestWorld::default();
 
@@ -547,6 +563,17 @@ mod tests {
         });
     }
 
+    #[test]
+    fn records_per_batch() {
+        // 256 * 512 multiplications per record
+        assert_eq!(381, conversion_records_per_batch::<CONV_CHUNK>(50_000_000));
+        assert_eq!(
+            2,
+            conversion_records_per_batch::<CONV_CHUNK>(2 * 256 * 512 + 1)
+        );
+        assert_eq!(1, conversion_records_per_batch::<CONV_CHUNK>(1));
+    }
+
     #[test]
     fn test_expand() {
         let mut rng = thread_rng();
@@ -20,11 +20,12 @@ use crate::{
     protocol::{
         basics::{BooleanArrayMul, BooleanProtocols, SecureMul},
         context::{
-            dzkp_validator::DZKPValidator, Context, SemiHonestContext, UpgradableContext,
-            UpgradedContext, UpgradedSemiHonestContext,
+            dzkp_validator::{check_proof_batches, DZKPValidator},
+            Context, SemiHonestContext, UpgradableContext, UpgradedContext,
+            UpgradedSemiHonestContext,
         },
         ipa_prf::{
-            boolean_ops::convert_to_fp25519,
+            boolean_ops::{conversion_records_per_batch, convert_to_fp25519},
             prf_eval::{eval_dy_prf, gen_prf_key},
             prf_sharding::{
                 attribute_cap_aggregate, histograms_ranges_sortkeys, PrfShardedIpaInputRow,
@@ -281,12 +282,6 @@ where
     Ok(noisy_histogram)
 }
 
-// We expect 2*256 = 512 gates in total for two additions per conversion. The vectorization factor
-// is CONV_CHUNK. Let `len` equal the number of converted shares. The total amount of
-// multiplications is CONV_CHUNK*512*len. We want CONV_CHUNK*512*len ≈ 50M, or len ≈ 381, for a
-// reasonably-sized proof.
-const CONV_PROOF_CHUNK: usize = 400;
-
 #[tracing::instrument(name = "compute_prf_for_inputs", skip_all)]
 async fn compute_prf_for_inputs<C, BK, TV, TS>(
     ctx: C,
@@ -304,8 +299,8 @@ where
     >,
     Replicated<Fp25519, PRF_CHUNK>: SecureMul<C> + FromPrss,
 {
-    let conv_records =
-        TotalRecords::specified(div_round_up(input_rows.len(), Const::<CONV_CHUNK>))?;
+    let conv_records_usize = div_round_up(input_rows.len(), Const::<CONV_CHUNK>);
+    let conv_records = TotalRecords::specified(conv_records_usize)?;
     let eval_records = TotalRecords::specified(div_round_up(input_rows.len(), Const::<PRF_CHUNK>))?;
     let convert_ctx = ctx
         .narrow(&Step::ConvertFp25519)
@@ -314,7 +309,11 @@ where
 
     let prf_key = gen_prf_key(&eval_ctx);
 
-    let validator = convert_ctx.dzkp_validator(CONV_PROOF_CHUNK);
+    // Conversions of all records are recorded into shared batches, each of them proving about
+    // `target_proof_size` multiplications.
+    let records_per_batch = conversion_records_per_batch::<CONV_CHUNK>(ctx.target_proof_size());
+    check_proof_batches(records_per_batch, conv_records_usize)?;
+    let validator = convert_ctx.dzkp_validator(records_per_batch);
 
     let curve_pts = seq_join(
         ctx.active_work(),
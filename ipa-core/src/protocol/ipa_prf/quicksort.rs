@@ -1,7 +1,10 @@
-use std::{convert::Infallible, mem, ops::Range};
+use std::{cmp::max, convert::Infallible, mem, ops::Range};
 
 use bitvec::prelude::{BitVec, Lsb0};
-use futures::stream::{self, repeat, StreamExt, TryStreamExt};
+use futures::{
+    future,
+    stream::{self, repeat, StreamExt, TryStreamExt},
+};
 use typenum::Const;
 
 use crate::{
@@ -14,7 +17,10 @@ use crate::{
     protocol::{
         basics::reveal,
         boolean::{step::ThirtyTwoBitStep, NBitStep},
-        context::{dzkp_validator::DZKPValidator, Context, UpgradableContext},
+        context::{
+            dzkp_validator::{check_proof_batches, DZKPValidator},
+            Context, UpgradableContext,
+        },
         ipa_prf::{
             boolean_ops::comparison_and_subtraction_sequential::compare_gt,
             step::{QuicksortPassStep, QuicksortStep as Step},
@@ -118,11 +124,41 @@ where
 /// # Panics
 /// If any of the input ranges are empty
 pub async fn quicksort_ranges_by_key_insecure<C, K, F, S>(
+    ctx: C,
+    list: &mut [S],
+    desc: bool,
+    get_key: F,
+    ranges_to_sort: Vec<Range<usize>>,
+) -> Result<(), Error>
+where
+    C: UpgradableContext,
+    S: Send + Sync,
+    F: Fn(&S) -> &AdditiveShare<K> + Sync + Send + Copy,
+    K: BooleanArray,
+    AdditiveShare<Boolean, SORT_CHUNK>:
+        BooleanProtocols<<C::DZKPValidator as DZKPValidator>::Context, SORT_CHUNK>,
+    BitDecomposed<AdditiveShare<Boolean, SORT_CHUNK>>:
+        for<'a> TransposeFrom<&'a [AdditiveShare<K>; SORT_CHUNK], Error = Infallible>,
+    BitDecomposed<AdditiveShare<Boolean, SORT_CHUNK>>:
+        for<'a> TransposeFrom<&'a [AdditiveShare<K>; SORT_CHUNK], Error = Infallible>,
+{
+    let records_per_batch = max(
+        1,
+        ctx.target_proof_size() / usize::try_from(K::BITS).unwrap() / SORT_CHUNK,
+    );
+    quicksort_ranges_by_key_in_batches(ctx, list, desc, get_key, ranges_to_sort, records_per_batch)
+        .await
+}
+
+/// Implementation of [`quicksort_ranges_by_key_insecure`]. Comparisons of each pass are validated
+/// in batches of `records_per_batch` records, each of them holding `SORT_CHUNK` comparisons.
+async fn quicksort_ranges_by_key_in_batches<C, K, F, S>(
     ctx: C,
     list: &mut [S],
     desc: bool,
     get_key: F,
     mut ranges_to_sort: Vec<Range<usize>>,
+    records_per_batch: usize,
 ) -> Result<(), Error>
 where
     C: UpgradableContext,
@@ -167,12 +203,11 @@ where
         let total_records_usize = div_round_up(num_comparisons_needed, Const::<SORT_CHUNK>);
         let total_records = TotalRecords::specified(total_records_usize)
             .expect("num_comparisons_needed should not be zero");
+        check_proof_batches(records_per_batch, total_records_usize)?;
         let v = ctx
             .narrow(&Step::QuicksortPass(quicksort_pass))
             .set_total_records(total_records)
-            // TODO: use something like this when validating in chunks
-            //.dzkp_validator(TARGET_PROOF_SIZE / usize::try_from(K::BITS).unwrap() / SORT_CHUNK);
-            .dzkp_validator(total_records_usize);
+            .dzkp_validator(records_per_batch);
         let c = v.context();
         let cmp_ctx = c.narrow(&QuicksortPassStep::Compare);
         let rvl_ctx = c.narrow(&QuicksortPassStep::Reveal);
@@ -192,9 +227,10 @@ where
             K::BITS <= ThirtyTwoBitStep::BITS,
             "ThirtyTwoBitStep is not large enough to accommodate this sort"
         );
-        let compare_results = seq_join(
-            ctx.active_work(),
-            process_stream_by_chunks::<_, _, _, _, _, _, SORT_CHUNK>(
+        // Comparisons are validated one batch at a time. Comparisons of the next batch run while
+        // the proof for a batch is generated, and a failed validation stops the sort.
+        let compare_results = v
+            .validated_seq_join(process_stream_by_chunks::<_, _, _, _, _, _, SORT_CHUNK>(
                 compare_index_pairs,
                 (Vec::new(), Vec::new()),
                 move |idx, (pivot, k)| {
@@ -208,13 +244,10 @@ where
                         .await
                     }
                 },
-            ),
-        )
-        .try_collect::<Vec<_>>()
-        .await?;
-
-        // TODO: validate in chunks rather than for the entire input
-        v.validate().await?;
+            ))
+            .and_then(future::ready)
+            .try_collect::<Vec<_>>()
+            .await?;
 
         let revealed: BitVec<usize, Lsb0> = seq_join(
             ctx.active_work(),
@@ -271,19 +304,39 @@ where
 pub mod tests {
     use std::{
         cmp::Ordering,
+        collections::HashMap,
         iter::{repeat, repeat_with},
     };
 
+    use futures::FutureExt;
     use ipa_step_derive::CompactStep;
     use rand::Rng;
 
     use crate::{
-        ff::{boolean_array::BA32, U128Conversions},
-        protocol::{context::Context, ipa_prf::quicksort::quicksort_ranges_by_key_insecure},
+        error::Error,
+        ff::{
+            boolean_array::{BA256, BA32},
+            Field, Fp61BitPrime, U128Conversions,
+        },
+        helpers::{
+            in_memory_config::{InspectContext, StreamInterceptor},
+            HelperIdentity, Role,
+        },
+        protocol::{
+            context::Context,
+            ipa_prf::{
+                quicksort::{quicksort_ranges_by_key_in_batches, quicksort_ranges_by_key_insecure},
+                SORT_CHUNK,
+            },
+            Gate, RecordId,
+        },
         rand::thread_rng,
         secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
+        sync::{Arc, Mutex},
         test_executor::run,
-        test_fixture::{Reconstruct, Runner, TestWorld},
+        test_fixture::{
+            first_error, Adversary, Reconstruct, Records, Runner, TestWorld, TestWorldConfig,
+        },
     };
 
     type TestSortKey = BA32;
@@ -387,6 +440,128 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn test_quicksort_insecure_malicious_batches() {
+        run(|| async move {
+            const COUNT: usize = 600;
+            let world = TestWorld::default();
+            let mut rng = thread_rng();
+
+            let records: Vec<TestSortKey> = repeat_with(|| rng.gen()).take(COUNT).collect();
+            let mut expected: Vec<u128> =
+                records.clone().into_iter().map(|x| x.as_u128()).collect();
+            expected.sort_unstable();
+
+            // the first pass compares 599 elements against the pivot, which takes three records
+            // and thus three batches to validate
+            let result: Vec<_> = world
+                .malicious(records.into_iter(), |ctx, mut r| async move {
+                    #[allow(clippy::single_range_in_vec_init)]
+                    quicksort_ranges_by_key_in_batches(
+                        ctx,
+                        &mut r,
+                        false,
+                        |x| x,
+                        vec![0..COUNT],
+                        1,
+                    )
+                    .await
+                    .unwrap();
+                    r
+                })
+                .await
+                .reconstruct();
+
+            assert_eq!(
+                result
+                    .into_iter()
+                    .map(|x| x.as_u128())
+                    .collect::<Vec<u128>>(),
+                expected
+            );
+        });
+    }
+
+    #[test]
+    fn test_quicksort_insecure_malicious_batch_failure() {
+        run(|| async move {
+            // 64 records of comparisons, far more than the helpers can run ahead of validation
+            const RECORDS: usize = 64;
+            const COUNT: usize = RECORDS * SORT_CHUNK + 1;
+            const RECORD_SIZE: usize = 32;
+            let mut rng = thread_rng();
+            let records: Vec<TestSortKey> = repeat_with(|| rng.gen()).take(COUNT).collect();
+
+            // H1 corrupts the comparisons of the second batch, which H3 detects when
+            // it verifies the proof of H1. H1 also corrupts what it sends to H2 while that batch
+            // is verified, so H2 rejects it too. H1 can't go past that batch without the others.
+            let adversary = Adversary::new(Role::H1)
+                .add(
+                    Records::at("quicksort_pass1/dzkp_malicious_protocol/compare/")
+                        .sent_to(Role::H2)
+                        .record(RecordId::from(1)),
+                    BA256::from((1u128, 0u128)),
+                )
+                .add(
+                    Records::at("quicksort_pass1/dzkp_validate/validation_chunk1/verify_proof")
+                        .sent_to(Role::H2),
+                    Fp61BitPrime::ONE,
+                );
+            let config = TestWorldConfig::default().with_adversary(adversary);
+
+            // bytes sent by every helper on every comparison gate of the first pass
+            let sent = Arc::new(Mutex::new(HashMap::<(HelperIdentity, Gate), usize>::new()));
+            let attack = Arc::clone(&config.stream_interceptor);
+            let config = TestWorldConfig {
+                stream_interceptor: Arc::new({
+                    let sent = Arc::clone(&sent);
+                    move |ctx: &InspectContext, data: &mut Vec<u8>| {
+                        attack.peek(ctx, data);
+                        let gate = ctx.gate.as_ref();
+                        if gate.contains("quicksort_pass1/dzkp_malicious_protocol/compare/") {
+                            *sent
+                                .lock()
+                                .unwrap()
+                                .entry((ctx.identity, ctx.gate.clone()))
+                                .or_default() += data.len();
+                        }
+                    }
+                }),
+                ..config
+            };
+
+            let err = first_error(&config, records.into_iter(), &|ctx, mut r| {
+                async move {
+                    #[allow(clippy::single_range_in_vec_init)]
+                    quicksort_ranges_by_key_in_batches(ctx, &mut r, false, |x| x, vec![0..COUNT], 1)
+                        .await
+                }
+                .boxed()
+            })
+            .await;
+            assert!(matches!(err, Some(Error::DZKPValidationFailed)), "{err:?}");
+
+            // helpers that rejected the second batch stopped comparing shortly after it, never
+            // reaching the last records
+            let sent = sent.lock().unwrap();
+            for identity in [HelperIdentity::TWO, HelperIdentity::THREE] {
+                let sizes = sent
+                    .iter()
+                    .filter(|((id, _), _)| *id == identity)
+                    .map(|(_, &size)| size)
+                    .collect::<Vec<_>>();
+                assert!(
+                    sizes.iter().any(|&size| size >= 2 * RECORD_SIZE),
+                    "{identity:?}: {sizes:?}"
+                );
+                assert!(
+                    sizes.iter().all(|&size| size < RECORDS * RECORD_SIZE),
+                    "{identity:?}: {sizes:?}"
+                );
+            }
+        });
+    }
+
     #[test]
     fn test_quicksort_insecure_semi_honest_trivial() {
         run(|| async move {
@@ -0,0 +1,492 @@
+//! Shuffle with integrity checks.
+//!
+//! [`shuffle`] is only secure against semi-honest helpers: a helper that applies its permutation
+//! can just as well modify, drop or duplicate rows. [`malicious_shuffle`] detects this by tagging
+//! every row with a MAC before the shuffle and checking the tags after it.
+//!
+//! Each row is extended with a random secret nonce, that none of the helpers knows. The row and
+//! the nonce are split into elements of `Gf32Bit` `x_1, ..., x_m` and tagged with
+//! `t = k_1 * x_1 + ... + k_m * x_m`, where the keys `k_1, ..., k_m` are secret-shared and never
+//! revealed. One such tag is too easy to forge, so every row gets two of them, computed with
+//! independent keys and stored next to each other as one 64-bit tag. Because addition in
+//! `Gf32Bit` is XOR, the tags are linear in the Boolean shares of the row, so the row, the nonce
+//! and the tags can be shuffled together as one wider Boolean array.
+//!
+//! Tags are products of secret-shared values, so computing them takes multiplications. A helper
+//! that adds errors to these multiplications could make the check below fail depending on the
+//! rows, so the multiplications are MAC-checked and validated as soon as the tags are computed,
+//! before anything depends on them.
+//!
+//! After the shuffle, helpers compute the tags again from the shuffled rows and check that they
+//! match the shuffled tags. A helper that modified a row would need to know the keys to adjust
+//! its tag. A helper that dropped or duplicated a row could keep the tags intact, so helpers
+//! also reveal the shuffled nonces and check that all of them are distinct. Nonces are random and
+//! not known to anyone before the shuffle, so revealing them says nothing about the permutation.
+//!
+//! A modified row goes unnoticed only if it passes both tags, which happens with probability
+//! `2^-64` because their keys are independent. Honest runs are rejected if two of the
+//! `n` random 64-bit nonces collide, which happens with probability less than `n^2 / 2^65`.
+
+use std::{
+    iter::{repeat, zip},
+    ops::Add,
+};
+
+use futures::{stream, StreamExt, TryStreamExt};
+use rand::{distributions::Standard, prelude::Distribution};
+
+use crate::{
+    error::Error,
+    ff::{
+        boolean_array::{BooleanArray, BA32, BA64},
+        Gf32Bit, U128Conversions,
+    },
+    protocol::{
+        basics::{malicious_reveal, share_validation::validate_replicated_shares, SecureMul},
+        context::{
+            Context, MaliciousContext, UpgradableContext, UpgradedContext,
+            UpgradedMaliciousContext, Validator,
+        },
+        ipa_prf::{
+            boolean_ops::{expand_shared_array_in_place, extract_from_shared_array},
+            shuffle::{
+                base::shuffle,
+                step::{ComputeTagsStep, VerifiedShuffleStep as Step},
+            },
+        },
+        prss::SharedRandomness,
+        RecordId,
+    },
+    secret_sharing::{
+        replicated::{
+            malicious::AdditiveShare as MaliciousReplicated, semi_honest::AdditiveShare,
+            ReplicatedSecretSharing,
+        },
+        SharedValue,
+    },
+    seq_join::seq_join,
+};
+
+/// Number of bits in the nonce that is added to every row.
+const NONCE_BITS: usize = BA64::BITS as usize;
+
+/// Number of bits in a `Gf32Bit` element.
+const ELEMENT_BITS: usize = BA32::BITS as usize;
+
+/// Number of bits in the tags of every row.
+const TAG_BITS: usize = BA64::BITS as usize;
+
+/// Number of independent `Gf32Bit` tags of every row.
+const TAGS: usize = TAG_BITS / ELEMENT_BITS;
+
+/// Shuffles `shares` and checks that no helper modified, dropped or duplicated any of them.
+///
+/// Rows are shuffled as `R`, which must be wide enough to hold a row of `S` followed by a 64-bit
+/// nonce and 64 bits of tags.
+///
+/// # Errors
+/// If the shuffled rows fail verification, or propagates errors from transport.
+/// # Panics
+/// If `R` is too narrow to hold a row of `S` with its nonce and tag.
+pub async fn malicious_shuffle<S, R>(
+    ctx: MaliciousContext<'_>,
+    shares: Vec<AdditiveShare<S>>,
+) -> Result<Vec<AdditiveShare<S>>, Error>
+where
+    S: BooleanArray,
+    R: BooleanArray,
+    for<'a> &'a R: Add<R, Output = R>,
+    for<'a> &'a R: Add<&'a R, Output = R>,
+    Standard: Distribution<R>,
+{
+    assert!(
+        S::BITS as usize + NONCE_BITS + TAG_BITS <= R::BITS as usize,
+        "{} bits are not enough to hold {} bit rows with their nonces and tags",
+        R::BITS,
+        S::BITS
+    );
+
+    if shares.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let keys = generate_keys::<_, S>(&ctx.narrow(&Step::GenerateKeys));
+    let tagged = tag_rows::<S, R>(&ctx, &keys, shares).await?;
+    let shuffled = shuffle(ctx.narrow(&Step::Shuffle), tagged).await?;
+    verify_shuffled::<S, R>(&ctx, &keys, shuffled).await
+}
+
+/// Number of bits covered by the tag: the row and its nonce.
+fn tagged_bits<S: BooleanArray>() -> usize {
+    S::BITS as usize + NONCE_BITS
+}
+
+/// Number of `Gf32Bit` elements covered by the tags.
+fn tagged_elements<S: BooleanArray>() -> usize {
+    tagged_bits::<S>().div_ceil(ELEMENT_BITS)
+}
+
+/// Generates one secret-shared key per `Gf32Bit` element covered by the tags, for each of the
+/// tags. The keys of every tag are next to each other.
+fn generate_keys<C: Context, S: BooleanArray>(ctx: &C) -> Vec<AdditiveShare<Gf32Bit>> {
+    (0..TAGS * tagged_elements::<S>())
+        .map(|i| ctx.prss().generate(RecordId::from(i)))
+        .collect()
+}
+
+/// Extends every row with a random nonce and the tags of both.
+async fn tag_rows<S, R>(
+    ctx: &MaliciousContext<'_>,
+    keys: &[AdditiveShare<Gf32Bit>],
+    rows: Vec<AdditiveShare<S>>,
+) -> Result<Vec<AdditiveShare<R>>, Error>
+where
+    S: BooleanArray,
+    R: BooleanArray,
+{
+    let nonce_ctx = ctx.narrow(&Step::GenerateNonces);
+    let rows = rows
+        .into_iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let nonce: AdditiveShare<BA64> = nonce_ctx.prss().generate(RecordId::from(i));
+            let mut tagged = AdditiveShare::<R>::ZERO;
+            expand_shared_array_in_place(&mut tagged, &row, 0);
+            expand_shared_array_in_place(&mut tagged, &nonce, S::BITS as usize);
+            tagged
+        })
+        .collect::<Vec<_>>();
+
+    let tags = compute_tags::<S, R>(ctx.narrow(&Step::ComputeTags), keys, &rows).await?;
+
+    Ok(zip(rows, tags)
+        .map(|(mut row, tag)| {
+            expand_shared_array_in_place(&mut row, &tag, tagged_bits::<S>());
+            row
+        })
+        .collect())
+}
+
+/// Checks the tags and nonces of the shuffled rows and returns the rows without them.
+async fn verify_shuffled<S, R>(
+    ctx: &MaliciousContext<'_>,
+    keys: &[AdditiveShare<Gf32Bit>],
+    shuffled: Vec<AdditiveShare<R>>,
+) -> Result<Vec<AdditiveShare<S>>, Error>
+where
+    S: BooleanArray,
+    R: BooleanArray,
+{
+    // Make sure every share is held by both helpers that should hold it, so that all helpers
+    // check the same rows below.
+    let (left, right): (Vec<R>, Vec<R>) =
+        shuffled.iter().map(|row| (row.left(), row.right())).unzip();
+    validate_replicated_shares(ctx.narrow(&Step::CheckConsistency), &left, &right).await?;
+
+    // `d = t - (k_1 * x_1 + ... + k_m * x_m)` is a sharing of zero for every row with valid
+    // tags. Each helper sends the hash of `d_left + d_right` to the helper on its right, which
+    // compares it with the hash of the share it holds and the sender doesn't.
+    let expected = compute_tags::<S, R>(ctx.narrow(&Step::RecomputeTags), keys, &shuffled).await?;
+    let (d_right, d_sum): (Vec<BA64>, Vec<BA64>) = zip(&shuffled, expected)
+        .map(|(row, expected)| {
+            let d = extract_from_shared_array::<R, BA64>(row, tagged_bits::<S>()) - &expected;
+            (d.right(), d.left() + d.right())
+        })
+        .unzip();
+    validate_replicated_shares(ctx.narrow(&Step::VerifyTags), &d_right, &d_sum)
+        .await
+        .map_err(|e| match e {
+            Error::InconsistentShares => {
+                Error::ShuffleValidationFailed("tags of shuffled rows do not match".to_string())
+            }
+            e => e,
+        })?;
+
+    let reveal_ctx = ctx
+        .narrow(&Step::RevealNonces)
+        .set_total_records(shuffled.len());
+    let shared_nonces = shuffled
+        .iter()
+        .map(|row| extract_from_shared_array::<R, BA64>(row, S::BITS as usize))
+        .collect::<Vec<_>>();
+    let mut nonces = seq_join(
+        reveal_ctx.active_work(),
+        stream::iter(shared_nonces.iter().enumerate())
+            .map(|(i, nonce)| malicious_reveal(reveal_ctx.clone(), RecordId::from(i), None, nonce)),
+    )
+    .map_ok(|nonce| BA64::from_array(&nonce.expect("nonces are revealed to all helpers")).as_u128())
+    .try_collect::<Vec<_>>()
+    .await?;
+    nonces.sort_unstable();
+    if nonces.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(Error::ShuffleValidationFailed(
+            "shuffled rows contain duplicates".to_string(),
+        ));
+    }
+
+    Ok(shuffled
+        .iter()
+        .map(|row| extract_from_shared_array(row, 0))
+        .collect())
+}
+
+/// Computes the tags of the first [`tagged_bits`] bits of `rows`.
+///
+/// The multiplications by the keys are MAC-checked and validated before the tags are returned,
+/// so the tags are correct even if a helper added errors to them.
+async fn compute_tags<S, R>(
+    ctx: MaliciousContext<'_>,
+    keys: &[AdditiveShare<Gf32Bit>],
+    rows: &[AdditiveShare<R>],
+) -> Result<Vec<AdditiveShare<BA64>>, Error>
+where
+    S: BooleanArray,
+    R: BooleanArray,
+{
+    let elements = rows
+        .iter()
+        .flat_map(|row| {
+            zip(
+                to_gf32bit(&row.left(), tagged_bits::<S>()),
+                to_gf32bit(&row.right(), tagged_bits::<S>()),
+            )
+            .map(|(left, right)| AdditiveShare::new(left, right))
+        })
+        .collect::<Vec<_>>();
+
+    let validator = ctx.validator::<Gf32Bit>();
+    let m_ctx = validator.context();
+    let keys = upgrade(m_ctx.narrow(&ComputeTagsStep::UpgradeKeys), keys.to_vec()).await?;
+    let elements = upgrade(m_ctx.narrow(&ComputeTagsStep::UpgradeElements), elements).await?;
+
+    // every tag covers all the elements of the row
+    let mul_ctx = m_ctx
+        .narrow(&ComputeTagsStep::Multiply)
+        .set_total_records(TAGS * elements.len());
+    let products = seq_join(
+        mul_ctx.active_work(),
+        stream::iter(
+            elements
+                .chunks(tagged_elements::<S>())
+                .flat_map(|row| repeat(row).take(TAGS).flatten())
+                .zip(keys.iter().cycle())
+                .enumerate(),
+        )
+        .map(|(i, (x, key))| key.multiply(x, mul_ctx.clone(), RecordId::from(i))),
+    )
+    .try_collect::<Vec<_>>()
+    .await?;
+    let products = validator.validate(products).await?;
+
+    Ok(products
+        .chunks(keys.len())
+        .map(|products| {
+            tags_to_array(products.chunks(tagged_elements::<S>()).map(|products| {
+                products
+                    .iter()
+                    .fold(AdditiveShare::ZERO, |tag, product| &tag + product)
+            }))
+        })
+        .collect())
+}
+
+/// Upgrades `values` to MAC-checked shares, so they can be multiplied in `ctx`.
+async fn upgrade(
+    ctx: UpgradedMaliciousContext<'_, Gf32Bit>,
+    values: Vec<AdditiveShare<Gf32Bit>>,
+) -> Result<Vec<MaliciousReplicated<Gf32Bit>>, Error> {
+    let ctx = ctx.set_total_records(values.len());
+    seq_join(
+        ctx.active_work(),
+        stream::iter(values.into_iter().enumerate())
+            .map(|(i, value)| ctx.upgrade_one(RecordId::from(i), value)),
+    )
+    .try_collect()
+    .await
+}
+
+/// Splits the first `bits` bits of `value` into elements of `Gf32Bit`. The last element is padded
+/// with zeros.
+fn to_gf32bit<R: BooleanArray>(value: &R, bits: usize) -> Vec<Gf32Bit> {
+    value
+        .iter()
+        .take(bits)
+        .collect::<Vec<_>>()
+        .chunks(ELEMENT_BITS)
+        .map(|chunk| {
+            Gf32Bit::truncate_from(
+                chunk
+                    .iter()
+                    .rev()
+                    .fold(0_u128, |acc, &bit| (acc << 1) | u128::from(bool::from(bit))),
+            )
+        })
+        .collect()
+}
+
+/// Stores the tags of a row next to each other, the first tag in the lowest bits.
+fn tags_to_array<I>(tags: I) -> AdditiveShare<BA64>
+where
+    I: IntoIterator<Item = AdditiveShare<Gf32Bit>>,
+{
+    let (left, right) =
+        tags.into_iter()
+            .enumerate()
+            .fold((0_u128, 0_u128), |(left, right), (i, tag)| {
+                let shift = i * ELEMENT_BITS;
+                (
+                    left | (tag.left().as_u128() << shift),
+                    right | (tag.right().as_u128() << shift),
+                )
+            });
+    AdditiveShare::new(BA64::truncate_from(left), BA64::truncate_from(right))
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::iter::repeat_with;
+
+    use super::{generate_keys, malicious_shuffle, tag_rows, verify_shuffled};
+    use crate::{
+        error::Error,
+        ff::{
+            boolean_array::{BA256, BA32, BA64},
+            Field, Gf32Bit, U128Conversions,
+        },
+        helpers::Role,
+        protocol::{
+            context::Context, ipa_prf::shuffle::step::VerifiedShuffleStep as Step, RecordId,
+        },
+        rand::{thread_rng, Rng},
+        test_executor::run,
+        test_fixture::{Adversary, Reconstruct, Records, Runner, TestWorld, TestWorldConfig},
+    };
+
+    const COUNT: usize = 20;
+
+    fn records() -> Vec<BA32> {
+        let mut rng = thread_rng();
+        repeat_with(|| rng.gen()).take(COUNT).collect()
+    }
+
+    #[test]
+    fn shuffles() {
+        run(|| async {
+            let world = TestWorld::default();
+            let mut records = records();
+
+            let mut result = world
+                .malicious(records.clone().into_iter(), |ctx, rows| async move {
+                    malicious_shuffle::<BA32, BA256>(ctx, rows).await.unwrap()
+                })
+                .await
+                .reconstruct();
+
+            assert_ne!(result, records);
+            records.sort_by_key(U128Conversions::as_u128);
+            result.sort_by_key(U128Conversions::as_u128);
+            assert_eq!(result, records);
+        });
+    }
+
+    #[test]
+    fn empty() {
+        run(|| async {
+            let world = TestWorld::default();
+            let result = world
+                .malicious(Vec::<BA64>::new().into_iter(), |ctx, rows| async move {
+                    malicious_shuffle::<BA64, BA256>(ctx, rows).await.unwrap()
+                })
+                .await;
+
+            assert!(result.iter().all(Vec::is_empty));
+        });
+    }
+
+    /// Shuffles rows of `BA32` as `BA256`, while H1 adds `delta` to the first row it sends.
+    async fn shuffle_modified(delta: BA256) {
+        // H1 sends its permuted rows to H2 at this step
+        let adversary =
+            Adversary::new(Role::H1).add(Records::at("transfer_x2").record(RecordId::FIRST), delta);
+        let world = TestWorld::new_with(TestWorldConfig::default().with_adversary(adversary));
+
+        let results = world
+            .malicious(records().into_iter(), |ctx, rows| async move {
+                malicious_shuffle::<BA32, BA256>(ctx, rows)
+                    .await
+                    .map(|_| ())
+            })
+            .await;
+
+        for result in results {
+            assert!(matches!(result, Err(Error::ShuffleValidationFailed(_))));
+        }
+    }
+
+    #[test]
+    fn detects_modified_row() {
+        run(|| shuffle_modified(BA256::from((1_u128, 0_u128))));
+    }
+
+    #[test]
+    fn detects_modified_tag() {
+        // rows are followed by 64 bits of nonce and two 32-bit tags, so the second tag starts at
+        // bit 128
+        run(|| shuffle_modified(BA256::from((0_u128, 1_u128))));
+    }
+
+    /// Shuffles rows of `BA32` as `BA256`, while H1 adds an error to the first product it sends
+    /// when computing tags at `step`.
+    async fn shuffle_with_tag_error(step: &str) {
+        let adversary = Adversary::new(Role::H1).add(
+            Records::at(&format!("/{step}/malicious_protocol/multiply")).record(RecordId::FIRST),
+            Gf32Bit::ONE,
+        );
+        let world = TestWorld::new_with(TestWorldConfig::default().with_adversary(adversary));
+
+        let results = world
+            .malicious(records().into_iter(), |ctx, rows| async move {
+                malicious_shuffle::<BA32, BA256>(ctx, rows)
+                    .await
+                    .map(|_| ())
+            })
+            .await;
+
+        for result in results {
+            assert!(matches!(result, Err(Error::MaliciousSecurityCheckFailed)));
+        }
+    }
+
+    #[test]
+    fn detects_error_in_tags() {
+        run(|| shuffle_with_tag_error("compute_tags"));
+    }
+
+    #[test]
+    fn detects_error_in_recomputed_tags() {
+        run(|| shuffle_with_tag_error("recompute_tags"));
+    }
+
+    #[test]
+    fn detects_duplicated_row() {
+        run(|| async {
+            let world = TestWorld::default();
+
+            let results = world
+                .malicious(records().into_iter(), |ctx, rows| async move {
+                    let keys = generate_keys::<_, BA32>(&ctx.narrow(&Step::GenerateKeys));
+                    let mut tagged = tag_rows::<BA32, BA256>(&ctx, &keys, rows).await.unwrap();
+                    // replace the last row with a copy of the first one, tag included
+                    tagged[COUNT - 1] = tagged[0].clone();
+                    verify_shuffled::<BA32, BA256>(&ctx, &keys, tagged)
+                        .await
+                        .map(|_| ())
+                })
+                .await;
+
+            for result in results {
+                assert!(matches!(result, Err(Error::ShuffleValidationFailed(_))));
+            }
+        });
+    }
+}
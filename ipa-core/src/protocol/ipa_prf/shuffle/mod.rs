@@ -1,13 +1,16 @@
-use self::base::shuffle;
+use self::{base::shuffle, malicious::malicious_shuffle};
 use super::boolean_ops::{expand_shared_array_in_place, extract_from_shared_array};
 use crate::{
     error::Error,
     ff::{
         boolean::Boolean,
-        boolean_array::{BooleanArray, BA112, BA64},
+        boolean_array::{BooleanArray, BA112, BA256, BA64},
         ArrayAccess,
     },
-    protocol::{context::Context, ipa_prf::OPRFIPAInputRow},
+    protocol::{
+        context::{Context, MaliciousContext},
+        ipa_prf::OPRFIPAInputRow,
+    },
     secret_sharing::{
         replicated::{semi_honest::AdditiveShare, ReplicatedSecretSharing},
         SharedValue,
@@ -15,6 +18,7 @@ use crate::{
 };
 
 pub mod base;
+pub mod malicious;
 #[cfg(descriptive_gate)]
 mod sharded;
 pub(crate) mod step;
@@ -43,6 +47,35 @@ where
         .collect::<Vec<_>>())
 }
 
+/// Same as [`shuffle_inputs`], but checks that no helper modified, dropped or duplicated any of
+/// the rows while shuffling them. See [`malicious_shuffle`].
+///
+/// # Errors
+/// If the shuffled rows fail verification, or propagates errors from transport.
+#[allow(dead_code)] // until OPRF IPA runs with malicious contexts
+#[tracing::instrument(name = "malicious_shuffle_inputs", skip_all)]
+pub async fn malicious_shuffle_inputs<BK, TV, TS>(
+    ctx: MaliciousContext<'_>,
+    input: Vec<OPRFIPAInputRow<BK, TV, TS>>,
+) -> Result<Vec<OPRFIPAInputRow<BK, TV, TS>>, Error>
+where
+    BK: BooleanArray,
+    TV: BooleanArray,
+    TS: BooleanArray,
+{
+    let shuffle_input: Vec<AdditiveShare<BA112>> = input
+        .into_iter()
+        .map(|item| oprfreport_to_shuffle_input::<BA112, BK, TV, TS>(&item))
+        .collect::<Vec<_>>();
+
+    let shuffled = malicious_shuffle::<BA112, BA256>(ctx, shuffle_input).await?;
+
+    Ok(shuffled
+        .into_iter()
+        .map(|item| shuffled_to_oprfreport(&item))
+        .collect::<Vec<_>>())
+}
+
 // This function converts OprfReport to an AdditiveShare needed for shuffle protocol
 pub fn oprfreport_to_shuffle_input<YS, BK, TV, TS>(
     input: &OPRFIPAInputRow<BK, TV, TS>,
@@ -117,7 +150,7 @@ pub mod tests {
 
     use crate::{
         ff::boolean_array::{BA20, BA3, BA8},
-        protocol::ipa_prf::shuffle::shuffle_inputs,
+        protocol::ipa_prf::shuffle::{malicious_shuffle_inputs, shuffle_inputs},
         test_executor::run,
         test_fixture::{ipa::TestRawDataRecord, Reconstruct, Runner, TestWorld},
     };
@@ -157,4 +190,40 @@ pub mod tests {
             assert_eq!(result, records);
         });
     }
+
+    #[test]
+    fn test_malicious_shuffle_inputs() {
+        const BATCHSIZE: usize = 50;
+        run(|| async {
+            let world = TestWorld::default();
+
+            let mut rng = rand::thread_rng();
+            let mut records = Vec::new();
+
+            for _ in 0..BATCHSIZE {
+                records.push({
+                    TestRawDataRecord {
+                        timestamp: rng.gen_range(0u64..1 << 20),
+                        user_id: rng.gen::<u64>(),
+                        is_trigger_report: rng.gen::<bool>(),
+                        breakdown_key: rng.gen_range(0u32..1 << 8),
+                        trigger_value: rng.gen_range(0u32..1 << 3),
+                    }
+                });
+            }
+
+            let mut result: Vec<TestRawDataRecord> = world
+                .malicious(records.clone().into_iter(), |ctx, input_rows| async move {
+                    malicious_shuffle_inputs::<BA8, BA3, BA20>(ctx, input_rows)
+                        .await
+                        .unwrap()
+                })
+                .await
+                .reconstruct();
+            assert_ne!(result, records);
+            records.sort();
+            result.sort();
+            assert_eq!(result, records);
+        });
+    }
 }
@@ -10,3 +10,25 @@ pub(crate) enum OPRFShuffleStep {
     TransferX2,
     TransferY1,
 }
+
+#[derive(CompactStep)]
+pub(crate) enum VerifiedShuffleStep {
+    GenerateKeys,
+    GenerateNonces,
+    #[step(child = ComputeTagsStep)]
+    ComputeTags,
+    #[step(child = OPRFShuffleStep)]
+    Shuffle,
+    CheckConsistency,
+    #[step(child = ComputeTagsStep)]
+    RecomputeTags,
+    VerifyTags,
+    RevealNonces,
+}
+
+#[derive(CompactStep)]
+pub(crate) enum ComputeTagsStep {
+    UpgradeKeys,
+    UpgradeElements,
+    Multiply,
+}
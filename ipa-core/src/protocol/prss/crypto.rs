@@ -269,10 +269,15 @@ impl KeyExchange {
 
     #[must_use]
     pub fn key_exchange(self, pk: &PublicKey) -> GeneratorFactory {
+        GeneratorFactory::new(&self.shared_secret(pk))
+    }
+
+    /// Returns the secret shared with the owner of `pk`. Generators created from it are the same
+    /// as the ones created by [`Self::key_exchange`].
+    #[must_use]
+    pub fn shared_secret(self, pk: &PublicKey) -> [u8; 32] {
         debug_assert_ne!(pk, &self.public_key(), "self key exchange detected");
-        let secret = self.sk.diffie_hellman(pk);
-        let kdf = Hkdf::<Sha256>::new(None, secret.as_bytes());
-        GeneratorFactory { kdf }
+        self.sk.diffie_hellman(pk).to_bytes()
     }
 }
 
@@ -283,6 +288,14 @@ pub struct GeneratorFactory {
 }
 
 impl GeneratorFactory {
+    /// Creates a factory from a secret established by [`KeyExchange::shared_secret`].
+    #[must_use]
+    pub fn new(secret: &[u8; 32]) -> Self {
+        Self {
+            kdf: Hkdf::<Sha256>::new(None, secret),
+        }
+    }
+
     /// Create a new generator using the provided context string.
     #[allow(clippy::missing_panics_doc)] // Panic should be impossible.
     #[must_use]
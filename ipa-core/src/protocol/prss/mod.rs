@@ -7,6 +7,7 @@ pub use crypto::{
 };
 use generic_array::{sequence::GenericSequence, ArrayLength, GenericArray};
 pub(super) use internal::PrssIndex128;
+use serde::{Deserialize, Serialize};
 use x25519_dalek::PublicKey;
 
 use crate::{
@@ -352,6 +353,20 @@ impl Endpoint {
     }
 }
 
+impl Endpoint {
+    /// Recreates the participant that was set up with the given seeds.
+    #[must_use]
+    pub fn from_seeds(seeds: &PrssSeeds) -> Self {
+        Endpoint {
+            inner: Mutex::new(EndpointInner {
+                left: GeneratorFactory::new(&seeds.left),
+                right: GeneratorFactory::new(&seeds.right),
+                items: HashMap::new(),
+            }),
+        }
+    }
+}
+
 impl Debug for Endpoint {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str("Participant")
@@ -436,6 +451,38 @@ impl EndpointSetup {
             }),
         }
     }
+
+    /// Same as [`Self::setup`], but also returns the seeds that the participant can be recreated
+    /// from with [`Endpoint::from_seeds`].
+    #[must_use]
+    pub fn setup_with_seeds(
+        self,
+        left_pk: &PublicKey,
+        right_pk: &PublicKey,
+    ) -> (Endpoint, PrssSeeds) {
+        let seeds = PrssSeeds {
+            left: self.left.shared_secret(left_pk),
+            right: self.right.shared_secret(right_pk),
+        };
+
+        (Endpoint::from_seeds(&seeds), seeds)
+    }
+}
+
+/// Secrets shared with the left and right participants. They determine all the randomness
+/// generated by an [`Endpoint`], so they must be kept as secret as the endpoint itself.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrssSeeds {
+    #[serde(with = "hex")]
+    pub left: [u8; 32],
+    #[serde(with = "hex")]
+    pub right: [u8; 32],
+}
+
+impl Debug for PrssSeeds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PrssSeeds")
+    }
 }
 
 #[cfg(all(test, unit_test))]
@@ -784,6 +831,23 @@ pub mod test {
         assert!(PrssIndex128::try_from(good_index).is_ok());
     }
 
+    #[test]
+    fn from_seeds() {
+        let setup1 = Endpoint::prepare(&mut thread_rng());
+        let setup2 = Endpoint::prepare(&mut thread_rng());
+        let (pk2_l, pk2_r) = setup2.public_keys();
+        let (endpoint, seeds) = setup1.setup_with_seeds(&pk2_r, &pk2_l);
+        let restored = Endpoint::from_seeds(&seeds);
+
+        let gate = Gate::default();
+        let expected: (u128, u128) = endpoint.indexed(&gate).generate(0_u32);
+        let actual: (u128, u128) = restored.indexed(&gate).generate(0_u32);
+        assert_eq!(expected, actual);
+
+        let json = serde_json::to_string(&seeds).unwrap();
+        assert_eq!(seeds, serde_json::from_str(&json).unwrap());
+    }
+
     fn assert_8_byte_index_is_valid(index: u32, offset: usize) {
         let index = PrssIndex(index);
         let index128 = u128::from(index.offset(offset));
@@ -3,6 +3,7 @@ use std::{
     fmt::Debug,
     future::{ready, Future},
     pin::Pin,
+    time::Instant,
 };
 
 use ::tokio::{
@@ -17,6 +18,7 @@ use rand::rngs::StdRng;
 use rand_core::SeedableRng;
 #[cfg(all(feature = "shuttle", test))]
 use shuttle::future as tokio;
+use tracing::Instrument;
 use typenum::Unsigned;
 
 #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
@@ -28,15 +30,20 @@ use crate::{
     helpers::{
         negotiate_prss,
         query::{QueryConfig, QueryType},
-        BodyStream, Gateway,
+        BodyStream, Gateway, HelperIdentity,
     },
-    hpke::PrivateKeyRegistry,
-    protocol::{context::SemiHonestContext, prss::Endpoint as PrssEndpoint, Gate},
+    hpke::{seal_result, PrivateKeyRegistry, ResultBinding, ResultEncryptionKey, ResultSigningKey},
+    protocol::{context::SemiHonestContext, prss::Endpoint as PrssEndpoint, Gate, QueryId},
     query::{
         runner::{OprfIpaQuery, QueryResult},
         state::RunningQuery,
+        QueryStage,
     },
     sync::Arc,
+    telemetry::{
+        labels::{OUTCOME, QUERY_ID, QUERY_TYPE},
+        metrics::{QUERIES_FINISHED, QUERIES_RUNNING, QUERIES_STARTED, QUERY_DURATION},
+    },
 };
 
 pub trait Result: Send + Debug {
@@ -60,55 +67,171 @@ where
     }
 }
 
+/// Query result encrypted to the report collector key and signed by this helper.
+/// See [`seal_result`] for details.
+#[derive(Debug)]
+struct SealedResult(Vec<u8>);
+
+impl Result for SealedResult {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+/// Seals query results before they are handed over to the report collector.
+pub struct ResultSealer {
+    pub identity: HelperIdentity,
+    pub helper_origin: String,
+    pub recipient: ResultEncryptionKey,
+    pub signing_key: Arc<ResultSigningKey>,
+}
+
+impl ResultSealer {
+    fn seal(&self, result: QueryResult, query_id: QueryId, config: &QueryConfig) -> QueryResult {
+        let binding = ResultBinding {
+            helper: self.identity,
+            helper_origin: &self.helper_origin,
+            query_id,
+            config,
+        };
+        let sealed = seal_result(
+            &result?.to_bytes(),
+            &binding,
+            &self.recipient,
+            &self.signing_key,
+            &mut StdRng::from_entropy(),
+        )
+        .map_err(crate::error::Error::ResultSealing)?;
+
+        Ok(Box::new(SealedResult(sealed)))
+    }
+}
+
+/// Reports query lifecycle metrics. Query task may be aborted at any await point, so metrics
+/// for finished queries are reported on drop, with `aborted` outcome unless [`Self::finish`]
+/// has been called.
+struct QueryMetrics {
+    query_id: &'static str,
+    query_type: String,
+    started: Instant,
+    outcome: &'static str,
+}
+
+impl QueryMetrics {
+    fn start(query_id: QueryId, query_type: &QueryType) -> Self {
+        let this = Self {
+            query_id: query_id.into(),
+            query_type: query_type.as_ref().to_string(),
+            started: Instant::now(),
+            outcome: "aborted",
+        };
+        metrics::increment_counter!(QUERIES_STARTED, QUERY_TYPE => this.query_type.clone());
+        metrics::increment_gauge!(QUERIES_RUNNING, 1.0,
+            QUERY_ID => this.query_id,
+            QUERY_TYPE => this.query_type.clone(),
+        );
+
+        this
+    }
+
+    fn finish(&mut self, result: &QueryResult) {
+        self.outcome = if result.is_ok() {
+            "completed"
+        } else {
+            "failed"
+        };
+    }
+}
+
+impl Drop for QueryMetrics {
+    fn drop(&mut self) {
+        metrics::decrement_gauge!(QUERIES_RUNNING, 1.0,
+            QUERY_ID => self.query_id,
+            QUERY_TYPE => self.query_type.clone(),
+        );
+        metrics::increment_counter!(QUERIES_FINISHED,
+            QUERY_TYPE => self.query_type.clone(),
+            OUTCOME => self.outcome,
+        );
+        metrics::histogram!(QUERY_DURATION, self.started.elapsed().as_secs_f64(),
+            QUERY_ID => self.query_id,
+            QUERY_TYPE => self.query_type.clone(),
+            OUTCOME => self.outcome,
+        );
+    }
+}
+
 /// Needless pass by value because IPA v3 does not make use of key registry yet.
 #[allow(clippy::too_many_lines, clippy::needless_pass_by_value)]
 pub fn execute<R: PrivateKeyRegistry>(
     config: QueryConfig,
     key_registry: Arc<R>,
+    helper_origin: String,
+    result_sealer: Option<ResultSealer>,
     gateway: Gateway,
     input: BodyStream,
 ) -> RunningQuery {
     match (config.query_type, config.field_type) {
         #[cfg(any(test, feature = "weak-field"))]
-        (QueryType::TestMultiply, FieldType::Fp31) => {
-            do_query(config, gateway, input, |prss, gateway, _config, input| {
+        (QueryType::TestMultiply, FieldType::Fp31) => do_query(
+            config,
+            gateway,
+            input,
+            result_sealer,
+            |prss, gateway, _config, input| {
                 Box::pin(execute_test_multiply::<crate::ff::Fp31>(
                     prss, gateway, input,
                 ))
-            })
-        }
+            },
+        ),
         #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
-        (QueryType::TestMultiply, FieldType::Fp32BitPrime) => {
-            do_query(config, gateway, input, |prss, gateway, _config, input| {
+        (QueryType::TestMultiply, FieldType::Fp32BitPrime) => do_query(
+            config,
+            gateway,
+            input,
+            result_sealer,
+            |prss, gateway, _config, input| {
                 Box::pin(execute_test_multiply::<Fp32BitPrime>(prss, gateway, input))
-            })
-        }
+            },
+        ),
         #[cfg(any(test, feature = "weak-field"))]
-        (QueryType::TestAddInPrimeField, FieldType::Fp31) => {
-            do_query(config, gateway, input, |prss, gateway, _config, input| {
+        (QueryType::TestAddInPrimeField, FieldType::Fp31) => do_query(
+            config,
+            gateway,
+            input,
+            result_sealer,
+            |prss, gateway, _config, input| {
                 Box::pin(test_add_in_prime_field::<crate::ff::Fp31>(
                     prss, gateway, input,
                 ))
-            })
-        }
+            },
+        ),
         #[cfg(any(test, feature = "cli", feature = "test-fixture"))]
-        (QueryType::TestAddInPrimeField, FieldType::Fp32BitPrime) => {
-            do_query(config, gateway, input, |prss, gateway, _config, input| {
+        (QueryType::TestAddInPrimeField, FieldType::Fp32BitPrime) => do_query(
+            config,
+            gateway,
+            input,
+            result_sealer,
+            |prss, gateway, _config, input| {
                 Box::pin(test_add_in_prime_field::<Fp32BitPrime>(
                     prss, gateway, input,
                 ))
-            })
-        }
+            },
+        ),
         // TODO(953): This is really using BA32, not Fp32bitPrime. The `FieldType` mechanism needs
         // to be reworked.
         (QueryType::OprfIpa(ipa_config), FieldType::Fp32BitPrime) => do_query(
             config,
             gateway,
             input,
+            result_sealer,
             move |prss, gateway, config, input| {
+                gateway
+                    .progress()
+                    .enter(QueryStage::Decrypting, config.size.into());
                 let ctx = SemiHonestContext::new(prss, gateway);
                 Box::pin(
-                    OprfIpaQuery::<BA32, R>::new(ipa_config, key_registry)
+                    OprfIpaQuery::<BA32, R>::new(ipa_config, key_registry, helper_origin)
                         .execute(ctx, config.size, input)
                         .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
                 )
@@ -121,12 +244,17 @@ pub fn execute<R: PrivateKeyRegistry>(
             config,
             gateway,
             input,
+            result_sealer,
             move |prss, gateway, config, input| {
+                gateway
+                    .progress()
+                    .enter(QueryStage::Decrypting, config.size.into());
                 let ctx = SemiHonestContext::new(prss, gateway);
                 Box::pin(
                     OprfIpaQuery::<crate::ff::boolean_array::BA16, R>::new(
                         ipa_config,
                         key_registry,
+                        helper_origin,
                     )
                     .execute(ctx, config.size, input)
                     .then(|res| ready(res.map(|out| Box::new(out) as Box<dyn Result>))),
@@ -140,6 +268,7 @@ pub fn do_query<B, F>(
     config: QueryConfig,
     gateway: B,
     input_stream: BodyStream,
+    result_sealer: Option<ResultSealer>,
     query_impl: F,
 ) -> RunningQuery
 where
@@ -154,36 +283,51 @@ where
     B: Borrow<Gateway> + Send + 'static,
 {
     let (tx, rx) = oneshot::channel();
-
-    let join_handle = tokio::spawn(async move {
-        let gateway = gateway.borrow();
-        // TODO: make it a generic argument for this function
-        let mut rng = StdRng::from_entropy();
-        // Negotiate PRSS using the initial gate for the protocol (no narrowing).
-        let prss = negotiate_prss(gateway, &prss_gate(), &mut rng)
-            .await
-            .unwrap();
-
-        // see private-attribution/ipa#1120
-        let v = if !cfg!(feature = "shuttle")
-            && Handle::current().runtime_flavor() == RuntimeFlavor::MultiThread
-        {
-            block_in_place(|| {
-                // block_on runs on the current thread, so if it is also responsible for IO
-                // it's been handed off already by block_in_place.
-                Handle::current()
-                    .block_on(async { query_impl(&prss, gateway, &config, input_stream).await })
-            })
-        } else {
-            query_impl(&prss, gateway, &config, input_stream).await
-        };
-
-        tx.send(v).unwrap();
-    });
+    let progress = gateway.borrow().progress();
+    #[cfg(feature = "stall-detection")]
+    let stall_observer = gateway.borrow().stall_observer();
+    let mut lifecycle = QueryMetrics::start(gateway.borrow().query_id(), &config.query_type);
+
+    let join_handle = tokio::spawn(
+        async move {
+            let gateway = gateway.borrow();
+            // TODO: make it a generic argument for this function
+            let mut rng = StdRng::from_entropy();
+            // Negotiate PRSS using the initial gate for the protocol (no narrowing).
+            let prss = negotiate_prss(gateway, &prss_gate(), &mut rng)
+                .await
+                .unwrap();
+
+            // see private-attribution/ipa#1120
+            let v = if !cfg!(feature = "shuttle")
+                && Handle::current().runtime_flavor() == RuntimeFlavor::MultiThread
+            {
+                block_in_place(|| {
+                    // block_on runs on the current thread, so if it is also responsible for IO
+                    // it's been handed off already by block_in_place.
+                    Handle::current()
+                        .block_on(async { query_impl(&prss, gateway, &config, input_stream).await })
+                })
+            } else {
+                query_impl(&prss, gateway, &config, input_stream).await
+            };
+            let v = match result_sealer {
+                Some(sealer) => sealer.seal(v, gateway.query_id(), &config),
+                None => v,
+            };
+            lifecycle.finish(&v);
+
+            tx.send(v).unwrap();
+        }
+        .instrument(tracing::Span::current()),
+    );
 
     RunningQuery {
         result: rx,
         join_handle,
+        progress,
+        #[cfg(feature = "stall-detection")]
+        stall_observer,
     }
 }
 
@@ -210,7 +354,7 @@ mod tests {
         ff::{FieldType, Fp31, U128Conversions},
         helpers::{
             query::{QueryConfig, QueryType},
-            BodyStream, Gateway, Role,
+            BodyStream, Gateway, Role, StreamCompression,
         },
         query::{executor::do_query, state::RunningQuery, ProtocolResult},
         secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
@@ -331,9 +475,13 @@ mod tests {
                 size: 1.try_into().unwrap(),
                 field_type: FieldType::Fp31,
                 query_type: QueryType::TestMultiply,
+                result_key: None,
+                compression: StreamCompression::None,
+                proof_size: None,
             },
             gateway,
             BodyStream::empty(),
+            None,
             move |_, _, _, _| {
                 Box::pin(async move {
                     f().await;
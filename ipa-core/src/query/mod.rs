@@ -1,13 +1,18 @@
 mod completion;
 mod executor;
 mod processor;
+mod progress;
 mod runner;
 mod state;
 
 use completion::Handle as CompletionHandle;
+#[cfg(all(any(test, feature = "test-fixture"), feature = "in-memory-infra"))]
+pub(crate) use executor::execute;
 pub use executor::Result as ProtocolResult;
 pub use processor::{
-    NewQueryError, PrepareQueryError, Processor as QueryProcessor, QueryCompletionError,
-    QueryInputError, QueryStatusError,
+    NewQueryError, NoResultSigningKey, PrepareQueryError, Processor as QueryProcessor,
+    QueryCompletionError, QueryDigestsError, QueryInputError, QueryKillError,
+    QueryStallReportError, QueryStatusError,
 };
+pub use progress::{ProgressTracker, QueryProgress, QueryStage, StageProgress};
 pub use state::QueryStatus;
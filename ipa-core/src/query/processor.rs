@@ -1,25 +1,30 @@
 use std::{
-    collections::hash_map::Entry,
+    collections::{hash_map::Entry, HashMap},
     fmt::{Debug, Formatter},
+    path::PathBuf,
+    time::SystemTime,
 };
 
 use futures::{future::try_join, stream};
+use tracing::info_span;
 
 use crate::{
     error::Error as ProtocolError,
     helpers::{
         query::{PrepareQuery, QueryConfig, QueryInput},
-        Gateway, GatewayConfig, MpcTransportError, MpcTransportImpl, Role, RoleAssignment,
-        ShardTransportImpl, Transport,
+        ChannelDigests, DigestReport, Gateway, GatewayConfig, MpcTransportError, MpcTransportImpl,
+        Role, RoleAssignment, ShardTransportImpl, StallReport, TranscriptRecorder, Transport,
     },
-    hpke::{KeyRegistry, PrivateKeyOnly},
+    hpke::{KeyRegistry, PrivateKeyOnly, ResultSigningKey, RotatingKeyRegistry},
     protocol::QueryId,
     query::{
-        executor,
+        executor::{self, ResultSealer},
         state::{QueryState, QueryStatus, RemoveQuery, RunningQueries, StateError},
-        CompletionHandle, ProtocolResult,
+        CompletionHandle, ProtocolResult, QueryProgress,
     },
-    sync::Arc,
+    report::DEFAULT_HELPER_ORIGIN,
+    sync::{Arc, Mutex},
+    telemetry::trace::{TraceContext, SPAN_TARGET},
 };
 
 /// `Processor` accepts and tracks requests to initiate new queries on this helper party
@@ -41,24 +46,50 @@ use crate::{
 /// [`AdditiveShare`]: crate::secret_sharing::replicated::semi_honest::AdditiveShare
 pub struct Processor {
     queries: RunningQueries,
-    key_registry: Arc<KeyRegistry<PrivateKeyOnly>>,
+    key_registry: Arc<RotatingKeyRegistry>,
+    helper_origin: String,
+    result_signing_key: Option<Arc<ResultSigningKey>>,
+    /// Trace context of the request that created or prepared the query. Query execution
+    /// is reported as a part of this trace. Entries are removed when the query starts running
+    /// or is killed before that.
+    traces: Mutex<HashMap<QueryId, TraceContext>>,
+    /// Directory where transcripts of the messages exchanged with other helpers are written.
+    transcript_dir: Option<PathBuf>,
+    /// Whether queries keep digests of the records exchanged with other helpers.
+    channel_digests: bool,
+    /// Channel digests of the most recent query, kept after it finishes so helpers can compare
+    /// them.
+    digests: Mutex<Option<(QueryId, ChannelDigests)>>,
 }
 
 impl Default for Processor {
     fn default() -> Self {
         Self {
             queries: RunningQueries::default(),
-            key_registry: Arc::new(KeyRegistry::<PrivateKeyOnly>::empty()),
+            key_registry: Arc::new(RotatingKeyRegistry::empty()),
+            helper_origin: DEFAULT_HELPER_ORIGIN.to_string(),
+            result_signing_key: None,
+            traces: Mutex::default(),
+            transcript_dir: None,
+            channel_digests: false,
+            digests: Mutex::default(),
         }
     }
 }
 
+/// Query asked for sealed results, which this helper can't produce.
+#[derive(thiserror::Error, Debug)]
+#[error("Query requested encrypted results, but this helper does not have a result signing key")]
+pub struct NoResultSigningKey;
+
 #[derive(thiserror::Error, Debug)]
 pub enum NewQueryError {
     #[error(transparent)]
     State(#[from] StateError),
     #[error(transparent)]
     MpcTransport(#[from] MpcTransportError),
+    #[error(transparent)]
+    NoResultSigningKey(#[from] NoResultSigningKey),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -68,6 +99,8 @@ pub enum PrepareQueryError {
     #[error("Query is already running")]
     AlreadyRunning,
     #[error(transparent)]
+    NoResultSigningKey(#[from] NoResultSigningKey),
+    #[error(transparent)]
     StateError {
         #[from]
         source: StateError,
@@ -79,6 +112,8 @@ pub enum QueryInputError {
     #[error("The query with id {0:?} does not exist")]
     NoSuchQuery(QueryId),
     #[error(transparent)]
+    NoResultSigningKey(#[from] NoResultSigningKey),
+    #[error(transparent)]
     StateError {
         #[from]
         source: StateError,
@@ -91,6 +126,29 @@ pub enum QueryStatusError {
     NoSuchQuery(QueryId),
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum QueryStallReportError {
+    #[error("The query with id {0:?} does not exist")]
+    NoSuchQuery(QueryId),
+    #[error("The query with id {query_id:?} is not running, its status is {status:?}")]
+    NotRunning {
+        query_id: QueryId,
+        status: QueryStatus,
+    },
+    #[error(
+        "Stall report is not available: stall detection is disabled or the query has finished"
+    )]
+    Unavailable,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum QueryDigestsError {
+    #[error("The query with id {0:?} does not exist or is not the most recent query")]
+    NoSuchQuery(QueryId),
+    #[error("This helper does not keep channel digests")]
+    Disabled,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum QueryCompletionError {
     #[error("The query with id {0:?} does not exist")]
@@ -104,6 +162,17 @@ pub enum QueryCompletionError {
     ExecutionError(#[from] ProtocolError),
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum QueryKillError {
+    #[error("The query with id {0:?} does not exist")]
+    NoSuchQuery(QueryId),
+    #[error(transparent)]
+    StateError {
+        #[from]
+        source: StateError,
+    },
+}
+
 impl Debug for Processor {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "QueryProcessor[{:?}]", self.queries)
@@ -113,9 +182,56 @@ impl Debug for Processor {
 impl Processor {
     #[must_use]
     pub fn new(key_registry: KeyRegistry<PrivateKeyOnly>) -> Self {
+        Self::with_rotating_keys(Arc::new(key_registry.into()), DEFAULT_HELPER_ORIGIN)
+    }
+
+    /// Creates a new processor that uses keys from `key_registry` to decrypt query inputs.
+    /// Every query uses the set of keys that were valid at the time it started receiving inputs.
+    /// Query inputs must be encrypted for `helper_origin`.
+    #[must_use]
+    pub fn with_rotating_keys(key_registry: Arc<RotatingKeyRegistry>, helper_origin: &str) -> Self {
         Self {
             queries: RunningQueries::default(),
-            key_registry: Arc::new(key_registry),
+            key_registry,
+            helper_origin: helper_origin.to_string(),
+            result_signing_key: None,
+            traces: Mutex::default(),
+            transcript_dir: None,
+            channel_digests: false,
+            digests: Mutex::default(),
+        }
+    }
+
+    /// Enables this processor to accept queries that request encrypted results. Results of
+    /// such queries are signed using `signing_key`.
+    #[must_use]
+    pub fn with_result_signing_key(mut self, signing_key: ResultSigningKey) -> Self {
+        self.result_signing_key = Some(Arc::new(signing_key));
+        self
+    }
+
+    /// Writes a transcript of every query to `dir`, named after the query id and the role of this
+    /// helper. Transcripts contain PRSS seeds of this helper and must be kept private.
+    #[must_use]
+    pub fn with_transcript_dir(mut self, dir: PathBuf) -> Self {
+        self.transcript_dir = Some(dir);
+        self
+    }
+
+    /// Keeps digests of the records exchanged with other helpers for the most recent query, so
+    /// helpers can find where their views of the query diverged. Every record goes through a
+    /// digest, so this is off by default.
+    #[must_use]
+    pub fn with_channel_digests(mut self) -> Self {
+        self.channel_digests = true;
+        self
+    }
+
+    fn check_result_signing_key(&self, config: &QueryConfig) -> Result<(), NoResultSigningKey> {
+        if config.result_key.is_some() && self.result_signing_key.is_none() {
+            Err(NoResultSigningKey)
+        } else {
+            Ok(())
         }
     }
 
@@ -140,6 +256,7 @@ impl Processor {
         transport: MpcTransportImpl,
         req: QueryConfig,
     ) -> Result<PrepareQuery, NewQueryError> {
+        self.check_result_signing_key(&req)?;
         let query_id = QueryId;
         let handle = self.queries.handle(query_id);
         handle.set_state(QueryState::Preparing(req))?;
@@ -166,6 +283,7 @@ impl Processor {
         .map_err(NewQueryError::MpcTransport)?;
 
         handle.set_state(QueryState::AwaitingInputs(query_id, req, roles))?;
+        self.set_trace(query_id);
 
         guard.restore();
         Ok(prepare_request)
@@ -189,6 +307,7 @@ impl Processor {
         if my_role == Role::H1 {
             return Err(PrepareQueryError::WrongTarget);
         }
+        self.check_result_signing_key(&req.config)?;
         let handle = self.queries.handle(req.query_id);
         if handle.status().is_some() {
             return Err(PrepareQueryError::AlreadyRunning);
@@ -199,14 +318,25 @@ impl Processor {
             req.config,
             req.roles,
         ))?;
+        self.set_trace(req.query_id);
 
         Ok(())
     }
 
+    /// Associates the query with the current trace, if there is one.
+    fn set_trace(&self, query_id: QueryId) {
+        let mut traces = self.traces.lock().unwrap();
+        match TraceContext::current() {
+            Some(context) => traces.insert(query_id, context),
+            None => traces.remove(&query_id),
+        };
+    }
+
     /// Receive inputs for the specified query. That triggers query processing
     ///
     /// ## Errors
-    /// if query is not registered on this helper.
+    /// if query is not registered on this helper or if it requested encrypted results, but this
+    /// helper has no result signing key.
     ///
     /// ## Panics
     /// If failed to obtain exclusive access to the query collection.
@@ -225,21 +355,75 @@ impl Processor {
                         input.query_id, query_id,
                         "received inputs for a different query"
                     );
-                    let gateway = Gateway::new(
+                    let result_sealer = match (config.result_key, &self.result_signing_key) {
+                        (None, _) => None,
+                        (Some(recipient), Some(signing_key)) => Some(ResultSealer {
+                            identity: mpc_transport.identity(),
+                            helper_origin: self.helper_origin.clone(),
+                            recipient,
+                            signing_key: Arc::clone(signing_key),
+                        }),
+                        (Some(_), None) => {
+                            queries.insert(
+                                query_id,
+                                QueryState::AwaitingInputs(query_id, config, role_assignment),
+                            );
+                            return Err(NoResultSigningKey.into());
+                        }
+                    };
+                    let mut gateway = Gateway::new(
                         query_id,
                         GatewayConfig::from(&config),
                         role_assignment,
                         mpc_transport,
                         shard_transport,
                     );
+                    if let Some(dir) = &self.transcript_dir {
+                        let role = gateway.role();
+                        let path = dir.join(format!("{query_id}-{role:?}.jsonl"));
+                        match TranscriptRecorder::create(&path, role) {
+                            Ok(recorder) => {
+                                recorder.record_query(&config);
+                                gateway = gateway.with_transcript(Arc::new(recorder));
+                            }
+                            Err(e) => tracing::warn!(
+                                "failed to create transcript {}: {e}",
+                                path.display()
+                            ),
+                        }
+                    }
+                    if self.channel_digests {
+                        let digests = ChannelDigests::new(gateway.role());
+                        gateway = gateway.with_digests(digests.clone());
+                        *self.digests.lock().unwrap() = Some((query_id, digests));
+                    }
+                    let traceparent = self
+                        .traces
+                        .lock()
+                        .unwrap()
+                        .remove(&query_id)
+                        .map(|context| context.to_string());
+                    let span = info_span!(
+                        target: SPAN_TARGET,
+                        parent: None,
+                        "query",
+                        otel.name = %format_args!("query {query_id}"),
+                        query_id = query_id.as_ref(),
+                        query_type = config.query_type.as_ref(),
+                        traceparent,
+                    );
                     queries.insert(
                         input.query_id,
-                        QueryState::Running(executor::execute(
-                            config,
-                            Arc::clone(&self.key_registry),
-                            gateway,
-                            input.input_stream,
-                        )),
+                        QueryState::Running(span.in_scope(|| {
+                            executor::execute(
+                                config,
+                                self.key_registry.snapshot(SystemTime::now()),
+                                self.helper_origin.clone(),
+                                result_sealer,
+                                gateway,
+                                input.input_stream,
+                            )
+                        })),
                     );
                     Ok(())
                 } else {
@@ -263,6 +447,20 @@ impl Processor {
     /// ## Panics
     /// If the query collection mutex is poisoned.
     pub fn query_status(&self, query_id: QueryId) -> Result<QueryStatus, QueryStatusError> {
+        self.query_progress(query_id).map(|(status, _)| status)
+    }
+
+    /// Returns the query status along with the progress of the query, if it is running.
+    ///
+    /// ## Errors
+    /// If query is not registered on this helper.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    pub fn query_progress(
+        &self,
+        query_id: QueryId,
+    ) -> Result<(QueryStatus, Option<QueryProgress>), QueryStatusError> {
         let mut queries = self.queries.inner.lock().unwrap();
         let Some(mut state) = queries.remove(&query_id) else {
             return Err(QueryStatusError::NoSuchQuery(query_id));
@@ -275,8 +473,58 @@ impl Processor {
         }
 
         let status = QueryStatus::from(&state);
+        let progress = match state {
+            QueryState::Running(ref running) => Some(running.progress.snapshot()),
+            _ => None,
+        };
         queries.insert(query_id, state);
-        Ok(status)
+        Ok((status, progress))
+    }
+
+    /// Returns the current state of the gateway used by the running query: outstanding
+    /// sends and receives per channel, and occupancy of channel buffers.
+    ///
+    /// ## Errors
+    /// If query is not registered on this helper, it is not running or stall detection is
+    /// disabled.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    pub fn stall_report(&self, query_id: QueryId) -> Result<StallReport, QueryStallReportError> {
+        let queries = self.queries.inner.lock().unwrap();
+        match queries.get(&query_id) {
+            Some(QueryState::Running(running)) => running
+                .stall_report()
+                .ok_or(QueryStallReportError::Unavailable),
+            Some(state) => Err(QueryStallReportError::NotRunning {
+                query_id,
+                status: QueryStatus::from(state),
+            }),
+            None => Err(QueryStallReportError::NoSuchQuery(query_id)),
+        }
+    }
+
+    /// Returns digests of the records this helper exchanged with other helpers while running
+    /// the query, per channel. Digests are available while the query is running and after it
+    /// finishes, until the next query starts.
+    ///
+    /// ## Errors
+    /// If this helper does not keep digests, or if query is not the most recent query that
+    /// started running on this helper.
+    ///
+    /// ## Panics
+    /// If the digest mutex is poisoned.
+    pub fn digests(&self, query_id: QueryId) -> Result<DigestReport, QueryDigestsError> {
+        if !self.channel_digests {
+            return Err(QueryDigestsError::Disabled);
+        }
+        self.digests
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|(id, _)| *id == query_id)
+            .map(|(_, digests)| digests.report())
+            .ok_or(QueryDigestsError::NoSuchQuery(query_id))
     }
 
     /// Awaits the query completion
@@ -315,6 +563,42 @@ impl Processor {
 
         Ok(handle.await?)
     }
+
+    /// Terminates the query and forgets about it. If the query is running, its task is aborted.
+    ///
+    /// ## Errors
+    /// If query is not registered on this helper or somebody is already waiting for it to
+    /// complete.
+    ///
+    /// ## Panics
+    /// If the query collection mutex is poisoned.
+    pub fn kill(&self, query_id: QueryId) -> Result<(), QueryKillError> {
+        let mut queries = self.queries.inner.lock().unwrap();
+        let state = queries.remove(&query_id);
+        if !matches!(state, None | Some(QueryState::AwaitingCompletion)) {
+            // A query that is killed before it receives inputs still holds its trace context.
+            self.traces.lock().unwrap().remove(&query_id);
+        }
+        match state {
+            Some(QueryState::Running(running)) => {
+                running.join_handle.abort();
+                Ok(())
+            }
+            Some(QueryState::AwaitingCompletion) => {
+                // The completion handle owns the query task and removes the query when it is
+                // done, there is nothing to abort here.
+                queries.insert(query_id, QueryState::AwaitingCompletion);
+                Err(QueryKillError::StateError {
+                    source: StateError::InvalidState {
+                        from: QueryStatus::AwaitingCompletion,
+                        to: QueryStatus::Completed,
+                    },
+                })
+            }
+            Some(_) => Ok(()),
+            None => Err(QueryKillError::NoSuchQuery(query_id)),
+        }
+    }
 }
 
 #[cfg(all(test, unit_test))]
@@ -333,9 +617,11 @@ mod tests {
             ApiError, HandlerBox, HelperIdentity, HelperResponse, InMemoryMpcNetwork,
             RequestHandler, RoleAssignment, Transport,
         },
+        hpke::{KeyPair, ResultEncryptionKey},
         protocol::QueryId,
         query::{
-            processor::Processor, state::StateError, NewQueryError, PrepareQueryError, QueryStatus,
+            processor::Processor, state::StateError, NewQueryError, PrepareQueryError,
+            QueryDigestsError, QueryStatus,
         },
     };
 
@@ -433,6 +719,33 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn rejects_result_key_without_signing_key() {
+        let network = InMemoryMpcNetwork::default();
+        let [t0, t1, _] = network.transports();
+        let p0 = Processor::default();
+        let request = test_multiply_config().with_result_key(ResultEncryptionKey::from(
+            &KeyPair::gen(&mut rand::thread_rng()),
+        ));
+
+        assert!(matches!(
+            p0.new_query(t0, request).await,
+            Err(NewQueryError::NoResultSigningKey(_)),
+        ));
+        assert!(matches!(
+            p0.prepare(
+                &t1,
+                PrepareQuery {
+                    query_id: QueryId,
+                    config: request,
+                    roles: RoleAssignment::new(HelperIdentity::make_three()),
+                },
+            ),
+            Err(PrepareQueryError::NoResultSigningKey(_)),
+        ));
+        assert!(p0.query_status(QueryId).is_err());
+    }
+
     #[tokio::test]
     async fn prepare_error() {
         let h2 = respond_ok();
@@ -478,7 +791,7 @@ mod tests {
 
     mod prepare {
         use super::*;
-        use crate::query::QueryStatusError;
+        use crate::query::{QueryStallReportError, QueryStatusError};
 
         fn prepare_query(identities: [HelperIdentity; 3]) -> PrepareQuery {
             PrepareQuery {
@@ -505,6 +818,33 @@ mod tests {
                 QueryStatus::AwaitingInputs,
                 processor.query_status(QueryId).unwrap()
             );
+            // progress is only reported for running queries
+            assert!(matches!(
+                processor.query_progress(QueryId).unwrap(),
+                (QueryStatus::AwaitingInputs, None)
+            ));
+        }
+
+        #[tokio::test]
+        async fn stall_report_requires_running_query() {
+            let network = InMemoryMpcNetwork::default();
+            let identities = HelperIdentity::make_three();
+            let req = prepare_query(identities);
+            let transport = network.transport(identities[1]);
+            let processor = Processor::default();
+            assert!(matches!(
+                processor.stall_report(QueryId),
+                Err(QueryStallReportError::NoSuchQuery(_))
+            ));
+
+            processor.prepare(&transport, req).unwrap();
+            assert!(matches!(
+                processor.stall_report(QueryId),
+                Err(QueryStallReportError::NotRunning {
+                    status: QueryStatus::AwaitingInputs,
+                    ..
+                })
+            ));
         }
 
         #[tokio::test]
@@ -536,9 +876,54 @@ mod tests {
         }
     }
 
+    mod kill {
+        use super::*;
+        use crate::query::{QueryKillError, QueryStatusError};
+
+        #[tokio::test]
+        async fn forgets_query() {
+            let network = InMemoryMpcNetwork::default();
+            let identities = HelperIdentity::make_three();
+            let req = PrepareQuery {
+                query_id: QueryId,
+                config: test_multiply_config(),
+                roles: RoleAssignment::new(identities),
+            };
+            let transport = network.transport(identities[1]);
+            let processor = Processor::default();
+
+            processor.prepare(&transport, req.clone()).unwrap();
+            processor.traces.lock().unwrap().insert(
+                QueryId,
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+                    .parse()
+                    .unwrap(),
+            );
+            processor.kill(QueryId).unwrap();
+            assert!(matches!(
+                processor.query_status(QueryId).unwrap_err(),
+                QueryStatusError::NoSuchQuery(_)
+            ));
+            assert!(processor.traces.lock().unwrap().is_empty());
+
+            // the query id is free to use again
+            processor.prepare(&transport, req).unwrap();
+        }
+
+        #[test]
+        fn no_such_query() {
+            let processor = Processor::default();
+            assert!(matches!(
+                processor.kill(QueryId),
+                Err(QueryKillError::NoSuchQuery(_))
+            ));
+        }
+    }
+
     mod e2e {
         use std::time::Duration;
 
+        use rand::thread_rng;
         use tokio::time::sleep;
 
         use super::*;
@@ -548,10 +933,17 @@ mod tests {
                 boolean_array::{BA20, BA3, BA8},
                 Fp31, U128Conversions,
             },
-            helpers::query::{IpaQueryConfig, QueryType},
+            helpers::{
+                first_mismatch,
+                query::{IpaQueryConfig, QueryType},
+                StreamCompression,
+            },
+            hpke::{open_result, KeyPair, ResultBinding, ResultEncryptionKey, ResultSigningKey},
             protocol::ipa_prf::OPRFIPAInputRow,
+            report::DEFAULT_HELPER_ORIGIN,
             secret_sharing::replicated::semi_honest,
             test_fixture::{ipa::TestRawDataRecord, Reconstruct, TestApp},
+            utils::array::zip3,
         };
 
         #[tokio::test]
@@ -575,6 +967,76 @@ mod tests {
             ))
         }
 
+        #[tokio::test]
+        async fn channel_digests_match() -> Result<(), BoxError> {
+            let app = TestApp::with_channel_digests();
+            let a = Fp31::truncate_from(4u128);
+            let b = Fp31::truncate_from(5u128);
+            let query_id = app
+                .start_query(vec![a, b].into_iter(), test_multiply_config())
+                .await?;
+            app.complete_query(query_id).await?;
+
+            // digests are still available after the query is complete
+            let reports = app.query_digests(query_id)?;
+            assert!(reports.iter().all(|r| !r.sent.is_empty()));
+            assert_eq!(None, first_mismatch(&reports));
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn channel_digests_disabled_by_default() -> Result<(), BoxError> {
+            let app = TestApp::default();
+            let a = Fp31::truncate_from(4u128);
+            let b = Fp31::truncate_from(5u128);
+            let query_id = app
+                .start_query(vec![a, b].into_iter(), test_multiply_config())
+                .await?;
+            app.complete_query(query_id).await?;
+
+            assert!(matches!(
+                app.query_digests(query_id),
+                Err(ApiError::QueryDigests(QueryDigestsError::Disabled))
+            ));
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn complete_query_sealed_results() -> Result<(), BoxError> {
+            let mut rng = thread_rng();
+            let signing_keys = array::from_fn(|_| ResultSigningKey::gen(&mut rng));
+            let verifying_keys = signing_keys.each_ref().map(ResultSigningKey::verifying_key);
+            let rc_keys = KeyPair::gen(&mut rng);
+            let app = TestApp::with_result_signing_keys(signing_keys);
+            let a = Fp31::truncate_from(4u128);
+            let b = Fp31::truncate_from(5u128);
+            let config =
+                test_multiply_config().with_result_key(ResultEncryptionKey::from(&rc_keys));
+            let results = app.execute_query(vec![a, b].into_iter(), config).await?;
+
+            let results = zip3(results, zip3(HelperIdentity::make_three(), verifying_keys)).map(
+                |(sealed, (identity, vk))| {
+                    let binding = ResultBinding {
+                        helper: identity,
+                        helper_origin: DEFAULT_HELPER_ORIGIN,
+                        query_id: QueryId,
+                        config: &config,
+                    };
+                    let bytes = open_result(&sealed, &binding, &vk, &rc_keys).unwrap();
+                    semi_honest::AdditiveShare::<Fp31>::from_byte_slice(&bytes)
+                        .collect::<Result<Vec<_>, _>>()
+                        .unwrap()
+                },
+            );
+
+            Ok(assert_eq!(
+                vec![Fp31::truncate_from(20u128)],
+                results.reconstruct()
+            ))
+        }
+
         #[tokio::test]
         async fn complete_query_status_poll() -> Result<(), BoxError> {
             let app = TestApp::default();
@@ -672,7 +1134,11 @@ mod tests {
                             with_dp: 0,
                             epsilon: 1.0,
                             plaintext_match_keys: true,
+                            unversioned_reports: false,
                         }),
+                        result_key: None,
+                        compression: StreamCompression::None,
+                        proof_size: None,
                     },
                 )
                 .await?;
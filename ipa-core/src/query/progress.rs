@@ -0,0 +1,274 @@
+use std::{
+    fmt::{Debug, Formatter},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    helpers::TotalRecords,
+    protocol::{
+        ipa_prf::{prf_sharding::step::AttributionStep, step::IpaPrfStep},
+        step::ProtocolStep,
+    },
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Stages of the IPA protocol, in the order they are executed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum QueryStage {
+    Decrypting,
+    Shuffling,
+    Prf,
+    Sorting,
+    Attribution,
+    Aggregation,
+    DifferentialPrivacy,
+}
+
+impl QueryStage {
+    const COUNT: usize = 7;
+
+    /// Maps a gate to the stage of IPA it belongs to, by matching the step that follows
+    /// [`ProtocolStep::IpaPrf`] in the gate against the `IpaPrfStep` that starts each stage.
+    /// Returns `None` for gates that are not a part of IPA.
+    #[must_use]
+    pub fn from_gate(gate: &str) -> Option<Self> {
+        let mut steps = gate
+            .split('/')
+            .skip_while(|&step| step != ProtocolStep::IpaPrf.as_ref())
+            .skip(1);
+        let step = steps.next()?;
+        let (_, stage) = [
+            (IpaPrfStep::Shuffle, Self::Shuffling),
+            (IpaPrfStep::ConvertFp25519, Self::Prf),
+            (IpaPrfStep::EvalPrf, Self::Prf),
+            (IpaPrfStep::SortByTimestamp, Self::Sorting),
+            (IpaPrfStep::Attribution, Self::Attribution),
+            (IpaPrfStep::DifferentialPrivacy, Self::DifferentialPrivacy),
+        ]
+        .into_iter()
+        .find(|(ipa_step, _)| ipa_step.as_ref() == step)?;
+
+        // aggregation runs as a part of attribution
+        if stage == Self::Attribution && steps.next() == Some(AttributionStep::Aggregate.as_ref()) {
+            Some(Self::Aggregation)
+        } else {
+            Some(stage)
+        }
+    }
+}
+
+/// Progress of a single stage of a running query.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageProgress {
+    pub stage: QueryStage,
+    /// The largest number of records processed by any step in this stage.
+    pub records: usize,
+    /// Time spent in this stage so far, or the total time if the stage is finished.
+    pub elapsed: Duration,
+    pub finished: bool,
+}
+
+/// Stages that a running query went through, in the order of execution. The last one is the
+/// stage that the query is currently in.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryProgress {
+    pub stages: Vec<StageProgress>,
+}
+
+impl QueryProgress {
+    #[must_use]
+    pub fn current(&self) -> Option<&StageProgress> {
+        self.stages.last()
+    }
+}
+
+/// Tracks the progress of a query, based on the gates of the channels that the query opens.
+///
+/// Stages only move forward. Some of them overlap, for example aggregation consumes the output
+/// of attribution as it is produced, in this case the later stage is reported.
+#[derive(Clone, Default)]
+pub struct ProgressTracker {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Index of the current stage plus one, zero if no stage has started yet.
+    current: AtomicUsize,
+    records: [AtomicUsize; QueryStage::COUNT],
+    started: Mutex<Vec<(QueryStage, Instant)>>,
+}
+
+impl ProgressTracker {
+    /// Records that the query is working on `stage` with `records` records.
+    pub fn enter(&self, stage: QueryStage, records: usize) {
+        self.inner.records[stage as usize].fetch_max(records, Ordering::Relaxed);
+        let idx = stage as usize + 1;
+        // Fast path: channels are requested for every record, while stage changes are rare.
+        if self.inner.current.fetch_max(idx, Ordering::AcqRel) < idx {
+            let mut started = self.inner.started.lock().unwrap();
+            let pos = started.partition_point(|&(s, _)| s < stage);
+            started.insert(pos, (stage, Instant::now()));
+        }
+    }
+
+    /// Records that a channel for `gate` was opened.
+    pub fn observe(&self, gate: &str, total_records: TotalRecords) {
+        if let Some(stage) = QueryStage::from_gate(gate) {
+            let records = match total_records {
+                TotalRecords::Specified(v) => v.get(),
+                TotalRecords::Unspecified | TotalRecords::Indeterminate => 0,
+            };
+            self.enter(stage, records);
+        }
+    }
+
+    /// ## Panics
+    /// If the tracker mutex is poisoned.
+    #[must_use]
+    pub fn snapshot(&self) -> QueryProgress {
+        let now = Instant::now();
+        let started = self.inner.started.lock().unwrap();
+        let ends = started
+            .iter()
+            .skip(1)
+            .map(|&(_, start)| Some(start))
+            .chain([None]);
+
+        QueryProgress {
+            stages: started
+                .iter()
+                .zip(ends)
+                .map(|(&(stage, start), end)| StageProgress {
+                    stage,
+                    records: self.inner.records[stage as usize].load(Ordering::Relaxed),
+                    elapsed: end.unwrap_or(now).saturating_duration_since(start),
+                    finished: end.is_some(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Debug for ProgressTracker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProgressTracker[{:?}]", self.snapshot().current())
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use ipa_step::StepNarrow;
+
+    use super::{ProgressTracker, QueryStage};
+    use crate::{
+        helpers::TotalRecords,
+        protocol::{
+            ipa_prf::{prf_sharding::step::AttributionStep, step::IpaPrfStep},
+            step::ProtocolStep,
+            Gate,
+        },
+    };
+
+    #[test]
+    fn stage_from_gate() {
+        assert_eq!(
+            Some(QueryStage::Shuffling),
+            QueryStage::from_gate("/ipa_prf/shuffle/transfer_x2")
+        );
+        assert_eq!(
+            Some(QueryStage::Prf),
+            QueryStage::from_gate("/ipa_prf/eval_prf/revealz")
+        );
+        assert_eq!(
+            Some(QueryStage::Sorting),
+            QueryStage::from_gate("/ipa_prf/sort_by_timestamp/quicksort_pass0/compare")
+        );
+        assert_eq!(
+            Some(QueryStage::Attribution),
+            QueryStage::from_gate("/ipa_prf/attribution/binary_validator/row1")
+        );
+        assert_eq!(
+            Some(QueryStage::Aggregation),
+            QueryStage::from_gate("/ipa_prf/attribution/aggregate/aggregate0")
+        );
+        assert_eq!(
+            Some(QueryStage::DifferentialPrivacy),
+            QueryStage::from_gate("/ipa_prf/dp/noise_gen")
+        );
+        assert_eq!(None, QueryStage::from_gate("/prss"));
+        assert_eq!(None, QueryStage::from_gate("/ipa_prf"));
+    }
+
+    #[test]
+    fn stage_from_narrowed_gate() {
+        let ipa = Gate::default().narrow(&ProtocolStep::IpaPrf);
+        let stage = |gate: Gate| QueryStage::from_gate(gate.as_ref());
+        assert_eq!(
+            Some(QueryStage::Sorting),
+            stage(ipa.narrow(&IpaPrfStep::SortByTimestamp))
+        );
+        assert_eq!(
+            Some(QueryStage::Attribution),
+            stage(
+                ipa.narrow(&IpaPrfStep::Attribution)
+                    .narrow(&AttributionStep::BinaryValidator)
+            )
+        );
+        assert_eq!(
+            Some(QueryStage::Aggregation),
+            stage(
+                ipa.narrow(&IpaPrfStep::Attribution)
+                    .narrow(&AttributionStep::Aggregate)
+            )
+        );
+        assert_eq!(None, stage(Gate::default().narrow(&ProtocolStep::Prss)));
+    }
+
+    #[test]
+    fn stages_move_forward() {
+        let tracker = ProgressTracker::default();
+        assert!(tracker.snapshot().stages.is_empty());
+
+        tracker.enter(QueryStage::Decrypting, 10);
+        tracker.observe(
+            "/ipa_prf/attribution/row0",
+            TotalRecords::Specified(NonZeroUsize::new(4).unwrap()),
+        );
+        tracker.observe(
+            "/ipa_prf/attribution/aggregate/aggregate0",
+            TotalRecords::Indeterminate,
+        );
+        // attribution overlaps with aggregation, it does not become current again
+        tracker.observe(
+            "/ipa_prf/attribution/row1",
+            TotalRecords::Specified(NonZeroUsize::new(8).unwrap()),
+        );
+
+        let progress = tracker.snapshot();
+        let stages = progress
+            .stages
+            .iter()
+            .map(|s| (s.stage, s.records, s.finished))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (QueryStage::Decrypting, 10, true),
+                (QueryStage::Attribution, 8, true),
+                (QueryStage::Aggregation, 0, false),
+            ],
+            stages
+        );
+        assert_eq!(
+            Some(QueryStage::Aggregation),
+            progress.current().map(|s| s.stage)
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use std::marker::PhantomData;
 
+use bytes::Bytes;
 use futures::{stream::iter, StreamExt, TryStreamExt};
 use futures_util::stream::repeat;
 
@@ -21,7 +22,7 @@ use crate::{
         ipa_prf::{oprf_ipa, OPRFIPAInputRow},
         step::ProtocolStep::IpaPrf,
     },
-    report::{EncryptedOprfReport, EventType},
+    report::{EncryptedOprfReport, EventType, VersionedEncryptedOprfReport},
     secret_sharing::{
         replicated::semi_honest::AdditiveShare as Replicated, BitDecomposed, SharedValue,
         TransposeFrom,
@@ -32,14 +33,16 @@ use crate::{
 pub struct OprfIpaQuery<'a, HV, R: PrivateKeyRegistry> {
     config: IpaQueryConfig,
     key_registry: Arc<R>,
+    helper_origin: String,
     phantom_data: PhantomData<&'a HV>,
 }
 
 impl<'a, HV, R: PrivateKeyRegistry> OprfIpaQuery<'a, HV, R> {
-    pub fn new(config: IpaQueryConfig, key_registry: Arc<R>) -> Self {
+    pub fn new(config: IpaQueryConfig, key_registry: Arc<R>, helper_origin: String) -> Self {
         Self {
             config,
             key_registry,
+            helper_origin,
             phantom_data: PhantomData,
         }
     }
@@ -64,6 +67,7 @@ where
         let Self {
             config,
             key_registry,
+            helper_origin,
             phantom_data: _,
         } = self;
         tracing::info!("New query: {config:?}");
@@ -77,14 +81,25 @@ where
             v.truncate(sz);
             v
         } else {
-            LengthDelimitedStream::<EncryptedOprfReport<BA8, BA3, BA20, _>, _>::new(input_stream)
+            // Each report is parsed according to its own version, unless the query was created
+            // for reports that predate versions.
+            let decrypt = |enc_report: Bytes| {
+                if config.unversioned_reports {
+                    EncryptedOprfReport::<BA8, BA3, BA20, _>::from_bytes(enc_report)
+                        .and_then(|report| report.decrypt(key_registry.as_ref(), &helper_origin))
+                } else {
+                    VersionedEncryptedOprfReport::from_bytes(enc_report)
+                        .and_then(|report| report.decrypt(key_registry.as_ref(), &helper_origin))
+                }
+            };
+            LengthDelimitedStream::<Bytes, _>::new(input_stream)
                 .map_err(Into::<Error>::into)
                 .map_ok(|enc_reports| {
-                    iter(enc_reports.into_iter().map(|enc_report| {
-                        enc_report
-                            .decrypt(key_registry.as_ref())
-                            .map_err(Into::<Error>::into)
-                    }))
+                    iter(
+                        enc_reports
+                            .into_iter()
+                            .map(|enc_report| decrypt(enc_report).map_err(Into::<Error>::into)),
+                    )
                 })
                 .try_flatten()
                 .take(sz)
@@ -137,6 +152,7 @@ where
 mod tests {
     use std::{iter::zip, sync::Arc};
 
+    use bytes::BufMut;
     use rand::rngs::StdRng;
     use rand_core::SeedableRng;
 
@@ -147,17 +163,26 @@ mod tests {
         },
         helpers::{
             query::{IpaQueryConfig, QuerySize},
-            BodyStream,
+            BodyStream, Role,
         },
         hpke::{KeyPair, KeyRegistry},
-        query::runner::OprfIpaQuery,
-        report::{OprfReport, DEFAULT_KEY_ID},
+        query::{runner::OprfIpaQuery, QueryStage},
+        report::{OprfReport, DEFAULT_HELPER_ORIGIN, DEFAULT_KEY_ID},
         secret_sharing::IntoShares,
         test_fixture::{ipa::TestRawDataRecord, join3v, Reconstruct, TestWorld},
     };
 
     #[tokio::test]
     async fn encrypted_reports() {
+        run_encrypted_reports(false).await;
+    }
+
+    #[tokio::test]
+    async fn unversioned_encrypted_reports() {
+        run_encrypted_reports(true).await;
+    }
+
+    async fn run_encrypted_reports(unversioned_reports: bool) {
         const EXPECTED: &[u128] = &[0, 8, 5];
 
         let records: Vec<TestRawDataRecord> = vec![
@@ -216,9 +241,28 @@ mod tests {
         let shares: [Vec<OprfReport<BA8, BA3, BA20>>; 3] = records.into_iter().share();
         for (buf, shares) in zip(&mut buffers, shares) {
             for share in shares {
-                share
-                    .delimited_encrypt_to(key_id, key_registry.as_ref(), &mut rng, buf)
-                    .unwrap();
+                if unversioned_reports {
+                    buf.put_u16_le(share.encrypted_len());
+                    share
+                        .encrypt_to(
+                            key_id,
+                            key_registry.as_ref(),
+                            DEFAULT_HELPER_ORIGIN,
+                            &mut rng,
+                            buf,
+                        )
+                        .unwrap();
+                } else {
+                    share
+                        .delimited_encrypt_to(
+                            key_id,
+                            key_registry.as_ref(),
+                            DEFAULT_HELPER_ORIGIN,
+                            &mut rng,
+                            buf,
+                        )
+                        .unwrap();
+                }
             }
         }
 
@@ -234,11 +278,16 @@ mod tests {
                 with_dp: 0,
                 epsilon: 1.0,
                 plaintext_match_keys: false,
+                unversioned_reports,
             };
             let input = BodyStream::from(buffer);
 
-            OprfIpaQuery::<BA16, KeyRegistry<KeyPair>>::new(query_config, Arc::clone(&key_registry))
-                .execute(ctx, query_size, input)
+            OprfIpaQuery::<BA16, KeyRegistry<KeyPair>>::new(
+                query_config,
+                Arc::clone(&key_registry),
+                DEFAULT_HELPER_ORIGIN.to_string(),
+            )
+            .execute(ctx, query_size, input)
         }))
         .await;
 
@@ -249,5 +298,15 @@ mod tests {
                 .collect::<Vec<u128>>(),
             EXPECTED
         );
+
+        // stages of the query are picked up from the gates it used
+        let progress = world.gateway(Role::H1).progress().snapshot();
+        let stages = progress.stages.iter().map(|s| s.stage).collect::<Vec<_>>();
+        assert!(stages.windows(2).all(|w| w[0] < w[1]), "{stages:?}");
+        assert_eq!(Some(&QueryStage::Shuffling), stages.first());
+        assert!(stages.contains(&QueryStage::Prf));
+        assert!(stages.contains(&QueryStage::Sorting));
+        assert_eq!(Some(&QueryStage::Aggregation), stages.last());
+        assert!(progress.stages.iter().all(|s| s.records > 0 || !s.finished));
     }
 }
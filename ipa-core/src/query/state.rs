@@ -9,10 +9,12 @@ use ::tokio::sync::oneshot::{error::TryRecvError, Receiver};
 use futures::{ready, FutureExt};
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "stall-detection")]
+use crate::helpers::StallObserver;
 use crate::{
-    helpers::{query::QueryConfig, RoleAssignment},
+    helpers::{query::QueryConfig, RoleAssignment, StallReport},
     protocol::QueryId,
-    query::runner::QueryResult,
+    query::{runner::QueryResult, ProgressTracker},
     sync::Mutex,
     task::JoinHandle,
 };
@@ -88,9 +90,31 @@ pub struct RunningQuery {
     /// We could return the result via the `JoinHandle`, except that we want to check the status
     /// of the task, and shuttle doesn't implement `JoinHandle::is_finished`.
     pub join_handle: JoinHandle<()>,
+
+    /// Progress of the query, updated by the query task as it moves through protocol stages.
+    pub progress: ProgressTracker,
+
+    /// Observes the gateway used by the query task to report stalls.
+    #[cfg(feature = "stall-detection")]
+    pub stall_observer: StallObserver,
 }
 
 impl RunningQuery {
+    /// Returns the current state of the query gateway, or `None` if stall detection is disabled
+    /// or the query task has already finished.
+    #[must_use]
+    #[cfg_attr(not(feature = "stall-detection"), allow(clippy::unused_self))]
+    pub fn stall_report(&self) -> Option<StallReport> {
+        #[cfg(feature = "stall-detection")]
+        {
+            self.stall_observer.report()
+        }
+        #[cfg(not(feature = "stall-detection"))]
+        {
+            None
+        }
+    }
+
     pub fn try_complete(&mut self) -> Option<QueryResult> {
         match self.result.try_recv() {
             Ok(result) => Some(result),
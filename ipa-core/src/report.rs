@@ -12,16 +12,21 @@ use typenum::{Sum, Unsigned, U1, U16};
 
 use crate::{
     error::BoxError,
-    ff::{boolean_array::BA64, Serializable},
+    ff::{
+        boolean_array::{BA20, BA3, BA64, BA8},
+        Serializable,
+    },
     hpke::{
-        open_in_place, seal_in_place, CryptError, EncapsulationSize, Info, PrivateKeyRegistry,
-        PublicKeyRegistry, TagSize,
+        open_in_place, seal_in_place, CryptError, EncapsulationSize, Info, InvalidInfoError,
+        PrivateKeyRegistry, PublicKeyRegistry, TagSize,
     },
     secret_sharing::{replicated::semi_honest::AdditiveShare as Replicated, SharedValue},
 };
 
-// TODO(679): This needs to come from configuration.
-static HELPER_ORIGIN: &str = "github.com/private-attribution";
+/// Helper origin used by helper networks that don't specify one in their configuration.
+/// Helper origin is authenticated by the report encryption, so reports encrypted for one
+/// helper network can't be decrypted by a network that uses a different origin.
+pub const DEFAULT_HELPER_ORIGIN: &str = "github.com/private-attribution";
 
 pub type KeyIdentifier = u8;
 pub const DEFAULT_KEY_ID: KeyIdentifier = 0;
@@ -149,6 +154,8 @@ pub enum InvalidReportError {
     BadEventType(#[from] ParseEventTypeError),
     #[error("bad site_domain: {0}")]
     NonAsciiString(#[from] NonAsciiStringError),
+    #[error(transparent)]
+    Info(#[from] InvalidInfoError),
     #[error("timestamp {0} out of range")]
     Timestamp(Timestamp),
     #[error("en/decryption failure: {0}")]
@@ -157,6 +164,64 @@ pub enum InvalidReportError {
     DeserializationError(&'static str, #[source] BoxError),
     #[error("report is too short: {0}, expected length at least: {1}")]
     Length(usize, usize),
+    #[error("report version {0} is not supported, supported versions are: {supported:?}", supported = ReportVersion::SUPPORTED.map(u8::from))]
+    UnsupportedVersion(u8),
+}
+
+/// Version of the encrypted report format.
+///
+/// Reports submitted to helpers start with a single byte that specifies the version of the
+/// format used to encode the rest of the report. The version determines the layout and sizes of
+/// all fields that follow it, as well as the associated data that is authenticated by the
+/// encryption. Each report is parsed according to its own version, so reports encoded using
+/// different versions can be mixed within the same query.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReportVersion {
+    /// The initial format, described in [`EncryptedOprfReport`]. Report version is not
+    /// authenticated.
+    V1,
+    /// Uses the same layout as [`V1`], but binds the version to both ciphertexts as associated
+    /// data, so it can't be modified without failing decryption.
+    ///
+    /// [`V1`]: Self::V1
+    V2,
+}
+
+impl ReportVersion {
+    /// All versions that helpers accept.
+    pub const SUPPORTED: [Self; 2] = [Self::V1, Self::V2];
+
+    /// The version that report collectors use by default.
+    pub const LATEST: Self = Self::V2;
+
+    /// Associated data authenticated by the report encryption.
+    fn aad(self) -> &'static [u8] {
+        match self {
+            Self::V1 => &[],
+            Self::V2 => &[2],
+        }
+    }
+}
+
+impl From<ReportVersion> for u8 {
+    fn from(value: ReportVersion) -> Self {
+        match value {
+            ReportVersion::V1 => 1,
+            ReportVersion::V2 => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for ReportVersion {
+    type Error = InvalidReportError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::V1),
+            2 => Ok(Self::V2),
+            _ => Err(InvalidReportError::UnsupportedVersion(value)),
+        }
+    }
 }
 
 // TODO: If we are parsing reports from CSV files, we may also want an owned version of EncryptedReport.
@@ -302,15 +367,32 @@ where
         })
     }
 
+    /// Decrypts this report using keys from `key_registry`. `helper_origin` must match
+    /// the origin used to encrypt it. This report must be encrypted according to
+    /// [`ReportVersion::V1`], use [`VersionedEncryptedOprfReport`] to decrypt reports
+    /// of other versions.
+    ///
     /// ## Errors
     /// If the match key shares in the report cannot be decrypted (e.g. due to a
-    /// failure of the authenticated encryption).
+    /// failure of the authenticated encryption) or if `helper_origin` is not valid.
+    pub fn decrypt<P: PrivateKeyRegistry>(
+        &self,
+        key_registry: &P,
+        helper_origin: &str,
+    ) -> Result<OprfReport<BK, TV, TS>, InvalidReportError> {
+        self.decrypt_with_aad(key_registry, helper_origin, ReportVersion::V1.aad())
+    }
+
+    /// ## Errors
+    /// If the match key shares in the report cannot be decrypted.
     /// ## Panics
     /// Should not panic. Only panics if a `Report` constructor failed to validate the
     /// contents properly, which would be a bug.
-    pub fn decrypt<P: PrivateKeyRegistry>(
+    fn decrypt_with_aad<P: PrivateKeyRegistry>(
         &self,
         key_registry: &P,
+        helper_origin: &str,
+        aad: &[u8],
     ) -> Result<OprfReport<BK, TV, TS>, InvalidReportError> {
         type CTMKLength = Sum<<Replicated<BA64> as Serializable>::Size, TagSize>;
         type CTBTTLength<BK, TV, TS> = Sum<
@@ -325,18 +407,19 @@ where
             self.key_id(),
             self.epoch(),
             self.event_type(),
-            HELPER_ORIGIN,
+            helper_origin,
             self.site_domain(),
-        )
-        .unwrap(); // validated on construction
+        )?;
 
         let mut ct_mk: GenericArray<u8, CTMKLength> =
             *GenericArray::from_slice(self.mk_ciphertext());
-        let plaintext_mk = open_in_place(key_registry, self.encap_key_mk(), &mut ct_mk, &info)?;
+        let plaintext_mk =
+            open_in_place(key_registry, self.encap_key_mk(), &mut ct_mk, &info, aad)?;
         let mut ct_btt: GenericArray<u8, CTBTTLength<BK, TV, TS>> =
             GenericArray::from_slice(self.btt_ciphertext()).clone();
 
-        let plaintext_btt = open_in_place(key_registry, self.encap_key_btt(), &mut ct_btt, &info)?;
+        let plaintext_btt =
+            open_in_place(key_registry, self.encap_key_btt(), &mut ct_btt, &info, aad)?;
 
         Ok(OprfReport::<BK, TV, TS> {
             timestamp: Replicated::<TS>::deserialize(GenericArray::from_slice(
@@ -390,6 +473,68 @@ where
     }
 }
 
+/// Report with the breakdown key, trigger value and timestamp sizes that IPA queries run with.
+pub type IpaReport = OprfReport<BA8, BA3, BA20>;
+
+/// An encrypted report prefixed with the [`ReportVersion`] used to encode it. This is the format
+/// that helpers expect report collectors to submit.
+///
+/// Each variant holds the report parsed with the field sizes and layout of its version. Versions
+/// that change field sizes add a variant with their own layout and convert decrypted reports to
+/// [`IpaReport`] in [`Self::decrypt`].
+pub enum VersionedEncryptedOprfReport {
+    V1(EncryptedOprfReport<BA8, BA3, BA20, Bytes>),
+    V2(EncryptedOprfReport<BA8, BA3, BA20, Bytes>),
+}
+
+impl VersionedEncryptedOprfReport {
+    #[must_use]
+    pub fn version(&self) -> ReportVersion {
+        match self {
+            Self::V1(_) => ReportVersion::V1,
+            Self::V2(_) => ReportVersion::V2,
+        }
+    }
+
+    /// ## Errors
+    /// If the report version is not supported or the report contents are invalid.
+    pub fn from_bytes(bytes: Bytes) -> Result<Self, InvalidReportError> {
+        let Some(&version) = bytes.first() else {
+            return Err(InvalidReportError::Length(0, 1));
+        };
+        let body = bytes.slice(1..);
+        Ok(match ReportVersion::try_from(version)? {
+            ReportVersion::V1 => Self::V1(EncryptedOprfReport::from_bytes(body)?),
+            ReportVersion::V2 => Self::V2(EncryptedOprfReport::from_bytes(body)?),
+        })
+    }
+
+    /// Decrypts this report according to its version.
+    ///
+    /// ## Errors
+    /// If the report cannot be decrypted, see [`EncryptedOprfReport::decrypt`].
+    pub fn decrypt<P: PrivateKeyRegistry>(
+        &self,
+        key_registry: &P,
+        helper_origin: &str,
+    ) -> Result<IpaReport, InvalidReportError> {
+        let version = self.version();
+        match self {
+            Self::V1(report) | Self::V2(report) => {
+                report.decrypt_with_aad(key_registry, helper_origin, version.aad())
+            }
+        }
+    }
+}
+
+impl TryFrom<Bytes> for VersionedEncryptedOprfReport {
+    type Error = InvalidReportError;
+
+    fn try_from(bytes: Bytes) -> Result<Self, InvalidReportError> {
+        VersionedEncryptedOprfReport::from_bytes(bytes)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct OprfReport<BK, TV, TS>
 where
@@ -443,17 +588,54 @@ where
         len.try_into().unwrap()
     }
 
+    /// Length of this report after it is encrypted and prefixed with its version.
+    ///
+    /// # Panics
+    /// If report length does not fit in `u16`.
+    pub fn versioned_encrypted_len(&self) -> u16 {
+        self.encrypted_len().checked_add(1).unwrap()
+    }
+
+    /// Encrypts this report using [`ReportVersion::LATEST`] and writes it to `out`, prefixed with
+    /// its length. This is the format helpers expect query inputs to be in.
+    ///
     /// # Errors
     /// If there is a problem encrypting the report.
     pub fn delimited_encrypt_to<R: CryptoRng + RngCore, B: BufMut>(
         &self,
         key_id: KeyIdentifier,
         key_registry: &impl PublicKeyRegistry,
+        helper_origin: &str,
         rng: &mut R,
         out: &mut B,
     ) -> Result<(), InvalidReportError> {
-        out.put_u16_le(self.encrypted_len());
-        self.encrypt_to(key_id, key_registry, rng, out)
+        out.put_u16_le(self.versioned_encrypted_len());
+        self.versioned_encrypt_to(
+            ReportVersion::LATEST,
+            key_id,
+            key_registry,
+            helper_origin,
+            rng,
+            out,
+        )
+    }
+
+    /// Encrypts this report according to `version` and writes it to `out`, prefixed with the
+    /// version byte.
+    ///
+    /// # Errors
+    /// If there is a problem encrypting the report.
+    pub fn versioned_encrypt_to<R: CryptoRng + RngCore, B: BufMut>(
+        &self,
+        version: ReportVersion,
+        key_id: KeyIdentifier,
+        key_registry: &impl PublicKeyRegistry,
+        helper_origin: &str,
+        rng: &mut R,
+        out: &mut B,
+    ) -> Result<(), InvalidReportError> {
+        out.put_u8(version.into());
+        self.encrypt_with_aad_to(key_id, key_registry, helper_origin, version.aad(), rng, out)
     }
 
     /// # Errors
@@ -462,20 +644,43 @@ where
         &self,
         key_id: KeyIdentifier,
         key_registry: &impl PublicKeyRegistry,
+        helper_origin: &str,
         rng: &mut R,
     ) -> Result<Vec<u8>, InvalidReportError> {
         let mut out = Vec::with_capacity(usize::from(self.encrypted_len()));
-        self.encrypt_to(key_id, key_registry, rng, &mut out)?;
+        self.encrypt_to(key_id, key_registry, helper_origin, rng, &mut out)?;
         debug_assert_eq!(out.len(), usize::from(self.encrypted_len()));
         Ok(out)
     }
 
+    /// Encrypts this report according to [`ReportVersion::V1`], without the version prefix.
+    ///
     /// # Errors
     /// If there is a problem encrypting the report.
     pub fn encrypt_to<R: CryptoRng + RngCore, B: BufMut>(
         &self,
         key_id: KeyIdentifier,
         key_registry: &impl PublicKeyRegistry,
+        helper_origin: &str,
+        rng: &mut R,
+        out: &mut B,
+    ) -> Result<(), InvalidReportError> {
+        self.encrypt_with_aad_to(
+            key_id,
+            key_registry,
+            helper_origin,
+            ReportVersion::V1.aad(),
+            rng,
+            out,
+        )
+    }
+
+    fn encrypt_with_aad_to<R: CryptoRng + RngCore, B: BufMut>(
+        &self,
+        key_id: KeyIdentifier,
+        key_registry: &impl PublicKeyRegistry,
+        helper_origin: &str,
+        aad: &[u8],
         rng: &mut R,
         out: &mut B,
     ) -> Result<(), InvalidReportError> {
@@ -483,7 +688,7 @@ where
             key_id,
             self.epoch,
             self.event_type,
-            HELPER_ORIGIN,
+            helper_origin,
             self.site_domain.as_ref(),
         )?;
 
@@ -503,10 +708,10 @@ where
         ));
 
         let (encap_key_mk, ciphertext_mk, tag_mk) =
-            seal_in_place(key_registry, plaintext_mk.as_mut(), &info, rng)?;
+            seal_in_place(key_registry, plaintext_mk.as_mut(), &info, aad, rng)?;
 
         let (encap_key_btt, ciphertext_btt, tag_btt) =
-            seal_in_place(key_registry, plaintext_btt.as_mut(), &info, rng)?;
+            seal_in_place(key_registry, plaintext_btt.as_mut(), &info, aad, rng)?;
 
         out.put_slice(&encap_key_mk.to_bytes());
         out.put_slice(ciphertext_mk);
@@ -560,13 +765,209 @@ mod test {
         let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
         let key_id = 0;
 
-        let enc_report_bytes = report.encrypt(key_id, &key_registry, &mut rng).unwrap();
+        let enc_report_bytes = report
+            .encrypt(key_id, &key_registry, DEFAULT_HELPER_ORIGIN, &mut rng)
+            .unwrap();
         let enc_report = EncryptedOprfReport::from_bytes(enc_report_bytes.as_slice()).unwrap();
-        let dec_report: OprfReport<BA8, BA3, BA20> = enc_report.decrypt(&key_registry).unwrap();
+        let dec_report: OprfReport<BA8, BA3, BA20> = enc_report
+            .decrypt(&key_registry, DEFAULT_HELPER_ORIGIN)
+            .unwrap();
 
         assert_eq!(dec_report, report);
     }
 
+    #[test]
+    fn helper_origin_mismatch() {
+        let mut rng = thread_rng();
+
+        let report = OprfReport::<BA8, BA3, BA20> {
+            match_key: AdditiveShare::new(rng.gen(), rng.gen()),
+            timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
+            breakdown_key: AdditiveShare::new(rng.gen(), rng.gen()),
+            trigger_value: AdditiveShare::new(rng.gen(), rng.gen()),
+            event_type: Trigger,
+            epoch: rng.gen(),
+            site_domain: "www.example.com".to_string(),
+        };
+
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let enc_report_bytes = report
+            .encrypt(0, &key_registry, "helpers.example.com", &mut rng)
+            .unwrap();
+        let enc_report: EncryptedOprfReport<BA8, BA3, BA20, &[u8]> =
+            EncryptedOprfReport::from_bytes(enc_report_bytes.as_slice()).unwrap();
+
+        assert!(matches!(
+            enc_report.decrypt(&key_registry, DEFAULT_HELPER_ORIGIN),
+            Err(InvalidReportError::Crypt(_))
+        ));
+        assert!(matches!(
+            enc_report.decrypt(&key_registry, ""),
+            Err(InvalidReportError::Info(_))
+        ));
+        assert_eq!(
+            report,
+            enc_report
+                .decrypt(&key_registry, "helpers.example.com")
+                .unwrap()
+        );
+    }
+
+    fn random_report<R: Rng>(rng: &mut R) -> OprfReport<BA8, BA3, BA20> {
+        OprfReport {
+            match_key: AdditiveShare::new(rng.gen(), rng.gen()),
+            timestamp: AdditiveShare::new(rng.gen(), rng.gen()),
+            breakdown_key: AdditiveShare::new(rng.gen(), rng.gen()),
+            trigger_value: AdditiveShare::new(rng.gen(), rng.gen()),
+            event_type: if rng.gen::<bool>() { Trigger } else { Source },
+            epoch: rng.gen(),
+            site_domain: "www.example.com".to_string(),
+        }
+    }
+
+    fn versioned_encrypt<R: Rng + CryptoRng>(
+        report: &OprfReport<BA8, BA3, BA20>,
+        version: ReportVersion,
+        key_registry: &KeyRegistry<KeyPair>,
+        rng: &mut R,
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        report
+            .versioned_encrypt_to(
+                version,
+                0,
+                key_registry,
+                DEFAULT_HELPER_ORIGIN,
+                rng,
+                &mut out,
+            )
+            .unwrap();
+        assert_eq!(usize::from(report.versioned_encrypted_len()), out.len());
+
+        out
+    }
+
+    #[test]
+    fn versioned_roundtrip() {
+        let mut rng = thread_rng();
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+
+        for version in ReportVersion::SUPPORTED {
+            let report = random_report(&mut rng);
+            let bytes = versioned_encrypt(&report, version, &key_registry, &mut rng);
+            assert_eq!(u8::from(version), bytes[0]);
+
+            let enc_report = VersionedEncryptedOprfReport::from_bytes(Bytes::from(bytes)).unwrap();
+            assert_eq!(version, enc_report.version());
+            assert_eq!(
+                report,
+                enc_report
+                    .decrypt(&key_registry, DEFAULT_HELPER_ORIGIN)
+                    .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn v1_body_is_unversioned_format() {
+        let mut rng = thread_rng();
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let report = random_report(&mut rng);
+
+        let bytes = versioned_encrypt(&report, ReportVersion::V1, &key_registry, &mut rng);
+        let enc_report = EncryptedOprfReport::<BA8, BA3, BA20, _>::from_bytes(&bytes[1..]).unwrap();
+        assert_eq!(
+            report,
+            enc_report
+                .decrypt(&key_registry, DEFAULT_HELPER_ORIGIN)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn version_is_authenticated() {
+        let mut rng = thread_rng();
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let report = random_report(&mut rng);
+
+        let mut bytes = versioned_encrypt(&report, ReportVersion::V2, &key_registry, &mut rng);
+        bytes[0] = ReportVersion::V1.into();
+        let enc_report = VersionedEncryptedOprfReport::from_bytes(Bytes::from(bytes)).unwrap();
+        assert!(matches!(
+            enc_report.decrypt(&key_registry, DEFAULT_HELPER_ORIGIN),
+            Err(InvalidReportError::Crypt(_))
+        ));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut rng = thread_rng();
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let report = random_report(&mut rng);
+
+        let mut bytes = versioned_encrypt(&report, ReportVersion::LATEST, &key_registry, &mut rng);
+        bytes[0] = 42;
+        let err = VersionedEncryptedOprfReport::from_bytes(Bytes::from(bytes))
+            .err()
+            .unwrap();
+        assert!(matches!(err, InvalidReportError::UnsupportedVersion(42)));
+        assert_eq!(
+            "report version 42 is not supported, supported versions are: [1, 2]",
+            err.to_string()
+        );
+
+        assert!(matches!(
+            VersionedEncryptedOprfReport::from_bytes(Bytes::new()),
+            Err(InvalidReportError::Length(0, 1))
+        ));
+    }
+
+    #[test]
+    fn versioned_reports_are_not_downgraded() {
+        let mut rng = thread_rng();
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let report = random_report(&mut rng);
+
+        // without the prefix, a V2 report does not decrypt as an unversioned one
+        let bytes = versioned_encrypt(&report, ReportVersion::V2, &key_registry, &mut rng);
+        let enc_report = EncryptedOprfReport::<BA8, BA3, BA20, _>::from_bytes(&bytes[1..]).unwrap();
+        assert!(matches!(
+            enc_report.decrypt(&key_registry, DEFAULT_HELPER_ORIGIN),
+            Err(InvalidReportError::Crypt(_))
+        ));
+    }
+
+    #[test]
+    fn mixed_versions_in_one_stream() {
+        let mut rng = thread_rng();
+        let key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
+        let reports = (0..4).map(|_| random_report(&mut rng)).collect::<Vec<_>>();
+
+        let mut buf = Vec::new();
+        for (i, report) in reports.iter().enumerate() {
+            let version = ReportVersion::SUPPORTED[i % ReportVersion::SUPPORTED.len()];
+            let bytes = versioned_encrypt(report, version, &key_registry, &mut rng);
+            buf.put_u16_le(u16::try_from(bytes.len()).unwrap());
+            buf.extend_from_slice(&bytes);
+        }
+
+        let mut buf = Bytes::from(buf);
+        let mut decrypted = Vec::new();
+        while !buf.is_empty() {
+            let len = usize::from(u16::from_le_bytes([buf[0], buf[1]]));
+            let enc_report =
+                VersionedEncryptedOprfReport::from_bytes(buf.slice(2..2 + len)).unwrap();
+            decrypted.push(
+                enc_report
+                    .decrypt(&key_registry, DEFAULT_HELPER_ORIGIN)
+                    .unwrap(),
+            );
+            buf = buf.slice(2 + len..);
+        }
+
+        assert_eq!(reports, decrypted);
+    }
+
     #[test]
     fn test_decryption_fails() {
         let mut rng = thread_rng();
@@ -592,11 +993,16 @@ mod test {
         let dec_key_registry = KeyRegistry::<KeyPair>::random(1, &mut rng);
 
         let enc_report_bytes = report
-            .encrypt(enc_key_id, &enc_key_registry, &mut rng)
+            .encrypt(
+                enc_key_id,
+                &enc_key_registry,
+                DEFAULT_HELPER_ORIGIN,
+                &mut rng,
+            )
             .unwrap();
         let enc_report: report::EncryptedOprfReport<BA8, BA3, BA20, &[u8]> =
             EncryptedOprfReport::from_bytes(enc_report_bytes.as_slice()).unwrap();
-        let dec_report = enc_report.decrypt(&dec_key_registry);
+        let dec_report = enc_report.decrypt(&dec_key_registry, DEFAULT_HELPER_ORIGIN);
 
         assert!(dec_report.is_err());
     }
@@ -657,7 +1063,9 @@ mod test {
         ))]);
 
         let enc_report = EncryptedOprfReport::from_bytes(encrypted_report_bytes).unwrap();
-        let dec_report: OprfReport<BA8, BA3, BA20> = enc_report.decrypt(&key_registry1).unwrap();
+        let dec_report: OprfReport<BA8, BA3, BA20> = enc_report
+            .decrypt(&key_registry1, DEFAULT_HELPER_ORIGIN)
+            .unwrap();
 
         assert_eq!(dec_report.event_type, expected.event_type);
         assert_eq!(dec_report.epoch, expected.epoch);
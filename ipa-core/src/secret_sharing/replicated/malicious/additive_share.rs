@@ -79,6 +79,15 @@ impl ExtendableField for Gf2 {
     }
 }
 
+// `Gf32Bit` is large enough to be its own extension, the same way prime fields are.
+impl ExtendableField for Gf32Bit {
+    type ExtendedField = Self;
+
+    fn to_extended(&self) -> Self::ExtendedField {
+        *self
+    }
+}
+
 impl<V: ExtendableFieldSimd<N>, const N: usize> SecretSharing<V> for AdditiveShare<V, N> {
     const ZERO: Self = AdditiveShare::ZERO;
 }
@@ -1,23 +1,39 @@
+pub mod prometheus;
 pub mod stats;
 mod step_stats;
+pub mod trace;
 
-pub use step_stats::CsvExporter as StepStatsCsvExporter;
+pub use step_stats::{CsvExporter as StepStatsCsvExporter, CsvView as StepStatsCsvView};
 
 pub mod labels {
     pub use ::ipa_step::descriptive::labels::STEP;
     pub const ROLE: &str = "role";
+    pub const QUERY_ID: &str = "query_id";
+    pub const QUERY_TYPE: &str = "query_type";
+    /// How the query ended: `completed`, `failed` or `aborted`.
+    pub const OUTCOME: &str = "outcome";
+    /// Route template of the HTTP request, e.g. `/query/:query_id/status`.
+    pub const ROUTE: &str = "route";
+    pub const STATUS: &str = "status";
 }
 
 pub mod metrics {
-    use metrics::{describe_counter, Unit};
+    use metrics::{describe_counter, describe_gauge, describe_histogram, Unit};
 
     pub const REQUESTS_RECEIVED: &str = "requests.received";
     pub const RECORDS_SENT: &str = "records.sent";
     pub const BYTES_SENT: &str = "bytes.sent";
+    pub const STREAM_BYTES_RAW: &str = "stream.bytes.raw";
+    pub const STREAM_BYTES_COMPRESSED: &str = "stream.bytes.compressed";
     pub const INDEXED_PRSS_GENERATED: &str = "i.prss.gen";
     pub const SEQUENTIAL_PRSS_GENERATED: &str = "s.prss.gen";
     pub use ::ipa_step::descriptive::labels::STEP_NARROWED;
     pub const DZKP_BATCH_INCREMENTS: &str = "batch.realloc.front";
+    pub const QUERIES_STARTED: &str = "queries.started";
+    pub const QUERIES_FINISHED: &str = "queries.finished";
+    pub const QUERIES_RUNNING: &str = "queries.running";
+    pub const QUERY_DURATION: &str = "query.duration";
+    pub const HTTP_REQUEST_DURATION: &str = "http.request.duration";
 
     #[cfg(feature = "web-app")]
     pub mod web {
@@ -89,6 +105,18 @@ pub mod metrics {
             "Bytes sent from the infrastructure layer to the network"
         );
 
+        describe_counter!(
+            STREAM_BYTES_RAW,
+            Unit::Bytes,
+            "Bytes written to compressed streams between helpers, before compression"
+        );
+
+        describe_counter!(
+            STREAM_BYTES_COMPRESSED,
+            Unit::Bytes,
+            "Bytes written to compressed streams between helpers, after compression"
+        );
+
         describe_counter!(
             INDEXED_PRSS_GENERATED,
             Unit::Count,
@@ -112,5 +140,35 @@ pub mod metrics {
             Unit::Count,
             "Number of DZKP Batch updates, i.e. verifications"
         );
+
+        describe_counter!(
+            QUERIES_STARTED,
+            Unit::Count,
+            "Number of queries that started running on this helper"
+        );
+
+        describe_counter!(
+            QUERIES_FINISHED,
+            Unit::Count,
+            "Number of queries that finished running on this helper, by outcome"
+        );
+
+        describe_gauge!(
+            QUERIES_RUNNING,
+            Unit::Count,
+            "Number of queries currently running on this helper"
+        );
+
+        describe_histogram!(
+            QUERY_DURATION,
+            Unit::Seconds,
+            "Time from the start of query execution until it produced a result or was aborted"
+        );
+
+        describe_histogram!(
+            HTTP_REQUEST_DURATION,
+            Unit::Seconds,
+            "Time taken by the helper server to respond to HTTP requests"
+        );
     }
 }
@@ -0,0 +1,417 @@
+//! Metrics recorder that exports metrics in the [Prometheus text format].
+//!
+//! Handles returned to the `metrics` crate must be backed by `std::sync::Arc`, so unlike the rest
+//! of the crate this module does not use the shuttle-aware primitives from `crate::sync`.
+//!
+//! [Prometheus text format]: https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use dashmap::DashMap;
+use metrics::{
+    Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Label, Recorder, SetRecorderError,
+    SharedString, Unit,
+};
+
+use crate::telemetry::{labels, metrics::register};
+
+/// Prefix added to all exported metric names.
+const NAMESPACE: &str = "ipa";
+
+/// Upper bounds of histogram buckets, in seconds. They need to cover both HTTP requests that
+/// take milliseconds and queries that run for hours.
+const BUCKETS: [f64; 16] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0,
+];
+
+/// Controls which high-cardinality labels are kept on exported metrics. If a label is turned off,
+/// series that only differ by its value are aggregated together.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PrometheusConfig {
+    /// Keep the [`labels::STEP`] label. IPA protocol has thousands of steps, so turning this on
+    /// multiplies the number of exported series accordingly.
+    pub per_step: bool,
+    /// Keep the [`labels::QUERY_ID`] label.
+    pub per_query: bool,
+}
+
+/// Recorder that keeps the current value of every metric in memory. Use [`PrometheusHandle`] to
+/// render them.
+pub struct PrometheusRecorder {
+    registry: Arc<Registry>,
+}
+
+/// Renders the metrics collected by [`PrometheusRecorder`].
+#[derive(Clone)]
+pub struct PrometheusHandle {
+    registry: Arc<Registry>,
+}
+
+struct Registry {
+    config: PrometheusConfig,
+    descriptions: DashMap<KeyName, SharedString>,
+    counters: Values<AtomicU64>,
+    /// `f64` values are stored as bits, the same way `metrics` does it for atomic gauges.
+    gauges: Values<AtomicU64>,
+    histograms: Values<Buckets>,
+}
+
+/// Values of one kind of metric.
+#[derive(Default)]
+struct Values<V> {
+    /// Values keyed by the labels that are exported.
+    exported: DashMap<Key, Arc<V>>,
+    /// Values of keys that have labels turned off, keyed by the key they are registered with.
+    /// Most metrics are registered on every emission, so this saves building the exported key
+    /// each time. Steps and query ids are bounded, and so is this map.
+    registered: DashMap<Key, Arc<V>>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    state: Mutex<BucketsState>,
+}
+
+#[derive(Default)]
+struct BucketsState {
+    /// Number of observations that fall into each bucket, not cumulative.
+    counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl HistogramFn for Buckets {
+    fn record(&self, value: f64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(i) = BUCKETS.iter().position(|&le| value <= le) {
+            state.counts[i] += 1;
+        }
+        state.sum += value;
+        state.count += 1;
+    }
+}
+
+impl Registry {
+    /// Returns `false` for labels that are turned off in the config.
+    fn keep(&self, label: &Label) -> bool {
+        match label.key() {
+            labels::STEP => self.config.per_step,
+            labels::QUERY_ID => self.config.per_query,
+            _ => true,
+        }
+    }
+
+    /// Returns the value of the series that `key` is exported as, with the labels that are
+    /// turned off removed.
+    fn get_or_create<V: Default>(&self, values: &Values<V>, key: &Key) -> Arc<V> {
+        if key.labels().all(|label| self.keep(label)) {
+            return get_or_create(&values.exported, key);
+        }
+        if let Some(v) = values.registered.get(key) {
+            return Arc::clone(v.value());
+        }
+
+        let exported = Key::from_parts(
+            key.name().to_owned(),
+            key.labels()
+                .filter(|&label| self.keep(label))
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        let value = get_or_create(&values.exported, &exported);
+        Arc::clone(
+            values
+                .registered
+                .entry(key.clone())
+                .or_insert(value)
+                .value(),
+        )
+    }
+
+    fn write_header(&self, out: &mut String, key_name: &str, name: &str, kind: &str) {
+        if let Some(description) = self.descriptions.get(&KeyName::from(key_name.to_owned())) {
+            let help = description.replace('\\', r"\\").replace('\n', r"\n");
+            writeln!(out, "# HELP {name} {help}").unwrap();
+        }
+        writeln!(out, "# TYPE {name} {kind}").unwrap();
+    }
+}
+
+fn get_or_create<V: Default>(map: &DashMap<Key, Arc<V>>, key: &Key) -> Arc<V> {
+    // Most metrics are updated via macros that register them every time, so check for
+    // an existing entry before taking the write lock.
+    if let Some(v) = map.get(key) {
+        return Arc::clone(v.value());
+    }
+    Arc::clone(map.entry(key.clone()).or_default().value())
+}
+
+type Series<V> = BTreeMap<String, (Vec<Label>, Arc<V>)>;
+
+/// Groups series by metric name and keys them by formatted labels. Both are sorted to keep
+/// the output stable.
+fn group<V>(map: &DashMap<Key, Arc<V>>) -> BTreeMap<String, Series<V>> {
+    let mut families = BTreeMap::<String, Series<V>>::new();
+    for entry in map {
+        let labels = entry.key().labels().cloned().collect::<Vec<_>>();
+        families
+            .entry(entry.key().name().to_owned())
+            .or_default()
+            .insert(
+                format_labels(&labels, None),
+                (labels, Arc::clone(entry.value())),
+            );
+    }
+    families
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn metric_name(name: &str) -> String {
+    format!("{NAMESPACE}_{}", sanitize(name))
+}
+
+/// Formats labels as `{name="value",...}`, or an empty string if there are none. `extra` is
+/// appended at the end, it is used for the `le` label of histogram buckets.
+fn format_labels(labels: &[Label], extra: Option<(&str, &str)>) -> String {
+    let labels = labels
+        .iter()
+        .map(|label| (sanitize(label.key()), label.value()))
+        .chain(extra.map(|(k, v)| (k.to_owned(), v)))
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', r"\\")
+                .replace('"', r#"\""#)
+                .replace('\n', r"\n");
+            format!("{k}=\"{v}\"")
+        })
+        .collect::<Vec<_>>();
+
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+impl PrometheusRecorder {
+    #[must_use]
+    pub fn new(config: PrometheusConfig) -> Self {
+        Self {
+            registry: Arc::new(Registry {
+                config,
+                descriptions: DashMap::default(),
+                counters: Values::default(),
+                gauges: Values::default(),
+                histograms: Values::default(),
+            }),
+        }
+    }
+
+    #[must_use]
+    pub fn handle(&self) -> PrometheusHandle {
+        PrometheusHandle {
+            registry: Arc::clone(&self.registry),
+        }
+    }
+
+    /// Installs this recorder as the global metrics recorder and registers IPA metrics with it.
+    ///
+    /// ## Errors
+    /// If a metrics recorder has already been installed.
+    pub fn install(self) -> Result<PrometheusHandle, SetRecorderError> {
+        let handle = self.handle();
+        metrics::set_boxed_recorder(Box::new(self))?;
+        register();
+
+        Ok(handle)
+    }
+}
+
+impl Recorder for PrometheusRecorder {
+    fn describe_counter(&self, key: KeyName, _unit: Option<Unit>, description: SharedString) {
+        self.registry.descriptions.insert(key, description);
+    }
+
+    fn describe_gauge(&self, key: KeyName, _unit: Option<Unit>, description: SharedString) {
+        self.registry.descriptions.insert(key, description);
+    }
+
+    fn describe_histogram(&self, key: KeyName, _unit: Option<Unit>, description: SharedString) {
+        self.registry.descriptions.insert(key, description);
+    }
+
+    fn register_counter(&self, key: &Key) -> Counter {
+        Counter::from_arc(self.registry.get_or_create(&self.registry.counters, key))
+    }
+
+    fn register_gauge(&self, key: &Key) -> Gauge {
+        Gauge::from_arc(self.registry.get_or_create(&self.registry.gauges, key))
+    }
+
+    fn register_histogram(&self, key: &Key) -> Histogram {
+        Histogram::from_arc(self.registry.get_or_create(&self.registry.histograms, key))
+    }
+}
+
+impl PrometheusHandle {
+    /// Renders all metrics recorded so far in the Prometheus text format.
+    ///
+    /// ## Panics
+    /// If a histogram mutex is poisoned.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let registry = &self.registry;
+        let mut out = String::new();
+
+        for (key_name, series) in group(&registry.counters.exported) {
+            let name = format!("{}_total", metric_name(&key_name));
+            registry.write_header(&mut out, &key_name, &name, "counter");
+            for (labels, (_, value)) in series {
+                writeln!(out, "{name}{labels} {}", value.load(Ordering::Relaxed)).unwrap();
+            }
+        }
+
+        for (key_name, series) in group(&registry.gauges.exported) {
+            let name = metric_name(&key_name);
+            registry.write_header(&mut out, &key_name, &name, "gauge");
+            for (labels, (_, value)) in series {
+                let value = f64::from_bits(value.load(Ordering::Relaxed));
+                writeln!(out, "{name}{labels} {value}").unwrap();
+            }
+        }
+
+        for (key_name, series) in group(&registry.histograms.exported) {
+            let name = metric_name(&key_name);
+            registry.write_header(&mut out, &key_name, &name, "histogram");
+            for (labels, (label_set, buckets)) in series {
+                let state = buckets.state.lock().unwrap();
+                let mut cumulative = 0;
+                for (le, count) in BUCKETS.iter().zip(state.counts) {
+                    cumulative += count;
+                    let labels = format_labels(&label_set, Some(("le", &le.to_string())));
+                    writeln!(out, "{name}_bucket{labels} {cumulative}").unwrap();
+                }
+                let inf = format_labels(&label_set, Some(("le", "+Inf")));
+                writeln!(out, "{name}_bucket{inf} {}", state.count).unwrap();
+                writeln!(out, "{name}_sum{labels} {}", state.sum).unwrap();
+                writeln!(out, "{name}_count{labels} {}", state.count).unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use metrics::{Key, Label, Recorder, SharedString};
+
+    use super::{PrometheusConfig, PrometheusRecorder};
+    use crate::telemetry::{
+        labels::{QUERY_ID, ROLE, STEP},
+        metrics::{QUERIES_RUNNING, QUERY_DURATION, RECORDS_SENT},
+    };
+
+    fn key(name: &'static str, labels: &[(&'static str, &'static str)]) -> Key {
+        Key::from_parts(
+            name,
+            labels
+                .iter()
+                .map(|&(k, v)| Label::new(k, v))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn counters_and_gauges() {
+        let recorder = PrometheusRecorder::new(PrometheusConfig {
+            per_step: true,
+            per_query: true,
+        });
+        recorder.describe_counter(
+            RECORDS_SENT.into(),
+            None,
+            SharedString::from("Records sent"),
+        );
+        recorder
+            .register_counter(&key(RECORDS_SENT, &[(STEP, "/a"), (ROLE, "H1")]))
+            .increment(2);
+        recorder
+            .register_counter(&key(RECORDS_SENT, &[(STEP, "/a"), (ROLE, "H1")]))
+            .increment(3);
+        recorder
+            .register_gauge(&key(QUERIES_RUNNING, &[(QUERY_ID, "0")]))
+            .increment(1.0);
+
+        assert_eq!(
+            "# HELP ipa_records_sent_total Records sent\n\
+             # TYPE ipa_records_sent_total counter\n\
+             ipa_records_sent_total{step=\"/a\",role=\"H1\"} 5\n\
+             # TYPE ipa_queries_running gauge\n\
+             ipa_queries_running{query_id=\"0\"} 1\n",
+            recorder.handle().render()
+        );
+    }
+
+    #[test]
+    fn drops_high_cardinality_labels() {
+        let recorder = PrometheusRecorder::new(PrometheusConfig::default());
+        for step in ["/a", "/b"] {
+            recorder
+                .register_counter(&key(RECORDS_SENT, &[(STEP, step), (ROLE, "H2")]))
+                .increment(1);
+        }
+        recorder
+            .register_gauge(&key(QUERIES_RUNNING, &[(QUERY_ID, "0")]))
+            .increment(1.0);
+
+        // registering again reuses the exported key built the first time
+        recorder
+            .register_counter(&key(RECORDS_SENT, &[(STEP, "/a"), (ROLE, "H2")]))
+            .increment(1);
+        assert_eq!(2, recorder.registry.counters.registered.len());
+        assert_eq!(1, recorder.registry.counters.exported.len());
+
+        let rendered = recorder.handle().render();
+        assert!(rendered.contains("ipa_records_sent_total{role=\"H2\"} 3\n"));
+        assert!(rendered.contains("ipa_queries_running 1\n"));
+        assert!(!rendered.contains("step="));
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let recorder = PrometheusRecorder::new(PrometheusConfig::default());
+        let histogram = recorder.register_histogram(&key(QUERY_DURATION, &[(ROLE, "H3")]));
+        histogram.record(0.25);
+        histogram.record(7.0);
+        histogram.record(10_000.0);
+
+        let rendered = recorder.handle().render();
+        for line in [
+            "ipa_query_duration_bucket{role=\"H3\",le=\"0.1\"} 0\n",
+            "ipa_query_duration_bucket{role=\"H3\",le=\"0.25\"} 1\n",
+            "ipa_query_duration_bucket{role=\"H3\",le=\"10\"} 2\n",
+            "ipa_query_duration_bucket{role=\"H3\",le=\"3600\"} 2\n",
+            "ipa_query_duration_bucket{role=\"H3\",le=\"+Inf\"} 3\n",
+            "ipa_query_duration_sum{role=\"H3\"} 10007.25\n",
+            "ipa_query_duration_count{role=\"H3\"} 3\n",
+        ] {
+            assert!(
+                rendered.contains(line),
+                "{line} is missing from\n{rendered}"
+            );
+        }
+    }
+}
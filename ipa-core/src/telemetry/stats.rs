@@ -11,11 +11,22 @@ use metrics_util::{
 
 use crate::{helpers::Role, protocol::Gate, telemetry::labels};
 
+/// Identifies a step executed by one of the helpers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StepRole {
+    pub step: String,
+    /// `None` for counters emitted without the role dimension, e.g. step narrowing.
+    pub role: Option<String>,
+}
+
 /// Simple counter stats
 #[derive(Debug, Default)]
 pub struct CounterDetails {
     pub total_value: u64,
     pub dimensions: HashMap<SharedString, HashMap<SharedString, u64>>,
+    /// Counter values keyed by both step and role. Unlike `dimensions`, this keeps the
+    /// values for different roles of the same step apart.
+    pub steps: HashMap<StepRole, u64>,
 }
 
 /// Container for metrics, their descriptions and values they've accumulated.
@@ -43,6 +54,19 @@ impl CounterDetails {
         let DebugValue::Counter(val) = val else {
             unreachable!()
         };
+        let mut step = None;
+        let mut role = None;
+        for label in key.key().labels() {
+            match label.key() {
+                labels::STEP => step = Some(label.value().to_owned()),
+                labels::ROLE => role = Some(label.value().to_owned()),
+                _ => {}
+            }
+        }
+        if let Some(step) = step {
+            *self.steps.entry(StepRole { step, role }).or_insert(0) += val;
+        }
+
         for label in key.key().labels() {
             let (label_key, label_val) = label.clone().into_parts();
             let dimension_values = self.dimensions.entry(label_key).or_default();
@@ -53,6 +77,16 @@ impl CounterDetails {
         self.total_value += val;
     }
 
+    /// Returns the counter value for the given step, summed over all roles if `role` is `None`.
+    #[must_use]
+    pub fn get_step(&self, step: &str, role: Option<&str>) -> u64 {
+        self.steps
+            .iter()
+            .filter(|(k, _)| k.step == step && (role.is_none() || k.role.as_deref() == role))
+            .map(|(_, v)| v)
+            .sum()
+    }
+
     #[must_use]
     pub fn iter(&self) -> Iter<'_, SharedString, HashMap<SharedString, u64>> {
         self.dimensions.iter()
@@ -198,6 +232,26 @@ impl<'a> MetricAssertion<'a> {
         self.clone()
     }
 
+    /// Validates metric value for the given step, as seen by one helper.
+    /// ## Panics
+    /// Panics if value is not equal to expected
+    pub fn per_step_and_helper<I: TryInto<u64>>(
+        &self,
+        gate: &Gate,
+        role: &Role,
+        expected: I,
+    ) -> Self {
+        let actual = self.snapshot.get_step(gate.as_ref(), Some(role.as_ref()));
+        let expected = expected.try_into().ok().unwrap();
+
+        assert_eq!(
+            expected, actual,
+            "expected {} to be {expected} at {gate:?} for {role:?}, got {actual}",
+            self.name
+        );
+        self.clone()
+    }
+
     /// Validates metric value per helper dimension.
     /// ## Panics
     /// Panics if value is not equal to expected
@@ -1,5 +1,6 @@
 //!
-//! Export metrics collected during protocol run in CSV format. Metrics are partitioned by step.
+//! Export metrics collected during protocol run in CSV format. Metrics are partitioned by step
+//! and, optionally, by the role of the helper that emitted them.
 
 use std::{
     collections::{BTreeMap, HashMap},
@@ -8,47 +9,72 @@ use std::{
 };
 
 use crate::telemetry::{
-    labels,
     metrics::{
         BYTES_SENT, INDEXED_PRSS_GENERATED, RECORDS_SENT, SEQUENTIAL_PRSS_GENERATED, STEP_NARROWED,
     },
     stats::Metrics,
 };
 
+/// Controls how rows are partitioned in the CSV output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CsvView {
+    /// One row per step, values are summed over all helpers.
+    #[default]
+    Aggregated,
+    /// One row per step and role. Useful to spot imbalanced communication between helpers.
+    /// Counters emitted without the role dimension are reported with an empty role.
+    PerRole,
+}
+
 pub trait CsvExporter {
-    /// Writes the serialized version of this instance into the provided writer in CSV format.
+    /// Writes the serialized version of this instance into the provided writer in CSV format,
+    /// aggregated over all roles.
+    ///
+    /// ## Errors
+    /// Returns an error if an IO error occurs while writing to `W`.
+    fn export<W: io::Write>(&self, w: &mut W) -> Result<(), io::Error> {
+        self.export_view(w, CsvView::Aggregated)
+    }
+
+    /// Same as [`Self::export`], but lets the caller choose how rows are partitioned.
     ///
     /// ## Errors
     /// Returns an error if an IO error occurs while writing to `W`.
-    fn export<W: io::Write>(&self, w: &mut W) -> Result<(), io::Error>;
+    fn export_view<W: io::Write>(&self, w: &mut W, view: CsvView) -> Result<(), io::Error>;
 }
 
 impl CsvExporter for Metrics {
-    fn export<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+    fn export_view<W: Write>(&self, w: &mut W, view: CsvView) -> Result<(), Error> {
         // first thing is to collect all the steps and metrics emitted
         let mut steps_stats = StepsStats::default();
         for (counter_name, details) in &self.counters {
-            if let Some(steps) = details.dimensions.get(labels::STEP) {
-                for (step, val) in steps {
-                    steps_stats.offer(step, counter_name.as_str(), *val);
-                }
+            for (key, val) in &details.steps {
+                let role = match view {
+                    CsvView::Aggregated => None,
+                    CsvView::PerRole => key.role.as_deref(),
+                };
+                steps_stats.offer((&key.step, role), counter_name.as_str(), *val);
             }
         }
 
         // then dump them to the provided Write interface
-        // TODO: include role dimension. That requires rethinking `Metrics` implementation
-        // because it does not allow such breakdown atm.
+        let role_column = view == CsvView::PerRole;
         if self.print_header {
             writeln!(
                 w,
-                "Step,Records sent,Bytes sent,Indexed PRSS,Sequential PRSS,Step narrowed"
+                "Step,{}Records sent,Bytes sent,Indexed PRSS,Sequential PRSS,Step narrowed",
+                if role_column { "Role," } else { "" }
             )?;
         }
-        for (step, stats) in steps_stats.all_steps() {
+        for ((step, role), stats) in steps_stats.all_steps() {
+            if role_column {
+                write!(w, "{step},{},", role.unwrap_or_default())?;
+            } else {
+                write!(w, "{step},")?;
+            }
             writeln!(
                 w,
-                "{},{},{},{},{},{}",
-                step,
+                "{},{},{},{},{}",
                 stats.get(RECORDS_SENT),
                 stats.get(BYTES_SENT),
                 stats.get(INDEXED_PRSS_GENERATED),
@@ -61,23 +87,20 @@ impl CsvExporter for Metrics {
     }
 }
 
+/// Step name and, if stats are partitioned by role, the role.
+type StepKey<'a> = (&'a str, Option<&'a str>);
+
 #[derive(Default)]
 struct StepsStats<'a> {
-    inner: BTreeMap<&'a str, StepStats<'a>>,
+    inner: BTreeMap<StepKey<'a>, StepStats<'a>>,
 }
 
 impl<'a> StepsStats<'a> {
-    pub fn offer(&mut self, step_name: &'a str, metric: &'a str, val: u64) {
-        if let Some(step_stats) = self.inner.get_mut(step_name) {
-            step_stats.offer(metric, val);
-        } else {
-            let mut step_stats = StepStats::default();
-            step_stats.offer(metric, val);
-            self.inner.insert(step_name, step_stats);
-        }
+    pub fn offer(&mut self, step: StepKey<'a>, metric: &'a str, val: u64) {
+        self.inner.entry(step).or_default().offer(metric, val);
     }
 
-    pub fn all_steps(&'a self) -> impl Iterator<Item = (&'a str, &'a StepStats<'a>)> {
+    pub fn all_steps(&'a self) -> impl Iterator<Item = (StepKey<'a>, &'a StepStats<'a>)> {
         self.inner.iter().map(|(k, v)| (*k, v))
     }
 }
@@ -89,14 +112,76 @@ struct StepStats<'a> {
 
 impl<'a> StepStats<'a> {
     fn offer(&mut self, metric_name: &'a str, val: u64) {
-        if let Some(v) = self.inner.get_mut(metric_name) {
-            *v += val;
-        } else {
-            self.inner.insert(metric_name, val);
-        }
+        *self.inner.entry(metric_name).or_insert(0) += val;
     }
 
     fn get(&'a self, metric_name: &'a str) -> u64 {
         *self.inner.get(metric_name).unwrap_or(&0)
     }
 }
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use metrics::{Key, Label, Recorder};
+    use metrics_util::debugging::DebuggingRecorder;
+
+    use super::{CsvExporter, CsvView};
+    use crate::telemetry::{
+        labels::{ROLE, STEP},
+        metrics::{BYTES_SENT, RECORDS_SENT, STEP_NARROWED},
+        stats::Metrics,
+    };
+
+    fn metrics() -> Metrics {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let emit = |name: &'static str, labels: Vec<Label>, val: u64| {
+            recorder
+                .register_counter(&Key::from_parts(name, labels))
+                .increment(val);
+        };
+        for (role, records) in [("H1", 3), ("H2", 1)] {
+            emit(
+                RECORDS_SENT,
+                vec![Label::new(STEP, "/a"), Label::new(ROLE, role)],
+                records,
+            );
+            emit(
+                BYTES_SENT,
+                vec![Label::new(STEP, "/a"), Label::new(ROLE, role)],
+                records * 4,
+            );
+        }
+        emit(STEP_NARROWED, vec![Label::new(STEP, "/a")], 1);
+
+        let mut metrics = Metrics::from_snapshot(snapshotter.snapshot());
+        metrics.print_header = true;
+        metrics
+    }
+
+    fn export(view: CsvView) -> String {
+        let mut buf = Vec::new();
+        metrics().export_view(&mut buf, view).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn aggregated() {
+        assert_eq!(
+            "Step,Records sent,Bytes sent,Indexed PRSS,Sequential PRSS,Step narrowed\n\
+             /a,4,16,0,0,1\n",
+            export(CsvView::Aggregated)
+        );
+    }
+
+    #[test]
+    fn per_role() {
+        assert_eq!(
+            "Step,Role,Records sent,Bytes sent,Indexed PRSS,Sequential PRSS,Step narrowed\n\
+             /a,,0,0,0,0,1\n\
+             /a,H1,3,12,0,0,0\n\
+             /a,H2,1,4,0,0,0\n",
+            export(CsvView::PerRole)
+        );
+    }
+}
@@ -0,0 +1,547 @@
+//! Distributed tracing for queries that span multiple helpers.
+//!
+//! Trace context is propagated between helpers using the [W3C `traceparent`] header. Spans that
+//! belong to a trace are exported in [OTLP JSON] format by [`SpanExporter`], either to a local
+//! file or to an OpenTelemetry collector.
+//!
+//! A span becomes a part of a trace if
+//! * it has a `traceparent` field. If the field value is a valid `traceparent`, the span is
+//!   a child of the remote span it refers to, otherwise it starts a new trace.
+//! * or its parent span is a part of a trace.
+//!
+//! Spans may also set `otel.name` to override the span name and `otel.kind` to `server` or
+//! `client`. Other fields are exported as span attributes.
+//!
+//! [W3C `traceparent`]: https://www.w3.org/TR/trace-context/#traceparent-header
+//! [OTLP JSON]: https://opentelemetry.io/docs/specs/otlp/#json-protobuf-encoding
+
+use std::{
+    fmt::{Debug, Display, Formatter},
+    path::PathBuf,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{
+    layer::Context,
+    registry::{LookupSpan, Registry},
+    Layer,
+};
+
+/// Name of the HTTP header that carries trace context.
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Target of the spans created for distributed tracing. These spans are not printed to the log.
+pub const SPAN_TARGET: &str = "ipa::trace";
+
+/// Upper bound on the number of spans sent in one export request.
+const MAX_BATCH: usize = 512;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceId([u8; 16]);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpanId([u8; 8]);
+
+impl TraceId {
+    fn random() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl SpanId {
+    fn random() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl Display for TraceId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl Debug for TraceId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TraceId({self})")
+    }
+}
+
+impl Display for SpanId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl Debug for SpanId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SpanId({self})")
+    }
+}
+
+/// Identifies a span within a distributed trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: TraceId,
+    pub span_id: SpanId,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid traceparent value: {0}")]
+pub struct TraceParentError(String);
+
+impl TraceContext {
+    /// Returns the trace context of the current span, if it is a part of a trace that is being
+    /// exported by [`SpanExporter`].
+    #[must_use]
+    pub fn current() -> Option<Self> {
+        tracing::Span::current()
+            .with_subscriber(|(id, dispatch)| {
+                let registry = dispatch.downcast_ref::<Registry>()?;
+                let span = registry.span(id)?;
+                let extensions = span.extensions();
+                extensions.get::<SpanData>().map(SpanData::context)
+            })
+            .flatten()
+    }
+}
+
+/// Formats the context as a `traceparent` header value. Spans are always sampled.
+impl Display for TraceContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "00-{}-{}-01", self.trace_id, self.span_id)
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = TraceParentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || TraceParentError(s.to_owned());
+        let mut parts = s.trim().split('-');
+        let (Some(version), Some(trace_id), Some(span_id), Some(_flags)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(err());
+        };
+        // Future versions may add more fields, but must keep these ones.
+        if version == "ff" || (version == "00" && parts.next().is_some()) {
+            return Err(err());
+        }
+
+        let mut trace = [0u8; 16];
+        let mut span = [0u8; 8];
+        hex::decode_to_slice(trace_id, &mut trace).map_err(|_| err())?;
+        hex::decode_to_slice(span_id, &mut span).map_err(|_| err())?;
+        if trace == [0; 16] || span == [0; 8] {
+            return Err(err());
+        }
+
+        Ok(Self {
+            trace_id: TraceId(trace),
+            span_id: SpanId(span),
+        })
+    }
+}
+
+/// Data attached to spans that are a part of a trace.
+struct SpanData {
+    trace_id: TraceId,
+    span_id: SpanId,
+    parent_span_id: Option<SpanId>,
+    name: String,
+    kind: u8,
+    attributes: Vec<(&'static str, Value)>,
+    start: SystemTime,
+}
+
+impl SpanData {
+    fn context(&self) -> TraceContext {
+        TraceContext {
+            trace_id: self.trace_id,
+            span_id: self.span_id,
+        }
+    }
+}
+
+/// Span that has been closed and is ready to be exported.
+#[derive(Debug)]
+struct FinishedSpan {
+    context: TraceContext,
+    parent_span_id: Option<SpanId>,
+    name: String,
+    kind: u8,
+    attributes: Vec<(&'static str, Value)>,
+    start: SystemTime,
+    end: SystemTime,
+}
+
+impl FinishedSpan {
+    fn to_json(&self) -> Value {
+        let nanos = |t: SystemTime| {
+            t.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+                .to_string()
+        };
+        let mut span = json!({
+            "traceId": self.context.trace_id.to_string(),
+            "spanId": self.context.span_id.to_string(),
+            "name": self.name,
+            "kind": self.kind,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(self.end),
+            "attributes": self
+                .attributes
+                .iter()
+                .map(|(key, value)| attribute(key, value.clone()))
+                .collect::<Vec<_>>(),
+        });
+        if let Some(parent) = self.parent_span_id {
+            span["parentSpanId"] = parent.to_string().into();
+        }
+
+        span
+    }
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::Bool(v) => json!({ "boolValue": v }),
+        // 64-bit integers are encoded as strings in OTLP JSON
+        Value::Number(v) if v.is_f64() => json!({ "doubleValue": v }),
+        Value::Number(v) => json!({ "intValue": v.to_string() }),
+        Value::String(v) => json!({ "stringValue": v }),
+        v => json!({ "stringValue": v.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// Formats spans as OTLP `ExportTraceServiceRequest`.
+fn export_request(service_name: &str, spans: &[FinishedSpan]) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", service_name.into())],
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME") },
+                "spans": spans.iter().map(FinishedSpan::to_json).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+/// Collects span fields.
+#[derive(Default)]
+struct FieldVisitor {
+    traceparent: Option<String>,
+    name: Option<String>,
+    kind: Option<u8>,
+    attributes: Vec<(&'static str, Value)>,
+}
+
+impl FieldVisitor {
+    fn record(&mut self, field: &Field, value: Value) {
+        match (field.name(), value) {
+            ("traceparent", Value::String(v)) => self.traceparent = Some(v),
+            ("otel.name", Value::String(v)) => self.name = Some(v),
+            ("otel.kind", Value::String(v)) => {
+                self.kind = match v.as_str() {
+                    "server" => Some(2),
+                    "client" => Some(3),
+                    _ => None,
+                };
+            }
+            ("message", _) => {}
+            (name, value) => self.attributes.push((name, value)),
+        }
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.record(field, format!("{value:?}").into());
+    }
+}
+
+/// Where [`SpanExporter`] sends spans.
+#[derive(Clone, Debug)]
+pub enum SpanSink {
+    /// Appends spans to a file, one `ExportTraceServiceRequest` JSON object per line. This is the
+    /// format read by the `otlpjsonfile` receiver of OpenTelemetry collector.
+    File(PathBuf),
+    /// Sends spans to an OpenTelemetry collector using OTLP/HTTP with JSON encoding. The URL is
+    /// the base collector endpoint, `/v1/traces` is added to it.
+    #[cfg(feature = "web-app")]
+    Otlp(hyper::Uri),
+}
+
+impl SpanSink {
+    async fn export(&self, body: Vec<u8>) -> Result<(), crate::error::BoxError> {
+        match self {
+            Self::File(path) => {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || {
+                    use std::io::Write;
+
+                    let mut file = std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)?;
+                    file.write_all(&body)?;
+                    file.write_all(b"\n")
+                })
+                .await??;
+            }
+            #[cfg(feature = "web-app")]
+            Self::Otlp(endpoint) => {
+                use bytes::Bytes;
+                use http_body_util::Full;
+                use hyper::{header::CONTENT_TYPE, Request};
+                use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+
+                let uri = format!("{}/v1/traces", endpoint.to_string().trim_end_matches('/'));
+                let request = Request::post(uri)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Full::new(Bytes::from(body)))?;
+                let response = Client::builder(TokioExecutor::new())
+                    .build_http()
+                    .request(request)
+                    .await?;
+                if !response.status().is_success() {
+                    return Err(format!("collector responded with {}", response.status()).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Tracing layer that exports spans that belong to a distributed trace. See the module
+/// documentation for how spans are selected.
+pub struct SpanExporter {
+    tx: UnboundedSender<FinishedSpan>,
+}
+
+impl SpanExporter {
+    fn new() -> (Self, UnboundedReceiver<FinishedSpan>) {
+        let (tx, rx) = unbounded_channel();
+        (Self { tx }, rx)
+    }
+
+    /// Creates the layer and spawns a task that exports spans to `sink`. Spans are identified
+    /// by `service_name` in the exported data.
+    ///
+    /// This must be called from within a Tokio runtime.
+    #[must_use]
+    pub fn spawn(service_name: String, sink: SpanSink) -> (Self, JoinHandle<()>) {
+        let (this, mut rx) = Self::new();
+        let handle = tokio::spawn(async move {
+            while let Some(span) = rx.recv().await {
+                let mut batch = vec![span];
+                while batch.len() < MAX_BATCH {
+                    match rx.try_recv() {
+                        Ok(span) => batch.push(span),
+                        Err(_) => break,
+                    }
+                }
+                let body = export_request(&service_name, &batch)
+                    .to_string()
+                    .into_bytes();
+                if let Err(e) = sink.export(body).await {
+                    tracing::warn!("failed to export {} spans to {sink:?}: {e}", batch.len());
+                }
+            }
+        });
+
+        (this, handle)
+    }
+}
+
+impl<S> Layer<S> for SpanExporter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = FieldVisitor::default();
+        attrs.record(&mut fields);
+
+        let (trace_id, parent_span_id) = if let Some(traceparent) = &fields.traceparent {
+            match traceparent.parse::<TraceContext>() {
+                Ok(remote) => (remote.trace_id, Some(remote.span_id)),
+                Err(_) => (TraceId::random(), None),
+            }
+        } else if let Some(parent) = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<SpanData>().map(SpanData::context))
+        {
+            (parent.trace_id, Some(parent.span_id))
+        } else {
+            return;
+        };
+
+        let data = SpanData {
+            trace_id,
+            span_id: SpanId::random(),
+            parent_span_id,
+            name: fields.name.unwrap_or_else(|| span.name().to_owned()),
+            kind: fields.kind.unwrap_or(1),
+            attributes: fields.attributes,
+            start: SystemTime::now(),
+        };
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            let mut fields = FieldVisitor::default();
+            values.record(&mut fields);
+            if let Some(name) = fields.name {
+                data.name = name;
+            }
+            data.attributes.extend(fields.attributes);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        if let Some(data) = span.extensions_mut().remove::<SpanData>() {
+            // the receiver is gone only if the export task has been stopped
+            let _ = self.tx.send(FinishedSpan {
+                context: data.context(),
+                parent_span_id: data.parent_span_id,
+                name: data.name,
+                kind: data.kind,
+                attributes: data.attributes,
+                start: data.start,
+                end: SystemTime::now(),
+            });
+        }
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use tracing::subscriber::with_default;
+    use tracing_subscriber::{layer::SubscriberExt, registry};
+
+    use super::{export_request, SpanExporter, TraceContext, SPAN_TARGET};
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    #[test]
+    fn traceparent() {
+        let context = TRACEPARENT.parse::<TraceContext>().unwrap();
+        assert_eq!(
+            "0af7651916cd43dd8448eb211c80319c",
+            context.trace_id.to_string()
+        );
+        assert_eq!("b7ad6b7169203331", context.span_id.to_string());
+        assert_eq!(TRACEPARENT, context.to_string());
+
+        for invalid in [
+            "",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-00",
+            "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+            "00-0af7651916cd43dd8448eb211c80319x-b7ad6b7169203331-01",
+        ] {
+            assert!(invalid.parse::<TraceContext>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn spans_join_remote_trace() {
+        let (exporter, mut rx) = SpanExporter::new();
+        let remote = TRACEPARENT.parse::<TraceContext>().unwrap();
+
+        let (query, step) = with_default(registry().with(exporter), || {
+            // not a part of any trace
+            tracing::info_span!("unrelated").in_scope(|| assert_eq!(None, TraceContext::current()));
+
+            let query = tracing::info_span!(target: SPAN_TARGET, "query", traceparent = TRACEPARENT, query_id = "0");
+            query.in_scope(|| {
+                let query = TraceContext::current().unwrap();
+                let step =
+                    tracing::info_span!(target: SPAN_TARGET, "send", otel.name = "send /step");
+                let step = step.in_scope(|| TraceContext::current().unwrap());
+                (query, step)
+            })
+        });
+
+        assert_eq!(remote.trace_id, query.trace_id);
+        assert_eq!(remote.trace_id, step.trace_id);
+        assert_ne!(remote.span_id, query.span_id);
+        assert_ne!(query.span_id, step.span_id);
+
+        let step_span = rx.try_recv().unwrap();
+        assert_eq!("send /step", step_span.name);
+        assert_eq!(step, step_span.context);
+        assert_eq!(Some(query.span_id), step_span.parent_span_id);
+
+        let query_span = rx.try_recv().unwrap();
+        assert_eq!(Some(remote.span_id), query_span.parent_span_id);
+        let json = export_request("helper", &[query_span]);
+        let span = &json["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], remote.trace_id.to_string());
+        assert_eq!(span["parentSpanId"], remote.span_id.to_string());
+        assert_eq!(span["attributes"][0]["key"], "query_id");
+        assert_eq!(span["attributes"][0]["value"]["stringValue"], "0");
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn invalid_traceparent_starts_new_trace() {
+        let (exporter, mut rx) = SpanExporter::new();
+        with_default(registry().with(exporter), || {
+            tracing::info_span!("create", traceparent = "").in_scope(|| {});
+        });
+
+        let span = rx.try_recv().unwrap();
+        assert_eq!(None, span.parent_span_id);
+    }
+}
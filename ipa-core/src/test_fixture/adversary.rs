@@ -0,0 +1,695 @@
+//! Typed adversaries for [`TestWorld`].
+//!
+//! [`MaliciousHelper`] gives tests access to the raw bytes one helper sends to the others. The
+//! [`Adversary`] defined here is built on top of it and lets tests describe attacks in terms of
+//! the values carried by the streams: it decodes records into their [`Serializable`] type and
+//! adds deltas to them, drops them or swaps them with the next record. Attacks apply to records
+//! at gates matched by step name, optionally restricted to one destination helper and one record.
+//!
+//! [`sweep`] uses it to check that a malicious protocol rejects an attack on every stream the
+//! malicious helper sends. Streams carry values of different types, so [`Attacks`] picks the
+//! type and the change to make for each stream.
+//!
+//! Attacks operate on the data as it is sent, so [`GatewayConfig::compression`] must be left
+//! disabled for worlds with an adversary.
+//!
+//! [`MaliciousHelper`]: crate::helpers::in_memory_config::MaliciousHelper
+//! [`GatewayConfig::compression`]: crate::helpers::GatewayConfig::compression
+
+use std::{collections::HashMap, fmt::Debug, iter::zip};
+
+use futures::{future::BoxFuture, stream::FuturesUnordered, StreamExt};
+use generic_array::GenericArray;
+use rand::{rngs::StdRng, SeedableRng};
+use typenum::Unsigned;
+
+use crate::{
+    ff::Serializable,
+    helpers::{
+        in_memory_config::{DynStreamInterceptor, MaliciousHelper, MaliciousHelperContext},
+        Role, RoleAssignment,
+    },
+    protocol::{context::MaliciousContext, Gate, RecordId},
+    secret_sharing::{IntoShares, SharedValue},
+    sync::{Arc, Mutex},
+    test_fixture::{TestWorld, TestWorldConfig},
+};
+
+/// Selects the records an attack applies to.
+#[derive(Clone, Debug)]
+pub struct Records {
+    gates: Gates,
+    dest: Option<Role>,
+    record: Option<RecordId>,
+}
+
+#[derive(Clone, Debug)]
+enum Gates {
+    All,
+    Containing(String),
+    Exact(Gate),
+}
+
+impl Records {
+    /// Records sent at every gate.
+    #[must_use]
+    pub fn all() -> Self {
+        Self {
+            gates: Gates::All,
+            dest: None,
+            record: None,
+        }
+    }
+
+    /// Records sent at every gate that contains `step` in its name.
+    #[must_use]
+    pub fn at(step: &str) -> Self {
+        Self {
+            gates: Gates::Containing(step.to_owned()),
+            dest: None,
+            record: None,
+        }
+    }
+
+    /// Records sent at `gate` only.
+    #[must_use]
+    pub fn gate(gate: Gate) -> Self {
+        Self {
+            gates: Gates::Exact(gate),
+            dest: None,
+            record: None,
+        }
+    }
+
+    /// Only records sent to `dest`.
+    #[must_use]
+    pub fn sent_to(mut self, dest: Role) -> Self {
+        self.dest = Some(dest);
+        self
+    }
+
+    /// Only the record with the given id.
+    #[must_use]
+    pub fn record(mut self, record_id: RecordId) -> Self {
+        self.record = Some(record_id);
+        self
+    }
+
+    fn matches(&self, ctx: &MaliciousHelperContext) -> bool {
+        self.matches_stream(&ctx.gate, ctx.dest)
+    }
+
+    fn matches_stream(&self, gate: &Gate, dest: Role) -> bool {
+        let gate_matches = match &self.gates {
+            Gates::All => true,
+            Gates::Containing(step) => gate.as_ref().contains(step.as_str()),
+            Gates::Exact(exact) => gate == exact,
+        };
+
+        gate_matches && self.dest.map_or(true, |d| d == dest)
+    }
+}
+
+type Tamper = Box<dyn Fn(&mut [u8]) + Send + Sync>;
+
+enum Action {
+    Tamper(Tamper),
+    Drop,
+    Swap,
+}
+
+struct Attack {
+    records: Records,
+    record_size: usize,
+    action: Action,
+}
+
+impl Attack {
+    /// Applies this attack to `data`, which starts with the record at `offset` bytes into the
+    /// stream.
+    fn apply(&self, offset: usize, data: &mut Vec<u8>) {
+        let size = self.record_size;
+        assert!(
+            offset % size == 0 && data.len() % size == 0,
+            "{} bytes at offset {offset} are not whole {size}-byte records",
+            data.len()
+        );
+        let first = offset / size;
+        let selected = |i: usize| {
+            self.records
+                .record
+                .map_or(true, |record_id| usize::from(record_id) == first + i)
+        };
+
+        match &self.action {
+            Action::Tamper(tamper) => {
+                for (i, record) in data.chunks_exact_mut(size).enumerate() {
+                    if selected(i) {
+                        tamper(record);
+                    }
+                }
+            }
+            Action::Drop => {
+                let mut i = 0;
+                data.retain(|_| {
+                    let keep = !selected(i / size);
+                    i += 1;
+                    keep
+                });
+            }
+            Action::Swap => {
+                let mut i = 0;
+                while (i + 2) * size <= data.len() {
+                    if selected(i) {
+                        let (left, right) = data[i * size..(i + 2) * size].split_at_mut(size);
+                        left.swap_with_slice(right);
+                        i += 2;
+                    } else {
+                        i += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A helper that deviates from the protocol by tampering with the records it sends.
+///
+/// Attacks apply in the order they were added. Each stream must carry values of the type the
+/// attack was added with. Record ids are counted from the start of the stream, so attacks that
+/// drop records shift the ids seen by the attacks added after them.
+///
+/// Records are only swapped with the next record if both are sent in the same chunk. Dropping
+/// records truncates the stream, so the receiver fails when it gets to the end of it.
+pub struct Adversary {
+    role: Role,
+    attacks: Vec<Attack>,
+    offsets: Mutex<HashMap<(Gate, Role), usize>>,
+}
+
+impl Adversary {
+    #[must_use]
+    pub fn new(role: Role) -> Self {
+        Self {
+            role,
+            attacks: Vec::new(),
+            offsets: Mutex::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Adds `delta` to the selected records.
+    #[must_use]
+    pub fn add<V: SharedValue>(self, records: Records, delta: V) -> Self {
+        self.tamper(records, move |v: V| v + delta)
+    }
+
+    /// Replaces the selected records with the result of `f`.
+    ///
+    /// ## Panics
+    /// If a selected record can't be deserialized as `V`.
+    #[must_use]
+    pub fn tamper<V, F>(self, records: Records, f: F) -> Self
+    where
+        V: Serializable + 'static,
+        F: Fn(V) -> V + Send + Sync + 'static,
+    {
+        self.with_attack::<V>(
+            records,
+            Action::Tamper(Box::new(move |record| {
+                let buf = GenericArray::from_mut_slice(record);
+                let v = V::deserialize(buf)
+                    .unwrap_or_else(|e| panic!("adversary failed to deserialize a record: {e}"));
+                f(v).serialize(buf);
+            })),
+        )
+    }
+
+    /// Removes the selected records from the stream.
+    #[must_use]
+    pub fn drop_records<V: Serializable>(self, records: Records) -> Self {
+        self.with_attack::<V>(records, Action::Drop)
+    }
+
+    /// Swaps each selected record with the record that follows it.
+    #[must_use]
+    pub fn swap_records<V: Serializable>(self, records: Records) -> Self {
+        self.with_attack::<V>(records, Action::Swap)
+    }
+
+    fn with_attack<V: Serializable>(mut self, records: Records, action: Action) -> Self {
+        self.attacks.push(Attack {
+            records,
+            record_size: V::Size::USIZE,
+            action,
+        });
+        self
+    }
+
+    pub(super) fn into_interceptor(self, role_assignment: &RoleAssignment) -> DynStreamInterceptor {
+        MaliciousHelper::new(
+            self.role,
+            role_assignment,
+            move |ctx: &MaliciousHelperContext, data: &mut Vec<u8>| self.intercept(ctx, data),
+        )
+    }
+
+    fn intercept(&self, ctx: &MaliciousHelperContext, data: &mut Vec<u8>) {
+        let offset = {
+            let mut offsets = self.offsets.lock().unwrap();
+            let offset = offsets.entry((ctx.gate.clone(), ctx.dest)).or_default();
+            let current = *offset;
+            *offset += data.len();
+            current
+        };
+
+        for attack in &self.attacks {
+            if attack.records.matches(ctx) {
+                attack.apply(offset, data);
+            }
+        }
+    }
+}
+
+type AttackFn = Box<dyn Fn(Adversary, Records) -> Adversary>;
+
+/// The attacks [`sweep`] makes, by the streams they apply to.
+///
+/// Each stream is attacked with the first entry whose [`Records`] select it, so more specific
+/// entries must be added first. Entries decode the stream as the type they were added with.
+#[derive(Default)]
+pub struct Attacks {
+    entries: Vec<(Records, AttackFn)>,
+}
+
+impl Attacks {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `delta` to the first record of each stream selected by `records`.
+    #[must_use]
+    pub fn add<V: SharedValue>(self, records: Records, delta: V) -> Self {
+        self.with_entry(records, move |adversary, attacked| {
+            adversary.add(attacked, delta)
+        })
+    }
+
+    /// Replaces the first record of each stream selected by `records` with the result of `f`.
+    /// Use this for streams that carry values which can't be added to.
+    #[must_use]
+    pub fn tamper<V, F>(self, records: Records, f: F) -> Self
+    where
+        V: Serializable + 'static,
+        F: Fn(V) -> V + Clone + Send + Sync + 'static,
+    {
+        self.with_entry(records, move |adversary, attacked| {
+            adversary.tamper(attacked, f.clone())
+        })
+    }
+
+    fn with_entry<F>(mut self, records: Records, f: F) -> Self
+    where
+        F: Fn(Adversary, Records) -> Adversary + 'static,
+    {
+        self.entries.push((records, Box::new(f)));
+        self
+    }
+
+    fn find(&self, gate: &Gate, dest: Role) -> Option<&AttackFn> {
+        self.entries
+            .iter()
+            .find(|(records, _)| records.matches_stream(gate, dest))
+            .map(|(_, attack)| attack)
+    }
+}
+
+/// Checks that a malicious protocol rejects an attack on every stream sent by `role`.
+///
+/// The protocol is first run without an adversary to find the streams that `role` sends. Then
+/// it is run once per stream, with the first record of that stream changed by the matching entry
+/// in `attacks`. A run is rejected as soon as one helper returns an error; the other helpers are
+/// not expected to finish after that. The error must be one that `rejected` accepts, so that
+/// failures unrelated to the attack, such as transport errors, are not mistaken for detection.
+/// Panics in the helpers are not caught.
+///
+/// Returns the streams that were attacked, as gates and destination helpers.
+///
+/// ## Panics
+/// If the protocol fails without an adversary, if `attacks` has no entry for one of the streams,
+/// or if an attack is not rejected with an error that `rejected` accepts.
+pub async fn sweep<I, A, O, E, R, H>(
+    config: &TestWorldConfig,
+    role: Role,
+    attacks: &Attacks,
+    input: I,
+    rejected: R,
+    helper_fn: H,
+) -> Vec<(Gate, Role)>
+where
+    I: IntoShares<A> + Clone,
+    E: Debug,
+    R: Fn(&E) -> bool,
+    H: for<'a> Fn(MaliciousContext<'a>, A) -> BoxFuture<'a, Result<O, E>>,
+{
+    let streams = Arc::new(Mutex::new(Vec::new()));
+    let recorder = MaliciousHelper::new(role, config.role_assignment(), {
+        let streams = Arc::clone(&streams);
+        move |ctx: &MaliciousHelperContext, _data: &mut Vec<u8>| {
+            let stream = (ctx.gate.clone(), ctx.dest);
+            let mut streams = streams.lock().unwrap();
+            if !streams.contains(&stream) {
+                streams.push(stream);
+            }
+        }
+    });
+    let honest = TestWorldConfig {
+        stream_interceptor: recorder,
+        ..config.clone()
+    };
+    if let Some(e) = first_error(&honest, input.clone(), &helper_fn).await {
+        panic!("protocol failed without an adversary: {e:?}");
+    }
+
+    let streams = streams.lock().unwrap().clone();
+    let uncovered = streams
+        .iter()
+        .filter(|(gate, dest)| attacks.find(gate, *dest).is_none())
+        .collect::<Vec<_>>();
+    assert!(
+        uncovered.is_empty(),
+        "no attack declared for streams {uncovered:?}"
+    );
+
+    for (gate, dest) in &streams {
+        let attack = attacks.find(gate, *dest).unwrap();
+        let adversary = attack(
+            Adversary::new(role),
+            Records::gate(gate.clone())
+                .sent_to(*dest)
+                .record(RecordId::FIRST),
+        );
+        let config = config.clone().with_adversary(adversary);
+        match first_error(&config, input.clone(), &helper_fn).await {
+            Some(e) => assert!(
+                rejected(&e),
+                "{role:?} attacking {gate:?} on the stream to {dest:?} failed with unexpected error {e:?}"
+            ),
+            None => {
+                panic!("{role:?} attacking {gate:?} on the stream to {dest:?} was not detected")
+            }
+        }
+    }
+
+    streams
+}
+
+/// Runs the protocol on a new world and returns the first error returned by any helper, without
+/// waiting for the other helpers. Use it for protocols that are expected to fail, where helpers
+/// that did not detect the failure may never finish.
+pub async fn first_error<I, A, O, E, H>(
+    config: &TestWorldConfig,
+    input: I,
+    helper_fn: &H,
+) -> Option<E>
+where
+    I: IntoShares<A>,
+    H: for<'a> Fn(MaliciousContext<'a>, A) -> BoxFuture<'a, Result<O, E>>,
+{
+    let world = TestWorld::new_with(config);
+    // share the input the same way on every run, in case the protocol depends on it
+    let shares = input.share_with(&mut StdRng::seed_from_u64(config.seed));
+    let mut runs = zip(world.malicious_contexts(), shares)
+        .map(|(ctx, share)| helper_fn(ctx, share))
+        .collect::<FuturesUnordered<_>>();
+    while let Some(result) = runs.next().await {
+        if let Err(e) = result {
+            return Some(e);
+        }
+    }
+
+    None
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use futures::{
+        future::{join_all, try_join, try_join_all},
+        FutureExt,
+    };
+
+    use super::{sweep, Adversary, Attacks, Records};
+    use crate::{
+        error::Error,
+        ff::{boolean::Boolean, Field, Fp31, Fp32BitPrime, Fp61BitPrime, U128Conversions},
+        helpers::{
+            hashing::{compute_hash, Hash},
+            Direction, Role,
+        },
+        protocol::{
+            basics::{malicious_reveal, SecureMul},
+            context::{
+                dzkp_validator::DZKPValidator, Context, UpgradableContext, UpgradedContext,
+                Validator,
+            },
+            RecordId,
+        },
+        rand::{thread_rng, Rng},
+        test_executor::run,
+        test_fixture::{Runner, TestWorld, TestWorldConfig},
+    };
+
+    const STEP: &str = "attack";
+    const COUNT: usize = 3;
+
+    /// Every helper sends `0..COUNT` to the helper on its right. Returns what each helper
+    /// received, in role order.
+    async fn send_right(adversary: Adversary) -> [Vec<Option<Fp31>>; 3] {
+        let world = TestWorld::new_with(TestWorldConfig::default().with_adversary(adversary));
+        world
+            .semi_honest((), |ctx, ()| async move {
+                let ctx = ctx.narrow(STEP).set_total_records(COUNT);
+                let right = ctx.send_channel(ctx.role().peer(Direction::Right));
+                let left = ctx.recv_channel::<Fp31>(ctx.role().peer(Direction::Left));
+                let (_, received) = try_join(
+                    try_join_all(
+                        (0..COUNT)
+                            .map(|i| right.send(RecordId::from(i), Fp31::truncate_from(i as u128))),
+                    ),
+                    join_all((0..COUNT).map(|i| left.receive(RecordId::from(i)))).map(Ok),
+                )
+                .await
+                .unwrap();
+
+                received.into_iter().map(Result::ok).collect::<Vec<_>>()
+            })
+            .await
+    }
+
+    fn values(v: &[u128]) -> Vec<Option<Fp31>> {
+        v.iter().map(|&v| Some(Fp31::truncate_from(v))).collect()
+    }
+
+    #[test]
+    fn add() {
+        run(|| async {
+            let adversary = Adversary::new(Role::H1).add(
+                Records::at(STEP)
+                    .sent_to(Role::H2)
+                    .record(RecordId::from(1)),
+                Fp31::ONE,
+            );
+            let [h1, h2, h3] = send_right(adversary).await;
+
+            assert_eq!(values(&[0, 1, 2]), h1);
+            assert_eq!(values(&[0, 2, 2]), h2);
+            assert_eq!(values(&[0, 1, 2]), h3);
+        });
+    }
+
+    #[test]
+    fn only_matching_steps() {
+        run(|| async {
+            let adversary = Adversary::new(Role::H1).add(Records::at("other"), Fp31::ONE);
+
+            assert_eq!(
+                [values(&[0, 1, 2]), values(&[0, 1, 2]), values(&[0, 1, 2])],
+                send_right(adversary).await
+            );
+        });
+    }
+
+    #[test]
+    fn drop_records() {
+        run(|| async {
+            let adversary = Adversary::new(Role::H2)
+                .drop_records::<Fp31>(Records::at(STEP).record(RecordId::FIRST));
+            let [_, _, h3] = send_right(adversary).await;
+
+            assert_eq!(
+                vec![Some(Fp31::ONE), Some(Fp31::truncate_from(2_u128)), None],
+                h3
+            );
+        });
+    }
+
+    #[test]
+    fn swap_records() {
+        run(|| async {
+            let adversary = Adversary::new(Role::H3)
+                .swap_records::<Fp31>(Records::at(STEP).record(RecordId::FIRST));
+            let [h1, _, _] = send_right(adversary).await;
+
+            assert_eq!(values(&[1, 0, 2]), h1);
+        });
+    }
+
+    #[test]
+    fn mac_validation_rejects_every_attack() {
+        run(|| async {
+            let mut rng = thread_rng();
+            let a = rng.gen::<Fp32BitPrime>();
+            let b = rng.gen::<Fp32BitPrime>();
+
+            let streams = sweep(
+                &TestWorldConfig::default(),
+                Role::H3,
+                &Attacks::new().add(Records::all(), Fp32BitPrime::ONE),
+                (a, b),
+                |e| {
+                    matches!(
+                        e,
+                        Error::MaliciousSecurityCheckFailed | Error::MaliciousRevealFailed
+                    )
+                },
+                |ctx, (a, b)| {
+                    async move {
+                        let v = ctx.validator::<Fp32BitPrime>();
+                        let m_ctx = v.context();
+                        let (a, b) = m_ctx.upgrade((a, b)).await?;
+                        // multiply both ways, so the MACs of both inputs are checked
+                        let mul_ctx = m_ctx.narrow("mul").set_total_records(2);
+                        let (ab, ba) = try_join(
+                            a.multiply(&b, mul_ctx.clone(), RecordId::FIRST),
+                            b.multiply(&a, mul_ctx, RecordId::from(1)),
+                        )
+                        .await?;
+                        v.validate((ab, ba)).await
+                    }
+                    .boxed()
+                },
+            )
+            .await;
+
+            assert!(streams
+                .iter()
+                .any(|(gate, _)| gate.as_ref().contains("mul")));
+        });
+    }
+
+    #[test]
+    fn dzkp_validation_rejects_every_attack() {
+        run(|| async {
+            let mut rng = thread_rng();
+            let a = rng.gen::<Boolean>();
+            let b = rng.gen::<Boolean>();
+
+            // the multiplication sends booleans, verifiers exchange proof hashes to agree on
+            // challenges, and everything else in the proof is in `Fp61BitPrime`
+            let attacks = Attacks::new()
+                .add(Records::at("mul"), Boolean::ONE)
+                .tamper(Records::at("challenge"), |hash: Hash| compute_hash([&hash]))
+                .add(Records::all(), Fp61BitPrime::ONE);
+            let streams = sweep(
+                &TestWorldConfig::default(),
+                Role::H2,
+                &attacks,
+                (a, b),
+                |e| matches!(e, Error::DZKPValidationFailed),
+                |ctx, (a, b)| {
+                    async move {
+                        let v = ctx.dzkp_validator(1);
+                        let ab = a
+                            .multiply(
+                                &b,
+                                v.context().narrow("mul").set_total_records(1),
+                                RecordId::FIRST,
+                            )
+                            .await?;
+                        v.validate().await?;
+                        Ok::<_, Error>(ab)
+                    }
+                    .boxed()
+                },
+            )
+            .await;
+
+            for step in ["mul", "challenge", "verify_proof"] {
+                assert!(
+                    streams.iter().any(|(gate, _)| gate.as_ref().contains(step)),
+                    "no stream at {step}"
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn malicious_reveal_rejects_every_attack() {
+        run(|| async {
+            let streams = sweep(
+                &TestWorldConfig::default(),
+                Role::H1,
+                &Attacks::new().add(Records::all(), Fp32BitPrime::ONE),
+                thread_rng().gen::<Fp32BitPrime>(),
+                |e| matches!(e, Error::MaliciousRevealFailed),
+                |ctx, share| {
+                    async move {
+                        malicious_reveal(
+                            ctx.narrow("reveal").set_total_records(1),
+                            RecordId::FIRST,
+                            None,
+                            &share,
+                        )
+                        .await
+                    }
+                    .boxed()
+                },
+            )
+            .await;
+
+            assert_eq!(2, streams.len());
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "no attack declared")]
+    fn sweep_requires_attack_for_every_stream() {
+        run(|| async {
+            sweep(
+                &TestWorldConfig::default(),
+                Role::H1,
+                &Attacks::new().add(Records::at("other"), Fp32BitPrime::ONE),
+                Fp32BitPrime::ONE,
+                |_: &Error| true,
+                |ctx, share| {
+                    async move {
+                        malicious_reveal(
+                            ctx.narrow("reveal").set_total_records(1),
+                            RecordId::FIRST,
+                            None,
+                            &share,
+                        )
+                        .await
+                    }
+                    .boxed()
+                },
+            )
+            .await;
+        });
+    }
+}
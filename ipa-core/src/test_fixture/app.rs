@@ -1,4 +1,4 @@
-use std::{array, iter::zip};
+use std::{array, iter::zip, path::Path};
 
 use generic_array::GenericArray;
 use typenum::Unsigned;
@@ -7,8 +7,9 @@ use crate::{
     ff::Serializable,
     helpers::{
         query::{QueryConfig, QueryInput},
-        ApiError, InMemoryMpcNetwork, InMemoryShardNetwork, Transport,
+        ApiError, DigestReport, HandlerRef, InMemoryMpcNetwork, InMemoryShardNetwork, Transport,
     },
+    hpke::ResultSigningKey,
     protocol::QueryId,
     query::QueryStatus,
     secret_sharing::IntoShares,
@@ -61,7 +62,40 @@ fn unzip_tuple_array<T, U>(input: [(T, U); 3]) -> ([T; 3], [U; 3]) {
 
 impl Default for TestApp {
     fn default() -> Self {
-        let (setup, handlers) = unzip_tuple_array(array::from_fn(|_| AppSetup::new()));
+        Self::with_setups(array::from_fn(|_| AppSetup::new()))
+    }
+}
+
+impl TestApp {
+    /// Creates a new app where helpers sign encrypted query results using `signing_keys`.
+    #[must_use]
+    pub fn with_result_signing_keys(signing_keys: [ResultSigningKey; 3]) -> Self {
+        Self::with_setups(signing_keys.map(|signing_key| {
+            let (setup, handler) = AppSetup::new();
+            (setup.with_result_signing_key(signing_key), handler)
+        }))
+    }
+
+    /// Creates a new app where helpers write transcripts of every query to `dir`.
+    #[must_use]
+    pub fn with_transcript_dir(dir: &Path) -> Self {
+        Self::with_setups(array::from_fn(|_| {
+            let (setup, handler) = AppSetup::new();
+            (setup.with_transcript_dir(dir.to_path_buf()), handler)
+        }))
+    }
+
+    /// Creates a new app where helpers keep digests of the records they exchange.
+    #[must_use]
+    pub fn with_channel_digests() -> Self {
+        Self::with_setups(array::from_fn(|_| {
+            let (setup, handler) = AppSetup::new();
+            (setup.with_channel_digests(), handler)
+        }))
+    }
+
+    fn with_setups(setups: [(AppSetup, HandlerRef); 3]) -> Self {
+        let (setup, handlers) = unzip_tuple_array(setups);
 
         let mpc_network = InMemoryMpcNetwork::new(handlers.map(Some));
         let shard_network = InMemoryShardNetwork::with_shards(1);
@@ -92,8 +126,19 @@ impl TestApp {
         I: IntoShares<A>,
         A: IntoBuf,
     {
-        let helpers_input = input.share().map(IntoBuf::into_buf);
+        self.start_query_with_inputs(input.share().map(IntoBuf::into_buf), query_config)
+            .await
+    }
 
+    /// Initiates a new query on all helpers, sending each of them the corresponding input.
+    ///
+    /// ## Errors
+    /// Returns an error if it can't start a query or send query input.
+    pub async fn start_query_with_inputs(
+        &self,
+        helpers_input: [Vec<u8>; 3],
+        query_config: QueryConfig,
+    ) -> Result<QueryId, ApiError> {
         // helper 1 initiates the query
         let query_id = self.drivers[0].start_query(query_config).await?;
 
@@ -124,6 +169,18 @@ impl TestApp {
             .unwrap())
     }
 
+    /// ## Errors
+    /// Propagates errors retrieving channel digests.
+    /// ## Panics
+    /// Never.
+    pub fn query_digests(&self, query_id: QueryId) -> Result<[DigestReport; 3], ApiError> {
+        Ok((0..3)
+            .map(|i| self.drivers[i].query_digests(query_id))
+            .collect::<Result<Vec<_>, _>>()?
+            .try_into()
+            .unwrap())
+    }
+
     /// ## Errors
     /// Returns an error if one or more helpers can't finish the processing.
     /// ## Panics
@@ -0,0 +1,60 @@
+use futures::TryStreamExt;
+use typenum::Const;
+
+use crate::{
+    error::UnwrapInfallible,
+    ff::boolean_array::BA64,
+    helpers::stream::{div_round_up, process_slice_by_chunks},
+    protocol::{
+        context::{dzkp_validator::DZKPValidator, Context, UpgradableContext},
+        ipa_prf::{boolean_ops::convert_to_fp25519, CONV_CHUNK, PRF_CHUNK},
+        RecordId,
+    },
+    rand::{thread_rng, Rng},
+    secret_sharing::{BitDecomposed, TransposeFrom},
+    seq_join::seq_join,
+    test_fixture::{Runner, TestWorld},
+};
+
+/// Generates match keys for the share conversion benchmark.
+#[must_use]
+pub fn conversion_setup(count: usize) -> Vec<BA64> {
+    let mut rng = thread_rng();
+    (0..count).map(|_| rng.gen::<BA64>()).collect()
+}
+
+/// Converts `match_keys` to `Fp25519` in a malicious setting. Each record converts `CONV_CHUNK`
+/// match keys and the multiplications of `records_per_batch` records are validated with a single
+/// proof.
+///
+/// # Panics
+/// On functional errors, since this is a benchmark.
+pub async fn malicious_conversion(records_per_batch: usize, match_keys: Vec<BA64>) {
+    let world = TestWorld::default();
+    let total_records = div_round_up(match_keys.len(), Const::<CONV_CHUNK>);
+
+    let results = world
+        .malicious(match_keys.into_iter(), |ctx, match_keys| async move {
+            let c_ctx = ctx.set_total_records(total_records);
+            let validator = &c_ctx.dzkp_validator(records_per_batch);
+            seq_join(
+                validator.context().active_work(),
+                process_slice_by_chunks(&match_keys, |idx, chunk| {
+                    let match_keys = BitDecomposed::transposed_from(&*chunk).unwrap_infallible();
+                    convert_to_fp25519::<_, CONV_CHUNK, PRF_CHUNK>(
+                        validator.clone(),
+                        RecordId::from(idx),
+                        match_keys,
+                    )
+                }),
+            )
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+        })
+        .await;
+
+    for result in results {
+        assert_eq!(total_records, result.len());
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "in-memory-infra")]
+mod adversary;
 pub mod input;
 mod sharing;
 #[cfg(feature = "in-memory-infra")]
@@ -10,22 +12,30 @@ mod app;
 
 #[cfg(feature = "in-memory-infra")]
 pub mod circuit;
+#[cfg(feature = "in-memory-infra")]
+pub mod conversion;
 mod event_gen;
 pub mod ipa;
 pub mod logging;
 pub mod metrics;
+#[cfg(feature = "in-memory-infra")]
+mod replay;
 pub(crate) mod step;
 #[cfg(feature = "in-memory-infra")]
 mod test_gate;
 
 use std::fmt::Debug;
 
+#[cfg(feature = "in-memory-infra")]
+pub use adversary::{first_error, sweep, Adversary, Attacks, Records};
 #[cfg(feature = "in-memory-infra")]
 pub use app::TestApp;
 pub use event_gen::{Config as EventGeneratorConfig, EventGenerator};
 use futures::TryFuture;
 use rand::{distributions::Standard, prelude::Distribution, rngs::mock::StepRng};
 use rand_core::{CryptoRng, RngCore};
+#[cfg(feature = "in-memory-infra")]
+pub use replay::{replay, replay_query, ReplayError};
 pub use sharing::{get_bits, into_bits, Reconstruct, ReconstructArr};
 #[cfg(feature = "in-memory-infra")]
 pub use world::{
@@ -35,7 +45,7 @@ pub use world::{
 
 use crate::{
     ff::{Field, U128Conversions},
-    protocol::prss::Endpoint as PrssEndpoint,
+    protocol::prss::{Endpoint as PrssEndpoint, PrssSeeds},
     secret_sharing::{
         replicated::semi_honest::AdditiveShare as Replicated, IntoShares, SharedValue,
     },
@@ -45,6 +55,13 @@ use crate::{
 /// p1 is left of p2, p2 is left of p3, p3 is left of p1...
 #[must_use]
 pub fn make_participants<R: RngCore + CryptoRng>(r: &mut R) -> [PrssEndpoint; 3] {
+    make_participants_with_seeds(r).0
+}
+
+/// Like [`make_participants`], but also returns the PRSS seeds of each participant.
+pub(crate) fn make_participants_with_seeds<R: RngCore + CryptoRng>(
+    r: &mut R,
+) -> ([PrssEndpoint; 3], [PrssSeeds; 3]) {
     let setup1 = PrssEndpoint::prepare(r);
     let setup2 = PrssEndpoint::prepare(r);
     let setup3 = PrssEndpoint::prepare(r);
@@ -52,11 +69,11 @@ pub fn make_participants<R: RngCore + CryptoRng>(r: &mut R) -> [PrssEndpoint; 3]
     let (pk2_l, pk2_r) = setup2.public_keys();
     let (pk3_l, pk3_r) = setup3.public_keys();
 
-    let p1 = setup1.setup(&pk3_r, &pk2_l);
-    let p2 = setup2.setup(&pk1_r, &pk3_l);
-    let p3 = setup3.setup(&pk2_r, &pk1_l);
+    let (p1, s1) = setup1.setup_with_seeds(&pk3_r, &pk2_l);
+    let (p2, s2) = setup2.setup_with_seeds(&pk1_r, &pk3_l);
+    let (p3, s3) = setup3.setup_with_seeds(&pk2_r, &pk1_l);
 
-    [p1, p2, p3]
+    ([p1, p2, p3], [s1, s2, s3])
 }
 
 pub type ReplicatedShares<T> = [Vec<Replicated<T>>; 3];
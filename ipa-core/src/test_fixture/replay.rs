@@ -0,0 +1,297 @@
+use std::{future::Future, pin::pin};
+
+use futures::{
+    future::{join_all, select, BoxFuture, Either},
+    stream, StreamExt,
+};
+
+use crate::{
+    helpers::{
+        routing::RouteId, BodyStream, Divergence, Gateway, GatewayConfig, HelperIdentity,
+        InMemoryMpcNetwork, InMemoryShardNetwork, RoleAssignment, StreamCompression, Transcript,
+        TranscriptRecorder, Transport,
+    },
+    hpke::PrivateKeyRegistry,
+    protocol::{context::SemiHonestContext, prss::Endpoint as PrssEndpoint, Gate, QueryId},
+    query::execute,
+    sharding::{NotSharded, ShardIndex},
+    sync::Arc,
+};
+
+/// Errors from replaying a query transcript with [`replay_query`].
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("transcript does not contain the query configuration")]
+    MissingQueryConfig,
+    #[error("transcript does not contain PRSS seeds")]
+    MissingPrss,
+    #[error("replayed helper diverged from the transcript: {0}")]
+    Diverged(#[from] Divergence),
+    #[error("replayed query failed: {0}")]
+    Query(#[from] crate::error::Error),
+}
+
+/// Runs `helper_fn` as the helper that recorded `transcript`, starting at `gate`.
+///
+/// The helper gets the PRSS seeds from the transcript and the records other helpers sent to it,
+/// for all channels under `gate`. Records it sends are compared with the ones in the transcript.
+/// This makes it possible to check a change to a protocol against a transcript recorded on a
+/// real helper, or to find the step where a helper started to misbehave, without the other two
+/// helpers. `gate` must be the gate the protocol was started at when the transcript was recorded.
+///
+/// Channels are replayed as they were recorded, so received records must have been read without
+/// gaps. The replayed helper uses the default [`GatewayConfig`], without compression. If
+/// `helper_fn` waits for records that are not in the transcript, the replay does not complete.
+///
+/// ## Errors
+/// Returns the first record that was sent differently from the transcript, or a record from the
+/// transcript that was not sent.
+///
+/// ## Panics
+/// If the transcript does not contain PRSS seeds.
+pub async fn replay<O, H>(
+    transcript: Transcript,
+    gate: &Gate,
+    helper_fn: H,
+) -> Result<O, Divergence>
+where
+    H: for<'a> FnOnce(SemiHonestContext<'a>) -> BoxFuture<'a, O>,
+{
+    let prss = PrssEndpoint::from_seeds(transcript.prss().expect("transcript has PRSS seeds"));
+    run(
+        transcript,
+        gate,
+        GatewayConfig::default(),
+        |gateway| async move {
+            let ctx = SemiHonestContext::new_with_gate(&prss, &gateway, NotSharded, gate.clone());
+            helper_fn(ctx).await
+        },
+    )
+    .await
+}
+
+/// Reruns the query recorded in `transcript` as the helper that wrote it, from the start.
+///
+/// `input` must be the query input this helper received, and `key_registry` must have the keys
+/// it used to decrypt it. Like [`replay`], the helper gets the records other helpers sent to it
+/// from the transcript and the records it sends are checked against the recording. The query
+/// configuration and PRSS seeds are also taken from the transcript. This works with transcripts
+/// written by helpers with the `--transcript-dir` option. If the helper waits for records that
+/// are not in the transcript, for example because it was given a different input, the replay does
+/// not complete.
+///
+/// ## Errors
+/// Returns [`ReplayError::Diverged`] with the first record that was sent differently from the
+/// transcript, or that was recorded but not sent. If the replayed query fails without diverging,
+/// its error is returned.
+pub async fn replay_query<R: PrivateKeyRegistry>(
+    transcript: Transcript,
+    key_registry: Arc<R>,
+    helper_origin: &str,
+    input: BodyStream,
+) -> Result<Vec<u8>, ReplayError> {
+    let config = *transcript
+        .query_config()
+        .ok_or(ReplayError::MissingQueryConfig)?;
+    if transcript.prss().is_none() {
+        return Err(ReplayError::MissingPrss);
+    }
+    let helper_origin = helper_origin.to_string();
+
+    let result = run(
+        transcript,
+        &Gate::default(),
+        GatewayConfig::from(&config),
+        |gateway| execute(config, key_registry, helper_origin, None, gateway, input),
+    )
+    .await?;
+
+    Ok(result?.to_bytes())
+}
+
+/// Runs `helper_fn` with a gateway that replays the channels under `gate` from `transcript`.
+async fn run<O, H, F>(
+    transcript: Transcript,
+    gate: &Gate,
+    config: GatewayConfig,
+    helper_fn: H,
+) -> Result<O, Divergence>
+where
+    H: FnOnce(Gateway) -> F,
+    F: Future<Output = O>,
+{
+    let role = transcript.role();
+    let transcript = Arc::new(transcript);
+    let recorder = Arc::new(TranscriptRecorder::check(Arc::clone(&transcript), gate));
+
+    let roles = RoleAssignment::new(HelperIdentity::make_three());
+    let network = InMemoryMpcNetwork::default();
+    let shard_network = InMemoryShardNetwork::with_shards(1);
+    let identity = roles.identity(role);
+    // records are stored uncompressed in the transcript
+    let gateway = Gateway::new(
+        QueryId,
+        GatewayConfig {
+            compression: StreamCompression::None,
+            ..config
+        },
+        roles.clone(),
+        network.transport(identity),
+        Transport::clone_ref(&shard_network.shard_transports(ShardIndex::FIRST)[role as usize]),
+    )
+    .with_transcript(Arc::clone(&recorder));
+
+    let in_scope = |name: &str| name.starts_with(gate.as_ref());
+
+    for recorded in transcript.received().filter(|s| in_scope(&s.gate)) {
+        let records = recorded.records.values().cloned().collect::<Vec<_>>();
+        network
+            .transport(roles.identity(recorded.peer))
+            .send(
+                identity,
+                (
+                    RouteId::Records,
+                    QueryId,
+                    Gate::from(recorded.gate.as_str()),
+                ),
+                stream::iter(records),
+            )
+            .await
+            .unwrap();
+    }
+
+    // Peers must read what the helper sends, or it will stall when its send buffers are full.
+    let drain = join_all(
+        transcript
+            .sent()
+            .filter(|s| in_scope(&s.gate))
+            .map(|expected| {
+                network
+                    .transport(roles.identity(expected.peer))
+                    .receive(identity, (QueryId, Gate::from(expected.gate.as_str())))
+                    .for_each(|_| async {})
+            }),
+    );
+
+    let output = match select(pin!(helper_fn(gateway)), pin!(drain)).await {
+        Either::Left((output, _)) => output,
+        Either::Right((_, helper)) => helper.await,
+    };
+
+    match recorder.divergence() {
+        Some(divergence) => Err(divergence),
+        None => Ok(output),
+    }
+}
+
+#[cfg(all(test, unit_test))]
+mod tests {
+    use futures::FutureExt;
+    use ipa_step::StepNarrow;
+
+    use super::{replay, replay_query, ReplayError};
+    use crate::{
+        ff::{Field, FieldType, Fp31, Fp32BitPrime, U128Conversions},
+        helpers::{
+            query::{QueryConfig, QueryType},
+            Direction, Role, Transcript,
+        },
+        hpke::{KeyRegistry, PrivateKeyOnly},
+        protocol::{
+            basics::SecureMul,
+            context::{Context, SemiHonestContext},
+            Gate, RecordId,
+        },
+        report::DEFAULT_HELPER_ORIGIN,
+        secret_sharing::{replicated::semi_honest::AdditiveShare, IntoShares},
+        sync::Arc,
+        test_fixture::{
+            app::IntoBuf, step::TestExecutionStep, Runner, TestApp, TestWorld, TestWorldConfig,
+        },
+    };
+
+    async fn square(ctx: SemiHonestContext<'_>) -> AdditiveShare<Fp31> {
+        let ctx = ctx.set_total_records(1);
+        let x: AdditiveShare<Fp31> = ctx.prss().generate(RecordId::FIRST);
+        x.multiply(&x, ctx, RecordId::FIRST).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_transcript() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let world = TestWorld::new_with(
+                TestWorldConfig::default().with_transcript_dir(dir.path().to_path_buf()),
+            );
+            world.semi_honest((), |ctx, ()| square(ctx)).await;
+        }
+        let gate = Gate::default().narrow(&TestExecutionStep::Iter(0));
+        let load = || Transcript::load(dir.path().join("0-H2.jsonl")).unwrap();
+        assert_eq!(Role::H2, load().role());
+
+        replay(load(), &gate, |ctx| square(ctx).boxed())
+            .await
+            .unwrap();
+
+        // a protocol that sends one more record than the recorded one
+        let divergence = replay(load(), &gate, |ctx| {
+            async move {
+                let extra = ctx.narrow("extra").set_total_records(1);
+                extra
+                    .send_channel::<Fp31>(extra.role().peer(Direction::Right))
+                    .send(RecordId::FIRST, Fp31::ONE)
+                    .await
+                    .unwrap();
+                square(ctx).await
+            }
+            .boxed()
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(format!("{}/extra", gate.as_ref()), divergence.gate);
+        assert_eq!(Role::H3, divergence.peer);
+        assert_eq!(None, divergence.expected);
+        assert_eq!(Some(vec![1]), divergence.actual);
+    }
+
+    #[tokio::test]
+    async fn replays_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = TestApp::with_transcript_dir(dir.path());
+        let share = |a: u128, b: u128| {
+            let shares: [Vec<AdditiveShare<Fp32BitPrime>>; 3] = vec![
+                Fp32BitPrime::truncate_from(a),
+                Fp32BitPrime::truncate_from(b),
+            ]
+            .into_iter()
+            .share();
+            shares.map(IntoBuf::into_buf)
+        };
+        let inputs = share(4, 5);
+        let config = QueryConfig::new(QueryType::TestMultiply, FieldType::Fp32BitPrime, 1).unwrap();
+        let query_id = app
+            .start_query_with_inputs(inputs.clone(), config)
+            .await
+            .unwrap();
+        let results = app.complete_query(query_id).await.unwrap();
+
+        let load = || Transcript::load(dir.path().join(format!("{query_id}-H2.jsonl"))).unwrap();
+        let key_registry = Arc::new(KeyRegistry::<PrivateKeyOnly>::empty());
+        let replayed = replay_query(
+            load(),
+            Arc::clone(&key_registry),
+            DEFAULT_HELPER_ORIGIN,
+            inputs[1].clone().into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(results[1], replayed);
+
+        // a different input changes what the helper sends
+        let [_, input, _] = share(6, 7);
+        let err = replay_query(load(), key_registry, DEFAULT_HELPER_ORIGIN, input.into())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ReplayError::Diverged(_)), "{err:?}");
+    }
+}
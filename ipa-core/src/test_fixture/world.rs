@@ -1,6 +1,9 @@
 // We have quite a bit of code that is only used when descriptive-gate is enabled.
 #![allow(dead_code)]
-use std::{array::from_fn, borrow::Borrow, fmt::Debug, io::stdout, iter::zip, marker::PhantomData};
+use std::{
+    array::from_fn, borrow::Borrow, fmt::Debug, io::stdout, iter::zip, marker::PhantomData,
+    path::PathBuf,
+};
 
 use async_trait::async_trait;
 use futures::{future::join_all, stream::FuturesOrdered, Future, StreamExt};
@@ -15,7 +18,7 @@ use crate::{
     helpers::{
         in_memory_config::{passthrough, DynStreamInterceptor},
         Gateway, GatewayConfig, HelperIdentity, InMemoryMpcNetwork, InMemoryShardNetwork,
-        InMemoryTransport, Role, RoleAssignment, Transport,
+        InMemoryTransport, Role, RoleAssignment, TranscriptRecorder, Transport,
     },
     protocol::{
         context::{
@@ -32,9 +35,11 @@ use crate::{
         IntoShares,
     },
     sharding::{NotSharded, ShardBinding, ShardIndex, Sharded},
-    telemetry::{stats::Metrics, StepStatsCsvExporter},
+    sync::Arc,
+    telemetry::{stats::Metrics, StepStatsCsvExporter, StepStatsCsvView},
     test_fixture::{
-        logging, make_participants,
+        adversary::Adversary,
+        logging, make_participants_with_seeds,
         metrics::MetricsHandle,
         sharing::ValidateMalicious,
         test_gate::{gate_vendor, TestGateVendor},
@@ -89,6 +94,7 @@ pub struct WithShards<const SHARDS: usize, D: Distribute = RoundRobin> {
 pub struct TestWorld<S: ShardingScheme = NotSharded> {
     shards: Box<[ShardWorld<S::ShardBinding>]>,
     metrics_handle: MetricsHandle,
+    step_stats_view: StepStatsCsvView,
     gate_vendor: Box<dyn TestGateVendor>,
     _shard_network: InMemoryShardNetwork,
     _phantom: PhantomData<S>,
@@ -139,6 +145,17 @@ pub struct TestWorldConfig {
     /// [`MaliciousHelper`]: crate::helpers::in_memory_config::MaliciousHelper
     /// [`passthrough`]: crate::helpers::in_memory_config::passthrough
     pub stream_interceptor: DynStreamInterceptor,
+
+    /// Directory to write transcripts of the messages exchanged by each helper to. Transcripts
+    /// are named after the shard and the role of the helper, `0-H1.jsonl` for the first helper
+    /// of a non-sharded world. They can be checked with [`replay`].
+    ///
+    /// [`replay`]: crate::test_fixture::replay
+    pub transcript_dir: Option<PathBuf>,
+
+    /// How the step statistics printed when the test world is dropped are partitioned. They are
+    /// printed if the `step-trace` feature or debug spans are enabled.
+    pub step_stats_view: StepStatsCsvView,
 }
 
 impl ShardingScheme for NotSharded {
@@ -248,7 +265,9 @@ impl<S: ShardingScheme> Drop for TestWorld<S> {
     fn drop(&mut self) {
         if tracing::span_enabled!(Level::DEBUG) || cfg!(feature = "step-trace") {
             let metrics = self.metrics_handle.snapshot();
-            metrics.export(&mut stdout()).unwrap();
+            metrics
+                .export_view(&mut stdout(), self.step_stats_view)
+                .unwrap();
         }
     }
 }
@@ -285,6 +304,7 @@ impl<S: ShardingScheme> TestWorld<S> {
         Self {
             shards,
             metrics_handle: MetricsHandle::new(config.metrics_level),
+            step_stats_view: config.step_stats_view,
             gate_vendor: gate_vendor(config.initial_gate.clone()),
             _shard_network: shard_network,
             _phantom: PhantomData,
@@ -317,6 +337,8 @@ impl Default for TestWorldConfig {
             seed: thread_rng().next_u64(),
             initial_gate: None,
             stream_interceptor: passthrough(),
+            transcript_dir: None,
+            step_stats_view: StepStatsCsvView::default(),
         }
     }
 }
@@ -334,6 +356,27 @@ impl TestWorldConfig {
         self
     }
 
+    /// Makes one of the helpers run the attacks described by `adversary`. This replaces
+    /// [`Self::stream_interceptor`], so the role assignment must be set before calling it.
+    #[must_use]
+    pub fn with_adversary(mut self, adversary: Adversary) -> Self {
+        self.stream_interceptor = adversary.into_interceptor(self.role_assignment());
+        self
+    }
+
+    #[must_use]
+    pub fn with_transcript_dir(mut self, dir: PathBuf) -> Self {
+        self.transcript_dir = Some(dir);
+        self
+    }
+
+    /// Breaks the step statistics printed on drop down by role, see [`StepStatsCsvView::PerRole`].
+    #[must_use]
+    pub fn with_step_stats_per_role(mut self) -> Self {
+        self.step_stats_view = StepStatsCsvView::PerRole;
+        self
+    }
+
     #[must_use]
     pub fn role_assignment(&self) -> &RoleAssignment {
         const DEFAULT_ASSIGNMENT: RoleAssignment = RoleAssignment::new([
@@ -674,7 +717,8 @@ impl<B: ShardBinding> ShardWorld<B> {
         shard_seed: u64,
         transports: [InMemoryTransport<ShardIndex>; 3],
     ) -> Self {
-        let participants = make_participants(&mut StdRng::seed_from_u64(config.seed + shard_seed));
+        let (participants, seeds) =
+            make_participants_with_seeds(&mut StdRng::seed_from_u64(config.seed + shard_seed));
         let network = InMemoryMpcNetwork::with_stream_interceptor(
             InMemoryMpcNetwork::noop_handlers(),
             &config.stream_interceptor,
@@ -694,6 +738,24 @@ impl<B: ShardBinding> ShardWorld<B> {
         #[allow(clippy::redundant_closure_for_method_calls)]
         gateways.sort_by_key(|g| g.role());
 
+        if let Some(dir) = &config.transcript_dir {
+            gateways = zip(gateways, seeds)
+                .map(|(gateway, seeds)| {
+                    let role = gateway.role();
+                    let recorder = TranscriptRecorder::create(
+                        dir.join(format!("{shard_seed}-{role:?}.jsonl")),
+                        role,
+                    )
+                    .unwrap();
+                    recorder.record_prss(&seeds);
+                    gateway.with_transcript(Arc::new(recorder))
+                })
+                .collect::<Vec<_>>()
+                .try_into()
+                .ok()
+                .unwrap();
+        }
+
         ShardWorld {
             shard_info,
             gateways,
@@ -131,6 +131,10 @@ pub fn spawn_helpers(
             command
                 .args(["-i", &id.to_string()])
                 .args(["--network".into(), config_path.join("network.toml")])
+                .args([
+                    "--result-signing-key".into(),
+                    config_path.join(format!("h{id}_result.key")),
+                ])
                 .silent();
 
             if https {
@@ -221,6 +225,17 @@ pub fn test_ipa(mode: IpaSecurityModel, https: bool) {
 }
 
 pub fn test_ipa_with_config(mode: IpaSecurityModel, https: bool, config: IpaQueryConfig) {
+    test_ipa_with_args(mode, https, config, &[]);
+}
+
+/// Runs IPA with additional report collector arguments, for example to split the input into
+/// several queries. Returns the report collector output.
+pub fn test_ipa_with_args(
+    mode: IpaSecurityModel,
+    https: bool,
+    config: IpaQueryConfig,
+    extra_args: &[&str],
+) -> IpaQueryResult {
     const INPUT_SIZE: usize = 100;
     // set to true to always keep the temp dir after test finishes
     let dir = TempDir::new_delete_on_drop();
@@ -279,7 +294,7 @@ pub fn test_ipa_with_config(mode: IpaSecurityModel, https: bool, config: IpaQuer
                 .args(["--epsilon", &config.epsilon.to_string()]);
         }
     }
-    command.stdin(Stdio::piped());
+    command.args(extra_args).stdin(Stdio::piped());
 
     if config.attribution_window_seconds.is_some() {
         command.args([
@@ -308,6 +323,111 @@ pub fn test_ipa_with_config(mode: IpaSecurityModel, https: bool, config: IpaQuer
         "Number of breakdowns does not match the expected",
     );
     assert_eq!(INPUT_SIZE, usize::from(output.input_size));
+
+    output
+}
+
+/// Runs IPA through separate `create`, `upload`, `status`, `wait` and `results` report
+/// collector invocations that share a state file. If `encrypt_offline` is set, reports are
+/// encrypted ahead of time with `encrypt` and sent with `upload-encrypted`.
+pub fn test_detached_ipa(https: bool, encrypt_offline: bool) {
+    const INPUT_SIZE: usize = 100;
+    let config = IpaQueryConfig::default();
+    let dir = TempDir::new_delete_on_drop();
+    let path = dir.path();
+
+    println!("generating configuration in {}", path.display());
+    let sockets = test_setup(path);
+    let _helpers = spawn_helpers(path, &sockets, https);
+
+    let inputs_file = path.join("ipa_inputs.txt");
+    let state_file = path.join("query.json");
+    let output_file = path.join("ipa_output.json");
+    Command::new(TEST_RC_BIN)
+        .args(["--output-file".as_ref(), inputs_file.as_os_str()])
+        .arg("gen-ipa-inputs")
+        .args(["--count", &INPUT_SIZE.to_string()])
+        .args(["--max-breakdown-key", &config.max_breakdown_key.to_string()])
+        .args(["--seed", &thread_rng().next_u64().to_string()])
+        .silent()
+        .status()
+        .unwrap_status();
+
+    let rc = |subcommand: &str| {
+        let mut command = Command::new(TEST_RC_BIN);
+        command
+            .args(["--network".into(), path.join("network.toml")])
+            .args(["--wait", "2"])
+            .silent();
+        if !https {
+            command.arg("--disable-https");
+        }
+        command
+            .arg(subcommand)
+            .args(["--state-file".as_ref(), state_file.as_os_str()]);
+        command
+    };
+
+    let mut create = rc("create");
+    create
+        .args(["--query-size", &INPUT_SIZE.to_string()])
+        .args(["--max-breakdown-key", &config.max_breakdown_key.to_string()])
+        .args([
+            "--per-user-credit-cap",
+            &config.per_user_credit_cap.to_string(),
+        ]);
+    if !https {
+        create.arg("--plaintext-match-keys");
+    }
+    create.status().unwrap_status();
+
+    // state file must not be overwritten by another query
+    assert!(!rc("create")
+        .args(["--query-size", &INPUT_SIZE.to_string()])
+        .status()
+        .unwrap()
+        .success());
+
+    if encrypt_offline {
+        let reports_dir = path.join("reports");
+        Command::new(TEST_RC_BIN)
+            .args(["--network".into(), path.join("network.toml")])
+            .args(["--input-file".as_ref(), inputs_file.as_os_str()])
+            .silent()
+            .arg("encrypt")
+            .args(["--output-dir".as_ref(), reports_dir.as_os_str()])
+            .status()
+            .unwrap_status();
+        rc("upload-encrypted")
+            .args(["--input-dir".as_ref(), reports_dir.as_os_str()])
+            .status()
+            .unwrap_status();
+    } else {
+        rc("upload")
+            .args(["--input-file".as_ref(), inputs_file.as_os_str()])
+            .status()
+            .unwrap_status();
+    }
+    rc("status").status().unwrap_status();
+    rc("wait")
+        .args(["--timeout", "60"])
+        .status()
+        .unwrap_status();
+    rc("results")
+        .args(["--output-file".as_ref(), output_file.as_os_str()])
+        .status()
+        .unwrap_status();
+
+    let output = serde_json::from_str::<IpaQueryResult>(
+        &std::fs::read_to_string(&output_file).expect("IPA results file exists"),
+    )
+    .expect("IPA results file is valid JSON");
+    assert_eq!(
+        usize::try_from(config.max_breakdown_key).unwrap(),
+        output.breakdowns.len(),
+        "Number of breakdowns does not match the expected",
+    );
+    assert_eq!(INPUT_SIZE, usize::from(output.input_size));
 }
 
 pub trait NetworkTest {
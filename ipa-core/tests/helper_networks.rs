@@ -3,10 +3,14 @@ mod common;
 use std::{array, net::TcpListener, path::Path, process::Command};
 
 use common::{
-    spawn_helpers, tempdir::TempDir, test_ipa, test_multiply, test_network, CommandExt,
-    UnwrapStatusExt, HELPER_BIN,
+    spawn_helpers, tempdir::TempDir, test_detached_ipa, test_ipa, test_ipa_with_args,
+    test_multiply, test_network, CommandExt, UnwrapStatusExt, HELPER_BIN,
+};
+use ipa_core::{
+    cli::CliPaths,
+    helpers::{query::IpaQueryConfig, HelperIdentity},
+    test_fixture::ipa::IpaSecurityModel,
 };
-use ipa_core::{cli::CliPaths, helpers::HelperIdentity, test_fixture::ipa::IpaSecurityModel};
 
 use crate::common::{AddInPrimeField, Multiply};
 
@@ -54,6 +58,36 @@ fn https_semi_honest_ipa() {
     test_ipa(IpaSecurityModel::SemiHonest, true);
 }
 
+#[test]
+#[cfg(all(test, web_test))]
+fn http_semi_honest_ipa_split() {
+    let output = test_ipa_with_args(
+        IpaSecurityModel::SemiHonest,
+        false,
+        IpaQueryConfig::default(),
+        &["--max-query-size", "40"],
+    );
+    assert!(output.partitions.get() >= 3);
+}
+
+#[test]
+#[cfg(all(test, web_test))]
+fn http_detached_ipa() {
+    test_detached_ipa(false, false);
+}
+
+#[test]
+#[cfg(all(test, web_test))]
+fn https_detached_ipa() {
+    test_detached_ipa(true, false);
+}
+
+#[test]
+#[cfg(all(test, web_test))]
+fn https_detached_ipa_encrypted_offline() {
+    test_detached_ipa(true, true);
+}
+
 /// Similar to [`network`] tests, but it uses keygen + confgen CLIs to generate helper client config
 /// and then just runs test multiply to make sure helpers are up and running
 ///
@@ -126,6 +160,18 @@ fn exec_keygen_cmd(helper_identity: HelperIdentity, dest_dir: &Path) {
         .args([
             "--mk-public-key".as_ref(),
             dest_dir.helper_mk_public_key(helper_identity).as_os_str(),
+        ])
+        .args([
+            "--result-signing-key".as_ref(),
+            dest_dir
+                .helper_result_signing_key(helper_identity)
+                .as_os_str(),
+        ])
+        .args([
+            "--result-verifying-key".as_ref(),
+            dest_dir
+                .helper_result_verifying_key(helper_identity)
+                .as_os_str(),
         ]);
 
     command.status().unwrap_status();